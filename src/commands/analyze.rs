@@ -0,0 +1,516 @@
+//! `analyze`: categorize packages by migration risk
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use console::{colors_enabled, style};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+
+use zb_migrate::migrate;
+use zb_migrate::{
+    AnalysisReport, BrewPackage, DependencyTreeLine, HomebrewMigrator, MigrationRisk,
+    PackageExplanation,
+};
+
+use super::{
+    format_dependency_chain, is_stdout, print_nothing_installed, print_timing_summary, write_report,
+};
+
+/// Options for `analyze`
+#[derive(Args)]
+pub struct AnalyzeArgs {
+    /// Output as JSON instead of formatted text
+    #[arg(long)]
+    pub json: bool,
+
+    /// With --json, write safe.json, risky.json, keep.json and summary.json to this
+    /// directory instead of printing one document
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "json",
+        conflicts_with = "explain"
+    )]
+    pub output_dir: Option<PathBuf>,
+
+    /// Layout of the text output
+    #[arg(long, value_enum, default_value_t = AnalyzeFormat::Text, conflicts_with = "json")]
+    pub format: AnalyzeFormat,
+
+    /// Write a markdown analysis report to this path (`-` for stdout)
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// Analyze the formulae listed in a Brewfile instead of querying Homebrew
+    #[arg(long, value_name = "BREWFILE")]
+    pub from: Option<PathBuf>,
+
+    /// Also categorize installed casks (reported only; casks cannot be migrated yet)
+    #[arg(long, conflicts_with = "from")]
+    pub include_casks: bool,
+
+    /// Also consider build-only dependencies (runtime dependencies only by default)
+    #[arg(long, conflicts_with = "from")]
+    pub include_build_deps: bool,
+
+    /// Only list packages at or above this risk level (summary totals still count all)
+    #[arg(long, value_enum, default_value_t = MinRisk::Safe)]
+    pub min_risk: MinRisk,
+
+    /// Treat any dependency on a keep-in-Homebrew package as blocking, not just risky
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Only follow dependencies this many levels deep when looking for problematic
+    /// ones (1 = direct dependencies only; default: unlimited)
+    #[arg(long, value_name = "N", conflicts_with = "explain")]
+    pub depth: Option<NonZeroUsize>,
+
+    /// Write an executable shell script that migrates every safe package (`-` for stdout)
+    #[arg(long, value_name = "PATH")]
+    pub emit_script: Option<PathBuf>,
+
+    /// Also migrate risky packages in the script, one at a time with a pause to test each
+    #[arg(long, requires = "emit_script")]
+    pub script_risky: bool,
+
+    /// Explain one package's classification: its problematic dependencies and the chains to them
+    #[arg(
+        long,
+        value_name = "PACKAGE",
+        conflicts_with_all = ["format", "report", "min_risk", "emit_script"]
+    )]
+    pub explain: Option<String>,
+
+    /// Show each top-level package's dependency tree, colored by risk
+    #[arg(
+        long,
+        conflicts_with_all = ["json", "format", "report", "min_risk", "emit_script", "explain"]
+    )]
+    pub tree: bool,
+}
+
+/// Text layouts for `analyze`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AnalyzeFormat {
+    /// Grouped summary with recommendations
+    Text,
+    /// One aligned row per package
+    Table,
+}
+
+/// Risk thresholds for `analyze --min-risk`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MinRisk {
+    /// Everything
+    Safe,
+    /// Risky and keep-in-Homebrew packages
+    Risky,
+    /// Only packages that should stay in Homebrew
+    Keep,
+}
+
+impl From<MinRisk> for MigrationRisk {
+    fn from(min: MinRisk) -> Self {
+        match min {
+            MinRisk::Safe => MigrationRisk::Safe,
+            MinRisk::Risky => MigrationRisk::Risky,
+            MinRisk::Keep => MigrationRisk::KeepInHomebrew,
+        }
+    }
+}
+
+/// Write a generated shell script and make it executable (or print it for `-`)
+fn write_script(path: &PathBuf, content: &str) -> Result<()> {
+    if is_stdout(path) {
+        out!("{}", content)?;
+        return Ok(());
+    }
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to write script to {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", path.display()))?;
+    }
+    eprintln!(
+        "{} Migration script written to {}; review it, then run it",
+        style("✓").green().bold(),
+        style(path.display()).white().bold()
+    );
+    Ok(())
+}
+
+/// `analyze`: categorize packages by migration risk
+pub fn run(
+    new_migrator: impl FnOnce() -> Result<HomebrewMigrator>,
+    args: AnalyzeArgs,
+) -> Result<()> {
+    let AnalyzeArgs {
+        json,
+        output_dir,
+        format,
+        report: report_path,
+        from,
+        include_casks,
+        include_build_deps,
+        min_risk,
+        strict,
+        depth,
+        emit_script,
+        script_risky,
+        explain,
+        tree,
+    } = args;
+    let report_to_stdout = report_path.as_deref().is_some_and(is_stdout);
+    let script_to_stdout = emit_script.as_deref().is_some_and(is_stdout);
+    if json && report_to_stdout {
+        bail!(
+            "--report - would mix the markdown report into the JSON on stdout\n\n\
+             Suggestions:\n\
+             - Write the report to a file: --report analysis.md\n\
+             - Drop --json to print the report alone"
+        );
+    }
+    if json && script_to_stdout {
+        bail!(
+            "--emit-script - would mix the shell script into the JSON on stdout\n\n\
+             Suggestions:\n\
+             - Write the script to a file: --emit-script migrate.sh\n\
+             - Drop --json to print the script alone"
+        );
+    }
+    if report_to_stdout && script_to_stdout {
+        bail!(
+            "--report - and --emit-script - would both write to stdout\n\n\
+             Suggestions:\n\
+             - Write one of them to a file, e.g. --emit-script migrate.sh"
+        );
+    }
+    let depth = depth.map(NonZeroUsize::get);
+    // Brewfile analysis works offline, without Homebrew
+    let migrator = match from {
+        Some(_) => None,
+        None => {
+            let mut migrator = new_migrator()?;
+            migrator.include_build_deps = include_build_deps;
+            migrator.strict = strict;
+            migrator.max_depth = depth;
+            Some(migrator)
+        }
+    };
+    let load_packages = || -> Result<Vec<BrewPackage>> {
+        match (&from, &migrator) {
+            (Some(brewfile), _) => Ok(migrate::load_brewfile(brewfile)?.formulae),
+            (None, Some(migrator)) => {
+                let mut packages = migrator.list_installed_formulae_detailed()?;
+                if include_casks {
+                    packages.extend(migrator.list_installed_casks()?);
+                }
+                Ok(packages)
+            }
+            (None, None) => unreachable!("a migrator is created when not using --from"),
+        }
+    };
+    let time_analysis = |analyze: &mut dyn FnMut() -> Result<()>| match &migrator {
+        Some(migrator) => migrator.time_analysis(analyze),
+        None => analyze(),
+    };
+    if tree || explain.is_some() {
+        let packages = load_packages()?;
+        if packages.is_empty() && migrator.is_some() && !json {
+            print_nothing_installed()?;
+            return Ok(());
+        }
+        time_analysis(&mut || {
+            if let Some(name) = &explain {
+                let explanation = HomebrewMigrator::explain_package(&packages, name, strict)?;
+                if json {
+                    outln!("{}", migrate::json_envelope(&explanation)?)?;
+                } else {
+                    print_explanation(&explanation)?;
+                }
+            } else {
+                let mut report = HomebrewMigrator::categorize_packages_with_depth(&packages, depth);
+                if strict {
+                    report.apply_strict();
+                }
+                print_dependency_tree(&report.dependency_tree(&packages))?;
+            }
+            Ok(())
+        })?;
+        if let Some(migrator) = &migrator {
+            print_timing_summary(migrator);
+        }
+        return Ok(());
+    }
+
+    let min_risk = MigrationRisk::from(min_risk);
+    let report = match from {
+        Some(brewfile) => {
+            let brewfile = migrate::load_brewfile(&brewfile)?;
+            for (kind, name) in &brewfile.unsupported {
+                eprintln!(
+                    "{} Skipping {} \"{}\": not supported by zerobrew",
+                    style("⚠").yellow().bold(),
+                    kind,
+                    name
+                );
+            }
+            eprintln!(
+                "{} Brewfiles carry no dependency information: only direct matches \
+                 against known problematic packages are detected.",
+                style("ℹ").cyan().bold()
+            );
+            let mut report = HomebrewMigrator::categorize_packages(&brewfile.formulae);
+            if strict {
+                report.apply_strict();
+            }
+            report
+        }
+        None => {
+            eprintln!("Analyzing installed packages...");
+            migrator
+                .as_ref()
+                .expect("a migrator is created when not using --from")
+                .analyze_packages(include_casks)?
+        }
+    };
+    if report.total_packages == 0 && migrator.is_some() && !json {
+        print_nothing_installed()?;
+        return Ok(());
+    }
+    if let Some(dir) = output_dir {
+        let written = report.at_or_above(&min_risk).write_json_files(&dir)?;
+        eprintln!(
+            "{} Wrote {} files to {}",
+            style("✓").green().bold(),
+            written.len(),
+            style(dir.display()).white().bold()
+        );
+    } else if json {
+        outln!(
+            "{}",
+            migrate::json_envelope(&report.at_or_above(&min_risk))?
+        )?;
+    } else if format == AnalyzeFormat::Table {
+        outln!("Total packages analyzed: {}\n", report.total_packages)?;
+        out!("{}", report.at_or_above(&min_risk).to_table())?;
+    } else {
+        print_analysis_summary(&report, &min_risk)?;
+    }
+    if let Some(path) = report_path {
+        write_report(&path, &report.to_markdown())?;
+    }
+    if let Some(path) = emit_script {
+        write_script(&path, &report.to_migration_script(script_risky))?;
+    }
+    if let Some(migrator) = &migrator {
+        print_timing_summary(migrator);
+    }
+    Ok(())
+}
+
+/// Print the `analyze --tree` view, coloring each package by its risk
+/// (or labeling it when colors are off)
+fn print_dependency_tree(lines: &[DependencyTreeLine]) -> Result<()> {
+    let colors = colors_enabled();
+    for line in lines {
+        let name = match line.risk {
+            Some(MigrationRisk::Safe) => style(&line.name).green(),
+            Some(MigrationRisk::Risky) => style(&line.name).yellow(),
+            Some(MigrationRisk::KeepInHomebrew) => style(&line.name).red(),
+            None => style(&line.name).dim(),
+        };
+        let name = if line.prefix.is_empty() {
+            name.bold()
+        } else {
+            name
+        };
+        let label = match &line.risk {
+            Some(risk) if !colors => format!(" [{}]", risk.label()),
+            _ => String::new(),
+        };
+        let repeated = if line.repeated {
+            format!(" {}", style("(see above)").dim())
+        } else {
+            String::new()
+        };
+        outln!("{}{}{}{}", style(&line.prefix).dim(), name, label, repeated)?;
+    }
+    if colors {
+        outln!(
+            "\n{} safe  {} risky  {} keep in Homebrew",
+            style("■").green(),
+            style("■").yellow(),
+            style("■").red()
+        )?;
+    }
+    Ok(())
+}
+
+/// Print the `analyze --explain` view of one package
+pub(super) fn print_explanation(explanation: &PackageExplanation) -> Result<()> {
+    let analysis = &explanation.analysis;
+    let risk = match analysis.risk {
+        MigrationRisk::Safe => style(analysis.risk.label()).green().bold(),
+        MigrationRisk::Risky => style(analysis.risk.label()).yellow().bold(),
+        MigrationRisk::KeepInHomebrew => style(analysis.risk.label()).red().bold(),
+    };
+    outln!(
+        "{} {}  {} {}",
+        style(&analysis.name).white().bold(),
+        style(&analysis.version).dim(),
+        risk,
+        style(format!("(score {})", analysis.risk_score)).dim()
+    )?;
+    outln!("Reason: {}", analysis.reason)?;
+    outln!(
+        "Bottle: {}",
+        if explanation.no_bottle {
+            "none for this platform (builds from source)"
+        } else {
+            "available"
+        }
+    )?;
+
+    outln!(
+        "\nProblematic direct dependencies: {}",
+        if explanation.direct_problematic.is_empty() {
+            "none".to_string()
+        } else {
+            explanation.direct_problematic.join(", ")
+        }
+    )?;
+    if !explanation.chains.is_empty() {
+        outln!("\nDependency chains to problematic packages:")?;
+        for chain in &explanation.chains {
+            outln!("  {}", chain.join(" → "))?;
+        }
+    }
+    if !explanation.reasons.is_empty() {
+        outln!("\nWhy these stay in Homebrew:")?;
+        for (name, reason) in &explanation.reasons {
+            outln!("  {:<24} {}", name, style(reason).dim())?;
+        }
+    }
+    Ok(())
+}
+
+/// Print a formatted summary of the analysis, listing categories at or above `min_risk`
+fn print_analysis_summary(report: &AnalysisReport, min_risk: &MigrationRisk) -> Result<()> {
+    outln!("\n=== Package Migration Analysis ===\n")?;
+    outln!("Total packages analyzed: {}\n", report.total_packages)?;
+
+    // Summary counts
+    outln!("Summary:")?;
+    outln!(
+        "  Safe to migrate:        {} packages",
+        report.safe_to_migrate.len()
+    )?;
+    outln!("  Risky (use caution):    {} packages", report.risky.len())?;
+    outln!(
+        "  Keep in Homebrew:       {} packages",
+        report.should_keep_in_homebrew.len()
+    )?;
+
+    // Safe packages
+    if MigrationRisk::Safe >= *min_risk && !report.safe_to_migrate.is_empty() {
+        outln!(
+            "\n--- Safe to Migrate ({}) ---",
+            report.safe_to_migrate.len()
+        )?;
+        outln!("These packages have no known issues and can be safely migrated:\n")?;
+        for pkg in &report.safe_to_migrate {
+            if pkg.is_cask {
+                outln!(
+                    "  [OK] {} @ {} (cask, migration not yet supported)",
+                    pkg.name,
+                    pkg.version
+                )?;
+            } else {
+                outln!("  [OK] {} @ {}", pkg.name, pkg.version)?;
+            }
+        }
+    }
+
+    // Risky packages
+    if MigrationRisk::Risky >= *min_risk && !report.risky.is_empty() {
+        outln!("\n--- Risky Packages ({}) ---", report.risky.len())?;
+        outln!("These packages depend on problematic packages, highest risk first. Migration may work but test carefully:\n")?;
+        for pkg in &report.risky {
+            outln!("  [!] {} @ {}", pkg.name, pkg.version)?;
+            outln!(
+                "      Reason: {} (risk score {})",
+                pkg.reason,
+                pkg.risk_score
+            )?;
+            if !pkg.problematic_dependencies.is_empty() {
+                outln!(
+                    "      Problematic deps: {}",
+                    pkg.problematic_dependencies.join(", ")
+                )?;
+            }
+        }
+    }
+
+    // Keep in Homebrew
+    if !report.should_keep_in_homebrew.is_empty() {
+        outln!(
+            "\n--- Keep in Homebrew ({}) ---",
+            report.should_keep_in_homebrew.len()
+        )?;
+        outln!("These packages are known to have issues and should remain in Homebrew:\n")?;
+        for pkg in &report.should_keep_in_homebrew {
+            let kind = if pkg.is_cask { " (cask)" } else { "" };
+            outln!("  [X] {} @ {}{}", pkg.name, pkg.version, kind)?;
+            outln!("      Reason: {}", pkg.reason)?;
+        }
+    }
+
+    if report.longest_chain.len() > 1 {
+        outln!(
+            "\nLongest dependency chain: {}",
+            format_dependency_chain(&report.longest_chain)
+        )?;
+    }
+
+    // Recommendations
+    outln!("\n=== Recommendations ===\n")?;
+
+    let safe_formulae = report.safe_formulae();
+    if !safe_formulae.is_empty() {
+        outln!("1. Start by migrating safe packages:")?;
+        outln!(
+            "   zb-migrate migrate --packages {}",
+            safe_formulae
+                .iter()
+                .take(5)
+                .copied()
+                .collect::<Vec<_>>()
+                .join(",")
+        )?;
+        if safe_formulae.len() > 5 {
+            outln!(
+                "   (showing first 5 of {} safe packages; --emit-script writes them all)",
+                safe_formulae.len()
+            )?;
+        }
+    }
+
+    if !report.risky.is_empty() {
+        outln!("\n2. For risky packages, migrate one at a time and test:")?;
+        outln!("   zb-migrate migrate --packages <package-name>")?;
+        outln!("   # Then test the package before proceeding")?;
+    }
+
+    if !report.should_keep_in_homebrew.is_empty() {
+        outln!("\n3. Leave problematic packages in Homebrew:")?;
+        outln!("   These packages are core dependencies that many other packages rely on.")?;
+        outln!("   Migrating them may break other software.")?;
+    }
+
+    outln!()?;
+    Ok(())
+}
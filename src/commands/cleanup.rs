@@ -0,0 +1,126 @@
+//! `cleanup`: remove migrated packages from Homebrew
+
+use anyhow::Result;
+use clap::Args;
+use console::style;
+
+use zb_migrate::HomebrewMigrator;
+
+/// Options for `cleanup`
+#[derive(Args)]
+pub struct CleanupArgs {
+    /// Force cleanup without confirmation
+    #[arg(long)]
+    pub force: bool,
+
+    /// Show which packages would be removed without uninstalling anything
+    #[arg(long, conflicts_with = "force")]
+    pub dry_run: bool,
+
+    /// Let brew refuse to remove packages other formulae still depend on
+    /// (drops --ignore-dependencies from brew uninstall)
+    #[arg(long, alias = "no-cleanup-deps")]
+    pub no_ignore_deps: bool,
+}
+
+/// `cleanup`: uninstall migrated packages from Homebrew
+pub fn run(migrator: &HomebrewMigrator, args: CleanupArgs) -> Result<()> {
+    let CleanupArgs {
+        force,
+        dry_run,
+        no_ignore_deps,
+    } = args;
+    let state = migrator.load_state()?;
+    // Dependents go first so no formula is left missing a dependency mid-teardown
+    let packages = HomebrewMigrator::cleanup_order(&state)?;
+
+    if packages.is_empty() {
+        outln!(
+            "{} No migrated packages to clean up.",
+            style("ℹ").cyan().bold()
+        )?;
+    } else if dry_run {
+        outln!(
+            "{} The following commands would run:\n",
+            style("[DRY RUN]").yellow().bold()
+        )?;
+        let running = migrator.list_running_services();
+        for name in packages.iter().filter(|n| running.contains(*n)) {
+            outln!("  brew services stop {}", name)?;
+        }
+        let uninstall_flags = if no_ignore_deps {
+            "uninstall"
+        } else {
+            "uninstall --ignore-dependencies"
+        };
+        for candidate in migrator.cleanup_candidates(&state)? {
+            let presence = if candidate.installed_in_homebrew {
+                style("installed in Homebrew").green()
+            } else {
+                style("no longer in Homebrew").dim()
+            };
+            outln!(
+                "  brew {} {:<24} {} ({})",
+                uninstall_flags,
+                candidate.name,
+                style(&candidate.version).dim(),
+                presence
+            )?;
+        }
+    } else if !force {
+        outln!("WARNING: This will uninstall packages from Homebrew.")?;
+        outln!("Make sure zerobrew has successfully installed them first.")?;
+        outln!("Run with --force to proceed.")?;
+    } else {
+        migrator.check_separate_prefixes()?;
+        let _lock = migrator.acquire_lock()?;
+        let running = migrator.list_running_services();
+        for name in packages.iter().filter(|n| running.contains(*n)) {
+            outln!("Stopping Homebrew service: {}", name)?;
+            if !migrator.stop_service(name).unwrap_or(false) {
+                outln!(
+                    "{} Failed to stop the {} service; stop it with `brew services stop {}`",
+                    style("✗").red().bold(),
+                    style(name).yellow(),
+                    name
+                )?;
+            }
+        }
+        if packages.iter().any(|n| running.contains(n)) {
+            outln!(
+                "{} Zerobrew does not manage services: set up a launchd service for the \
+                 migrated daemons if you still need them running.",
+                style("ℹ").cyan().bold()
+            )?;
+        }
+        let mut refused = Vec::new();
+        for name in &packages {
+            outln!("Removing from Homebrew: {}", name)?;
+            if !migrator
+                .uninstall_from_homebrew(name, !no_ignore_deps)
+                .unwrap_or(false)
+            {
+                outln!(
+                    "{} Failed to remove {} from Homebrew",
+                    style("✗").red().bold(),
+                    style(name).yellow()
+                )?;
+                refused.push(name.as_str());
+            }
+        }
+        if no_ignore_deps && !refused.is_empty() {
+            outln!(
+                "\n{} Homebrew kept {} package(s), most likely because other formulae \
+                 still depend on them: {}",
+                style("ℹ").cyan().bold(),
+                refused.len(),
+                refused.join(", ")
+            )?;
+            outln!(
+                "  Remove their dependents first, or rerun without --no-ignore-deps to \
+                 force removal."
+            )?;
+        }
+    }
+    Ok(())
+}
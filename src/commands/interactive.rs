@@ -0,0 +1,245 @@
+//! `migrate --interactive`: ask before migrating each package
+
+use anyhow::Result;
+use console::style;
+use dialoguer::{theme::ColorfulTheme, Select};
+use std::cell::RefCell;
+
+use zb_migrate::{
+    BrewPackage, HomebrewMigrator, MigrateOptions, MigrateResult, MigrationEvent, MigrationPlan,
+    MigrationReport, MigrationRisk, PackageChoice,
+};
+
+use super::analyze::print_explanation;
+use super::migrate::{
+    execute, print_install_mode, print_pin_fallback, print_renamed, print_version_drift,
+};
+
+/// Interactive migration mode - prompts user before each package of `plan`.
+///
+/// Callers fall back to a plain migration when there is no terminal to prompt on.
+pub(super) fn migrate_interactive(
+    migrator: &HomebrewMigrator,
+    plan: &MigrationPlan,
+    options: &MigrateOptions,
+) -> Result<MigrationReport> {
+    if options.dry_run {
+        outln!("\n=== Interactive Migration Mode (DRY RUN - nothing will be installed) ===\n")?;
+    } else {
+        outln!("\n=== Interactive Migration Mode ===\n")?;
+    }
+    outln!("Found {} formulae to migrate.\n", plan.formulae.len())?;
+    outln!("Options for each package:")?;
+    outln!("  (y)es     - Migrate this package")?;
+    outln!("  (n)o      - Skip this package")?;
+    outln!("  (a)ll yes - Migrate all remaining packages")?;
+    outln!("  (d)etails - Explain the package's risk, then ask again")?;
+    outln!("  (q)uit    - Stop migration\n")?;
+
+    let sorted = &plan.formulae;
+    let analysis = HomebrewMigrator::categorize_packages(sorted);
+    let mut migrate_all_remaining = false;
+
+    let mut prompt = |idx: usize, pkg: &BrewPackage| -> Result<PackageChoice> {
+        // Show package info
+        outln!(
+            "{}",
+            style(format!("--- Package {}/{} ---", idx + 1, sorted.len())).bold()
+        )?;
+        outln!("  Name:    {}", style(&pkg.name).cyan())?;
+        outln!("  Version: {}", pkg.version)?;
+        if let Some(ref tap) = pkg.tap {
+            outln!("  Tap:     {}", tap)?;
+        }
+        if !pkg.dependencies.is_empty() {
+            outln!("  Deps:    {}", pkg.dependencies.join(", "))?;
+        }
+        if pkg.pinned {
+            outln!("  Status:  {}", style("[pinned]").yellow())?;
+        }
+        if pkg.keg_only {
+            outln!(
+                "  Keg-only: {}",
+                style("yes (not linked into the Homebrew prefix)").yellow()
+            )?;
+        }
+        if pkg.head {
+            outln!(
+                "  HEAD:    {}",
+                style("yes (zerobrew will install the stable version)").yellow()
+            )?;
+        }
+        if pkg.no_bottle {
+            outln!(
+                "  Bottle:  {}",
+                style("none for this platform (will build from source)").yellow()
+            )?;
+        }
+        let risk = analysis.find(&pkg.name);
+        if let Some(entry) = risk {
+            let label = match entry.risk {
+                MigrationRisk::Safe => style(entry.risk.label()).green(),
+                MigrationRisk::Risky => style(entry.risk.label()).yellow(),
+                MigrationRisk::KeepInHomebrew => style(entry.risk.label()).red(),
+            };
+            outln!(
+                "  Risk:    {} - {}",
+                label.bold(),
+                style(&entry.reason).dim()
+            )?;
+        }
+        outln!()?;
+
+        if migrate_all_remaining {
+            outln!("  Auto-migrating (all yes mode)...")?;
+        } else {
+            // Show interactive prompt
+            let items = vec![
+                "(y)es - Migrate this package",
+                "(n)o - Skip this package",
+                "(a)ll yes - Migrate all remaining",
+                "(d)etails - Explain the risk",
+                "(q)uit - Stop migration",
+            ];
+
+            // Default to "no" for packages that should stay in Homebrew
+            let default = match risk.map(|entry| &entry.risk) {
+                Some(MigrationRisk::KeepInHomebrew) => 1,
+                _ => 0,
+            };
+
+            loop {
+                let selection = Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("What would you like to do?")
+                    .items(&items)
+                    .default(default)
+                    .interact();
+
+                match selection {
+                    Ok(0) => break, // Yes
+                    Ok(1) => {
+                        // No/Skip
+                        outln!("  {} Skipped\n", style("->").yellow())?;
+                        return Ok(PackageChoice::Skip("User skipped".to_string()));
+                    }
+                    Ok(2) => {
+                        // All yes
+                        migrate_all_remaining = true;
+                        break;
+                    }
+                    Ok(3) => {
+                        // Details, then ask about the same package again
+                        outln!()?;
+                        match HomebrewMigrator::explain_package(sorted, &pkg.name, migrator.strict)
+                        {
+                            Ok(explanation) => print_explanation(&explanation)?,
+                            Err(e) => eprintln!("{} {}", style("⚠").yellow().bold(), e),
+                        }
+                        outln!()?;
+                    }
+                    _ => {
+                        // Quit
+                        outln!("\n{}", style("Migration stopped by user.").yellow())?;
+                        return Ok(PackageChoice::Stop);
+                    }
+                }
+            }
+        }
+
+        if options.dry_run {
+            outln!(
+                "  {} Would migrate: {} @ {}\n",
+                style("[DRY RUN]").yellow().bold(),
+                pkg.name,
+                pkg.version
+            )?;
+        }
+        Ok(PackageChoice::Migrate)
+    };
+
+    // A failed write (e.g. stdout closed) stops before the next package rather than
+    // aborting the run, so the packages already migrated are still recorded
+    let write_error = RefCell::new(None);
+    let report = execute(
+        migrator,
+        plan,
+        options,
+        &mut |event| match event {
+            MigrationEvent::Started { package, .. } => {
+                eprintln!("Migrating: {} ({})", package.name, package.version)
+            }
+            MigrationEvent::Finished { result, .. } => {
+                let mut write_error = write_error.borrow_mut();
+                if write_error.is_none() {
+                    *write_error = print_interactive_result(result).err();
+                }
+            }
+        },
+        &mut |idx, pkg| {
+            if write_error.borrow().is_some() {
+                return Ok(PackageChoice::Stop);
+            }
+            prompt(idx, pkg).or_else(|e| {
+                *write_error.borrow_mut() = Some(e);
+                Ok(PackageChoice::Stop)
+            })
+        },
+    )?;
+    match write_error.into_inner() {
+        Some(e) => Err(e),
+        None => Ok(report),
+    }
+}
+
+/// Print the outcome of a package migrated in interactive mode
+fn print_interactive_result(result: &MigrateResult) -> Result<()> {
+    match result {
+        MigrateResult::Success {
+            name,
+            version,
+            installed_version,
+            pin_fallback,
+            zerobrew_name,
+            install_mode,
+        } => {
+            if let Some(zb_name) = zerobrew_name {
+                print_renamed(zb_name)?;
+            }
+            print_install_mode(*install_mode)?;
+            if *pin_fallback {
+                print_pin_fallback(version)?;
+            }
+            print_version_drift(version, installed_version.as_deref())?;
+            outln!(
+                "  {} Migrated: {} @ {}\n",
+                style("OK").green(),
+                name,
+                version
+            )?;
+        }
+        MigrateResult::Failed { name, reason, .. } => {
+            outln!("  {} Failed: {} - {}\n", style("X").red(), name, reason)?;
+        }
+    }
+    Ok(())
+}
+
+/// Summarize the choices made in `migrate --interactive --dry-run`
+pub(super) fn print_interactive_plan(report: &MigrationReport) -> Result<()> {
+    outln!("\n=== Interactive Dry Run Summary ===")?;
+    outln!("Would migrate: {}", report.planned.len())?;
+    outln!("Skipped: {}", report.skipped.len())?;
+    if report.planned.is_empty() {
+        return Ok(());
+    }
+    outln!("\nPackages chosen for migration:")?;
+    for name in &report.planned {
+        outln!("  {}", name)?;
+    }
+    outln!("\nTo migrate exactly these packages, run:")?;
+    outln!(
+        "  zb-migrate migrate --packages {}",
+        report.planned.join(",")
+    )?;
+    Ok(())
+}
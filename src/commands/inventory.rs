@@ -0,0 +1,279 @@
+//! `list`, `export`, `taps` and `stats`: report on the Homebrew installation
+
+use anyhow::{bail, Result};
+use clap::Args;
+use console::style;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use zb_migrate::migrate;
+use zb_migrate::{BrewPackage, HomebrewMigrator, HomebrewStats};
+
+use super::{format_dependency_chain, is_stdout};
+
+/// Options for `list`
+#[derive(Args)]
+pub struct ListArgs {
+    /// Include casks in the listing
+    #[arg(long)]
+    pub casks: bool,
+
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+
+    /// Output as CSV (name,version,tap,is_cask,pinned,dependencies) for spreadsheets
+    #[arg(long, conflicts_with_all = ["json", "with_dependents"])]
+    pub csv: bool,
+
+    /// Show how many installed formulae depend on each formula
+    /// (slower: loads every formula's dependencies)
+    #[arg(long)]
+    pub with_dependents: bool,
+
+    /// Group formulae under their tap (slower: looks up each formula's tap)
+    #[arg(long, conflicts_with_all = ["json", "csv"])]
+    pub group_by_tap: bool,
+}
+
+/// `list`: print installed formulae, and casks with --casks
+pub fn list(migrator: &HomebrewMigrator, args: ListArgs) -> Result<()> {
+    let ListArgs {
+        casks,
+        json,
+        csv,
+        with_dependents,
+        group_by_tap,
+    } = args;
+    // CSV and tap groups need dependency counts and taps from the detailed listing
+    let formulae = if with_dependents || csv || group_by_tap {
+        migrator.list_installed_formulae_detailed()?
+    } else {
+        migrator.list_installed_formulae()?
+    };
+    let dependents = if with_dependents {
+        migrate::dependent_counts(&formulae)
+    } else {
+        HashMap::new()
+    };
+
+    if csv {
+        let mut all_packages = formulae;
+        if casks {
+            all_packages.extend(migrator.list_installed_casks()?);
+        }
+        out!("{}", migrate::packages_to_csv(&all_packages))?;
+    } else if json && with_dependents {
+        let mut all_packages = formulae
+            .iter()
+            .map(|pkg| {
+                let mut value = serde_json::to_value(pkg)?;
+                value["dependents"] = dependents[pkg.name.as_str()].into();
+                Ok(value)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if casks {
+            for cask in migrator.list_installed_casks()? {
+                all_packages.push(serde_json::to_value(cask)?);
+            }
+        }
+        outln!("{}", migrate::json_envelope(&all_packages)?)?;
+    } else if json {
+        let mut all_packages = formulae.clone();
+        if casks {
+            all_packages.extend(migrator.list_installed_casks()?);
+        }
+        outln!("{}", migrate::json_envelope(&all_packages)?)?;
+    } else {
+        outln!(
+            "{} {}",
+            style("📦 Homebrew Formulae").cyan().bold(),
+            style(format!("({})", formulae.len())).dim()
+        )?;
+        outln!("{}", style("─".repeat(50)).dim())?;
+        let print_formula = |pkg: &BrewPackage, indent: &str| {
+            let pinned = if pkg.pinned {
+                format!(" {}", style("[pinned]").yellow())
+            } else {
+                String::new()
+            };
+            let dependency = if pkg.installed_on_request {
+                String::new()
+            } else {
+                format!(" {}", style("[dependency]").dim())
+            };
+            // The group header already names the tap
+            let tap = pkg
+                .tap
+                .as_ref()
+                .filter(|_| !group_by_tap)
+                .map(|t| format!(" {}", style(format!("({})", t)).dim()))
+                .unwrap_or_default();
+            let dependent_count = match dependents.get(pkg.name.as_str()) {
+                Some(0) => format!(" {}", style("[leaf]").green()),
+                Some(count) => {
+                    format!(" {}", style(format!("[{} dependents]", count)).cyan())
+                }
+                None => String::new(),
+            };
+            outln!(
+                "{}{:<28} {}{}{}{}{}",
+                indent,
+                style(&pkg.name).white().bold(),
+                style(&pkg.version).dim(),
+                tap,
+                pinned,
+                dependency,
+                dependent_count
+            )
+        };
+        if group_by_tap {
+            for (idx, (tap, packages)) in migrate::group_by_tap(&formulae).into_iter().enumerate() {
+                if idx > 0 {
+                    outln!()?;
+                }
+                outln!(
+                    "  {} {}",
+                    style(tap).yellow().bold(),
+                    style(format!("({})", packages.len())).dim()
+                )?;
+                for pkg in packages {
+                    print_formula(pkg, "    ")?;
+                }
+            }
+        } else {
+            for pkg in &formulae {
+                print_formula(pkg, "  ")?;
+            }
+        }
+
+        if casks {
+            let cask_list = migrator.list_installed_casks()?;
+            outln!(
+                "\n{} {}",
+                style("🖥️  Homebrew Casks").cyan().bold(),
+                style(format!("({})", cask_list.len())).dim()
+            )?;
+            outln!("{}", style("─".repeat(50)).dim())?;
+            for pkg in &cask_list {
+                outln!(
+                    "  {:<28} {}",
+                    style(&pkg.name).white().bold(),
+                    style(&pkg.version).dim()
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `export`: write installed packages as a Brewfile
+pub fn export(migrator: &HomebrewMigrator, output: PathBuf, append: bool) -> Result<()> {
+    if is_stdout(&output) {
+        if append {
+            bail!(
+                "--append needs an existing Brewfile to merge into and cannot write to stdout.\n\
+                 Suggestion: pass a file path with --output, or drop --append."
+            );
+        }
+        out!("{}", migrator.render_installed_brewfile()?)?;
+        return Ok(());
+    }
+    eprintln!(
+        "{} Exporting to {}...",
+        style("→").cyan().bold(),
+        style(output.display()).white()
+    );
+    let written = migrator.export_to_brewfile(&output, append)?;
+    if append {
+        outln!(
+            "{} Added {} new entries to {}",
+            style("✓").green().bold(),
+            style(written).white().bold(),
+            style(output.display()).white().bold()
+        )?;
+    } else {
+        outln!(
+            "{} Brewfile created at {}",
+            style("✓").green().bold(),
+            style(output.display()).white().bold()
+        )?;
+    }
+    Ok(())
+}
+
+/// `taps`: print the third-party taps in use
+pub fn taps(migrator: &HomebrewMigrator, json: bool, brewfile: bool) -> Result<()> {
+    let taps = migrator.list_taps()?;
+    if json {
+        outln!("{}", migrate::json_envelope(&taps)?)?;
+    } else if brewfile {
+        out!("{}", migrate::render_taps(&taps))?;
+    } else if taps.is_empty() {
+        eprintln!("{} No third-party taps in use.", style("ℹ").cyan().bold());
+    } else {
+        for tap in &taps {
+            outln!("{}", tap)?;
+        }
+    }
+    Ok(())
+}
+
+/// `stats`: summarize the Homebrew installation
+pub fn stats(migrator: &HomebrewMigrator, json: bool) -> Result<()> {
+    let stats = migrator.collect_stats()?;
+    if json {
+        outln!("{}", migrate::json_envelope(&stats)?)?;
+    } else {
+        print_stats(&stats)?;
+    }
+    Ok(())
+}
+
+/// Print a formatted overview of the Homebrew installation
+fn print_stats(stats: &HomebrewStats) -> Result<()> {
+    outln!("{}", style("📊 Homebrew Statistics").cyan().bold())?;
+    outln!("{}", style("─".repeat(50)).dim())?;
+    outln!(
+        "  {:<28} {}",
+        "Formulae",
+        style(stats.total_formulae).bold()
+    )?;
+    outln!("  {:<28} {}", "Casks", style(stats.total_casks).bold())?;
+    outln!("  {:<28} {}", "Pinned", style(stats.pinned).bold())?;
+    outln!(
+        "  {:<28} {}",
+        "From third-party taps",
+        style(stats.from_third_party_taps).bold()
+    )?;
+
+    outln!("\n{}", style("Migration risk").cyan().bold())?;
+    outln!(
+        "  {:<28} {}",
+        "Safe to migrate",
+        style(stats.safe_to_migrate).green().bold()
+    )?;
+    outln!("  {:<28} {}", "Risky", style(stats.risky).yellow().bold())?;
+    outln!(
+        "  {:<28} {}",
+        "Keep in Homebrew",
+        style(stats.keep_in_homebrew).red().bold()
+    )?;
+
+    if stats.longest_chain.len() > 1 {
+        outln!("\n{}", style("Longest dependency chain").cyan().bold())?;
+        outln!("  {}", format_dependency_chain(&stats.longest_chain))?;
+    }
+
+    if !stats.most_depended_upon.is_empty() {
+        outln!("\n{}", style("Most depended-upon packages").cyan().bold())?;
+        for entry in &stats.most_depended_upon {
+            outln!(
+                "  {:<28} {}",
+                style(&entry.name).white().bold(),
+                style(format!("{} dependents", entry.dependents)).dim()
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,1353 @@
+//! `migrate` and `retry-failed`: plan, confirm and run migrations
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use console::style;
+use dialoguer::{theme::ColorfulTheme, Confirm};
+use indicatif::ProgressBar;
+use std::io::{IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use zb_migrate::migrate;
+use zb_migrate::{
+    BrewPackage, HomebrewMigrator, InstallMode, MigrateOptions, MigrateResult, MigrationEvent,
+    MigrationPlan, MigrationReport, OrderConstraint, PackageChoice, PackageImpact,
+};
+
+use super::interactive::{migrate_interactive, print_interactive_plan};
+use super::{
+    is_stdout, nothing_installed, print_nothing_installed, print_timing_summary, write_report,
+};
+
+/// Options for `migrate`
+#[derive(Args)]
+pub struct MigrateArgs {
+    /// Dry run - show what would be migrated without making changes
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// With --dry-run, look up dependencies so the preview shows the exact install order
+    #[arg(long, requires = "dry_run", conflicts_with = "packages")]
+    pub exact_order: bool,
+
+    /// Migrate only specific packages; accepts globs like 'node*' or 'python@*',
+    /// and @FILE to read names from a file (one per line)
+    #[arg(short, long, value_delimiter = ',')]
+    pub packages: Option<Vec<String>>,
+
+    /// Also migrate the installed dependencies of each named package first
+    #[arg(long, requires = "packages")]
+    pub with_deps: bool,
+
+    /// Also migrate installed packages that depend on the named packages
+    #[arg(long, requires = "packages")]
+    pub with_dependents: bool,
+
+    /// Install named packages with zb even if they aren't installed in Homebrew
+    #[arg(long, requires = "packages")]
+    pub allow_uninstalled: bool,
+
+    /// Before installing, show what each package pulls in and what depends on it
+    #[arg(long, requires = "packages")]
+    pub show_impact: bool,
+
+    /// How many levels of reverse dependents to follow with --with-dependents
+    #[arg(long, default_value_t = 3, requires = "with_dependents")]
+    pub dependents_depth: usize,
+
+    /// Interactive mode - prompt before each package migration
+    #[arg(short, long)]
+    pub interactive: bool,
+
+    /// Only migrate formulae installed within this duration (e.g. 12h, 7d, 2w)
+    #[arg(long, value_parser = migrate::parse_duration, conflicts_with = "packages")]
+    pub since: Option<Duration>,
+
+    /// Leave formulae from these taps in Homebrew (comma-separated, e.g. user/x,user/y)
+    #[arg(
+        long,
+        value_name = "TAPS",
+        value_delimiter = ',',
+        conflicts_with = "packages"
+    )]
+    pub exclude_taps: Vec<String>,
+
+    /// Reinstall formulae an earlier run already migrated
+    #[arg(long, conflicts_with = "packages")]
+    pub force: bool,
+
+    /// Only migrate formulae you installed explicitly; zerobrew installs their
+    /// dependencies itself
+    #[arg(long, conflicts_with = "packages")]
+    pub requested_only: bool,
+
+    /// Migrate PKG after OTHER even though Homebrew declares no dependency between them
+    /// (repeatable or comma-separated, e.g. --after git:curl)
+    #[arg(
+        long,
+        value_name = "PKG:OTHER",
+        value_delimiter = ',',
+        value_parser = OrderConstraint::parse_after
+    )]
+    pub after: Vec<OrderConstraint>,
+
+    /// Migrate PKG before OTHER even though Homebrew declares no dependency between them
+    #[arg(
+        long,
+        value_name = "PKG:OTHER",
+        value_delimiter = ',',
+        value_parser = OrderConstraint::parse_before
+    )]
+    pub before: Vec<OrderConstraint>,
+
+    /// Stop at the first failed package instead of continuing
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Give up once N packages have failed (partial results are still saved)
+    #[arg(long, value_name = "N", conflicts_with = "fail_fast")]
+    pub max_failures: Option<NonZeroUsize>,
+
+    /// Keep install logs for successful packages (failed installs are always logged)
+    #[arg(long)]
+    pub keep_logs: bool,
+
+    /// Install the Homebrew version (`zb install name@version`), falling back to the
+    /// latest version when zerobrew doesn't have it
+    #[arg(long)]
+    pub pin_versions: bool,
+
+    /// Build from source instead of using bottles; give packages (comma-separated)
+    /// to limit it to them, e.g. --build-from-source=llvm,gcc
+    #[arg(
+        long,
+        value_name = "PACKAGES",
+        num_args = 0..=1,
+        require_equals = true,
+        value_delimiter = ','
+    )]
+    pub build_from_source: Option<Vec<String>>,
+
+    /// Install from bottles only, never building from source; give packages
+    /// (comma-separated) to limit it to them
+    #[arg(
+        long,
+        value_name = "PACKAGES",
+        num_args = 0..=1,
+        require_equals = true,
+        value_delimiter = ','
+    )]
+    pub force_bottle: Option<Vec<String>>,
+
+    /// Keep the progress bar but print only the final summary, not each package
+    #[arg(long, conflicts_with_all = ["interactive", "json_lines"])]
+    pub summary_only: bool,
+
+    /// Stream one JSON object per migrated package to stdout instead of human output
+    #[arg(long, conflicts_with_all = ["packages", "interactive", "dry_run"])]
+    pub json_lines: bool,
+
+    /// Also order packages by build-only dependencies (runtime dependencies only by default)
+    #[arg(long)]
+    pub include_build_deps: bool,
+
+    /// Write a markdown migration report to this path (`-` for stdout)
+    #[arg(long, value_name = "PATH")]
+    pub report: Option<PathBuf>,
+
+    /// Write the names of failed packages to this path, one per line, for
+    /// retrying with `--packages @PATH`
+    #[arg(long, value_name = "PATH")]
+    pub export_failures: Option<PathBuf>,
+
+    /// Save the ordered migration plan, with risk annotations, as JSON to this path
+    /// (`-` for stdout) instead of migrating
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = [
+            "packages",
+            "interactive",
+            "json_lines",
+            "dry_run",
+            "plan",
+            "report",
+            "export_failures",
+            "fail_fast",
+            "max_failures",
+            "keep_logs",
+            "pin_versions",
+            "build_from_source",
+            "force_bottle",
+            "summary_only",
+            "force",
+            "yes",
+            "with_deps",
+            "with_dependents",
+            "dependents_depth",
+            "allow_uninstalled",
+            "show_impact",
+        ]
+    )]
+    pub save_plan: Option<PathBuf>,
+
+    /// Migrate the packages in a plan saved with --save-plan, in its order
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = [
+            "packages",
+            "interactive",
+            "since",
+            "exclude_taps",
+            "requested_only",
+            "after",
+            "before",
+            "exact_order",
+        ]
+    )]
+    pub plan: Option<PathBuf>,
+
+    /// Skip the confirmation prompt before migrating everything (required without a TTY)
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+/// Options for `retry-failed`
+#[derive(Args)]
+pub struct RetryFailedArgs {
+    /// Show which packages would be retried without making changes
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Stop at the first failed package instead of continuing
+    #[arg(long)]
+    pub fail_fast: bool,
+
+    /// Keep install logs for successful packages (failed installs are always logged)
+    #[arg(long)]
+    pub keep_logs: bool,
+}
+
+/// `migrate`: plan, confirm and run a migration
+pub fn run(mut migrator: HomebrewMigrator, args: MigrateArgs) -> Result<()> {
+    let MigrateArgs {
+        dry_run,
+        exact_order,
+        packages,
+        with_deps,
+        with_dependents,
+        allow_uninstalled,
+        show_impact,
+        dependents_depth,
+        interactive,
+        since,
+        exclude_taps,
+        force,
+        requested_only,
+        after,
+        before,
+        fail_fast,
+        max_failures,
+        keep_logs,
+        pin_versions,
+        build_from_source,
+        force_bottle,
+        summary_only,
+        json_lines,
+        include_build_deps,
+        report: report_path,
+        export_failures,
+        save_plan,
+        plan: plan_file,
+        yes,
+    } = args;
+    if json_lines && report_path.as_deref().is_some_and(is_stdout) {
+        bail!(
+            "--report - would mix the markdown report into the JSON lines on stdout\n\n\
+             Suggestions:\n\
+             - Write the report to a file: --report migration.md\n\
+             - Drop --json-lines to print the report alone"
+        );
+    }
+    if json_lines && export_failures.as_deref().is_some_and(is_stdout) {
+        bail!(
+            "--export-failures - would mix package names into the JSON lines on stdout\n\n\
+             Suggestions:\n\
+             - Write the list to a file: --export-failures failed.txt\n\
+             - Read the failed packages from the JSON lines (\"result\": \"failed\")"
+        );
+    }
+    migrator.include_build_deps = include_build_deps;
+    let _lock = if dry_run || save_plan.is_some() {
+        None
+    } else {
+        migrator.check_separate_prefixes()?;
+        let lock = migrator.acquire_lock()?;
+        // Refuse a corrupt or newer state file before installing anything
+        migrator.load_state()?;
+        Some(lock)
+    };
+    let options = MigrateOptions {
+        dry_run,
+        // Interactive mode explains each package's risk, which needs its dependencies
+        exact_order: exact_order || interactive,
+        since,
+        exclude_taps,
+        force,
+        requested_only,
+        fail_fast,
+        max_failures: max_failures.map(NonZeroUsize::get),
+        keep_logs,
+        pin_versions,
+        build_from_source,
+        force_bottle,
+        order_constraints: after.into_iter().chain(before).collect(),
+        ..Default::default()
+    };
+    options.validate_install_modes()?;
+    if let Some(path) = save_plan {
+        let saved = migrator.save_plan(&options)?;
+        if saved.plan.total_formulae == 0 {
+            print_nothing_installed()?;
+            return Ok(());
+        }
+        write_plan(&path, &saved)?;
+        return Ok(());
+    }
+    if !dry_run {
+        migrator.check_install_mode_support(&options)?;
+    }
+    let saved_plan = plan_file
+        .as_deref()
+        .map(migrate::load_saved_plan)
+        .transpose()?;
+    let plan_migration = || match &saved_plan {
+        Some(saved) => migrator.replay_plan(saved, &options),
+        None => migrator.plan_migration(&options),
+    };
+
+    let report = if let Some(pkg_names) = packages {
+        let pkg_names = migrate::expand_package_files(pkg_names)?;
+        // Migrate specific packages
+        let all_formulae = if with_deps || with_dependents {
+            migrator.list_installed_formulae_detailed()?
+        } else {
+            migrator.list_installed_formulae()?
+        };
+        let mut skipped = Vec::new();
+
+        let (pkg_names, unmatched) = migrate::expand_package_patterns(
+            &pkg_names,
+            all_formulae.iter().map(|p| p.name.as_str()),
+        );
+        for pattern in unmatched {
+            eprintln!(
+                "{} Pattern matched no installed formulae: {}",
+                style("⚠").yellow().bold(),
+                style(&pattern).yellow()
+            );
+        }
+
+        let mut found = Vec::new();
+        let mut uninstalled = Vec::new();
+        for name in pkg_names {
+            if all_formulae.iter().any(|p| p.name == name) {
+                found.push(name);
+            } else if allow_uninstalled && !migrate::is_glob_pattern(&name) {
+                eprintln!(
+                    "{} {} is not installed in Homebrew; installing it directly with zb",
+                    style("ℹ").cyan().bold(),
+                    style(&name).white().bold()
+                );
+                uninstalled.push(BrewPackage::not_installed(&name));
+            } else {
+                let suggestions = migrate::suggest_similar(
+                    &name,
+                    all_formulae.iter().map(|p| p.name.as_str()),
+                    3,
+                );
+                let hint = if suggestions.is_empty() {
+                    String::new()
+                } else {
+                    format!(" (did you mean {}?)", suggestions.join(", "))
+                };
+                eprintln!(
+                    "{} Package not found: {}{}",
+                    style("✗").red().bold(),
+                    style(&name).yellow(),
+                    style(hint).dim()
+                );
+                skipped.push((name, "Not installed".to_string()));
+            }
+        }
+
+        if show_impact {
+            for name in &found {
+                print_impact(&migrator.package_impact(name)?);
+            }
+        }
+
+        if with_dependents && !found.is_empty() {
+            let dependents = migrator.find_dependents(&found, dependents_depth)?;
+            if dependents.is_empty() {
+                eprintln!(
+                    "{} No installed packages depend on {}",
+                    style("ℹ").cyan().bold(),
+                    found.join(", ")
+                );
+            } else {
+                eprintln!(
+                    "{} Including {} reverse dependent(s): {}",
+                    style("ℹ").cyan().bold(),
+                    style(dependents.len()).white().bold(),
+                    dependents.join(", ")
+                );
+                if !confirm_dependents(dependents.len(), dry_run)? {
+                    outln!("Migration cancelled.")?;
+                    return Ok(());
+                }
+                found.extend(dependents);
+            }
+        }
+
+        let targets: Vec<BrewPackage> = if with_deps {
+            let plan = HomebrewMigrator::plan_with_deps(&found, &all_formulae)?;
+            for (name, reason) in plan.kept_in_homebrew {
+                eprintln!(
+                    "{} Keeping dependency in Homebrew: {} ({})",
+                    style("ℹ").cyan().bold(),
+                    style(&name).white().bold(),
+                    style(&reason).dim()
+                );
+                skipped.push((name, format!("Keep in Homebrew: {}", reason)));
+            }
+            plan.to_migrate
+        } else {
+            let selected: Vec<BrewPackage> = found
+                .iter()
+                .filter_map(|name| all_formulae.iter().find(|p| &p.name == name))
+                .cloned()
+                .collect();
+            if with_dependents {
+                HomebrewMigrator::topological_sort(&selected)?
+            } else {
+                selected
+            }
+        };
+        let targets = if options.order_constraints.is_empty() {
+            targets
+        } else {
+            migrate::validate_order_constraints(&options.order_constraints, &all_formulae)?;
+            HomebrewMigrator::topological_sort_with(&targets, &options.order_constraints)?
+        };
+        // zb resolves dependencies of packages Homebrew never had
+        let plan = MigrationPlan {
+            formulae: targets.into_iter().chain(uninstalled).collect(),
+            skipped,
+            total_formulae: all_formulae.len(),
+            ..Default::default()
+        };
+
+        if dry_run {
+            for pkg in &plan.formulae {
+                outln!(
+                    "{} Would migrate: {} {}",
+                    style("[DRY RUN]").yellow().bold(),
+                    style(&pkg.name).white().bold(),
+                    style(&pkg.version).dim()
+                )?;
+            }
+            plan.dry_run_report()
+        } else {
+            let report = execute_with_progress(
+                &migrator,
+                &plan,
+                &options,
+                MigrationProgress {
+                    summary_only,
+                    detailed: true,
+                    ..Default::default()
+                },
+            )?;
+            if summary_only {
+                print_migration_summary(&report)?;
+            } else {
+                print_running_services(&report)?;
+            }
+            report
+        }
+    } else {
+        let plan = plan_migration()?;
+        if plan.total_formulae == 0 {
+            if json_lines {
+                // Keep stdout to JSON lines
+                eprintln!("{}", nothing_installed());
+            } else {
+                print_nothing_installed()?;
+            }
+            return Ok(());
+        }
+        // Without a terminal to prompt on, run as a plain migration, confirmation included
+        let interactive = interactive && {
+            let prompts = std::io::stdin().is_terminal() && !migrate::is_ci();
+            if !prompts {
+                outln!(
+                    "Non-interactive environment detected. Falling back to non-interactive mode."
+                )?;
+            }
+            prompts
+        };
+        if interactive {
+            // Interactive migration mode; with --dry-run it only records the choices
+            let report = migrate_interactive(&migrator, &plan, &options)?;
+            if dry_run {
+                print_interactive_plan(&report)?;
+            } else {
+                print_migration_summary(&report)?;
+            }
+            report
+        } else if dry_run {
+            print_dry_run(&plan)?;
+            plan.dry_run_report()
+        } else if json_lines {
+            if !confirm_migration(&plan, yes)? {
+                eprintln!("Migration cancelled.");
+                return Ok(());
+            }
+            let mut write_error = None;
+            let report = execute(
+                &migrator,
+                &plan,
+                &options,
+                &mut |event| {
+                    if write_error.is_none() {
+                        write_error = print_json_line(event).err();
+                    }
+                },
+                &mut |_, _| Ok(PackageChoice::Migrate),
+            )?;
+            if let Some(e) = write_error {
+                return Err(e);
+            }
+            outln!(
+                "{}",
+                serde_json::json!({
+                    "schema_version": migrate::JSON_SCHEMA_VERSION,
+                    "event": "summary",
+                    "successful": report.successful.len(),
+                    "failed": report.failed.len(),
+                    "skipped": report.skipped.len(),
+                })
+            )?;
+            report
+        } else {
+            // Migrate all
+            if !confirm_migration(&plan, yes)? {
+                eprintln!("Migration cancelled.");
+                return Ok(());
+            }
+            let report = execute_with_progress(
+                &migrator,
+                &plan,
+                &options,
+                MigrationProgress {
+                    summary_only,
+                    ..Default::default()
+                },
+            )?;
+            print_migration_summary(&report)?;
+            report
+        }
+    };
+
+    if let Some((previous, current)) = &report.zerobrew_version_change {
+        eprintln!(
+            "{} zerobrew version changed since the last migration: {} -> {}",
+            style("ℹ").cyan().bold(),
+            previous,
+            current
+        );
+    }
+
+    if let Some(path) = report_path {
+        write_report(&path, &report.to_markdown())?;
+    }
+    if let Some(path) = export_failures {
+        write_failure_list(&path, &report)?;
+    }
+    print_timing_summary(&migrator);
+    exit_if_interrupted(&report);
+    Ok(())
+}
+
+/// `retry-failed`: migrate the packages earlier runs failed on
+pub fn retry_failed(migrator: &HomebrewMigrator, args: RetryFailedArgs) -> Result<()> {
+    let RetryFailedArgs {
+        dry_run,
+        fail_fast,
+        keep_logs,
+    } = args;
+    let options = MigrateOptions {
+        dry_run,
+        fail_fast,
+        keep_logs,
+        history_action: Some("retry-failed"),
+        ..Default::default()
+    };
+    let _lock = if dry_run {
+        None
+    } else {
+        migrator.check_separate_prefixes()?;
+        let lock = migrator.acquire_lock()?;
+        // Refuse a corrupt or newer state file before installing anything
+        migrator.load_state()?;
+        Some(lock)
+    };
+    let plan = migrator.plan_retry()?;
+    if plan.total_formulae == 0 {
+        outln!("{} No failed packages to retry.", style("ℹ").cyan().bold())?;
+    } else if dry_run {
+        print_dry_run(&plan)?;
+    } else {
+        let report =
+            execute_with_progress(migrator, &plan, &options, MigrationProgress::default())?;
+        print_migration_summary(&report)?;
+        exit_if_interrupted(&report);
+    }
+    Ok(())
+}
+
+/// Above this many reverse dependents, ask before migrating them all
+const DEPENDENTS_CONFIRM_THRESHOLD: usize = 20;
+
+/// Confirm a large `--with-dependents` expansion when running in a terminal
+fn confirm_dependents(count: usize, dry_run: bool) -> Result<bool> {
+    if dry_run || count <= DEPENDENTS_CONFIRM_THRESHOLD || !std::io::stdin().is_terminal() {
+        return Ok(true);
+    }
+    Ok(Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!(
+            "Migrate all {} dependents? (lower --dependents-depth to narrow the set)",
+            count
+        ))
+        .default(false)
+        .interact()?)
+}
+
+/// Set by SIGINT/SIGTERM; migrations check it between packages so results are saved
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Route SIGINT and SIGTERM to [`INTERRUPTED`] instead of killing the process.
+///
+/// The handler only flips an atomic flag, which is async-signal-safe and never touches
+/// the terminal, so it can't deadlock with progress bar drawing. A second signal exits
+/// immediately for runs that are stuck.
+fn install_interrupt_handler() -> &'static AtomicBool {
+    #[cfg(unix)]
+    {
+        extern "C" fn on_signal(_: libc::c_int) {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                // SAFETY: _exit is async-signal-safe
+                unsafe { libc::_exit(130) };
+            }
+        }
+        let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // SAFETY: the handler only performs async-signal-safe operations
+        unsafe {
+            libc::signal(libc::SIGINT, handler);
+            libc::signal(libc::SIGTERM, handler);
+        }
+    }
+    &INTERRUPTED
+}
+
+/// After an interrupted run has been saved, say so and exit with the SIGINT status
+fn exit_if_interrupted(report: &MigrationReport) {
+    if report.interrupted {
+        eprintln!(
+            "{} Interrupted: progress was saved ({} migrated). Run the same command again to continue.",
+            style("⚠").yellow().bold(),
+            report.successful.len()
+        );
+        std::process::exit(130);
+    }
+}
+
+/// How many package names to preview before confirming a full migration
+const CONFIRM_PREVIEW_COUNT: usize = 5;
+
+/// Confirm a full migration, showing how many formulae it covers after warning about
+/// any `--HEAD` formulae.
+///
+/// Without a terminal there is no one to ask, so `--yes` is required.
+fn confirm_migration(plan: &MigrationPlan, yes: bool) -> Result<bool> {
+    warn_head_formulae(plan);
+    if yes || plan.formulae.is_empty() {
+        return Ok(true);
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!(
+            "Refusing to migrate {} formulae without confirmation: stdin is not a terminal.\n\n\
+             Suggestions:\n\
+             - Re-run with --yes to confirm non-interactively\n\
+             - Preview the plan first with --dry-run",
+            plan.formulae.len()
+        );
+    }
+
+    eprintln!(
+        "About to migrate {} formulae to zerobrew:",
+        plan.formulae.len()
+    );
+    for pkg in plan.formulae.iter().take(CONFIRM_PREVIEW_COUNT) {
+        eprintln!("  {} {}", pkg.name, style(&pkg.version).dim());
+    }
+    if plan.formulae.len() > CONFIRM_PREVIEW_COUNT {
+        eprintln!(
+            "  ... and {} more (see --dry-run for the full list)",
+            plan.formulae.len() - CONFIRM_PREVIEW_COUNT
+        );
+    }
+    Ok(Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Proceed with the migration?")
+        .default(false)
+        .interact()?)
+}
+
+/// Warn that `--HEAD` formulae in the plan will be replaced by their stable version
+fn warn_head_formulae(plan: &MigrationPlan) {
+    for pkg in plan.head_formulae() {
+        eprintln!(
+            "{} {} was installed with --HEAD ({}); zerobrew will install the stable version",
+            style("⚠").yellow().bold(),
+            pkg.name,
+            pkg.version
+        );
+    }
+}
+
+/// Write a plan for `migrate --save-plan` (or print it for `-`)
+fn write_plan(path: &Path, saved: &migrate::SavedPlan) -> Result<()> {
+    let content = format!("{}\n", serde_json::to_string_pretty(saved)?);
+    if is_stdout(path) {
+        out!("{}", content)?;
+        return Ok(());
+    }
+    migrate::write_atomic(path, content.as_bytes())
+        .with_context(|| format!("Failed to write plan to {}", path.display()))?;
+    eprintln!(
+        "{} Plan for {} formulae written to {}; replay it with --plan {}",
+        style("✓").green().bold(),
+        saved.plan.formulae.len(),
+        style(path.display()).white().bold(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Write the failed package names for `--export-failures` (or print them for `-`)
+fn write_failure_list(path: &PathBuf, report: &MigrationReport) -> Result<()> {
+    let content = migrate::render_failure_list(report);
+    if is_stdout(path) {
+        out!("{}", content)?;
+        return Ok(());
+    }
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to write failed packages to {}", path.display()))?;
+    eprintln!(
+        "{} {} failed package(s) written to {}; retry with --packages @{}",
+        style("✓").green().bold(),
+        report.failed.len(),
+        style(path.display()).white().bold(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Print the end-of-run migration summary
+fn print_migration_summary(report: &MigrationReport) -> Result<()> {
+    outln!("\n=== Migration Summary ===")?;
+    outln!("Total formulae: {}", report.total_formulae)?;
+    outln!("Total casks: {}", report.total_casks)?;
+    outln!("Successful: {}", report.successful.len())?;
+    outln!("Failed: {}", report.failed.len())?;
+    outln!("Skipped: {}", report.skipped.len())?;
+
+    if !report.failed.is_empty() {
+        outln!("\nFailed packages:")?;
+        for (name, reason) in &report.failed {
+            outln!("  {} - {}", name, reason)?;
+            if let Some(log) = report.logs.get(name) {
+                outln!("    log: {}", log.display())?;
+            }
+        }
+    }
+
+    if report.failure_limit_reached {
+        outln!(
+            "\n{} Aborted after {} failures (--max-failures); remaining packages were not attempted.",
+            style("⚠").yellow().bold(),
+            report.failed.len()
+        )?;
+    }
+
+    if !report.renamed.is_empty() {
+        outln!("\nInstalled under a different zerobrew name:")?;
+        for (brew_name, zb_name) in &report.renamed {
+            outln!("  {} - {}", brew_name, zb_name)?;
+        }
+    }
+
+    if !report.install_modes.is_empty() {
+        outln!("\nInstall method:")?;
+        for (name, mode) in &report.install_modes {
+            outln!("  {} - {}", name, mode)?;
+        }
+    }
+
+    if !report.pin_fallbacks.is_empty() {
+        outln!("\nUnpinned versions (zerobrew didn't have the Homebrew version):")?;
+        for fallback in &report.pin_fallbacks {
+            outln!(
+                "  {} - requested {}, installed {}",
+                fallback.name,
+                fallback.requested_version,
+                fallback.installed_version.as_deref().unwrap_or("latest")
+            )?;
+        }
+    }
+
+    if !report.head_installs.is_empty() {
+        outln!("\nHEAD installs (zerobrew installed the stable version):")?;
+        for head in &report.head_installs {
+            outln!(
+                "  {} - Homebrew {}, zerobrew {}",
+                head.name,
+                head.homebrew_version,
+                head.zerobrew_version.as_deref().unwrap_or("stable")
+            )?;
+        }
+    }
+
+    if !report.version_drift.is_empty() {
+        outln!("\nVersion drift (zerobrew installed a different version):")?;
+        for drift in &report.version_drift {
+            outln!(
+                "  {} - Homebrew {}, zerobrew {}",
+                drift.name,
+                drift.homebrew_version,
+                drift.zerobrew_version
+            )?;
+        }
+    }
+
+    let (already_migrated, skipped): (Vec<_>, Vec<_>) = report
+        .skipped
+        .iter()
+        .partition(|(_, reason)| reason == migrate::ALREADY_MIGRATED_REASON);
+    if !skipped.is_empty() {
+        outln!("\nSkipped packages:")?;
+        for (name, reason) in skipped {
+            outln!("  {} - {}", name, reason)?;
+        }
+    }
+    if !already_migrated.is_empty() {
+        outln!(
+            "\nAlready migrated: {} (use --force to reinstall)",
+            already_migrated.len()
+        )?;
+    }
+
+    print_running_services(report)?;
+    Ok(())
+}
+
+/// Warn about migrated packages whose Homebrew service is still running
+fn print_running_services(report: &MigrationReport) -> Result<()> {
+    if !report.running_services.is_empty() {
+        outln!(
+            "\n{} Still running as Homebrew services: {}",
+            style("⚠").yellow().bold(),
+            report.running_services.join(", ")
+        )?;
+        outln!("  Both copies may now compete for ports and data directories.")?;
+        outln!("  `zb-migrate cleanup --force` stops them; set up a launchd service for the")?;
+        outln!("  zerobrew install before relying on it.")?;
+    }
+    Ok(())
+}
+
+/// Note that an alias installed the package under its zerobrew name
+pub(super) fn print_renamed(zerobrew_name: &str) -> Result<()> {
+    outln!(
+        "  {} installed as {} in zerobrew",
+        style("ℹ").cyan().bold(),
+        style(zerobrew_name).cyan()
+    )?;
+    Ok(())
+}
+
+/// Note a package installed with `--build-from-source` or `--force-bottle`
+pub(super) fn print_install_mode(mode: InstallMode) -> Result<()> {
+    if mode != InstallMode::Default {
+        outln!("  {} {}", style("ℹ").cyan().bold(), mode)?;
+    }
+    Ok(())
+}
+
+/// Warn that `--pin-versions` couldn't get the Homebrew version from zerobrew
+pub(super) fn print_pin_fallback(homebrew_version: &str) -> Result<()> {
+    outln!(
+        "  {} zerobrew doesn't have {}; installed the latest version instead",
+        style("⚠").yellow().bold(),
+        homebrew_version
+    )?;
+    Ok(())
+}
+
+/// Warn when zerobrew installed a different version than Homebrew had, or the
+/// stable version in place of a `--HEAD` build
+pub(super) fn print_version_drift(
+    homebrew_version: &str,
+    installed_version: Option<&str>,
+) -> Result<()> {
+    if migrate::is_head_version(homebrew_version) {
+        outln!(
+            "  {} Homebrew had a --HEAD build ({}); zerobrew installed the stable version {}",
+            style("⚠").yellow().bold(),
+            homebrew_version,
+            installed_version.unwrap_or("instead")
+        )?;
+        return Ok(());
+    }
+    if let Some(installed) = installed_version {
+        if !migrate::versions_match(homebrew_version, installed) {
+            outln!(
+                "  {} zerobrew installed {} (Homebrew had {})",
+                style("⚠").yellow().bold(),
+                style(installed).yellow(),
+                homebrew_version
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Overall progress bar for a bulk migration, with one log line per finished package
+#[derive(Default)]
+pub(super) struct MigrationProgress {
+    bar: Option<ProgressBar>,
+    /// Only advance the bar; leave per-package results to the final summary
+    summary_only: bool,
+    /// Print each result in full (renames, install mode, log path), as for named packages
+    detailed: bool,
+    /// First failed write to stdout; later results are no longer printed
+    write_error: Option<anyhow::Error>,
+}
+
+impl MigrationProgress {
+    fn on_event(&mut self, event: MigrationEvent) {
+        match event {
+            MigrationEvent::Started { total, package, .. } => {
+                let bar = self
+                    .bar
+                    .get_or_insert_with(|| migrate::create_progress_bar(total as u64, ""));
+                bar.set_message(format!("Migrating: {} ({})", package.name, package.version));
+            }
+            MigrationEvent::Finished { index, result, .. } => {
+                if self.summary_only {
+                    if let Some(bar) = &self.bar {
+                        bar.set_position(index as u64 + 1);
+                    }
+                    return;
+                }
+                if self.detailed {
+                    if self.write_error.is_some() {
+                        return;
+                    }
+                    self.write_error = match &self.bar {
+                        Some(bar) if !bar.is_hidden() => {
+                            let printed = bar.suspend(|| print_package_result(result));
+                            bar.set_position(index as u64 + 1);
+                            printed.err()
+                        }
+                        _ => print_package_result(result).err(),
+                    };
+                    return;
+                }
+                let line = match result {
+                    MigrateResult::Success { name, version, .. } => format!(
+                        "{} {} {}",
+                        style("✓").green().bold(),
+                        name,
+                        style(version).dim()
+                    ),
+                    MigrateResult::Failed { name, reason, .. } => format!(
+                        "{} {} failed: {}",
+                        style("✗").red().bold(),
+                        name,
+                        style(reason).dim()
+                    ),
+                };
+                match &self.bar {
+                    Some(bar) if !bar.is_hidden() => {
+                        bar.println(line);
+                        bar.set_position(index as u64 + 1);
+                    }
+                    // No terminal to draw on: keep the per-package log
+                    _ => eprintln!("{}", line),
+                }
+            }
+        }
+    }
+
+    /// Remove the bar, e.g. before printing the summary, and report a failed write
+    fn finish(self) -> Result<()> {
+        if let Some(bar) = self.bar {
+            bar.finish_and_clear();
+        }
+        self.write_error.map_or(Ok(()), Err)
+    }
+}
+
+/// Run a migration plan behind an overall progress bar
+pub(super) fn execute_with_progress(
+    migrator: &HomebrewMigrator,
+    plan: &MigrationPlan,
+    options: &MigrateOptions,
+    mut progress: MigrationProgress,
+) -> Result<MigrationReport> {
+    let report = execute(
+        migrator,
+        plan,
+        options,
+        &mut |event| progress.on_event(event),
+        &mut |_, _| Ok(PackageChoice::Migrate),
+    );
+    // Only fail on a closed stdout once the run's results are recorded
+    let written = progress.finish();
+    let report = report?;
+    written?;
+    Ok(report)
+}
+
+/// Execute a plan, only now routing Ctrl-C to a clean stop after the current package.
+///
+/// During planning and confirmation prompts Ctrl-C still exits right away, so nothing
+/// is recorded as interrupted before a single package was attempted.
+pub(super) fn execute(
+    migrator: &HomebrewMigrator,
+    plan: &MigrationPlan,
+    options: &MigrateOptions,
+    on_event: &mut dyn FnMut(MigrationEvent),
+    choose: &mut dyn FnMut(usize, &BrewPackage) -> Result<PackageChoice>,
+) -> Result<MigrationReport> {
+    let options = MigrateOptions {
+        cancel: (!options.dry_run).then(install_interrupt_handler),
+        ..options.clone()
+    };
+    migrator.execute_plan_with(plan, &options, on_event, choose)
+}
+
+/// Print the outcome of migrating one named package
+fn print_package_result(result: &MigrateResult) -> Result<()> {
+    match result {
+        MigrateResult::Success {
+            name,
+            version,
+            installed_version,
+            pin_fallback,
+            zerobrew_name,
+            install_mode,
+        } => {
+            outln!(
+                "{} {} {} migrated successfully",
+                style("✓").green().bold(),
+                style(name).white().bold(),
+                style(version).dim()
+            )?;
+            if let Some(zb_name) = zerobrew_name {
+                print_renamed(zb_name)?;
+            }
+            print_install_mode(*install_mode)?;
+            if *pin_fallback {
+                print_pin_fallback(version)?;
+            }
+            print_version_drift(version, installed_version.as_deref())?;
+        }
+        MigrateResult::Failed {
+            name, reason, log, ..
+        } => {
+            outln!(
+                "{} {} failed: {}",
+                style("✗").red().bold(),
+                style(name).white().bold(),
+                style(reason).dim()
+            )?;
+            if let Some(log) = log {
+                outln!("  {} {}", style("log:").dim(), log.display())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stream a finished package as one JSON line, flushing so monitors see it immediately
+fn print_json_line(event: MigrationEvent) -> Result<()> {
+    let Some(line) = json_line(event) else {
+        return Ok(());
+    };
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", line)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// The `--json-lines` object for a finished package; other events print nothing
+fn json_line(event: MigrationEvent) -> Option<serde_json::Value> {
+    let MigrationEvent::Finished {
+        index,
+        total,
+        result,
+        elapsed,
+    } = event
+    else {
+        return None;
+    };
+
+    let line = match result {
+        MigrateResult::Success {
+            name,
+            version,
+            installed_version,
+            pin_fallback,
+            zerobrew_name,
+            install_mode,
+        } => serde_json::json!({
+            "schema_version": migrate::JSON_SCHEMA_VERSION,
+            "event": "package",
+            "index": index + 1,
+            "total": total,
+            "name": name,
+            "result": "success",
+            "version": version,
+            "installed_version": installed_version,
+            "head": migrate::is_head_version(version),
+            "pin_fallback": pin_fallback,
+            "zerobrew_name": zerobrew_name,
+            "install_mode": install_mode,
+            "duration_ms": elapsed.as_millis() as u64,
+        }),
+        MigrateResult::Failed {
+            name,
+            reason,
+            error,
+            log,
+        } => serde_json::json!({
+            "schema_version": migrate::JSON_SCHEMA_VERSION,
+            "event": "package",
+            "index": index + 1,
+            "total": total,
+            "name": name,
+            "result": "failed",
+            "reason": reason,
+            "error": error,
+            "log": log,
+            "duration_ms": elapsed.as_millis() as u64,
+        }),
+    };
+    Some(line)
+}
+
+/// Print the packages a dry run would migrate
+pub(super) fn print_dry_run(plan: &MigrationPlan) -> Result<()> {
+    outln!("\n=== DRY RUN - No changes will be made ===\n")?;
+    outln!(
+        "Found {} formulae and {} casks to migrate:\n",
+        plan.formulae.len(),
+        plan.casks.len()
+    )?;
+
+    if !plan.formulae.is_empty() {
+        if plan.dependencies_loaded {
+            outln!("Formulae in migration order:")?;
+        } else {
+            outln!(
+                "Formulae (approximate order, dependencies not loaded; add --exact-order to load them):"
+            )?;
+        }
+    }
+    let width = plan.formulae.len().to_string().len();
+    for (idx, pkg) in plan.formulae.iter().enumerate() {
+        if plan.dependencies_loaded {
+            outln!(
+                "  {:>width$}. [formula] {} @ {} (direct deps: {})",
+                idx + 1,
+                pkg.name,
+                pkg.version,
+                pkg.dependencies.len()
+            )?;
+        } else {
+            outln!(
+                "  {:>width$}. [formula] {} @ {}",
+                idx + 1,
+                pkg.name,
+                pkg.version
+            )?;
+        }
+    }
+    for pkg in &plan.casks {
+        outln!("  [cask] {} @ {}", pkg.name, pkg.version)?;
+    }
+    if !plan.skipped.is_empty() {
+        outln!(
+            "\nSkipping {} formulae excluded by --since, --exclude-taps or --requested-only.",
+            plan.skipped.len()
+        )?;
+    }
+    if !plan.already_migrated.is_empty() {
+        outln!(
+            "\nSkipping {} formulae migrated by an earlier run (use --force to reinstall).",
+            plan.already_migrated.len()
+        )?;
+    }
+    if !plan.already_in_zerobrew.is_empty() {
+        outln!(
+            "\nSkipping {} formulae already installed in zerobrew (recorded as migrated).",
+            plan.already_in_zerobrew.len()
+        )?;
+    }
+    let head = plan.head_formulae();
+    if !head.is_empty() {
+        outln!(
+            "\n{} formulae were installed with --HEAD; zerobrew will install the stable version: {}",
+            head.len(),
+            head.iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )?;
+    }
+    Ok(())
+}
+
+/// Print the `--show-impact` summary for one package
+fn print_impact(impact: &PackageImpact) {
+    let list = |names: &[String]| {
+        if names.is_empty() {
+            "none".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+    eprintln!(
+        "{} Impact of migrating {}:",
+        style("ℹ").cyan().bold(),
+        style(&impact.name).white().bold()
+    );
+    eprintln!(
+        "  Dependencies to install:  {}",
+        list(&impact.dependencies_to_install)
+    );
+    let present = impact.dependencies.len() - impact.dependencies_to_install.len();
+    if present > 0 {
+        eprintln!(
+            "  {}",
+            style(format!("({} more already in zerobrew)", present)).dim()
+        );
+    }
+    eprintln!("  Installed dependents:     {}", list(&impact.dependents));
+    if !impact.dependents.is_empty() {
+        eprintln!(
+            "  {}",
+            style("These keep using the Homebrew copy until they are migrated too (--with-dependents).")
+                .dim()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zb_migrate::MigrateError;
+
+    #[test]
+    fn test_json_line_event_shapes() {
+        let package = BrewPackage {
+            name: "jq".to_string(),
+            version: "1.7.1".to_string(),
+            ..Default::default()
+        };
+        let started = MigrationEvent::Started {
+            index: 0,
+            total: 2,
+            package: &package,
+        };
+        assert_eq!(json_line(started), None);
+
+        let success = MigrateResult::Success {
+            name: "jq".to_string(),
+            version: "1.7.1".to_string(),
+            installed_version: Some("1.7.1".to_string()),
+            pin_fallback: false,
+            zerobrew_name: None,
+            install_mode: InstallMode::BuildFromSource,
+        };
+        let line = json_line(MigrationEvent::Finished {
+            index: 0,
+            total: 2,
+            result: &success,
+            elapsed: Duration::from_millis(1500),
+        });
+        assert_eq!(
+            line,
+            Some(serde_json::json!({
+                "schema_version": migrate::JSON_SCHEMA_VERSION,
+                "event": "package",
+                "index": 1,
+                "total": 2,
+                "name": "jq",
+                "result": "success",
+                "version": "1.7.1",
+                "installed_version": "1.7.1",
+                "head": false,
+                "pin_fallback": false,
+                "zerobrew_name": null,
+                "install_mode": "build-from-source",
+                "duration_ms": 1500,
+            }))
+        );
+
+        let failed = MigrateResult::Failed {
+            name: "curl".to_string(),
+            reason: "Error: no formula".to_string(),
+            error: MigrateError::NotFoundInZerobrew,
+            log: Some(PathBuf::from("/tmp/curl.log")),
+        };
+        let line = json_line(MigrationEvent::Finished {
+            index: 1,
+            total: 2,
+            result: &failed,
+            elapsed: Duration::from_millis(20),
+        });
+        assert_eq!(
+            line,
+            Some(serde_json::json!({
+                "schema_version": migrate::JSON_SCHEMA_VERSION,
+                "event": "package",
+                "index": 2,
+                "total": 2,
+                "name": "curl",
+                "result": "failed",
+                "reason": "Error: no formula",
+                "error": {"kind": "not_found_in_zerobrew"},
+                "log": "/tmp/curl.log",
+                "duration_ms": 20,
+            }))
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Command handlers, one module per group of related subcommands.
+//!
+//! Handlers print their results and return errors for `main` to report; the
+//! helpers below are shared by several commands.
+//!
+//! Output goes through [`outln!`] and [`out!`] rather than `println!`, so a closed
+//! stdout (e.g. `zb-migrate list | head -1`) ends the command with a
+//! `BrokenPipe` error, which `main` treats as success, instead of a panic.
+
+/// `println!` that returns the write error instead of panicking
+macro_rules! outln {
+    ($($arg:tt)*) => {{
+        use std::io::Write as _;
+        writeln!(std::io::stdout().lock(), $($arg)*)
+    }};
+}
+
+/// `print!` that returns the write error instead of panicking
+macro_rules! out {
+    ($($arg:tt)*) => {{
+        use std::io::Write as _;
+        write!(std::io::stdout().lock(), $($arg)*)
+    }};
+}
+
+pub mod analyze;
+pub mod cleanup;
+mod interactive;
+pub mod inventory;
+pub mod migrate;
+pub mod state;
+pub mod system;
+
+use anyhow::{Context, Result};
+use console::style;
+use std::path::{Path, PathBuf};
+
+use zb_migrate::HomebrewMigrator;
+
+/// Whether an output path is `-`, meaning stdout
+fn is_stdout(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Write a rendered report to disk (or stdout for `-`), confirming on stderr
+/// so JSON stdout stays clean
+fn write_report(path: &PathBuf, content: &str) -> Result<()> {
+    if is_stdout(path) {
+        out!("{}", content)?;
+        return Ok(());
+    }
+    std::fs::write(path, content)
+        .with_context(|| format!("Failed to write report to {}", path.display()))?;
+    eprintln!(
+        "{} Report written to {}",
+        style("✓").green().bold(),
+        style(path.display()).white().bold()
+    );
+    Ok(())
+}
+
+/// Explain an empty Homebrew installation instead of rendering an empty report
+fn print_nothing_installed() -> Result<()> {
+    outln!("{}", nothing_installed())?;
+    Ok(())
+}
+
+/// The note printed when no Homebrew formulae are installed
+fn nothing_installed() -> String {
+    format!(
+        "{} No Homebrew formulae installed — nothing to do.",
+        style("ℹ").cyan().bold()
+    )
+}
+
+/// Number of slowest commands listed in the verbose timing summary
+const SLOWEST_COMMANDS_SHOWN: usize = 5;
+
+/// With --verbose, print where the run spent its time to stderr
+fn print_timing_summary(migrator: &HomebrewMigrator) {
+    let Some(timings) = migrator.timing_summary() else {
+        return;
+    };
+    let calls = |program: &Path| {
+        timings
+            .commands
+            .iter()
+            .filter(|c| c.program == program)
+            .count()
+    };
+    eprintln!("\n[verbose] Timing summary:");
+    eprintln!(
+        "[verbose]   brew commands: {:>10.2?} ({} calls)",
+        timings.total_for(&migrator.brew_bin),
+        calls(&migrator.brew_bin)
+    );
+    eprintln!(
+        "[verbose]   zb commands:   {:>10.2?} ({} calls)",
+        timings.total_for(&migrator.zb_bin),
+        calls(&migrator.zb_bin)
+    );
+    eprintln!("[verbose]   analysis:      {:>10.2?}", timings.analysis);
+    let slowest = timings.slowest(SLOWEST_COMMANDS_SHOWN);
+    if !slowest.is_empty() {
+        eprintln!("[verbose]   Slowest commands:");
+        for command in slowest {
+            eprintln!(
+                "[verbose]     {:>10.2?}  {}",
+                command.elapsed,
+                command.command_line()
+            );
+        }
+    }
+}
+
+/// "7 packages: a → b → c", the minimum number of sequential install steps
+fn format_dependency_chain(chain: &[String]) -> String {
+    format!("{} packages: {}", chain.len(), chain.join(" → "))
+}
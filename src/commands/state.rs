@@ -0,0 +1,359 @@
+//! `status`, `verify`, `prune-state`, `restore-state` and `history`: inspect and
+//! maintain the migration state
+
+use anyhow::{bail, Result};
+use clap::Args;
+use console::style;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use zb_migrate::migrate;
+use zb_migrate::{HistoryEntry, HomebrewMigrator, VerifyStatus};
+
+/// Options for `status`
+#[derive(Args)]
+pub struct StatusArgs {
+    /// Output as JSON
+    #[arg(long)]
+    pub json: bool,
+
+    /// Also show disk usage of the Homebrew and zerobrew prefixes (slow on large prefixes)
+    #[arg(long)]
+    pub with_sizes: bool,
+
+    /// Show why each failed package failed (JSON output always includes reasons)
+    #[arg(long)]
+    pub show_reasons: bool,
+}
+
+/// `status`: show what earlier runs migrated, failed and skipped
+pub fn status(migrator: &HomebrewMigrator, args: StatusArgs) -> Result<()> {
+    let StatusArgs {
+        json,
+        with_sizes,
+        show_reasons,
+    } = args;
+    let state = migrator.load_state()?;
+    let sizes = with_sizes.then(|| {
+        if !json {
+            eprintln!("Measuring prefix sizes...");
+        }
+        (
+            migrate::prefix_disk_usage(&migrator.homebrew_prefix),
+            migrator
+                .zerobrew_prefix
+                .as_deref()
+                .and_then(migrate::prefix_disk_usage),
+        )
+    });
+    if json {
+        let mut status = serde_json::json!({
+                "migrated_count": state.migrated_packages.len(),
+                "failed_count": state.failed_packages.len(),
+                "skipped_count": state.skipped_packages.len(),
+                "migrated_packages": state.migrated_packages,
+                "failed_packages": state
+                    .failed_packages
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+                "failure_reasons": state
+                    .failed_packages
+                    .iter()
+                    .filter(|(_, reason)| !reason.is_empty())
+                    .cloned()
+                    .collect::<BTreeMap<_, _>>(),
+                "skipped_packages": state.skipped_packages,
+                "homebrew_prefix": state.homebrew_prefix,
+                "zerobrew_version": state.zerobrew_version,
+                "migration_records": state.migration_records,
+        });
+        if let Some((homebrew, zerobrew)) = sizes {
+            status["homebrew_size_bytes"] = homebrew.into();
+            status["zerobrew_size_bytes"] = zerobrew.into();
+        }
+        outln!("{}", migrate::json_envelope(&status)?)?;
+        return Ok(());
+    }
+    outln!(
+        "{}",
+        style("╭─ Migration Status ─────────────────────╮").cyan()
+    )?;
+    outln!(
+        "{}  {} Migrated:  {} packages              {}",
+        style("│").cyan(),
+        style("✓").green().bold(),
+        style(state.migrated_packages.len()).white().bold(),
+        style("│").cyan()
+    )?;
+    outln!(
+        "{}  {} Failed:    {} packages              {}",
+        style("│").cyan(),
+        style("✗").red().bold(),
+        style(state.failed_packages.len()).white().bold(),
+        style("│").cyan()
+    )?;
+    outln!(
+        "{}  {} Skipped:   {} packages              {}",
+        style("│").cyan(),
+        style("-").dim().bold(),
+        style(state.skipped_packages.len()).white().bold(),
+        style("│").cyan()
+    )?;
+    outln!(
+        "{}",
+        style("╰────────────────────────────────────────╯").cyan()
+    )?;
+
+    if let Some(version) = &state.zerobrew_version {
+        outln!(
+            "\n{} {}",
+            style("zerobrew version:").dim(),
+            style(version).white()
+        )?;
+    }
+
+    if let Some((homebrew, zerobrew)) = sizes {
+        let size = |bytes: Option<u64>| {
+            bytes
+                .map(migrate::format_size)
+                .unwrap_or_else(|| "unknown".to_string())
+        };
+        outln!("\n{}", style("Prefix sizes (Cellar + opt):").dim())?;
+        outln!(
+            "  Homebrew {:<24} {}",
+            migrator.homebrew_prefix.display(),
+            style(size(homebrew)).white().bold()
+        )?;
+        match &migrator.zerobrew_prefix {
+            Some(prefix) => outln!(
+                "  zerobrew {:<24} {}",
+                prefix.display(),
+                style(size(zerobrew)).white().bold()
+            )?,
+            None => outln!("  zerobrew {}", style("(prefix not detected)").dim())?,
+        }
+    }
+
+    if !state.migrated_packages.is_empty() {
+        outln!("\n{}", style("Migrated:").green().bold())?;
+        for (name, pkg) in &state.migrated_packages {
+            let via = state
+                .migration_records
+                .get(name)
+                .and_then(|r| r.zerobrew_version.as_ref())
+                .map(|v| format!(" {}", style(format!("(via {})", v)).dim()))
+                .unwrap_or_default();
+            outln!(
+                "  {:<28} {}{}",
+                style(name).white().bold(),
+                style(&pkg.version).dim(),
+                via
+            )?;
+        }
+    }
+
+    if !state.failed_packages.is_empty() {
+        outln!("\n{}", style("Failed:").red().bold())?;
+        for (name, reason) in &state.failed_packages {
+            if show_reasons {
+                let reason = if reason.is_empty() {
+                    "(no reason recorded)"
+                } else {
+                    reason.as_str()
+                };
+                outln!("  {:<28} {}", style(name).red(), style(reason).dim())?;
+            } else {
+                outln!("  {}", style(name).red())?;
+            }
+        }
+    }
+
+    if !state.skipped_packages.is_empty() {
+        outln!("\n{}", style("Skipped:").dim().bold())?;
+        for (name, reason) in &state.skipped_packages {
+            outln!("  {:<28} {}", name, style(reason).dim())?;
+        }
+    }
+    Ok(())
+}
+
+/// `verify`: check that migrated packages are still installed in zerobrew
+pub fn verify(migrator: &HomebrewMigrator, info: bool) -> Result<()> {
+    let state = migrator.load_state()?;
+    if state.migrated_packages.is_empty() {
+        outln!(
+            "{} No migrated packages to verify.",
+            style("ℹ").cyan().bold()
+        )?;
+        return Ok(());
+    }
+
+    if let Some(prefix) = &migrator.zerobrew_prefix {
+        outln!(
+            "{} {}\n",
+            style("zerobrew prefix:").dim(),
+            style(prefix.display()).white()
+        )?;
+    }
+    let results = migrator.verify_migrated(&state, info)?;
+    let mut missing = Vec::new();
+    let mut broken = 0;
+    for result in &results {
+        match &result.status {
+            VerifyStatus::Ok => outln!(
+                "  {} {:<28} {}",
+                style("✓").green().bold(),
+                result.name,
+                style(&result.version).dim()
+            )?,
+            VerifyStatus::Missing => {
+                outln!(
+                    "  {} {:<28} {}",
+                    style("✗").red().bold(),
+                    result.name,
+                    style("missing from zerobrew").red()
+                )?;
+                missing.push(result.name.as_str());
+            }
+            VerifyStatus::Broken(reason) => {
+                outln!(
+                    "  {} {:<28} {}",
+                    style("!").yellow().bold(),
+                    result.name,
+                    style(reason).yellow()
+                )?;
+                broken += 1;
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        outln!("\nTo re-migrate missing packages:")?;
+        outln!(
+            "  {}",
+            style(format!(
+                "zb-migrate migrate --packages {}",
+                missing.join(",")
+            ))
+            .white()
+            .bold()
+        )?;
+    }
+    if !missing.is_empty() || broken > 0 {
+        bail!(
+            "{} of {} migrated packages failed verification",
+            missing.len() + broken,
+            results.len()
+        );
+    }
+    outln!(
+        "\n{} All {} migrated packages verified.",
+        style("✓").green().bold(),
+        results.len()
+    )?;
+    Ok(())
+}
+
+/// `prune-state`: drop entries for packages installed nowhere
+pub fn prune_state(migrator: &HomebrewMigrator, dry_run: bool) -> Result<()> {
+    let _lock = if dry_run {
+        None
+    } else {
+        Some(migrator.acquire_lock()?)
+    };
+    let pruned = migrator.prune_state(dry_run)?;
+    if pruned.is_empty() {
+        outln!(
+            "{} Migration state is up to date; nothing to prune.",
+            style("✓").green().bold()
+        )?;
+    } else {
+        let verb = if dry_run { "Would prune" } else { "Pruned" };
+        outln!(
+            "{} {} {} stale entries (installed in neither zerobrew nor Homebrew):",
+            style("ℹ").cyan().bold(),
+            verb,
+            pruned.len()
+        )?;
+        for name in &pruned {
+            outln!("  - {}", name)?;
+        }
+    }
+    Ok(())
+}
+
+/// `restore-state`: list state backups or roll back to one
+pub fn restore_state(migrator: &HomebrewMigrator, file: Option<PathBuf>, list: bool) -> Result<()> {
+    if list {
+        let backups = migrator.list_state_backups()?;
+        if backups.is_empty() {
+            outln!(
+                "{} No state backups found. Create them with --backup-state.",
+                style("ℹ").cyan().bold()
+            )?;
+        }
+        for backup in backups {
+            outln!("{}", backup.display())?;
+        }
+    } else if let Some(file) = file {
+        let _lock = migrator.acquire_lock()?;
+        let state = migrator.restore_state(&file)?;
+        outln!(
+            "{} Restored migration state from {} ({} migrated, {} failed)",
+            style("✓").green().bold(),
+            file.display(),
+            state.migrated_packages.len(),
+            state.failed_packages.len()
+        )?;
+    }
+    Ok(())
+}
+
+/// `history`: show past migration runs
+pub fn history(migrator: &HomebrewMigrator, json: bool) -> Result<()> {
+    let history = migrator.load_history()?;
+    if json {
+        outln!("{}", migrate::json_envelope(&history)?)?;
+    } else if history.is_empty() {
+        outln!(
+            "{} No migration runs recorded yet.",
+            style("ℹ").cyan().bold()
+        )?;
+    } else {
+        for entry in &history {
+            print_history_entry(entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Print one recorded run with its package lists
+fn print_history_entry(entry: &HistoryEntry) -> Result<()> {
+    outln!(
+        "{}  {}  {} {}  {} {}  {} {}",
+        style(migrate::format_unix_time(entry.timestamp))
+            .white()
+            .bold(),
+        entry.action,
+        style("✓").green().bold(),
+        entry.successful.len(),
+        style("✗").red().bold(),
+        entry.failed.len(),
+        style("-").dim().bold(),
+        entry.skipped.len()
+    )?;
+    if let Some(version) = &entry.zerobrew_version {
+        outln!("  {} {}", style("zerobrew:").dim(), version)?;
+    }
+    for (label, names) in [
+        ("migrated:", &entry.successful),
+        ("failed:", &entry.failed),
+        ("skipped:", &entry.skipped),
+    ] {
+        if !names.is_empty() {
+            outln!("  {} {}", style(label).dim(), names.join(", "))?;
+        }
+    }
+    Ok(())
+}
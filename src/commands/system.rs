@@ -0,0 +1,116 @@
+//! `outdated`, `upgrade`, `path-check` and `benchmark`: guidance and checks about
+//! the tools themselves rather than the migrated packages
+
+use anyhow::{bail, Result};
+use console::style;
+
+use zb_migrate::migrate;
+use zb_migrate::{BrewBenchmark, HomebrewMigrator, PathOrder};
+
+/// `outdated`: point to the commands that check for updates
+pub fn outdated() -> Result<()> {
+    outln!(
+        "{} Zerobrew does not currently support checking for updates.\n",
+        style("ℹ").cyan().bold()
+    )?;
+    outln!("To check for updates on packages still in Homebrew:")?;
+    outln!("  {}", style("brew outdated").white().bold())?;
+    outln!("\nTo update a Zerobrew package, reinstall it:")?;
+    outln!("  {}", style("zb uninstall <package>").white().bold())?;
+    outln!("  {}", style("zb install <package>").white().bold())?;
+    Ok(())
+}
+
+/// `upgrade`: point to the commands that upgrade packages
+pub fn upgrade() -> Result<()> {
+    outln!(
+        "{} Zerobrew does not currently support bulk upgrades.\n",
+        style("ℹ").cyan().bold()
+    )?;
+    outln!("To upgrade packages still in Homebrew:")?;
+    outln!("  {}", style("brew upgrade").white().bold())?;
+    outln!("\nTo upgrade a Zerobrew package, reinstall it:")?;
+    outln!("  {}", style("zb uninstall <package>").white().bold())?;
+    outln!("  {}", style("zb install <package>").white().bold())?;
+    outln!("\nTo list installed Zerobrew packages:")?;
+    outln!("  {}", style("zb list").white().bold())?;
+    Ok(())
+}
+
+/// `path-check`: make sure zerobrew's bin directory comes first in PATH
+pub fn path_check(migrator: &HomebrewMigrator) -> Result<()> {
+    let Some(zb_prefix) = &migrator.zerobrew_prefix else {
+        bail!(
+            "Could not determine the zerobrew prefix.\n\n\
+             Suggestions:\n\
+             - Check that 'zb' is installed and in your PATH\n\
+             - Run 'zb --prefix' to confirm it works"
+        );
+    };
+    let brew_bin = migrator.homebrew_prefix.join("bin");
+    let zb_bin = zb_prefix.join("bin");
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    outln!("{} {}", style("Homebrew bin:").dim(), brew_bin.display())?;
+    outln!("{} {}\n", style("zerobrew bin:").dim(), zb_bin.display())?;
+
+    let problem = match migrate::check_path_order(&path, &brew_bin, &zb_bin) {
+        PathOrder::ZerobrewFirst => {
+            outln!(
+                "{} zerobrew's bin directory comes before Homebrew's in PATH.",
+                style("✓").green().bold()
+            )?;
+            return Ok(());
+        }
+        PathOrder::HomebrewFirst => {
+            "Homebrew's bin directory comes before zerobrew's in PATH, \
+             so migrated commands still run the Homebrew copies."
+        }
+        PathOrder::ZerobrewMissing => "zerobrew's bin directory is not in PATH.",
+    };
+    outln!("{} {}", style("⚠").yellow().bold(), problem)?;
+
+    let shell = std::env::var("SHELL").ok();
+    let (rc_file, line) = migrate::path_fix_suggestion(shell.as_deref(), &zb_bin);
+    outln!("\nAdd this line to the end of {}:", rc_file)?;
+    outln!("  {}", style(&line).white().bold())?;
+    outln!("\nThen open a new shell (or run `hash -r`) and check with `which -a <command>`.")?;
+    bail!("PATH does not put zerobrew ahead of Homebrew");
+}
+
+/// `benchmark`: time the brew commands behind `analyze`
+pub fn benchmark(
+    migrator: &HomebrewMigrator,
+    package: Option<String>,
+    iterations: usize,
+) -> Result<()> {
+    let benchmark = migrator.benchmark_brew(package.as_deref(), iterations)?;
+    print_benchmark(&benchmark, iterations.max(1))?;
+    Ok(())
+}
+
+/// Print the `benchmark` breakdown, one row per brew command
+fn print_benchmark(benchmark: &BrewBenchmark, iterations: usize) -> Result<()> {
+    outln!(
+        "{} {} run(s) each, sample package: {}\n",
+        style("Benchmark:").bold(),
+        iterations,
+        benchmark.sample
+    )?;
+    outln!("  {:>10}  {:>10}  {:>10}  command", "min", "median", "max")?;
+    for command in &benchmark.commands {
+        outln!(
+            "  {:>10.2?}  {:>10.2?}  {:>10.2?}  {}",
+            command.min(),
+            command.median(),
+            command.max(),
+            command.command
+        )?;
+    }
+    outln!(
+        "\n{} analyze runs `brew deps` and `brew info` for each of {} formulae: about {:.1?} in brew.",
+        style("Estimate:").bold(),
+        benchmark.formula_count,
+        benchmark.estimated_analysis()
+    )?;
+    Ok(())
+}
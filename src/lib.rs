@@ -0,0 +1,14 @@
+//! Zerobrew Migration Library
+//!
+//! The migration logic behind the `zb-migrate` CLI, usable as a dependency:
+//! list Homebrew packages, analyze migration risk and migrate them to Zerobrew.
+//! Methods return data (reports, plans, candidates) and leave rendering to the caller.
+
+pub mod migrate;
+
+pub use migrate::{
+    AnalysisReport, BrewBenchmark, BrewPackage, CliConfig, ConfigValue, DependencyTreeLine,
+    HistoryEntry, HomebrewMigrator, HomebrewStats, InstallMode, MigrateError, MigrateOptions,
+    MigrateResult, MigrationEvent, MigrationPlan, MigrationReport, MigrationRisk, MockBrewRunner,
+    OrderConstraint, PackageChoice, PackageExplanation, PackageImpact, PathOrder, VerifyStatus,
+};
@@ -1,15 +1,29 @@
 //! Zerobrew Migration CLI
 //!
 //! A tool to migrate from Homebrew to Zerobrew
+//!
+//! Output policy: results (JSON, Brewfiles, reports written to `-`, summaries)
+//! go to stdout; progress, status notes and diagnostics go to stderr, so
+//! stdout can be redirected or piped without picking up progress noise.
 
-mod migrate;
-
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
 use console::{set_colors_enabled, style};
+use std::ffi::OsString;
+use std::io::Write;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
-use migrate::HomebrewMigrator;
+mod commands;
+
+use commands::analyze::AnalyzeArgs;
+use commands::cleanup::CleanupArgs;
+use commands::inventory::ListArgs;
+use commands::migrate::{MigrateArgs, RetryFailedArgs};
+use commands::state::StatusArgs;
+use zb_migrate::migrate;
+use zb_migrate::{CliConfig, ConfigValue, HomebrewMigrator, MockBrewRunner};
 
 #[derive(Parser)]
 #[command(name = "zb-migrate")]
@@ -23,6 +37,52 @@ struct Cli {
     #[arg(long, global = true)]
     no_color: bool,
 
+    /// Always use colored output, even when not writing to a terminal
+    #[arg(long, global = true, conflicts_with = "no_color")]
+    force_color: bool,
+
+    /// Use plain CI-style progress output (default: detected from CI env vars)
+    #[arg(long, global = true, overrides_with = "no_ci")]
+    ci: bool,
+
+    /// Use interactive progress output even when CI env vars are set
+    #[arg(long, global = true, overrides_with = "ci")]
+    no_ci: bool,
+
+    /// Homebrew executable to run
+    #[arg(long, global = true, env = "BREW_BIN", default_value = "brew")]
+    brew_bin: PathBuf,
+
+    /// Zerobrew executable to run
+    #[arg(long, global = true, env = "ZB_BIN", default_value = "zb")]
+    zb_bin: PathBuf,
+
+    /// Back up migration_state.json before overwriting it
+    #[arg(long, global = true)]
+    backup_state: bool,
+
+    /// Number of state backups to keep with --backup-state
+    #[arg(long, global = true, value_name = "N", default_value_t = migrate::DEFAULT_KEEP_BACKUPS)]
+    keep_backups: usize,
+
+    /// How many formulae to load details for at once when analyzing (migrations
+    /// still install one package at a time, in dependency order)
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        default_value_t = NonZeroUsize::new(migrate::DEFAULT_ANALYZE_JOBS).unwrap()
+    )]
+    analyze_jobs: NonZeroUsize,
+
+    /// Read default options from this file instead of ~/.zerobrew/zb-migrate.toml
+    #[arg(long, global = true, env = "ZB_MIGRATE_CONFIG", value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Answer brew commands from a JSON fixture instead of running Homebrew (for testing)
+    #[arg(long, global = true, hide = true, value_name = "FIXTURES")]
+    mock_brew: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -30,38 +90,36 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// List all installed Homebrew packages
-    List {
-        /// Include casks in the listing
-        #[arg(long)]
-        casks: bool,
-
-        /// Output as JSON
-        #[arg(long)]
-        json: bool,
-    },
+    List(ListArgs),
 
     /// Export Homebrew packages to a Brewfile
     Export {
-        /// Output file path (default: ./Brewfile)
+        /// Output file path, or `-` for stdout (default: ./Brewfile)
         #[arg(short, long, default_value = "Brewfile")]
         output: PathBuf,
-    },
 
-    /// Migrate packages from Homebrew to Zerobrew
-    Migrate {
-        /// Dry run - show what would be migrated without making changes
+        /// Merge into an existing Brewfile, adding only entries not already listed
         #[arg(long)]
-        dry_run: bool,
+        append: bool,
+    },
 
-        /// Migrate only specific packages
-        #[arg(short, long)]
-        packages: Option<Vec<String>>,
+    /// List the third-party taps installed formulae come from
+    Taps {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
 
-        /// Interactive mode - prompt before each package migration
-        #[arg(short, long)]
-        interactive: bool,
+        /// Output Brewfile `tap "..."` lines instead of a plain list
+        #[arg(long, conflicts_with = "json")]
+        brewfile: bool,
     },
 
+    /// Migrate packages from Homebrew to Zerobrew
+    Migrate(MigrateArgs),
+
+    /// Retry the packages that failed in earlier migrations
+    RetryFailed(RetryFailedArgs),
+
     /// Check for available updates
     Outdated,
 
@@ -69,255 +127,462 @@ enum Commands {
     Upgrade,
 
     /// Cleanup Homebrew after successful migration
-    Cleanup {
-        /// Force cleanup without confirmation
+    Cleanup(CleanupArgs),
+
+    /// Show migration status
+    Status(StatusArgs),
+
+    /// Remove state entries for packages no longer installed in zerobrew or Homebrew
+    PruneState {
+        /// Show which entries would be removed without changing the state file
         #[arg(long)]
-        force: bool,
+        dry_run: bool,
     },
 
-    /// Show migration status
-    Status,
+    /// Roll migration state back to a backup made with --backup-state
+    RestoreState {
+        /// Backup file to restore
+        #[arg(required_unless_present = "list")]
+        file: Option<PathBuf>,
 
-    /// Analyze packages and categorize by migration risk
-    Analyze {
-        /// Output as JSON instead of formatted text
+        /// List available backups instead of restoring one
+        #[arg(long, conflicts_with = "file")]
+        list: bool,
+    },
+
+    /// Show past migration runs, oldest first
+    History {
+        /// Output as JSON
         #[arg(long)]
         json: bool,
     },
-}
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+    /// Check that migrated packages are still installed in Zerobrew
+    Verify {
+        /// Also run `zb info` on each package as a sanity check
+        #[arg(long)]
+        info: bool,
+    },
 
-    // Handle --no-color flag
-    if cli.no_color {
-        set_colors_enabled(false);
+    /// Check that zerobrew's bin directory comes before Homebrew's in PATH
+    PathCheck,
+
+    /// Summarize the local Homebrew installation for migration planning
+    Stats {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Analyze packages and categorize by migration risk
+    Analyze(AnalyzeArgs),
+
+    /// Time the brew commands behind `analyze` on this machine
+    #[command(hide = true)]
+    Benchmark {
+        /// Formula to run `brew deps` and `brew info` against (default: the first installed)
+        #[arg(long, value_name = "NAME")]
+        package: Option<String>,
+
+        /// How many times to run each command
+        #[arg(long, default_value_t = 3, value_name = "N")]
+        iterations: usize,
+    },
+
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+/// Parse the command line, filling in options it doesn't set from the config file.
+///
+/// Precedence is command line > environment variable > config file > built-in default:
+/// config values are only added for options that weren't passed or set through `env`.
+fn parse_cli() -> Result<Cli> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let matches = Cli::command().get_matches_from(&args);
+    let Some(path) = config_path(&matches)? else {
+        return Ok(Cli::from_arg_matches(&matches)?);
+    };
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let config = migrate::parse_config(&content)
+        .and_then(|config| config_args(&config, &matches))
+        .with_context(|| format!("Invalid config file {}", path.display()))?;
+    if config.is_empty() {
+        return Ok(Cli::from_arg_matches(&matches)?);
     }
 
-    let migrator = HomebrewMigrator::new(cli.verbose)?;
+    let matches = Cli::command()
+        .try_get_matches_from(with_config_args(args, config))
+        .map_err(|e| {
+            anyhow!(
+                "Invalid option in config file {}.\n\n\
+                 {}",
+                path.display(),
+                e
+            )
+        })?;
+    Ok(Cli::from_arg_matches(&matches)?)
+}
 
-    match cli.command {
-        Commands::List { casks, json } => {
-            let formulae = migrator.list_installed_formulae()?;
+/// Insert config arguments after the command line's own, before any `--`
+fn with_config_args(mut args: Vec<OsString>, config: Vec<String>) -> Vec<OsString> {
+    let at = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    args.splice(at..at, config.into_iter().map(OsString::from));
+    args
+}
 
-            if json {
-                let mut all_packages = formulae.clone();
-                if casks {
-                    all_packages.extend(migrator.list_installed_casks()?);
-                }
-                println!("{}", serde_json::to_string_pretty(&all_packages)?);
-            } else {
-                println!(
-                    "{} {}",
-                    style("📦 Homebrew Formulae").cyan().bold(),
-                    style(format!("({})", formulae.len())).dim()
-                );
-                println!("{}", style("─".repeat(50)).dim());
-                for pkg in &formulae {
-                    let pinned = if pkg.pinned {
-                        format!(" {}", style("[pinned]").yellow())
-                    } else {
-                        String::new()
-                    };
-                    let tap = pkg
-                        .tap
-                        .as_ref()
-                        .map(|t| format!(" {}", style(format!("({})", t)).dim()))
-                        .unwrap_or_default();
-                    println!(
-                        "  {:<28} {}{}{}",
-                        style(&pkg.name).white().bold(),
-                        style(&pkg.version).dim(),
-                        tap,
-                        pinned
-                    );
-                }
+/// The config file to read: `--config` / `ZB_MIGRATE_CONFIG`, else
+/// `zb-migrate.toml` in the zerobrew config directory when it exists
+fn config_path(matches: &ArgMatches) -> Result<Option<PathBuf>> {
+    let explicit = matches
+        .subcommand()
+        .and_then(|(_, sub)| sub.get_one::<PathBuf>("config"))
+        .or_else(|| matches.get_one::<PathBuf>("config"));
+    if let Some(path) = explicit {
+        return Ok(Some(path.clone()));
+    }
+    let home = migrate::resolve_zerobrew_home(
+        std::env::var(migrate::ZEROBREW_HOME_ENV).ok(),
+        std::env::var("HOME").ok(),
+    );
+    Ok(home
+        .ok()
+        .map(|dir| dir.join(migrate::CONFIG_FILE_NAME))
+        .filter(|path| path.exists()))
+}
 
-                if casks {
-                    let cask_list = migrator.list_installed_casks()?;
-                    println!(
-                        "\n{} {}",
-                        style("🖥️  Homebrew Casks").cyan().bold(),
-                        style(format!("({})", cask_list.len())).dim()
-                    );
-                    println!("{}", style("─".repeat(50)).dim());
-                    for pkg in &cask_list {
-                        println!(
-                            "  {:<28} {}",
-                            style(&pkg.name).white().bold(),
-                            style(&pkg.version).dim()
-                        );
-                    }
-                }
+/// Turn config options into command-line arguments, skipping options already set on
+/// the command line or through an environment variable, and options that conflict with
+/// those (e.g. `fail-fast` in the config when `--max-failures` is passed)
+fn config_args(config: &CliConfig, matches: &ArgMatches) -> Result<Vec<String>> {
+    let mut cli = Cli::command();
+    cli.build();
+    let active = matches.subcommand();
+
+    // Check every table, not just the running command's, so typos surface early
+    let mut sections = vec![(None, &cli)];
+    for table in config.commands.keys() {
+        let command = cli
+            .find_subcommand(table)
+            .with_context(|| format!("Unknown command [{}]", table))?;
+        sections.push((Some(table.as_str()), command));
+    }
+
+    let mut args = Vec::new();
+    for (section, command) in sections {
+        let table = section.map(|s| format!(" in [{}]", s)).unwrap_or_default();
+        // Global options may be given before or after the subcommand
+        let scopes: Vec<&ArgMatches> = match (section, active) {
+            (None, Some((_, sub))) => vec![matches, sub],
+            (None, None) => vec![matches],
+            (Some(s), Some((name, sub))) if s == name => vec![sub],
+            _ => Vec::new(),
+        };
+
+        for (key, value) in config.options(section) {
+            let arg = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(key.as_str()) && key != "config")
+                .with_context(|| format!("Unknown option `{}`{}", key, table))?;
+            let takes_value = arg.get_action().takes_values();
+            if !takes_value && !matches!(value, ConfigValue::Bool(_)) {
+                bail!("`{}`{} is a flag; set it with `{} = true`", key, table, key);
             }
-        }
 
-        Commands::Export { output } => {
-            println!(
-                "{} Exporting to {}...",
-                style("→").cyan().bold(),
-                style(output.display()).white()
-            );
-            migrator.export_to_brewfile(&output)?;
-            println!(
-                "{} Brewfile created at {}",
-                style("✓").green().bold(),
-                style(output.display()).white().bold()
-            );
-        }
+            let explicit = |other: &clap::Arg| {
+                scopes.iter().any(|m| {
+                    matches!(
+                        m.value_source(other.get_id().as_str()),
+                        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                    )
+                })
+            };
+            let overridden = explicit(arg)
+                || command
+                    .get_arguments()
+                    .filter(|other| explicit(other))
+                    .any(|other| {
+                        command.get_arg_conflicts_with(arg).contains(&other)
+                            || command.get_arg_conflicts_with(other).contains(&arg)
+                    });
+            if scopes.is_empty() || overridden {
+                continue;
+            }
 
-        Commands::Migrate {
-            dry_run,
-            packages,
-            interactive,
-        } => {
-            if let Some(pkg_names) = packages {
-                // Migrate specific packages
-                let all_formulae = migrator.list_installed_formulae()?;
-                for name in pkg_names {
-                    if let Some(pkg) = all_formulae.iter().find(|p| p.name == name) {
-                        if dry_run {
-                            println!(
-                                "{} Would migrate: {} {}",
-                                style("[DRY RUN]").yellow().bold(),
-                                style(&pkg.name).white().bold(),
-                                style(&pkg.version).dim()
-                            );
-                        } else {
-                            let result = migrator.migrate_package(pkg)?;
-                            match result {
-                                migrate::MigrateResult::Success { name, version } => {
-                                    println!(
-                                        "{} {} {} migrated successfully",
-                                        style("✓").green().bold(),
-                                        style(&name).white().bold(),
-                                        style(&version).dim()
-                                    );
-                                }
-                                migrate::MigrateResult::Failed { name, reason } => {
-                                    println!(
-                                        "{} {} failed: {}",
-                                        style("✗").red().bold(),
-                                        style(&name).white().bold(),
-                                        style(&reason).dim()
-                                    );
-                                }
-                            }
-                        }
-                    } else {
-                        println!(
-                            "{} Package not found: {}",
-                            style("✗").red().bold(),
-                            style(&name).yellow()
-                        );
-                    }
-                }
-            } else if interactive && !dry_run {
-                // Interactive migration mode
-                let report = migrator.migrate_interactive()?;
-                report.print_summary();
-            } else {
-                // Migrate all
-                let report = migrator.migrate_all(dry_run)?;
-                if !dry_run {
-                    report.print_summary();
+            match value {
+                ConfigValue::Bool(true) if !takes_value => args.push(format!("--{}", key)),
+                ConfigValue::Bool(false) if !takes_value => {}
+                ConfigValue::Bool(b) => args.push(format!("--{}={}", key, b)),
+                ConfigValue::Integer(n) => args.push(format!("--{}={}", key, n)),
+                ConfigValue::String(s) => args.push(format!("--{}={}", key, s)),
+                ConfigValue::Array(items) => {
+                    args.extend(items.iter().map(|item| format!("--{}={}", key, item)))
                 }
             }
         }
+    }
+    Ok(args)
+}
 
-        Commands::Outdated => {
-            println!(
-                "{} Zerobrew does not currently support checking for updates.\n",
-                style("ℹ").cyan().bold()
-            );
-            println!("To check for updates on packages still in Homebrew:");
-            println!("  {}", style("brew outdated").white().bold());
-            println!("\nTo update a Zerobrew package, reinstall it:");
-            println!("  {}", style("zb uninstall <package>").white().bold());
-            println!("  {}", style("zb install <package>").white().bold());
-        }
+/// Rust ignores SIGPIPE, so a closed stdout (e.g. `zb-migrate list | head -1`) shows
+/// up as a `BrokenPipe` write error; like standard UNIX tools, stop quietly with
+/// exit 0. Commands only fail on it after saving their results.
+fn main() -> Result<()> {
+    match run() {
+        Err(e) if is_broken_pipe(&e) => Ok(()),
+        result => result,
+    }
+}
+
+/// Whether an error was caused by writing to a closed stdout pipe
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::BrokenPipe)
+    })
+}
+
+fn run() -> Result<()> {
+    let cli = parse_cli()?;
 
-        Commands::Upgrade => {
-            println!(
-                "{} Zerobrew does not currently support bulk upgrades.\n",
-                style("ℹ").cyan().bold()
+    // Handle --no-color / --force-color flags
+    if cli.no_color {
+        set_colors_enabled(false);
+    }
+    if cli.force_color {
+        set_colors_enabled(true);
+    }
+    if cli.ci || cli.no_ci {
+        migrate::set_ci_override(Some(cli.ci));
+    }
+
+    // Constructed lazily so commands that work offline don't require Homebrew
+    let new_migrator = || -> Result<HomebrewMigrator> {
+        let mut migrator = match &cli.mock_brew {
+            Some(fixtures) => HomebrewMigrator::with_runner(
+                cli.verbose,
+                cli.brew_bin.clone(),
+                cli.zb_bin.clone(),
+                Box::new(MockBrewRunner::load(fixtures, cli.brew_bin.clone())?),
+            )?,
+            None => HomebrewMigrator::with_binaries(
+                cli.verbose,
+                cli.brew_bin.clone(),
+                cli.zb_bin.clone(),
+            )?,
+        };
+        migrator.backup_state = cli.backup_state;
+        migrator.keep_backups = cli.keep_backups;
+        migrator.analyze_jobs = cli.analyze_jobs.get();
+        if cli.verbose {
+            migrator.enable_timings();
+        }
+        if migrator.brew_bin != cli.brew_bin {
+            eprintln!(
+                "{} {} is not on PATH; using {}. To fix this, add to your shell profile:\n  eval \"$({} shellenv)\"",
+                style("ℹ").cyan().bold(),
+                cli.brew_bin.display(),
+                migrator.brew_bin.display(),
+                migrator.brew_bin.display()
             );
-            println!("To upgrade packages still in Homebrew:");
-            println!("  {}", style("brew upgrade").white().bold());
-            println!("\nTo upgrade a Zerobrew package, reinstall it:");
-            println!("  {}", style("zb uninstall <package>").white().bold());
-            println!("  {}", style("zb install <package>").white().bold());
-            println!("\nTo list installed Zerobrew packages:");
-            println!("  {}", style("zb list").white().bold());
         }
+        Ok(migrator)
+    };
 
-        Commands::Cleanup { force } => {
-            let state = migrator.load_state()?;
-            let packages: Vec<String> = state.migrated_packages.keys().cloned().collect();
-
-            if packages.is_empty() {
-                println!(
-                    "{} No migrated packages to clean up.",
-                    style("ℹ").cyan().bold()
-                );
-            } else {
-                migrator.cleanup_homebrew(&packages, force)?;
-            }
+    match cli.command {
+        Commands::List(args) => commands::inventory::list(&new_migrator()?, args),
+        Commands::Export { output, append } => {
+            commands::inventory::export(&new_migrator()?, output, append)
+        }
+        Commands::Taps { json, brewfile } => {
+            commands::inventory::taps(&new_migrator()?, json, brewfile)
+        }
+        Commands::Migrate(args) => commands::migrate::run(new_migrator()?, args),
+        Commands::RetryFailed(args) => commands::migrate::retry_failed(&new_migrator()?, args),
+        Commands::Outdated => commands::system::outdated(),
+        Commands::Upgrade => commands::system::upgrade(),
+        Commands::Cleanup(args) => commands::cleanup::run(&new_migrator()?, args),
+        Commands::Status(args) => commands::state::status(&new_migrator()?, args),
+        Commands::Verify { info } => commands::state::verify(&new_migrator()?, info),
+        Commands::PruneState { dry_run } => commands::state::prune_state(&new_migrator()?, dry_run),
+        Commands::RestoreState { file, list } => {
+            commands::state::restore_state(&new_migrator()?, file, list)
         }
+        Commands::History { json } => commands::state::history(&new_migrator()?, json),
+        Commands::Stats { json } => commands::inventory::stats(&new_migrator()?, json),
+        Commands::Analyze(args) => commands::analyze::run(new_migrator, args),
+        Commands::PathCheck => commands::system::path_check(&new_migrator()?),
+        Commands::Benchmark {
+            package,
+            iterations,
+        } => commands::system::benchmark(&new_migrator()?, package, iterations),
+        Commands::Completions { shell } => {
+            let mut cli = Cli::command();
+            let bin = cli.get_name().to_string();
+            // Generated into a buffer: clap_complete panics if a write fails
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut cli, bin, &mut script);
+            std::io::stdout().lock().write_all(&script)?;
+            Ok(())
+        }
+    }
+}
 
-        Commands::Status => {
-            let state = migrator.load_state()?;
-            println!(
-                "{}",
-                style("╭─ Migration Status ─────────────────────╮").cyan()
-            );
-            println!(
-                "{}  {} Migrated:  {} packages              {}",
-                style("│").cyan(),
-                style("✓").green().bold(),
-                style(state.migrated_packages.len()).white().bold(),
-                style("│").cyan()
-            );
-            println!(
-                "{}  {} Failed:    {} packages              {}",
-                style("│").cyan(),
-                style("✗").red().bold(),
-                style(state.failed_packages.len()).white().bold(),
-                style("│").cyan()
-            );
-            println!(
-                "{}",
-                style("╰────────────────────────────────────────╯").cyan()
-            );
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `args` with the config file `content` applied, as `parse_cli` does
+    fn parse_with_config(args: &[&str], content: &str) -> Result<Cli> {
+        let args: Vec<OsString> = std::iter::once("zb-migrate")
+            .chain(args.iter().copied())
+            .map(OsString::from)
+            .collect();
+        let matches = Cli::command().try_get_matches_from(&args)?;
+        let config = config_args(&migrate::parse_config(content)?, &matches)?;
+        let matches = Cli::command().try_get_matches_from(with_config_args(args, config))?;
+        Ok(Cli::from_arg_matches(&matches)?)
+    }
 
-            if !state.migrated_packages.is_empty() {
-                println!("\n{}", style("Migrated:").green().bold());
-                for (name, pkg) in &state.migrated_packages {
-                    println!(
-                        "  {:<28} {}",
-                        style(name).white().bold(),
-                        style(&pkg.version).dim()
-                    );
-                }
-            }
+    #[test]
+    fn test_config_args_fill_unset_options() {
+        let config = "verbose = true\n\
+                      [migrate]\n\
+                      keep-logs = true\n\
+                      max-failures = 5\n\
+                      [analyze]\n\
+                      strict = true\n";
+        let args = |cli_args: &[&str]| {
+            let matches = Cli::command()
+                .try_get_matches_from(std::iter::once("zb-migrate").chain(cli_args.iter().copied()))
+                .unwrap();
+            config_args(&migrate::parse_config(config).unwrap(), &matches).unwrap()
+        };
+
+        // Only the running command's table applies
+        assert_eq!(
+            args(&["migrate"]),
+            vec!["--verbose", "--keep-logs", "--max-failures=5"]
+        );
+        assert_eq!(args(&["analyze"]), vec!["--verbose", "--strict"]);
+        // The command line wins, before or after the subcommand
+        assert_eq!(
+            args(&["--verbose", "migrate", "--max-failures", "3"]),
+            vec!["--keep-logs"]
+        );
+        assert_eq!(args(&["status", "--verbose"]), Vec::<String>::new());
+    }
 
-            if !state.failed_packages.is_empty() {
-                println!("\n{}", style("Failed:").red().bold());
-                for name in &state.failed_packages {
-                    println!("  {}", style(name).red());
-                }
-            }
-        }
+    #[test]
+    fn test_command_line_overrides_conflicting_config() {
+        let cli = parse_with_config(
+            &["migrate", "--max-failures", "3"],
+            "[migrate]\nfail-fast = true\n",
+        )
+        .unwrap();
+        let Commands::Migrate(args) = cli.command else {
+            panic!("expected migrate");
+        };
+        assert!(!args.fail_fast);
+        assert_eq!(args.max_failures, NonZeroUsize::new(3));
+
+        // The reverse direction of a conflict is overridden too
+        let cli = parse_with_config(&["--force-color", "list"], "no-color = true\n").unwrap();
+        assert!(cli.force_color && !cli.no_color);
+
+        // Conflicts within the config file itself are still reported
+        let error = parse_with_config(&["list"], "no-color = true\nforce-color = true\n")
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("cannot be used with"));
+    }
 
-        Commands::Analyze { json } => {
-            let report = migrator.analyze_packages()?;
-            if json {
-                println!("{}", report.to_json()?);
-            } else {
-                report.print_summary();
-            }
-        }
+    #[test]
+    fn test_config_args_reject_unknown_options() {
+        let error = |content: &str| {
+            let matches = Cli::command()
+                .try_get_matches_from(["zb-migrate", "list"])
+                .unwrap();
+            config_args(&migrate::parse_config(content).unwrap(), &matches)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error("[migrat]\nyes = true\n"), "Unknown command [migrat]");
+        assert_eq!(
+            error("[migrate]\nyess = true\n"),
+            "Unknown option `yess` in [migrate]"
+        );
+        assert_eq!(
+            error("[migrate]\nfail-fast = 1\n"),
+            "`fail-fast` in [migrate] is a flag; set it with `fail-fast = true`"
+        );
     }
 
-    Ok(())
+    #[test]
+    fn test_since_conflicts_with_packages() {
+        let args = ["zb-migrate", "migrate", "--since", "7d", "--packages", "jq"];
+        let err = Cli::try_parse_from(args).err().unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        assert!(Cli::try_parse_from(["zb-migrate", "migrate", "--since", "7d"]).is_ok());
+    }
+
+    #[test]
+    fn test_plan_options_reject_flags_they_ignore() {
+        let conflict = |args: &[&str]| {
+            let args = ["zb-migrate", "migrate"].iter().chain(args);
+            Cli::try_parse_from(args).err().map(|e| e.kind())
+        };
+        for flag in [
+            &["--requested-only"][..],
+            &["--after", "git:curl"],
+            &["--before", "curl:git"],
+            &["--dry-run", "--exact-order"],
+        ] {
+            let args: Vec<&str> = ["--plan", "plan.json"]
+                .iter()
+                .chain(flag)
+                .copied()
+                .collect();
+            assert_eq!(
+                conflict(&args),
+                Some(clap::error::ErrorKind::ArgumentConflict),
+                "{:?}",
+                flag
+            );
+        }
+        for flag in [
+            &["--report", "report.md"][..],
+            &["--export-failures", "failed.txt"],
+            &["--fail-fast"],
+            &["--max-failures", "3"],
+            &["--keep-logs"],
+            &["--pin-versions"],
+            &["--build-from-source"],
+            &["--force-bottle=llvm"],
+            &["--summary-only"],
+            &["--force"],
+            &["--yes"],
+            &["--with-deps"],
+            &["--with-dependents"],
+            &["--dependents-depth", "2"],
+            &["--allow-uninstalled"],
+            &["--show-impact"],
+        ] {
+            let args: Vec<&str> = ["--save-plan", "-"].iter().chain(flag).copied().collect();
+            assert_eq!(
+                conflict(&args),
+                Some(clap::error::ErrorKind::ArgumentConflict),
+                "{:?}",
+                flag
+            );
+        }
+        assert_eq!(conflict(&["--plan", "plan.json", "--fail-fast"]), None);
+    }
 }
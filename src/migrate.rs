@@ -6,25 +6,68 @@
 //! 3. Handle subsequent updates via Zerobrew
 
 use anyhow::{bail, Context, Result};
-use console::style;
-use dialoguer::{theme::ColorfulTheme, Select};
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fs;
-use std::io::IsTerminal;
-use std::path::PathBuf;
-use std::process::Command;
-use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Environment variables set by common CI providers
+pub const CI_ENV_VARS: &[&str] = &[
+    "CI",
+    "GITHUB_ACTIONS",
+    "GITLAB_CI",
+    "CIRCLECI",
+    "TRAVIS",
+    "BUILDKITE",
+    "JENKINS_URL",
+    "TF_BUILD",
+    "TEAMCITY_VERSION",
+    "BITBUCKET_BUILD_NUMBER",
+];
+
+/// `--ci`/`--no-ci` override: 0 = auto-detect, 1 = CI, 2 = not CI
+static CI_OVERRIDE: AtomicU8 = AtomicU8::new(0);
+
+/// Force CI mode on or off (`None` goes back to auto-detection)
+pub fn set_ci_override(ci: Option<bool>) {
+    let value = match ci {
+        None => 0,
+        Some(true) => 1,
+        Some(false) => 2,
+    };
+    CI_OVERRIDE.store(value, Ordering::Relaxed);
+}
+
+/// Whether any CI provider variable is set, looking variables up with `var`.
+/// Empty values and `false`/`0` (e.g. `CI=false`) don't count.
+pub fn detect_ci(var: impl Fn(&str) -> Option<String>) -> bool {
+    CI_ENV_VARS.iter().any(|name| {
+        var(name).is_some_and(|value| {
+            let value = value.trim();
+            !value.is_empty() && value != "0" && !value.eq_ignore_ascii_case("false")
+        })
+    })
+}
 
-/// Check if running in CI environment
-fn is_ci() -> bool {
-    std::env::var("CI").is_ok()
+/// Check if running in CI environment, honoring `--ci`/`--no-ci`
+pub fn is_ci() -> bool {
+    match CI_OVERRIDE.load(Ordering::Relaxed) {
+        1 => true,
+        2 => false,
+        _ => detect_ci(|name| std::env::var(name).ok()),
+    }
 }
 
-/// Create a progress bar with appropriate style for the environment
-fn create_progress_bar(total: u64, message: &str) -> ProgressBar {
-    let pb = ProgressBar::new(total);
+/// Create a progress bar with appropriate style for the environment.
+/// Progress always draws to stderr so stdout stays clean for results.
+pub fn create_progress_bar(total: u64, message: &str) -> ProgressBar {
+    let pb = ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::stderr());
 
     if is_ci() {
         // Simple style for CI without animations
@@ -50,7 +93,7 @@ fn create_progress_bar(total: u64, message: &str) -> ProgressBar {
 /// Create a spinner for indeterminate progress
 #[allow(dead_code)]
 fn create_spinner(message: &str) -> ProgressBar {
-    let pb = ProgressBar::new_spinner();
+    let pb = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
 
     if is_ci() {
         pb.set_style(ProgressStyle::with_template("{msg}").unwrap());
@@ -65,6 +108,280 @@ fn create_spinner(message: &str) -> ProgressBar {
     pb
 }
 
+/// Stderr lines Homebrew prints on success that are not errors
+/// (auto-update notices, hints and analytics messages)
+const BENIGN_BREW_STDERR_PATTERNS: &[&str] = &[
+    "Already up-to-date",
+    "Auto-updated Homebrew",
+    "Auto-updating Homebrew",
+    "Updating Homebrew",
+    "Updated Homebrew from",
+    "HOMEBREW_NO_AUTO_UPDATE",
+    "HOMEBREW_AUTO_UPDATE_SECS",
+    "HOMEBREW_NO_ENV_HINTS",
+    "HOMEBREW_NO_INSTALL_CLEANUP",
+    "HOMEBREW_NO_ANALYTICS",
+    "brew analytics",
+    "Homebrew has enabled anonymous aggregate",
+    "Read the analytics documentation",
+    "Homebrew is run entirely by unpaid volunteers",
+];
+
+/// Lines following Homebrew's outdated-formulae notice, matched exactly
+const OUTDATED_NOTICE_HINTS: &[&str] = &[
+    "You can upgrade it with brew upgrade",
+    "You can upgrade them with brew upgrade",
+    "or list it with brew outdated.",
+    "or list them with brew outdated.",
+];
+
+/// Remove benign Homebrew noise from stderr, keeping genuine error text
+pub fn filter_brew_stderr(stderr: &str) -> String {
+    stderr
+        .lines()
+        .filter(|line| {
+            let line = line.trim();
+            !line.is_empty()
+                && !BENIGN_BREW_STDERR_PATTERNS
+                    .iter()
+                    .any(|pattern| line.contains(pattern))
+                && !is_updated_taps_notice(line)
+                && !is_outdated_notice(line)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Matches notices like "==> Updated 2 taps (homebrew/core and homebrew/cask)."
+fn is_updated_taps_notice(line: &str) -> bool {
+    line.trim_start_matches("==> ")
+        .strip_prefix("Updated ")
+        .is_some_and(|rest| rest.contains(" tap"))
+}
+
+/// Matches "You have 3 outdated formulae installed." and the hints that follow it
+fn is_outdated_notice(line: &str) -> bool {
+    let count_line = line
+        .strip_prefix("You have ")
+        .and_then(|rest| rest.split_once(' '))
+        .is_some_and(|(count, rest)| {
+            count.chars().all(|c| c.is_ascii_digit())
+                && matches!(
+                    rest,
+                    "outdated formula installed." | "outdated formulae installed."
+                )
+        });
+    count_line || OUTDATED_NOTICE_HINTS.contains(&line)
+}
+
+/// The `Error output:` section of an error message, or nothing when Homebrew's
+/// stderr was empty once filtered
+fn error_output_section(stderr: &str) -> String {
+    if stderr.is_empty() {
+        String::new()
+    } else {
+        format!("\n\nError output: {}", stderr)
+    }
+}
+
+/// Levenshtein edit distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+    let mut curr = vec![0; b_chars.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b_chars.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j + 1] + 1).min(curr[j] + 1).min(prev[j] + cost);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    prev[b_chars.len()]
+}
+
+/// Suggest up to `limit` candidate names close to `name`, closest first
+pub fn suggest_similar<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+    limit: usize,
+) -> Vec<String> {
+    let max_distance = (name.chars().count() / 3).max(2);
+    let mut matches: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .collect();
+    matches.sort();
+    matches
+        .into_iter()
+        .take(limit)
+        .map(|(_, c)| c.to_string())
+        .collect()
+}
+
+/// Whether a `--packages` entry is a glob pattern rather than an exact name
+pub fn is_glob_pattern(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+/// Shell-style glob match supporting `*` (any run of characters) and `?` (one character)
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Iterative matcher that backtracks to the most recent `*`
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Replace `@path` entries in a `--packages` list with the names listed in that file,
+/// one per line. Blank lines and `#` comments are ignored, so a file written by
+/// `--export-failures` can be edited and fed straight back in.
+pub fn expand_package_files(args: Vec<String>) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for arg in args {
+        let Some(path) = arg.strip_prefix('@') else {
+            names.push(arg);
+            continue;
+        };
+        let content = fs::read_to_string(path).with_context(|| {
+            format!(
+                "Failed to read package list {}.\n\
+                 Suggestion: pass an existing file with one package name per line.",
+                path
+            )
+        })?;
+        names.extend(
+            content
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string()),
+        );
+    }
+    Ok(names)
+}
+
+/// Render failed package names one per line, for `--export-failures`
+pub fn render_failure_list(report: &MigrationReport) -> String {
+    report
+        .failed
+        .iter()
+        .map(|(name, _)| format!("{}\n", name))
+        .collect()
+}
+
+/// Expand glob patterns against installed package names, keeping exact names as-is.
+/// Returns the expanded names (deduplicated, in order) and the patterns that matched nothing.
+pub fn expand_package_patterns<'a>(
+    entries: &[String],
+    installed: impl IntoIterator<Item = &'a str> + Clone,
+) -> (Vec<String>, Vec<String>) {
+    let mut names: Vec<String> = Vec::new();
+    let mut unmatched = Vec::new();
+    for entry in entries {
+        if !is_glob_pattern(entry) {
+            if !names.contains(entry) {
+                names.push(entry.clone());
+            }
+            continue;
+        }
+        let mut matches: Vec<&str> = installed
+            .clone()
+            .into_iter()
+            .filter(|name| glob_match(entry, name))
+            .collect();
+        if matches.is_empty() {
+            unmatched.push(entry.clone());
+        }
+        matches.sort_unstable();
+        for name in matches {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    (names, unmatched)
+}
+
+/// Parse a human-friendly duration such as `30m`, `12h`, `7d` or `2w`.
+///
+/// A bare number is interpreted as days.
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+
+    let value: u64 = number.parse().with_context(|| {
+        format!(
+            "Invalid duration '{}': expected a number followed by s, m, h, d or w (e.g. 7d)",
+            input
+        )
+    })?;
+
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "" | "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!(
+            "Invalid duration unit '{}' in '{}': expected s, m, h, d or w",
+            unit,
+            input
+        ),
+    };
+
+    match value.checked_mul(seconds) {
+        Some(total) => Ok(Duration::from_secs(total)),
+        None => bail!("Duration '{}' is too large", input),
+    }
+}
+
+/// Risk score given to packages that should stay in Homebrew
+const KEEP_IN_HOMEBREW_SCORE: u32 = 100;
+/// Score weight of a problematic direct dependency
+const DIRECT_DEP_WEIGHT: u32 = 10;
+/// Score weight of a problematic transitive dependency
+const TRANSITIVE_DEP_WEIGHT: u32 = 4;
+/// Score weight of a problematic build-only dependency
+const BUILD_DEP_WEIGHT: u32 = 1;
+/// Risk score added for keg-only formulae, which aren't linked into the prefix
+const KEG_ONLY_SCORE: u32 = 5;
+/// Analysis reason for keg-only formulae without problematic dependencies
+const KEG_ONLY_REASON: &str = "Keg-only: not linked into the Homebrew prefix";
+/// Risk score added for formulae without a bottle for this platform, which are built
+/// from source: slow and more likely to fail
+const NO_BOTTLE_SCORE: u32 = 8;
+/// Analysis reason for formulae whose only issue is the missing bottle
+const NO_BOTTLE_REASON: &str = "No bottle for this platform: will be built from source";
+
 /// Known problematic packages that may cause issues during migration.
 /// These packages often have complex linking requirements, system dependencies,
 /// or are deeply integrated into other packages' build processes.
@@ -141,8 +458,8 @@ pub const KNOWN_PROBLEMATIC_PACKAGES: &[&str] = &[
     "pcre2",
 ];
 
-/// Categorization of a package for migration analysis
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Categorization of a package for migration analysis, ordered from least to most severe
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum MigrationRisk {
     /// Safe to migrate - no known issues
     Safe,
@@ -152,6 +469,22 @@ pub enum MigrationRisk {
     KeepInHomebrew,
 }
 
+impl MigrationRisk {
+    /// Short human-readable label
+    pub fn label(&self) -> &'static str {
+        match self {
+            MigrationRisk::Safe => "Safe",
+            MigrationRisk::Risky => "Risky",
+            MigrationRisk::KeepInHomebrew => "Keep in Homebrew",
+        }
+    }
+}
+
+/// Quote `text` as one POSIX shell word, e.g. for names read from a Brewfile
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
 /// Detailed information about a package's migration risk
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PackageAnalysis {
@@ -160,10 +493,55 @@ pub struct PackageAnalysis {
     pub risk: MigrationRisk,
     pub reason: String,
     pub problematic_dependencies: Vec<String>,
+    /// Casks are analyzed for completeness but cannot be migrated yet
+    #[serde(default)]
+    pub is_cask: bool,
+    /// Relative risk within the category; higher means more likely to break
+    #[serde(default)]
+    pub risk_score: u32,
 }
 
-/// Complete analysis report for all installed packages
+/// Why one package got its risk classification, shown by `analyze --explain`
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageExplanation {
+    pub analysis: PackageAnalysis,
+    /// Problematic packages among the direct runtime dependencies
+    pub direct_problematic: Vec<String>,
+    /// Shortest runtime dependency path from the package to each problematic package
+    /// it reaches, starting with the package itself
+    pub chains: Vec<Vec<String>>,
+    /// Why each problematic package involved should stay in Homebrew
+    pub reasons: BTreeMap<String, String>,
+    /// No bottle for this platform, so zerobrew builds it from source
+    #[serde(default)]
+    pub no_bottle: bool,
+}
+
+/// Category counts of an [`AnalysisReport`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnalysisSummary {
+    pub total_packages: usize,
+    pub safe_to_migrate: usize,
+    pub risky: usize,
+    pub should_keep_in_homebrew: usize,
+    /// Longest runtime dependency chain, dependencies first
+    pub longest_chain: Vec<String>,
+}
+
+/// One row of the `analyze --tree` view
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyTreeLine {
+    /// Box-drawing indentation placing the row under its parent
+    pub prefix: String,
+    pub name: String,
+    /// `None` for dependencies that weren't analyzed (not installed)
+    pub risk: Option<MigrationRisk>,
+    /// Already expanded earlier in the tree; its dependencies aren't repeated
+    pub repeated: bool,
+}
+
+/// Complete analysis report for all installed packages
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct AnalysisReport {
     /// Packages that are safe to migrate
     pub safe_to_migrate: Vec<PackageAnalysis>,
@@ -173,6 +551,9 @@ pub struct AnalysisReport {
     pub should_keep_in_homebrew: Vec<PackageAnalysis>,
     /// Total number of packages analyzed
     pub total_packages: usize,
+    /// Longest runtime dependency chain, dependencies first; empty when not computed
+    #[serde(default)]
+    pub longest_chain: Vec<String>,
 }
 
 impl AnalysisReport {
@@ -183,105 +564,510 @@ impl AnalysisReport {
             risky: Vec::new(),
             should_keep_in_homebrew: Vec::new(),
             total_packages: 0,
+            longest_chain: Vec::new(),
         }
     }
 
-    /// Print a formatted summary of the analysis
-    pub fn print_summary(&self) {
-        println!("\n=== Package Migration Analysis ===\n");
-        println!("Total packages analyzed: {}\n", self.total_packages);
+    /// Reclassify risky packages that depend on keep-in-Homebrew packages as
+    /// keep-in-Homebrew.
+    ///
+    /// Such packages depend (directly, transitively or to build) on something that
+    /// should stay in Homebrew; a conservative policy treats that as blocking too.
+    /// Packages that are risky for other reasons (keg-only, no bottle) stay risky.
+    pub fn apply_strict(&mut self) {
+        let (blocked, risky): (Vec<_>, Vec<_>) = std::mem::take(&mut self.risky)
+            .into_iter()
+            .partition(|pkg| !pkg.problematic_dependencies.is_empty());
+        self.risky = risky;
+        for mut pkg in blocked {
+            pkg.risk = MigrationRisk::KeepInHomebrew;
+            pkg.reason = format!(
+                "Strict mode: depends on {}",
+                pkg.problematic_dependencies.join(", ")
+            );
+            self.should_keep_in_homebrew.push(pkg);
+        }
+        self.should_keep_in_homebrew
+            .sort_by(|a, b| a.name.cmp(&b.name));
+    }
 
-        // Summary counts
-        println!("Summary:");
-        println!(
-            "  Safe to migrate:        {} packages",
-            self.safe_to_migrate.len()
-        );
-        println!("  Risky (use caution):    {} packages", self.risky.len());
-        println!(
-            "  Keep in Homebrew:       {} packages",
-            self.should_keep_in_homebrew.len()
+    /// A copy keeping only the categories at or above `min` severity.
+    /// `total_packages` still counts every analyzed package.
+    pub fn at_or_above(&self, min: &MigrationRisk) -> Self {
+        let keep = |risk: MigrationRisk, packages: &Vec<PackageAnalysis>| {
+            if risk >= *min {
+                packages.clone()
+            } else {
+                Vec::new()
+            }
+        };
+        Self {
+            safe_to_migrate: keep(MigrationRisk::Safe, &self.safe_to_migrate),
+            risky: keep(MigrationRisk::Risky, &self.risky),
+            should_keep_in_homebrew: keep(
+                MigrationRisk::KeepInHomebrew,
+                &self.should_keep_in_homebrew,
+            ),
+            total_packages: self.total_packages,
+            longest_chain: self.longest_chain.clone(),
+        }
+    }
+
+    /// Category counts for `summary.json` in `analyze --json --output-dir`
+    pub fn summary(&self) -> AnalysisSummary {
+        AnalysisSummary {
+            total_packages: self.total_packages,
+            safe_to_migrate: self.safe_to_migrate.len(),
+            risky: self.risky.len(),
+            should_keep_in_homebrew: self.should_keep_in_homebrew.len(),
+            longest_chain: self.longest_chain.clone(),
+        }
+    }
+
+    /// Write each category to its own JSON file in `dir` (created if missing):
+    /// `safe.json`, `risky.json`, `keep.json` and `summary.json`, each in the
+    /// `--json` envelope. Returns the files written.
+    pub fn write_json_files(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create output directory {}", dir.display()))?;
+        let files = [
+            ("safe.json", json_envelope(&self.safe_to_migrate)?),
+            ("risky.json", json_envelope(&self.risky)?),
+            ("keep.json", json_envelope(&self.should_keep_in_homebrew)?),
+            ("summary.json", json_envelope(&self.summary())?),
+        ];
+        let mut written = Vec::new();
+        for (name, json) in files {
+            let path = dir.join(name);
+            write_atomic(&path, format!("{}\n", json).as_bytes())?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+
+    /// Look up the analysis for a package by name
+    pub fn find(&self, name: &str) -> Option<&PackageAnalysis> {
+        self.safe_to_migrate
+            .iter()
+            .chain(&self.risky)
+            .chain(&self.should_keep_in_homebrew)
+            .find(|p| p.name == name)
+    }
+
+    /// Export the report as JSON
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Names of safe packages that can actually be migrated (casks excluded)
+    pub fn safe_formulae(&self) -> Vec<&str> {
+        self.safe_to_migrate
+            .iter()
+            .filter(|p| !p.is_cask)
+            .map(|p| p.name.as_str())
+            .collect()
+    }
+
+    /// Render a shell script that migrates every safe formula in one run and, with
+    /// `include_risky`, each risky formula on its own, pausing so it can be tested
+    pub fn to_migration_script(&self, include_risky: bool) -> String {
+        const PACKAGES_PER_LINE: usize = 8;
+
+        let mut script = String::from(
+            "#!/bin/sh\n\
+             # Generated by `zb-migrate analyze --emit-script`. Review before running.\n\
+             # Stops at the first failed migration.\n\
+             set -e\n",
         );
 
-        // Safe packages
-        if !self.safe_to_migrate.is_empty() {
-            println!("\n--- Safe to Migrate ({}) ---", self.safe_to_migrate.len());
-            println!("These packages have no known issues and can be safely migrated:\n");
-            for pkg in &self.safe_to_migrate {
-                println!("  [OK] {} @ {}", pkg.name, pkg.version);
+        let safe = self.safe_formulae();
+        if !safe.is_empty() {
+            script.push_str(&format!(
+                "\n# Safe packages ({})\nzb-migrate migrate",
+                safe.len()
+            ));
+            for chunk in safe.chunks(PACKAGES_PER_LINE) {
+                script.push_str(&format!(
+                    " \\\n    --packages {}",
+                    shell_quote(&chunk.join(","))
+                ));
             }
+            script.push('\n');
         }
 
-        // Risky packages
-        if !self.risky.is_empty() {
-            println!("\n--- Risky Packages ({}) ---", self.risky.len());
-            println!("These packages depend on problematic packages. Migration may work but test carefully:\n");
-            for pkg in &self.risky {
-                println!("  [!] {} @ {}", pkg.name, pkg.version);
-                println!("      Reason: {}", pkg.reason);
-                if !pkg.problematic_dependencies.is_empty() {
-                    println!(
-                        "      Problematic deps: {}",
-                        pkg.problematic_dependencies.join(", ")
-                    );
-                }
+        let risky: Vec<&PackageAnalysis> = self.risky.iter().filter(|p| !p.is_cask).collect();
+        if include_risky && !risky.is_empty() {
+            script.push_str(&format!(
+                "\n# Risky packages ({}), one at a time: test each before continuing\n",
+                risky.len()
+            ));
+            for pkg in risky {
+                let prompt = format!(
+                    "Test {}, then press Enter to continue (Ctrl-C to stop)... ",
+                    pkg.name
+                );
+                script.push_str(&format!(
+                    "\n# {}\n\
+                     zb-migrate migrate --packages {}\n\
+                     printf '%s' {}\n\
+                     read -r _\n",
+                    pkg.reason.replace(['\n', '\r'], " "),
+                    shell_quote(&pkg.name),
+                    shell_quote(&prompt)
+                ));
             }
         }
 
-        // Keep in Homebrew
-        if !self.should_keep_in_homebrew.is_empty() {
-            println!(
-                "\n--- Keep in Homebrew ({}) ---",
-                self.should_keep_in_homebrew.len()
-            );
-            println!("These packages are known to have issues and should remain in Homebrew:\n");
-            for pkg in &self.should_keep_in_homebrew {
-                println!("  [X] {} @ {}", pkg.name, pkg.version);
-                println!("      Reason: {}", pkg.reason);
+        script
+    }
+
+    /// Lay out `packages` as dependency trees annotated with this report's risk levels.
+    ///
+    /// Each package nothing else depends on starts a tree, in name order. A subtree is
+    /// expanded the first time it appears; later occurrences are marked `repeated`.
+    pub fn dependency_tree(&self, packages: &[BrewPackage]) -> Vec<DependencyTreeLine> {
+        fn walk<'a>(
+            report: &AnalysisReport,
+            pkg_map: &HashMap<&str, &'a BrewPackage>,
+            name: &'a str,
+            prefix: String,
+            child_prefix: String,
+            shown: &mut HashSet<&'a str>,
+            lines: &mut Vec<DependencyTreeLine>,
+        ) {
+            let deps = pkg_map
+                .get(name)
+                .map(|p| p.dependencies.as_slice())
+                .unwrap_or_default();
+            let repeated = !deps.is_empty() && shown.contains(name);
+            lines.push(DependencyTreeLine {
+                prefix,
+                name: name.to_string(),
+                risk: report.find(name).map(|a| a.risk.clone()),
+                repeated,
+            });
+            if repeated || !shown.insert(name) {
+                return;
+            }
+            for (idx, dep) in deps.iter().enumerate() {
+                let last = idx + 1 == deps.len();
+                let (branch, indent) = if last {
+                    ("└── ", "    ")
+                } else {
+                    ("├── ", "│   ")
+                };
+                walk(
+                    report,
+                    pkg_map,
+                    dep,
+                    format!("{child_prefix}{branch}"),
+                    format!("{child_prefix}{indent}"),
+                    shown,
+                    lines,
+                );
             }
         }
 
-        // Recommendations
-        println!("\n=== Recommendations ===\n");
-
-        if !self.safe_to_migrate.is_empty() {
-            println!("1. Start by migrating safe packages:");
-            println!(
-                "   zb-migrate migrate --packages {}",
-                self.safe_to_migrate
-                    .iter()
-                    .take(5)
-                    .map(|p| p.name.as_str())
-                    .collect::<Vec<_>>()
-                    .join(",")
+        let pkg_map: HashMap<&str, &BrewPackage> =
+            packages.iter().map(|p| (p.name.as_str(), p)).collect();
+        let dependents = dependent_counts(packages);
+        let mut roots: Vec<&str> = packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .filter(|name| dependents.get(name) == Some(&0))
+            .collect();
+        roots.sort_unstable();
+
+        let mut shown = HashSet::new();
+        let mut lines = Vec::new();
+        for root in roots {
+            walk(
+                self,
+                &pkg_map,
+                root,
+                String::new(),
+                String::new(),
+                &mut shown,
+                &mut lines,
             );
-            if self.safe_to_migrate.len() > 5 {
-                println!(
-                    "   (showing first 5 of {} safe packages)",
-                    self.safe_to_migrate.len()
+        }
+        // Packages only reachable through a dependency cycle have no root of their own
+        let mut rest: Vec<&str> = packages
+            .iter()
+            .map(|p| p.name.as_str())
+            .filter(|name| !shown.contains(name))
+            .collect();
+        rest.sort_unstable();
+        for name in rest {
+            if !shown.contains(name) {
+                walk(
+                    self,
+                    &pkg_map,
+                    name,
+                    String::new(),
+                    String::new(),
+                    &mut shown,
+                    &mut lines,
                 );
             }
         }
+        lines
+    }
 
-        if !self.risky.is_empty() {
-            println!("\n2. For risky packages, migrate one at a time and test:");
-            println!("   zb-migrate migrate --packages <package-name>");
-            println!("   # Then test the package before proceeding");
+    /// Render every analyzed package as a plain-text table with aligned columns
+    pub fn to_table(&self) -> String {
+        const HEADERS: [&str; 4] = ["PACKAGE", "VERSION", "RISK", "PROBLEMATIC DEPS"];
+
+        let rows: Vec<[String; 4]> = self
+            .safe_to_migrate
+            .iter()
+            .chain(&self.risky)
+            .chain(&self.should_keep_in_homebrew)
+            .map(|pkg| {
+                let name = if pkg.is_cask {
+                    format!("{} (cask)", pkg.name)
+                } else {
+                    pkg.name.clone()
+                };
+                let deps = if pkg.problematic_dependencies.is_empty() {
+                    "-".to_string()
+                } else {
+                    pkg.problematic_dependencies.join(", ")
+                };
+                [
+                    name,
+                    pkg.version.clone(),
+                    pkg.risk.label().to_string(),
+                    deps,
+                ]
+            })
+            .collect();
+
+        // The last column is left unpadded so lines carry no trailing spaces
+        let mut widths = [0; 3];
+        for (i, width) in widths.iter_mut().enumerate() {
+            *width = rows
+                .iter()
+                .map(|row| row[i].chars().count())
+                .chain(std::iter::once(HEADERS[i].len()))
+                .max()
+                .unwrap_or_default();
+        }
+
+        let format_row = |cells: [&str; 4]| {
+            format!(
+                "{:<w0$}  {:<w1$}  {:<w2$}  {}\n",
+                cells[0],
+                cells[1],
+                cells[2],
+                cells[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2]
+            )
+        };
+
+        let mut table = format_row(HEADERS);
+        table.push_str(&format_row([
+            &"-".repeat(widths[0]),
+            &"-".repeat(widths[1]),
+            &"-".repeat(widths[2]),
+            &"-".repeat(HEADERS[3].len()),
+        ]));
+        for row in &rows {
+            table.push_str(&format_row([&row[0], &row[1], &row[2], &row[3]]));
+        }
+        table
+    }
+
+    /// Render the report as a markdown document
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        md.push_str("# Package Migration Analysis\n\n");
+        md.push_str(&format!(
+            "Total packages analyzed: **{}**\n\n",
+            self.total_packages
+        ));
+
+        md.push_str("## Summary\n\n");
+        md.push_str("| Category | Packages |\n|---|---|\n");
+        md.push_str(&format!(
+            "| Safe to migrate | {} |\n",
+            self.safe_to_migrate.len()
+        ));
+        md.push_str(&format!("| Risky | {} |\n", self.risky.len()));
+        md.push_str(&format!(
+            "| Keep in Homebrew | {} |\n\n",
+            self.should_keep_in_homebrew.len()
+        ));
+
+        for (title, packages) in [
+            ("Safe to Migrate", &self.safe_to_migrate),
+            ("Risky Packages", &self.risky),
+            ("Keep in Homebrew", &self.should_keep_in_homebrew),
+        ] {
+            if packages.is_empty() {
+                continue;
+            }
+            md.push_str(&format!("## {} ({})\n\n", title, packages.len()));
+            md.push_str("| Package | Version | Reason | Problematic deps |\n|---|---|---|---|\n");
+            for pkg in packages {
+                let name = if pkg.is_cask {
+                    format!("{} (cask)", pkg.name)
+                } else {
+                    pkg.name.clone()
+                };
+                md.push_str(&format!(
+                    "| {} | {} | {} | {} |\n",
+                    md_cell(&name),
+                    md_cell(&pkg.version),
+                    md_cell(&pkg.reason),
+                    md_cell(&pkg.problematic_dependencies.join(", "))
+                ));
+            }
+            md.push('\n');
         }
 
+        md.push_str("## Recommendations\n\n");
+        let safe_formulae = self.safe_formulae();
+        if !safe_formulae.is_empty() {
+            md.push_str(&format!(
+                "- Start by migrating safe packages: `zb-migrate migrate --packages {}`\n",
+                safe_formulae.join(",")
+            ));
+        }
+        if !self.risky.is_empty() {
+            md.push_str(
+                "- Migrate risky packages one at a time and test each before proceeding.\n",
+            );
+        }
         if !self.should_keep_in_homebrew.is_empty() {
-            println!("\n3. Leave problematic packages in Homebrew:");
-            println!("   These packages are core dependencies that many other packages rely on.");
-            println!("   Migrating them may break other software.");
+            md.push_str(
+                "- Leave problematic packages in Homebrew; migrating them may break other software.\n",
+            );
         }
 
-        println!();
+        md
     }
+}
 
-    /// Export the report as JSON
-    pub fn to_json(&self) -> Result<String> {
-        Ok(serde_json::to_string_pretty(self)?)
+/// Escape text for use inside a markdown table cell
+fn md_cell(text: &str) -> String {
+    let text = text.trim();
+    if text.is_empty() {
+        return "-".to_string();
+    }
+    text.replace('|', "\\|").replace('\n', "<br>")
+}
+
+/// Version of the CLI's `--json` output layout, bumped on breaking changes
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// The versioned wrapper around every `--json` payload
+#[derive(Debug, Serialize)]
+struct JsonEnvelope<'a, T: ?Sized> {
+    schema_version: u32,
+    data: &'a T,
+}
+
+/// Serialize a `--json` payload as `{"schema_version": N, "data": ...}`
+pub fn json_envelope<T: Serialize + ?Sized>(data: &T) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&JsonEnvelope {
+        schema_version: JSON_SCHEMA_VERSION,
+        data,
+    })?)
+}
+
+/// Number of installed packages that depend on a given package
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DependentCount {
+    pub name: String,
+    pub dependents: usize,
+}
+
+/// Overview of the local Homebrew installation for migration planning
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HomebrewStats {
+    pub total_formulae: usize,
+    pub total_casks: usize,
+    pub pinned: usize,
+    pub from_third_party_taps: usize,
+    pub safe_to_migrate: usize,
+    pub risky: usize,
+    pub keep_in_homebrew: usize,
+    /// Packages with the most installed dependents, highest first
+    pub most_depended_upon: Vec<DependentCount>,
+    /// Longest runtime dependency chain, dependencies first
+    #[serde(default)]
+    pub longest_chain: Vec<String>,
+}
+
+/// Invert the dependency graph: how many of `packages` depend on each package.
+/// Every package in `packages` is included, leaves with a count of zero.
+pub fn dependent_counts(packages: &[BrewPackage]) -> HashMap<&str, usize> {
+    let mut counts: HashMap<&str, usize> = packages.iter().map(|p| (p.name.as_str(), 0)).collect();
+    for pkg in packages {
+        let unique: HashSet<&str> = pkg.dependencies.iter().map(|d| d.as_str()).collect();
+        for dep in unique {
+            *counts.entry(dep).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// Invert the dependency graph and return the `limit` packages with the most dependents.
+/// Ties are broken alphabetically so the output is stable.
+pub fn most_depended_upon(packages: &[BrewPackage], limit: usize) -> Vec<DependentCount> {
+    let mut ranked: Vec<DependentCount> = dependent_counts(packages)
+        .into_iter()
+        .filter(|&(_, dependents)| dependents > 0)
+        .map(|(name, dependents)| DependentCount {
+            name: name.to_string(),
+            dependents,
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.dependents.cmp(&a.dependents).then(a.name.cmp(&b.name)));
+    ranked.truncate(limit);
+    ranked
+}
+
+/// Arguments for `brew uninstall`, optionally skipping brew's dependents check
+pub fn uninstall_args(name: &str, ignore_dependencies: bool) -> Vec<&str> {
+    let mut args = vec!["uninstall"];
+    if ignore_dependencies {
+        args.push("--ignore-dependencies");
+    }
+    args.push(name);
+    args
+}
+
+/// The longest chain of installed runtime dependencies, dependencies first.
+///
+/// Each link has to be installed after the previous one, so the chain's length is the
+/// minimum number of sequential install steps however much the rest is parallelized.
+/// Ties are broken alphabetically so the output is stable.
+pub fn longest_dependency_chain(packages: &[BrewPackage]) -> Result<Vec<String>> {
+    // In dependency order every dependency's chain is known before its dependents'
+    let ordered = HomebrewMigrator::topological_sort(packages)?;
+    let mut chains: HashMap<&str, Vec<&str>> = HashMap::new();
+
+    for pkg in &ordered {
+        let mut chain = pkg
+            .dependencies
+            .iter()
+            .filter_map(|dep| chains.get(dep.as_str()))
+            .max_by(|a, b| a.len().cmp(&b.len()).then_with(|| b.cmp(a)))
+            .cloned()
+            .unwrap_or_default();
+        chain.push(pkg.name.as_str());
+        chains.insert(pkg.name.as_str(), chain);
     }
+
+    Ok(chains
+        .into_values()
+        .max_by(|a, b| a.len().cmp(&b.len()).then_with(|| b.cmp(a)))
+        .map(|chain| chain.into_iter().map(str::to_string).collect())
+        .unwrap_or_default())
 }
 
 /// Represents a Homebrew package with its metadata
@@ -291,1818 +1077,9997 @@ pub struct BrewPackage {
     pub version: String,
     pub tap: Option<String>,
     pub is_cask: bool,
+    /// Runtime dependencies
     pub dependencies: Vec<String>,
     pub pinned: bool,
+    /// Not symlinked into the Homebrew prefix (`keg_only` in `brew info --json=v2`)
+    #[serde(default)]
+    pub keg_only: bool,
+    /// Dependencies only needed to build from source; loaded with `include_build_deps`
+    #[serde(default)]
+    pub build_dependencies: Vec<String>,
+    /// `brew info --json=v2` lists no bottle for this platform, so installing it
+    /// means building from source
+    #[serde(default)]
+    pub no_bottle: bool,
+    /// Installed explicitly rather than pulled in as a dependency
+    /// (`brew list --installed-on-request`); true when Homebrew can't tell
+    #[serde(default = "default_true")]
+    pub installed_on_request: bool,
+    /// Installed from the development branch with `--HEAD`; zerobrew can only
+    /// install the stable version
+    #[serde(default)]
+    pub head: bool,
 }
 
-/// Represents the migration state
-#[derive(Debug, Serialize, Deserialize, Default)]
-pub struct MigrationState {
-    pub migrated_packages: HashMap<String, BrewPackage>,
-    pub failed_packages: Vec<String>,
-    pub homebrew_prefix: PathBuf,
-}
-
-/// Main migrator struct
-pub struct HomebrewMigrator {
-    pub homebrew_prefix: PathBuf,
-    state_file: PathBuf,
-    verbose: bool,
+fn default_true() -> bool {
+    true
 }
 
-impl HomebrewMigrator {
-    /// Create a new migrator instance
-    pub fn new(verbose: bool) -> Result<Self> {
-        let homebrew_prefix = Self::detect_homebrew_prefix(verbose)?;
-        let home = std::env::var("HOME").context(
-            "HOME environment variable is not set.\n\
-             This is required to locate the zerobrew configuration directory.\n\
-             Suggestion: Ensure you are running in a proper shell environment.",
-        )?;
-
-        Ok(Self {
-            homebrew_prefix,
-            state_file: PathBuf::from(format!("{}/.zerobrew/migration_state.json", home)),
-            verbose,
-        })
+/// An installed-on-request formula with nothing else known about it
+impl Default for BrewPackage {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            version: String::new(),
+            tap: None,
+            is_cask: false,
+            dependencies: Vec::new(),
+            pinned: false,
+            keg_only: false,
+            build_dependencies: Vec::new(),
+            no_bottle: false,
+            installed_on_request: true,
+            head: false,
+        }
     }
+}
 
-    /// Detect Homebrew installation prefix
-    fn detect_homebrew_prefix(verbose: bool) -> Result<PathBuf> {
-        let start = Instant::now();
-        if verbose {
-            eprintln!("[verbose] Running: brew --prefix");
+impl BrewPackage {
+    /// A formula that isn't installed in Homebrew, to be installed directly with zerobrew
+    pub fn not_installed(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            version: UNKNOWN_VERSION.to_string(),
+            ..Default::default()
         }
+    }
+}
 
-        // Try to get prefix from brew command
-        let output = Command::new("brew")
-            .arg("--prefix")
-            .output()
-            .context(
-                "Failed to run 'brew --prefix': Homebrew does not appear to be installed.\n\n\
-                 To install Homebrew, run:\n\
-                   /bin/bash -c \"$(curl -fsSL https://raw.githubusercontent.com/Homebrew/install/HEAD/install.sh)\"\n\n\
-                 For more information, visit: https://brew.sh",
-            )?;
+/// Outcome of looking up which tap a package was installed from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TapLookup {
+    /// Package comes from one of the default taps (homebrew/core or homebrew/cask)
+    Core,
+    /// Package comes from a third-party tap
+    Tap(String),
+    /// Tap could not be determined (e.g. formula deleted from its tap)
+    Unknown,
+}
 
-        let elapsed = start.elapsed();
-        if verbose {
-            eprintln!("[verbose] Command completed in {:.2?}", elapsed);
-            eprintln!("[verbose] Exit code: {}", output.status);
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            if !stdout.trim().is_empty() {
-                eprintln!("[verbose] stdout: {}", stdout.trim());
-            }
-            if !stderr.trim().is_empty() {
-                eprintln!("[verbose] stderr: {}", stderr.trim());
-            }
+impl TapLookup {
+    /// Third-party tap name, if any, as stored on `BrewPackage::tap`
+    pub fn into_tap(self) -> Option<String> {
+        match self {
+            TapLookup::Tap(tap) => Some(tap),
+            TapLookup::Core | TapLookup::Unknown => None,
         }
+    }
+}
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!(
-                "Failed to detect Homebrew prefix: 'brew --prefix' returned an error.\n\n\
-                 Error output: {}\n\n\
-                 Suggestions:\n\
-                 - Ensure Homebrew is properly installed and configured\n\
-                 - Try running 'brew doctor' to diagnose issues\n\
-                 - Check that 'brew' is in your PATH",
-                stderr.trim()
-            );
-        }
+/// What `brew info --json=v2` says about an installed formula
+#[derive(Debug)]
+struct FormulaInfo {
+    tap: TapLookup,
+    keg_only: bool,
+    no_bottle: bool,
+    /// The installed `--HEAD` version, if that's the one in use
+    head_version: Option<String>,
+}
 
-        let prefix = String::from_utf8_lossy(&output.stdout).trim().to_string();
+/// Everything `list_installed_formulae_detailed` loads for one formula
+#[derive(Debug)]
+struct FormulaDetails {
+    dependencies: Vec<String>,
+    build_dependencies: Vec<String>,
+    info: FormulaInfo,
+}
 
-        Ok(PathBuf::from(prefix))
+/// Extract the tap from `brew info --json=v2` output.
+///
+/// Checks `formulae` first and falls back to `casks` when no formula entry is present.
+pub fn parse_tap_from_info_json(json: &str) -> TapLookup {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(json) else {
+        return TapLookup::Unknown;
+    };
+
+    let entry = ["formulae", "casks"].iter().find_map(|key| {
+        value[key]
+            .as_array()
+            .and_then(|entries| entries.first())
+            .cloned()
+    });
+
+    match entry.as_ref().and_then(|e| e["tap"].as_str()) {
+        Some("homebrew/core") | Some("homebrew/cask") => TapLookup::Core,
+        Some(tap) if !tap.is_empty() => TapLookup::Tap(tap.to_string()),
+        _ => TapLookup::Unknown,
     }
+}
 
-    /// List all installed Homebrew formulae (fast mode - minimal brew calls)
-    pub fn list_installed_formulae(&self) -> Result<Vec<BrewPackage>> {
-        let start = Instant::now();
-        if self.verbose {
-            eprintln!("[verbose] Running: brew list --formula --versions");
+/// Whether a Homebrew version string is a `--HEAD` install, e.g. `HEAD-abc1234`
+pub fn is_head_version(version: &str) -> bool {
+    version.starts_with("HEAD")
+}
+
+/// The `--HEAD` version `brew info --json=v2` output reports as installed: the
+/// linked keg when it is a HEAD one, otherwise the first HEAD keg if nothing is linked
+pub fn parse_head_version_from_info_json(json: &str) -> Option<String> {
+    let value = serde_json::from_str::<serde_json::Value>(json).ok()?;
+    let formula = &value["formulae"][0];
+    if let Some(linked) = formula["linked_keg"].as_str() {
+        return is_head_version(linked).then(|| linked.to_string());
+    }
+    formula["installed"]
+        .as_array()?
+        .iter()
+        .filter_map(|keg| keg["version"].as_str())
+        .find(|version| is_head_version(version))
+        .map(str::to_string)
+}
+
+/// Whether `brew info --json=v2` output marks the formula as keg-only
+pub fn parse_keg_only_from_info_json(json: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(json)
+        .ok()
+        .and_then(|value| value["formulae"][0]["keg_only"].as_bool())
+        .unwrap_or(false)
+}
+
+/// Whether `brew info --json=v2` output lists no stable bottle for `bottle_tag`
+/// (e.g. `arm64_sonoma`) or for all platforms. Output without bottle information
+/// counts as having one, so nothing is flagged when brew can't tell.
+pub fn parse_no_bottle_from_info_json(json: &str, bottle_tag: &str) -> bool {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(json) else {
+        return false;
+    };
+    let Some(bottle) = value["formulae"][0]["bottle"].as_object() else {
+        return false;
+    };
+    match bottle.get("stable").map(|stable| &stable["files"]) {
+        Some(serde_json::Value::Object(files)) => {
+            !files.contains_key(bottle_tag) && !files.contains_key("all")
         }
+        _ => true,
+    }
+}
 
-        let output = Command::new("brew")
-            .args(["list", "--formula", "--versions"])
-            .output()
-            .context(
-                "Failed to list Homebrew formulae: Could not execute 'brew list'.\n\n\
-                 Suggestions:\n\
-                 - Verify Homebrew is installed: run 'brew --version'\n\
-                 - Check your internet connection if this is a network-related issue\n\
-                 - Try running 'brew update' to refresh Homebrew",
-            )?;
+/// Homebrew's bottle tag for a platform, e.g. `arm64_sonoma` or `x86_64_linux`;
+/// `None` for platforms (or macOS versions) it has no tag for
+pub fn bottle_tag(os: &str, arch: &str, macos_version: Option<&str>) -> Option<String> {
+    let arch = match arch {
+        "aarch64" | "arm64" => "arm64",
+        "x86_64" => "x86_64",
+        _ => return None,
+    };
+    match os {
+        "linux" => Some(format!("{}_linux", arch)),
+        "macos" => {
+            let major: u32 = macos_version?.trim().split('.').next()?.parse().ok()?;
+            let codename = match major {
+                11 => "big_sur",
+                12 => "monterey",
+                13 => "ventura",
+                14 => "sonoma",
+                15 => "sequoia",
+                26 => "tahoe",
+                _ => return None,
+            };
+            Some(if arch == "arm64" {
+                format!("arm64_{}", codename)
+            } else {
+                codename.to_string()
+            })
+        }
+        _ => None,
+    }
+}
 
-        let elapsed = start.elapsed();
-        if self.verbose {
-            eprintln!("[verbose] Command completed in {:.2?}", elapsed);
-            eprintln!("[verbose] Exit code: {}", output.status);
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            eprintln!("[verbose] Found {} lines of output", stdout.lines().count());
+impl MigrationState {
+    /// Drop migrated entries installed in neither zerobrew nor Homebrew, along with
+    /// their migration records. Returns the pruned names, sorted.
+    pub fn prune_stale(
+        &mut self,
+        zb_installed: &HashSet<String>,
+        brew_installed: &HashSet<String>,
+    ) -> Vec<String> {
+        let mut stale: Vec<String> = self
+            .migrated_packages
+            .keys()
+            .filter(|name| !zb_installed.contains(*name) && !brew_installed.contains(*name))
+            .cloned()
+            .collect();
+        stale.sort();
+        for name in &stale {
+            self.migrated_packages.remove(name);
+            self.migration_records.remove(name);
         }
+        stale
+    }
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!(
-                "Failed to list Homebrew formulae: 'brew list --formula' returned an error.\n\n\
-                 Error output: {}\n\n\
-                 Suggestions:\n\
-                 - Run 'brew doctor' to check for issues\n\
-                 - Try 'brew update' to refresh package information",
-                stderr.trim()
+    /// Fold a finished run into the state: successes move into `migrated_packages`
+    /// (leaving `failed_packages`), failures are recorded once each with their latest reason
+    pub fn apply_report(
+        &mut self,
+        report: &MigrationReport,
+        formulae: &[BrewPackage],
+        zerobrew_version: &Option<String>,
+        migrated_at: u64,
+    ) {
+        for name in &report.successful {
+            if let Some(pkg) = formulae.iter().find(|p| &p.name == name) {
+                self.migrated_packages.insert(name.clone(), pkg.clone());
+                self.migration_records.insert(
+                    name.clone(),
+                    MigrationRecord {
+                        zerobrew_version: zerobrew_version.clone(),
+                        migrated_at,
+                        installed_version: report.installed_versions.get(name).cloned(),
+                    },
+                );
+            }
+        }
+        for pkg in &report.already_in_zerobrew {
+            if self.migrated_packages.contains_key(&pkg.name) {
+                continue;
+            }
+            self.migrated_packages.insert(pkg.name.clone(), pkg.clone());
+            self.migration_records.insert(
+                pkg.name.clone(),
+                MigrationRecord {
+                    zerobrew_version: None,
+                    migrated_at,
+                    installed_version: None,
+                },
             );
         }
+        let migrated = |name: &String| {
+            report.successful.contains(name)
+                || report.already_in_zerobrew.iter().any(|p| &p.name == name)
+        };
+        self.failed_packages.retain(|(name, _)| !migrated(name));
+        for (name, reason) in &report.failed {
+            match self.failed_packages.iter_mut().find(|(n, _)| n == name) {
+                Some(entry) => entry.1 = reason.clone(),
+                None => self.failed_packages.push((name.clone(), reason.clone())),
+            }
+        }
 
-        // Get pinned packages once
-        let pinned_set = self.get_pinned_packages()?;
+        self.skipped_packages.retain(|(name, _)| !migrated(name));
+        for (name, reason) in &report.skipped {
+            if migrated(name) || self.migrated_packages.contains_key(name) {
+                continue;
+            }
+            match self.skipped_packages.iter_mut().find(|(n, _)| n == name) {
+                Some(entry) => entry.1 = reason.clone(),
+                None => self.skipped_packages.push((name.clone(), reason.clone())),
+            }
+        }
+    }
+}
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut packages = Vec::new();
+/// Take an exclusive `flock` on `file` without blocking; `Ok(false)` if another
+/// process holds it. The lock is released when the file is closed.
+#[cfg(unix)]
+fn try_lock_exclusive(file: &fs::File) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
 
-        for line in stdout.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                let name = parts[0].to_string();
-                let version = parts[1].to_string();
-                let pinned = pinned_set.contains(&name);
+    // SAFETY: the descriptor stays open for the duration of the call
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    if err.kind() == std::io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(err)
+    }
+}
 
-                packages.push(BrewPackage {
-                    name,
-                    version,
-                    tap: None, // Skip tap lookup for speed
-                    is_cask: false,
-                    dependencies: Vec::new(), // Lazy load when needed
-                    pinned,
-                });
-            }
+/// Homebrew only runs on unix, so other platforms go without the lock
+#[cfg(not(unix))]
+fn try_lock_exclusive(_file: &fs::File) -> std::io::Result<bool> {
+    Ok(true)
+}
+
+/// Guard for the exclusive migration lock; the lock is released when it is dropped
+#[derive(Debug)]
+pub struct MigrationLock {
+    _file: fs::File,
+}
+
+/// Details about when and how a package was migrated
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct MigrationRecord {
+    /// Output of `zb --version` at migration time
+    pub zerobrew_version: Option<String>,
+    /// Unix timestamp (seconds) of the migration
+    pub migrated_at: u64,
+    /// Package version zerobrew installed; the Homebrew version is kept in `migrated_packages`
+    #[serde(default)]
+    pub installed_version: Option<String>,
+}
+
+/// One run recorded in the append-only migration history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Unix timestamp (seconds) when the run finished
+    pub timestamp: u64,
+    /// What kind of run this was, e.g. `migrate`
+    pub action: String,
+    /// Output of `zb --version` during the run
+    #[serde(default)]
+    pub zerobrew_version: Option<String>,
+    #[serde(default)]
+    pub successful: Vec<String>,
+    #[serde(default)]
+    pub failed: Vec<String>,
+    #[serde(default)]
+    pub skipped: Vec<String>,
+}
+
+impl HistoryEntry {
+    /// Summarize a finished run for the history log
+    pub fn from_report(
+        action: &str,
+        report: &MigrationReport,
+        zerobrew_version: &Option<String>,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            timestamp,
+            action: action.to_string(),
+            zerobrew_version: zerobrew_version.clone(),
+            successful: report.successful.clone(),
+            failed: report.failed.iter().map(|(n, _)| n.clone()).collect(),
+            skipped: report.skipped.iter().map(|(n, _)| n.clone()).collect(),
         }
+    }
+}
 
-        Ok(packages)
+/// Parse the JSON-lines history log.
+///
+/// Returns the entries in file order and the number of lines that could not be
+/// parsed (for example a record cut short by an interrupted write).
+pub fn parse_history(content: &str) -> (Vec<HistoryEntry>, usize) {
+    let mut entries = Vec::new();
+    let mut malformed = 0;
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => malformed += 1,
+        }
     }
+    (entries, malformed)
+}
 
-    /// Get all pinned packages at once
-    fn get_pinned_packages(&self) -> Result<std::collections::HashSet<String>> {
-        let output = Command::new("brew").args(["list", "--pinned"]).output()?;
+/// Names of the services `brew services list --json` reports as started
+pub fn parse_running_services(json: &str) -> Result<BTreeSet<String>> {
+    let services: Vec<serde_json::Value> =
+        serde_json::from_str(json).context("Unexpected output from 'brew services list --json'")?;
+    Ok(services
+        .iter()
+        .filter(|s| s["status"].as_str() == Some("started"))
+        .filter_map(|s| s["name"].as_str().map(|n| n.to_string()))
+        .collect())
+}
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        Ok(stdout.lines().map(|s| s.to_string()).collect())
-    }
+/// Default number of state backups kept by `--backup-state`
+pub const DEFAULT_KEEP_BACKUPS: usize = 10;
 
-    /// List installed formulae with full details (slower, used for migration)
-    pub fn list_installed_formulae_detailed(&self) -> Result<Vec<BrewPackage>> {
-        let mut packages = self.list_installed_formulae()?;
-        let total = packages.len();
+/// Default number of formulae whose details are loaded from `brew` at once
+pub const DEFAULT_ANALYZE_JOBS: usize = 4;
 
-        let pb = create_progress_bar(total as u64, "Loading package details...");
+/// Timestamp of a `migration_state.<timestamp>.json.bak` file name
+fn parse_backup_timestamp(file_name: &str) -> Option<u64> {
+    file_name
+        .strip_prefix("migration_state.")?
+        .strip_suffix(".json.bak")?
+        .parse()
+        .ok()
+}
 
-        for (i, pkg) in packages.iter_mut().enumerate() {
-            pb.set_message(format!("Loading: {}", pkg.name));
-            pkg.dependencies = self.get_dependencies(&pkg.name)?;
-            pkg.tap = self.get_tap(&pkg.name)?;
-            pb.set_position((i + 1) as u64);
-        }
+/// Format a Unix timestamp (seconds) as `YYYY-MM-DD HH:MM UTC`
+pub fn format_unix_time(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days conversion (proleptic Gregorian calendar)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60
+    )
+}
 
-        pb.finish_with_message(format!("Loaded {} packages", total));
-        Ok(packages)
+/// Format a byte count with decimal units, like Finder and `du -h --si`: `1.5 GB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
     }
+}
 
-    /// List all installed Homebrew casks
-    pub fn list_installed_casks(&self) -> Result<Vec<BrewPackage>> {
-        let output = Command::new("brew")
-            .args(["list", "--cask", "--versions"])
-            .output()
-            .context(
-                "Failed to list Homebrew casks: Could not execute 'brew list --cask'.\n\n\
-                 Suggestions:\n\
-                 - Verify Homebrew is installed: run 'brew --version'\n\
-                 - Check your internet connection if this is a network-related issue",
-            )?;
+/// Package directories under a prefix whose disk usage `status --with-sizes` reports
+const PREFIX_SIZE_DIRS: [&str; 2] = ["Cellar", "opt"];
+
+/// Disk usage in bytes of the `Cellar` and `opt` directories under `prefix`, or
+/// `None` when neither exists. Symlinks aren't followed and hard-linked files are
+/// counted once, so the result matches `du`.
+pub fn prefix_disk_usage(prefix: &Path) -> Option<u64> {
+    let mut seen = HashSet::new();
+    let mut total = None;
+    for dir in PREFIX_SIZE_DIRS {
+        let path = prefix.join(dir);
+        if path.is_dir() {
+            *total.get_or_insert(0) += disk_usage(&path, &mut seen);
+        }
+    }
+    total
+}
 
-        if !output.status.success() {
-            // Casks might not be installed, return empty
-            return Ok(Vec::new());
+/// Recursively sum the space used by `path`; unreadable entries count as zero
+fn disk_usage(path: &Path, seen: &mut HashSet<(u64, u64)>) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    #[cfg(unix)]
+    let size = {
+        use std::os::unix::fs::MetadataExt;
+        if metadata.nlink() > 1 && !seen.insert((metadata.dev(), metadata.ino())) {
+            return 0;
         }
+        metadata.blocks() * 512
+    };
+    #[cfg(not(unix))]
+    let size = {
+        let _ = &seen;
+        metadata.len()
+    };
+
+    if !metadata.is_dir() {
+        return size;
+    }
+    let children = fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| disk_usage(&entry.path(), seen))
+                .sum()
+        })
+        .unwrap_or(0);
+    size + children
+}
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut packages = Vec::new();
+/// Version recorded for packages whose Homebrew version isn't known
+/// (Brewfile entries, or packages that were never installed in Homebrew)
+pub const UNKNOWN_VERSION: &str = "unknown";
+
+/// Entries read from a Brewfile, e.g. one written by `brew bundle dump`
+#[derive(Debug, Clone, Default)]
+pub struct Brewfile {
+    pub taps: Vec<String>,
+    pub formulae: Vec<BrewPackage>,
+    pub casks: Vec<BrewPackage>,
+    /// Entries zerobrew has no equivalent for (`mas`, `vscode`, ...), as `(kind, name)`
+    pub unsupported: Vec<(String, String)>,
+}
 
-        for line in stdout.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                packages.push(BrewPackage {
-                    name: parts[0].to_string(),
-                    version: parts[1].to_string(),
-                    tap: None,
-                    is_cask: true,
-                    dependencies: Vec::new(),
-                    pinned: false,
-                });
+/// Parse every entry of Brewfile content.
+///
+/// Handles tap-qualified names (`brew "user/tap/name"`) and drops trailing options such
+/// as `args: [...]` or `restart_service: true`. Entries of other kinds (`mas`, `vscode`,
+/// `whalebrew`, ...) are collected in `unsupported` so callers can warn about them.
+/// Versions and dependencies are not recorded in a Brewfile, so versions are set to
+/// `unknown` and dependency lists are empty.
+pub fn parse_brewfile_entries(content: &str) -> Brewfile {
+    let mut brewfile = Brewfile::default();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some((kind, full_name)) = parse_brewfile_entry(line) else {
+            continue;
+        };
+        match kind {
+            "tap" => brewfile.taps.push(full_name),
+            "brew" => brewfile.formulae.push(brewfile_package(&full_name, false)),
+            "cask" => brewfile.casks.push(brewfile_package(&full_name, true)),
+            _ if kind.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+                brewfile.unsupported.push((kind.to_string(), full_name))
             }
+            _ => {}
         }
+    }
 
-        Ok(packages)
+    brewfile
+}
+
+/// Parse the formulae from Brewfile content; see [`parse_brewfile_entries`]
+pub fn parse_brewfile(content: &str) -> Vec<BrewPackage> {
+    parse_brewfile_entries(content).formulae
+}
+
+/// Build a package for a Brewfile entry, splitting off the tap of qualified names
+fn brewfile_package(full_name: &str, is_cask: bool) -> BrewPackage {
+    let (name, tap) = match full_name.rsplit_once('/') {
+        Some((tap, name)) => (name.to_string(), Some(tap.to_string())),
+        None => (full_name.to_string(), None),
+    };
+
+    BrewPackage {
+        name,
+        version: UNKNOWN_VERSION.to_string(),
+        tap,
+        is_cask,
+        dependencies: Vec::new(),
+        build_dependencies: Vec::new(),
+        pinned: false,
+        keg_only: false,
+        no_bottle: false,
+        installed_on_request: true,
+        head: false,
     }
+}
 
-    /// Get dependencies for a package
-    fn get_dependencies(&self, name: &str) -> Result<Vec<String>> {
-        let output = Command::new("brew")
-            .args(["deps", "--installed", name])
-            .output();
+/// Distinct third-party taps used by the given packages, sorted by name
+pub fn distinct_taps(packages: &[BrewPackage]) -> Vec<String> {
+    let taps: BTreeSet<&str> = packages.iter().filter_map(|p| p.tap.as_deref()).collect();
+    taps.into_iter().map(|t| t.to_string()).collect()
+}
 
-        match output {
-            Ok(out) if out.status.success() => {
-                let stdout = String::from_utf8_lossy(&out.stdout);
-                Ok(stdout.lines().map(|s| s.to_string()).collect())
-            }
-            _ => Ok(Vec::new()),
+/// Group heading for formulae from homebrew/core or with no known tap
+pub const DEFAULT_TAP_GROUP: &str = "homebrew/core";
+
+/// Group packages by tap, keeping each group in input order. The `DEFAULT_TAP_GROUP`
+/// (core and untapped packages) comes first, then third-party taps sorted by name.
+pub fn group_by_tap(packages: &[BrewPackage]) -> Vec<(&str, Vec<&BrewPackage>)> {
+    let mut groups: BTreeMap<(bool, &str), Vec<&BrewPackage>> = BTreeMap::new();
+    for pkg in packages {
+        let tap = pkg.tap.as_deref().unwrap_or(DEFAULT_TAP_GROUP);
+        groups
+            .entry((tap != DEFAULT_TAP_GROUP, tap))
+            .or_default()
+            .push(pkg);
+    }
+    groups
+        .into_iter()
+        .map(|((_, tap), packages)| (tap, packages))
+        .collect()
+}
+
+/// Render taps as Brewfile `tap` lines
+pub fn render_taps(taps: &[String]) -> String {
+    taps.iter()
+        .map(|tap| format!("tap {}\n", brewfile_quote(tap)))
+        .collect()
+}
+
+/// Header row written by `packages_to_csv`
+pub const CSV_HEADER: &str = "name,version,tap,is_cask,pinned,dependencies";
+
+/// Render packages as CSV for spreadsheets, one row per package under `CSV_HEADER`.
+/// The `dependencies` column holds the number of runtime dependencies.
+pub fn packages_to_csv(packages: &[BrewPackage]) -> String {
+    let mut csv = format!("{CSV_HEADER}\r\n");
+    for pkg in packages {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\r\n",
+            csv_field(&pkg.name),
+            csv_field(&pkg.version),
+            csv_field(pkg.tap.as_deref().unwrap_or_default()),
+            pkg.is_cask,
+            pkg.pinned,
+            pkg.dependencies.len()
+        ));
+    }
+    csv
+}
+
+/// Quote a CSV field (RFC 4180) when it contains a comma, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Replace `path` with `contents` without ever leaving it half-written.
+///
+/// The data goes to a temporary file in the same directory, which is then renamed over
+/// `path`; if anything fails the original file is left untouched. An existing file's
+/// permissions carry over to the new one.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file path", path.display()))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".tmp-{}", std::process::id()));
+    let tmp = dir.join(tmp_name);
+
+    let result = (|| -> std::io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp)?;
+        file.write_all(contents)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
         }
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("Failed to write {}", path.display()));
     }
+    Ok(())
+}
 
-    /// Get the tap for a package
-    fn get_tap(&self, name: &str) -> Result<Option<String>> {
-        let output = Command::new("brew")
-            .args(["info", "--json=v2", name])
-            .output();
+/// Quote a name as a Ruby double-quoted string for a Brewfile.
+///
+/// Brewfiles are Ruby, so `"`, `\` and `#` (interpolation) are escaped and control
+/// characters are written as escape sequences: a name can never end the string early
+/// or inject code, even if it comes from an untrusted tap.
+pub fn brewfile_quote(name: &str) -> String {
+    let mut quoted = String::with_capacity(name.len() + 2);
+    quoted.push('"');
+    for c in name.chars() {
+        match c {
+            '"' | '\\' | '#' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
 
-        match output {
-            Ok(out) if out.status.success() => {
-                let stdout = String::from_utf8_lossy(&out.stdout);
-                // Parse JSON to extract tap
-                if let Ok(json) = serde_json::from_str::<serde_json::Value>(&stdout) {
-                    if let Some(tap) = json["formulae"][0]["tap"].as_str() {
-                        if tap != "homebrew/core" {
-                            return Ok(Some(tap.to_string()));
-                        }
+/// Read the quoted string at the start of `input`, undoing `brewfile_quote`.
+/// Ruby single-quoted strings (`'jq'`) are accepted too; in those only `\'` and `\\`
+/// are escapes. Returns `None` if there is no opening quote or the string is
+/// unterminated.
+fn brewfile_unquote(input: &str) -> Option<String> {
+    if let Some(rest) = input.strip_prefix('\'') {
+        let mut chars = rest.chars();
+        let mut name = String::new();
+        loop {
+            match chars.next()? {
+                '\'' => return Some(name),
+                '\\' => match chars.next()? {
+                    c @ ('\'' | '\\') => name.push(c),
+                    c => {
+                        name.push('\\');
+                        name.push(c);
                     }
-                }
-                Ok(None)
+                },
+                c => name.push(c),
             }
-            _ => Ok(None),
         }
     }
+    let mut chars = input.strip_prefix('"')?.chars();
+    let mut name = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(name),
+            '\\' => match chars.next()? {
+                'n' => name.push('\n'),
+                'r' => name.push('\r'),
+                't' => name.push('\t'),
+                'u' => {
+                    let (hex, rest) = chars.as_str().strip_prefix('{')?.split_once('}')?;
+                    name.push(char::from_u32(u32::from_str_radix(hex, 16).ok()?)?);
+                    chars = rest.chars();
+                }
+                c => name.push(c),
+            },
+            c => name.push(c),
+        }
+    }
+}
 
-    /// Export Homebrew packages to a Brewfile-compatible format for zerobrew
-    pub fn export_to_brewfile(&self, path: &PathBuf) -> Result<()> {
-        let formulae = self.list_installed_formulae()?;
-        let casks = self.list_installed_casks()?;
+/// Render a fresh Brewfile for the given formulae and casks
+pub fn render_brewfile(formulae: &[BrewPackage], casks: &[BrewPackage]) -> String {
+    let mut content = String::new();
+    content.push_str("# Zerobrew Migration Brewfile\n");
+    content.push_str("# Generated from Homebrew installation\n\n");
 
-        let mut content = String::new();
-        content.push_str("# Zerobrew Migration Brewfile\n");
-        content.push_str("# Generated from Homebrew installation\n\n");
+    for tap in distinct_taps(formulae) {
+        content.push_str(&format!("tap {}\n", brewfile_quote(&tap)));
+    }
+    content.push('\n');
 
-        // Collect taps
-        let mut taps: std::collections::HashSet<String> = std::collections::HashSet::new();
-        for pkg in &formulae {
-            if let Some(ref tap) = pkg.tap {
-                taps.insert(tap.clone());
-            }
-        }
+    // Formulae
+    for pkg in formulae {
+        content.push_str(&format!("brew {}\n", brewfile_quote(&pkg.name)));
+    }
+    content.push('\n');
 
-        for tap in &taps {
-            content.push_str(&format!("tap \"{}\"\n", tap));
-        }
-        content.push('\n');
+    // Casks
+    for pkg in casks {
+        content.push_str(&format!("cask {}\n", brewfile_quote(&pkg.name)));
+    }
 
-        // Formulae
-        for pkg in &formulae {
-            content.push_str(&format!("brew \"{}\"\n", pkg.name));
-        }
-        content.push('\n');
+    content
+}
 
-        // Casks
-        for pkg in &casks {
-            content.push_str(&format!("cask \"{}\"\n", pkg.name));
+/// Parse a Brewfile line into its entry kind and quoted name, e.g. `("brew", "git")`
+fn parse_brewfile_entry(line: &str) -> Option<(&str, String)> {
+    let line = line.trim();
+    let (kind, rest) = line.split_once(char::is_whitespace)?;
+    let name = brewfile_unquote(rest.trim())?;
+    (!name.is_empty()).then_some((kind, name))
+}
+
+/// Merge installed packages into an existing Brewfile.
+///
+/// Existing lines (including comments and ordering) are kept as-is. New entries are
+/// inserted after the last existing entry of the same kind, or appended at the end.
+/// Returns the merged content and the number of entries added.
+pub fn merge_brewfile(
+    existing: &str,
+    formulae: &[BrewPackage],
+    casks: &[BrewPackage],
+) -> (String, usize) {
+    // `brew "user/tap/name"` and `brew "name"` are the same formula
+    let key = |kind: &str, name: &str| -> (String, String) {
+        let name = match kind {
+            "tap" => name,
+            _ => name.rsplit('/').next().unwrap_or(name),
+        };
+        (kind.to_string(), name.to_string())
+    };
+    let mut lines: Vec<String> = existing.lines().map(|l| l.to_string()).collect();
+    let listed: HashSet<(String, String)> = lines
+        .iter()
+        .filter_map(|l| parse_brewfile_entry(l))
+        .map(|(kind, name)| key(kind, &name))
+        .collect();
+
+    let taps: BTreeSet<&str> = formulae.iter().filter_map(|p| p.tap.as_deref()).collect();
+    let candidates: [(&str, Vec<&str>); 3] = [
+        ("tap", taps.into_iter().collect()),
+        ("brew", formulae.iter().map(|p| p.name.as_str()).collect()),
+        ("cask", casks.iter().map(|p| p.name.as_str()).collect()),
+    ];
+
+    let mut added = 0;
+    for (kind, names) in candidates {
+        let new_entries: Vec<String> = names
+            .into_iter()
+            .filter(|name| !listed.contains(&key(kind, name)))
+            .map(|name| format!("{} {}", kind, brewfile_quote(name)))
+            .collect();
+        if new_entries.is_empty() {
+            continue;
         }
 
-        fs::write(path, content)?;
-        Ok(())
+        let insert_at = lines
+            .iter()
+            .rposition(|l| parse_brewfile_entry(l).is_some_and(|(k, _)| k == kind))
+            .map(|i| i + 1)
+            .unwrap_or(lines.len());
+        added += new_entries.len();
+        lines.splice(insert_at..insert_at, new_entries);
     }
 
-    /// Migrate a single package from Homebrew to Zerobrew
-    pub fn migrate_package(&self, package: &BrewPackage) -> Result<MigrateResult> {
-        println!("Migrating: {} ({})", package.name, package.version);
+    let mut content = lines.join("\n");
+    content.push('\n');
+    (content, added)
+}
 
-        let start = Instant::now();
-        if self.verbose {
-            eprintln!("[verbose] Running: zb install {}", package.name);
-        }
+/// Read and parse a Brewfile on disk
+pub fn load_brewfile(path: &Path) -> Result<Brewfile> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read Brewfile at {}", path.display()))?;
+    Ok(parse_brewfile_entries(&content))
+}
 
-        // Step 1: Install via zerobrew (it will use cache if available)
-        let zb_result = Command::new("zb").args(["install", &package.name]).output();
+/// Format version written to `migration_state.json`; bump it when a change would
+/// make older zb-migrate versions misread the file
+pub const STATE_FORMAT_VERSION: u32 = 2;
 
-        let elapsed = start.elapsed();
+/// Represents the migration state
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MigrationState {
+    /// State file format, 0 for files written before the field existed
+    #[serde(default)]
+    pub format_version: u32,
+    pub migrated_packages: HashMap<String, BrewPackage>,
+    /// Packages whose last migration attempt failed, with the failure reason
+    /// (empty for failures recorded before format version 2)
+    #[serde(deserialize_with = "deserialize_failed_packages")]
+    pub failed_packages: Vec<(String, String)>,
+    pub homebrew_prefix: PathBuf,
+    /// zerobrew version used by the most recent migration run
+    #[serde(default)]
+    pub zerobrew_version: Option<String>,
+    /// Per-package migration details, keyed by package name
+    #[serde(default)]
+    pub migration_records: HashMap<String, MigrationRecord>,
+    /// Packages skipped in earlier runs, with the most recent reason
+    #[serde(default)]
+    pub skipped_packages: Vec<(String, String)>,
+}
 
-        match zb_result {
-            Ok(output) if output.status.success() => {
-                if self.verbose {
-                    eprintln!("[verbose] Command completed in {:.2?}", elapsed);
-                    eprintln!("[verbose] Exit code: {}", output.status);
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    if !stdout.trim().is_empty() {
-                        eprintln!("[verbose] stdout: {}", stdout.trim());
-                    }
-                    if !stderr.trim().is_empty() {
-                        eprintln!("[verbose] stderr: {}", stderr.trim());
-                    }
-                }
-                // Step 2: Optionally uninstall from Homebrew to free space
-                // (We don't do this automatically - user should confirm)
-                Ok(MigrateResult::Success {
-                    name: package.name.clone(),
-                    version: package.version.clone(),
-                })
-            }
-            Ok(output) => {
-                if self.verbose {
-                    eprintln!("[verbose] Command completed in {:.2?}", elapsed);
-                    eprintln!("[verbose] Exit code: {}", output.status);
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    if !stdout.trim().is_empty() {
-                        eprintln!("[verbose] stdout: {}", stdout.trim());
-                    }
-                }
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                if self.verbose {
-                    eprintln!("[verbose] stderr: {}", stderr.trim());
-                }
-                Ok(MigrateResult::Failed {
-                    name: package.name.clone(),
-                    reason: stderr.to_string(),
-                })
-            }
-            Err(e) => {
-                if self.verbose {
-                    eprintln!("[verbose] Command failed after {:.2?}: {}", elapsed, e);
-                }
-                Ok(MigrateResult::Failed {
-                    name: package.name.clone(),
-                    reason: format!("Failed to run zb: {}", e),
-                })
-            }
+impl Default for MigrationState {
+    fn default() -> Self {
+        Self {
+            format_version: STATE_FORMAT_VERSION,
+            migrated_packages: HashMap::new(),
+            failed_packages: Vec::new(),
+            homebrew_prefix: PathBuf::new(),
+            zerobrew_version: None,
+            migration_records: HashMap::new(),
+            skipped_packages: Vec::new(),
         }
     }
+}
 
-    /// Migrate all packages from Homebrew to Zerobrew
-    pub fn migrate_all(&self, dry_run: bool) -> Result<MigrationReport> {
-        let casks = self.list_installed_casks()?;
+/// Read `failed_packages` as `[name, reason]` pairs, accepting the bare names
+/// written before format version 2
+fn deserialize_failed_packages<'de, D>(deserializer: D) -> Result<Vec<(String, String)>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum FailedPackage {
+        Name(String),
+        WithReason(String, String),
+    }
 
-        // Use fast version for dry-run, detailed version for actual migration
-        let formulae = if dry_run {
-            self.list_installed_formulae()?
-        } else {
-            self.list_installed_formulae_detailed()?
-        };
+    Ok(Vec::<FailedPackage>::deserialize(deserializer)?
+        .into_iter()
+        .map(|entry| match entry {
+            FailedPackage::Name(name) => (name, String::new()),
+            FailedPackage::WithReason(name, reason) => (name, reason),
+        })
+        .collect())
+}
 
-        let mut report = MigrationReport {
-            total_formulae: formulae.len(),
-            total_casks: casks.len(),
-            ..Default::default()
-        };
+/// Parse a migration state file's contents. An empty file (e.g. from an interrupted
+/// write) counts as no state; files from a newer, incompatible format are rejected.
+pub fn parse_state(content: &str, path: &Path) -> Result<MigrationState> {
+    if content.trim().is_empty() {
+        return Ok(MigrationState::default());
+    }
+    state_from_json(content, path, || {
+        format!(
+            "Migration state file {} is corrupt or was only partially written.\n\n\
+             Suggestions:\n\
+             - Restore a backup: zb-migrate restore-state --list\n\
+             - Move the file aside and re-run 'zb-migrate migrate' to rebuild it; packages\n  \
+             already in zerobrew are recorded as migrated without reinstalling\n\
+             - Pass --backup-state so future changes keep a backup",
+            path.display()
+        )
+    })
+}
 
-        if dry_run {
-            println!("\n=== DRY RUN - No changes will be made ===\n");
-            println!(
-                "Found {} formulae and {} casks to migrate:\n",
-                formulae.len(),
-                casks.len()
-            );
+/// Parse state JSON, upgrading older formats to the current one. The format version is
+/// checked before the rest, so state from a newer release isn't mistaken for corruption.
+fn state_from_json(
+    content: &str,
+    path: &Path,
+    invalid: impl Fn() -> String,
+) -> Result<MigrationState> {
+    let value: serde_json::Value = serde_json::from_str(content).with_context(&invalid)?;
+    let format_version = value["format_version"].as_u64().unwrap_or(0);
+    if format_version > u64::from(STATE_FORMAT_VERSION) {
+        bail!(
+            "Migration state file {} uses format version {}, but this zb-migrate only \
+             understands up to version {}.\n\n\
+             Suggestions:\n\
+             - Upgrade zb-migrate to the version that wrote the file\n\
+             - Restore an older backup: zb-migrate restore-state --list",
+            path.display(),
+            format_version,
+            STATE_FORMAT_VERSION
+        );
+    }
+    let mut state: MigrationState = serde_json::from_value(value).with_context(invalid)?;
+    state.format_version = STATE_FORMAT_VERSION;
+    Ok(state)
+}
 
-            for pkg in &formulae {
-                println!("  [formula] {} @ {}", pkg.name, pkg.version);
-            }
-            for pkg in &casks {
-                println!("  [cask] {} @ {}", pkg.name, pkg.version);
-            }
-            return Ok(report);
-        }
+/// Options controlling a bulk or interactive migration run
+#[derive(Debug, Clone, Default)]
+pub struct MigrateOptions {
+    /// Show what would be migrated without making changes
+    pub dry_run: bool,
+    /// With `dry_run`, load dependencies so the plan shows the exact install order
+    pub exact_order: bool,
+    /// Only migrate formulae installed within this duration
+    pub since: Option<Duration>,
+    /// Leave formulae from these taps (`user/repo`) in Homebrew
+    pub exclude_taps: Vec<String>,
+    /// Reinstall formulae already recorded as migrated or present in zerobrew
+    pub force: bool,
+    /// Only migrate formulae installed on request, leaving their dependencies to zerobrew
+    pub requested_only: bool,
+    /// Stop at the first failed package instead of continuing
+    pub fail_fast: bool,
+    /// Stop once this many packages have failed
+    pub max_failures: Option<usize>,
+    /// Keep install logs for successful packages too (failures are always logged)
+    pub keep_logs: bool,
+    /// Ask zerobrew for the Homebrew version (`zb install name@version`), falling back
+    /// to the latest version when that one isn't available
+    pub pin_versions: bool,
+    /// Build these packages from source; an empty list means every package
+    pub build_from_source: Option<Vec<String>>,
+    /// Install these packages from bottles only; an empty list means every package
+    pub force_bottle: Option<Vec<String>>,
+    /// Set (e.g. by a signal handler) to stop before the next package
+    pub cancel: Option<&'static AtomicBool>,
+    /// Extra ordering edges from `--after` and `--before`, for dependencies Homebrew
+    /// doesn't declare
+    pub order_constraints: Vec<OrderConstraint>,
+    /// Command recorded in the run history, e.g. `retry-failed`; `migrate` when unset
+    pub history_action: Option<&'static str>,
+}
 
-        // Migrate formulae (respect dependency order)
-        let sorted = self.topological_sort(&formulae)?;
-        for pkg in sorted {
-            match self.migrate_package(&pkg)? {
-                MigrateResult::Success { name, .. } => {
-                    report.successful.push(name);
-                }
-                MigrateResult::Failed { name, reason } => {
-                    report.failed.push((name, reason));
-                }
-            }
-        }
+/// One package that must be migrated after another, on top of the declared dependencies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderConstraint {
+    /// Migrated first
+    pub first: String,
+    /// Migrated after `first`
+    pub then: String,
+}
 
-        // Note: Casks are currently not supported by zerobrew
-        for pkg in &casks {
-            report
-                .skipped
-                .push((pkg.name.clone(), "Casks not yet supported".to_string()));
+impl OrderConstraint {
+    /// Parse `--after PKG:OTHER`: PKG is migrated after OTHER
+    pub fn parse_after(input: &str) -> Result<Self> {
+        let (pkg, other) = split_order_pair(input)?;
+        Ok(Self {
+            first: other,
+            then: pkg,
+        })
+    }
+
+    /// Parse `--before PKG:OTHER`: PKG is migrated before OTHER
+    pub fn parse_before(input: &str) -> Result<Self> {
+        let (pkg, other) = split_order_pair(input)?;
+        Ok(Self {
+            first: pkg,
+            then: other,
+        })
+    }
+}
+
+impl std::fmt::Display for OrderConstraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} after {}", self.then, self.first)
+    }
+}
+
+fn split_order_pair(input: &str) -> Result<(String, String)> {
+    match input.split_once(':').map(|(a, b)| (a.trim(), b.trim())) {
+        Some((a, b)) if !a.is_empty() && !b.is_empty() && a != b => {
+            Ok((a.to_string(), b.to_string()))
         }
+        _ => bail!(
+            "Invalid ordering constraint '{}': expected two different package names as \
+             PKG:OTHER (e.g. git:curl)",
+            input
+        ),
+    }
+}
 
-        // Save migration state
-        let mut state = self.load_state().unwrap_or_default();
-        state.homebrew_prefix = self.homebrew_prefix.clone();
-        for name in &report.successful {
-            if let Some(pkg) = formulae.iter().find(|p| &p.name == name) {
-                state.migrated_packages.insert(name.clone(), pkg.clone());
+/// Check that every package named by an ordering constraint is installed
+pub fn validate_order_constraints(
+    constraints: &[OrderConstraint],
+    installed: &[BrewPackage],
+) -> Result<()> {
+    for constraint in constraints {
+        for name in [&constraint.first, &constraint.then] {
+            if !installed.iter().any(|p| &p.name == name) {
+                bail!(
+                    "Unknown package '{}' in ordering constraint '{}': it is not an installed \
+                     Homebrew formula.\n\n\
+                     Suggestions:\n\
+                     - Run 'zb-migrate list' to see installed formulae\n\
+                     - Check the spelling of --after / --before",
+                    name,
+                    constraint
+                );
             }
         }
-        for (name, _) in &report.failed {
-            state.failed_packages.push(name.clone());
-        }
-        self.save_state(&state)?;
-
-        Ok(report)
     }
+    Ok(())
+}
 
-    /// Interactive migration mode - prompts user before each package
-    pub fn migrate_interactive(&self) -> Result<MigrationReport> {
-        let casks = self.list_installed_casks()?;
-        let formulae = self.list_installed_formulae_detailed()?;
-
-        let mut report = MigrationReport {
-            total_formulae: formulae.len(),
-            total_casks: casks.len(),
-            ..Default::default()
-        };
+/// How zerobrew is asked to install a package
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InstallMode {
+    /// Whatever zerobrew does by default
+    #[default]
+    Default,
+    /// `zb install --build-from-source`
+    BuildFromSource,
+    /// `zb install --force-bottle`
+    ForceBottle,
+}
 
-        // Check if we're in a TTY environment
-        let is_tty = std::io::stdin().is_terminal();
-        if !is_tty {
-            println!("Non-interactive environment detected. Falling back to non-interactive mode.");
-            return self.migrate_all(false);
+impl InstallMode {
+    /// The `zb install` flag selecting this mode, if any
+    pub fn zb_flag(self) -> Option<&'static str> {
+        match self {
+            InstallMode::Default => None,
+            InstallMode::BuildFromSource => Some("--build-from-source"),
+            InstallMode::ForceBottle => Some("--force-bottle"),
         }
+    }
+}
 
-        println!("\n=== Interactive Migration Mode ===\n");
-        println!("Found {} formulae to migrate.\n", formulae.len());
-        println!("Options for each package:");
-        println!("  (y)es     - Migrate this package");
-        println!("  (n)o      - Skip this package");
-        println!("  (a)ll yes - Migrate all remaining packages");
-        println!("  (q)uit    - Stop migration\n");
-
-        let sorted = self.topological_sort(&formulae)?;
-        let mut migrate_all_remaining = false;
+impl std::fmt::Display for InstallMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            InstallMode::Default => "default",
+            InstallMode::BuildFromSource => "built from source",
+            InstallMode::ForceBottle => "bottle",
+        })
+    }
+}
 
-        for (idx, pkg) in sorted.iter().enumerate() {
-            // Show package info
-            println!(
-                "{}",
-                style(format!("--- Package {}/{} ---", idx + 1, sorted.len())).bold()
-            );
-            println!("  Name:    {}", style(&pkg.name).cyan());
-            println!("  Version: {}", pkg.version);
-            if let Some(ref tap) = pkg.tap {
-                println!("  Tap:     {}", tap);
-            }
-            if !pkg.dependencies.is_empty() {
-                println!("  Deps:    {}", pkg.dependencies.join(", "));
-            }
-            if pkg.pinned {
-                println!("  Status:  {}", style("[pinned]").yellow());
-            }
-            println!();
+impl MigrateOptions {
+    /// Whether the run has been asked to stop
+    pub fn cancelled(&self) -> bool {
+        self.cancel.is_some_and(|flag| flag.load(Ordering::SeqCst))
+    }
 
-            let should_migrate = if migrate_all_remaining {
-                println!("  Auto-migrating (all yes mode)...");
-                true
-            } else {
-                // Show interactive prompt
-                let items = vec![
-                    "(y)es - Migrate this package",
-                    "(n)o - Skip this package",
-                    "(a)ll yes - Migrate all remaining",
-                    "(q)uit - Stop migration",
-                ];
-
-                let selection = Select::with_theme(&ColorfulTheme::default())
-                    .with_prompt("What would you like to do?")
-                    .items(&items)
-                    .default(0)
-                    .interact();
-
-                match selection {
-                    Ok(0) => true, // Yes
-                    Ok(1) => {
-                        // No/Skip
-                        report
-                            .skipped
-                            .push((pkg.name.clone(), "User skipped".to_string()));
-                        println!("  {} Skipped\n", style("->").yellow());
-                        continue;
-                    }
-                    Ok(2) => {
-                        // All yes
-                        migrate_all_remaining = true;
-                        true
-                    }
-                    Ok(3) | Err(_) => {
-                        // Quit
-                        println!("\n{}", style("Migration stopped by user.").yellow());
-                        break;
-                    }
-                    _ => continue,
-                }
-            };
+    /// Whether `failures` failed packages reach the `max_failures` threshold
+    pub fn failure_limit_reached(&self, failures: usize) -> bool {
+        self.max_failures.is_some_and(|max| failures >= max)
+    }
 
-            if should_migrate {
-                match self.migrate_package(pkg)? {
-                    MigrateResult::Success { name, version } => {
-                        println!(
-                            "  {} Migrated: {} @ {}\n",
-                            style("OK").green(),
-                            name,
-                            version
-                        );
-                        report.successful.push(name);
-                    }
-                    MigrateResult::Failed { name, reason } => {
-                        println!("  {} Failed: {} - {}\n", style("X").red(), name, reason);
-                        report.failed.push((name, reason));
-                    }
-                }
-            }
+    /// The install mode for a package. A package named in one of the lists wins over
+    /// a flag given without a list, so `--force-bottle --build-from-source=llvm`
+    /// builds only llvm from source.
+    pub fn install_mode(&self, name: &str) -> InstallMode {
+        let names =
+            |list: &Option<Vec<String>>| list.as_ref().is_some_and(|l| l.iter().any(|n| n == name));
+        let all = |list: &Option<Vec<String>>| list.as_ref().is_some_and(Vec::is_empty);
+        if names(&self.build_from_source) {
+            InstallMode::BuildFromSource
+        } else if names(&self.force_bottle) {
+            InstallMode::ForceBottle
+        } else if all(&self.build_from_source) {
+            InstallMode::BuildFromSource
+        } else if all(&self.force_bottle) {
+            InstallMode::ForceBottle
+        } else {
+            InstallMode::Default
         }
+    }
 
-        // Handle casks
-        for pkg in &casks {
-            report
-                .skipped
-                .push((pkg.name.clone(), "Casks not yet supported".to_string()));
+    /// Reject install mode selections that contradict each other
+    pub fn validate_install_modes(&self) -> Result<()> {
+        let (Some(source), Some(bottle)) = (&self.build_from_source, &self.force_bottle) else {
+            return Ok(());
+        };
+        if source.is_empty() && bottle.is_empty() {
+            bail!(
+                "--build-from-source and --force-bottle can't both apply to every package\n\n\
+                 Suggestions:\n\
+                 - Name the packages for one of them, e.g. --build-from-source=llvm"
+            );
         }
+        if let Some(name) = source.iter().find(|n| bottle.contains(n)) {
+            bail!(
+                "{} is listed for both --build-from-source and --force-bottle\n\n\
+                 Suggestions:\n\
+                 - Remove it from one of the lists",
+                name
+            );
+        }
+        Ok(())
+    }
 
-        // Save migration state
-        let mut state = self.load_state().unwrap_or_default();
-        state.homebrew_prefix = self.homebrew_prefix.clone();
-        for name in &report.successful {
-            if let Some(pkg) = formulae.iter().find(|p| &p.name == name) {
-                state.migrated_packages.insert(name.clone(), pkg.clone());
-            }
+    /// The non-default install modes this run can use
+    pub fn requested_install_modes(&self) -> Vec<InstallMode> {
+        let mut modes = Vec::new();
+        if self.build_from_source.is_some() {
+            modes.push(InstallMode::BuildFromSource);
         }
-        for (name, _) in &report.failed {
-            state.failed_packages.push(name.clone());
+        if self.force_bottle.is_some() {
+            modes.push(InstallMode::ForceBottle);
         }
-        self.save_state(&state)?;
+        modes
+    }
+}
 
-        Ok(report)
+/// Ordered set of packages to migrate for `migrate --packages ... --with-deps`
+#[derive(Debug, Default)]
+pub struct DependencyPlan {
+    /// Packages to migrate, dependencies first
+    pub to_migrate: Vec<BrewPackage>,
+    /// Dependencies left in Homebrew, with the reason
+    pub kept_in_homebrew: Vec<(String, String)>,
+}
+
+/// Environment variable that relocates the zerobrew config directory
+pub const ZEROBREW_HOME_ENV: &str = "ZEROBREW_HOME";
+
+/// Error context for a failed write to `path` under the zerobrew home, with the
+/// usual cause and fix
+fn unwritable_home_error(failure: &str, path: &Path) -> String {
+    format!(
+        "{} {}.\n\
+         The location may be read-only (common in sandboxes and CI).\n\
+         Suggestion: set {} to a writable directory.",
+        failure,
+        path.display(),
+        ZEROBREW_HOME_ENV
+    )
+}
+
+/// Resolve the directory holding migration state and logs: `$ZEROBREW_HOME`
+/// when set, otherwise `$HOME/.zerobrew`
+pub fn resolve_zerobrew_home(
+    zerobrew_home: Option<String>,
+    home: Option<String>,
+) -> Result<PathBuf> {
+    if let Some(dir) = zerobrew_home.filter(|d| !d.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    match home.filter(|h| !h.is_empty()) {
+        Some(home) => Ok(PathBuf::from(home).join(".zerobrew")),
+        None => bail!(
+            "HOME environment variable is not set.\n\
+             This is required to locate the zerobrew configuration directory.\n\
+             Suggestion: Ensure you are running in a proper shell environment,\n\
+             or set ZEROBREW_HOME to a writable directory."
+        ),
     }
+}
 
-    /// Topological sort for dependency order
-    fn topological_sort(&self, packages: &[BrewPackage]) -> Result<Vec<BrewPackage>> {
-        let mut result = Vec::new();
-        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
-        let pkg_map: HashMap<String, &BrewPackage> =
-            packages.iter().map(|p| (p.name.clone(), p)).collect();
+/// Whether two install prefixes are the same directory or one is nested in the other.
+/// Existing paths are canonicalized first so symlinked prefixes are caught too.
+pub fn prefixes_overlap(a: &Path, b: &Path) -> bool {
+    let a = fs::canonicalize(a).unwrap_or_else(|_| a.to_path_buf());
+    let b = fs::canonicalize(b).unwrap_or_else(|_| b.to_path_buf());
+    a.starts_with(&b) || b.starts_with(&a)
+}
 
-        fn visit(
-            name: &str,
-            pkg_map: &HashMap<String, &BrewPackage>,
-            visited: &mut std::collections::HashSet<String>,
-            result: &mut Vec<BrewPackage>,
-        ) {
-            if visited.contains(name) {
-                return;
-            }
-            visited.insert(name.to_string());
+/// Where zerobrew's bin directory sits in `PATH` relative to Homebrew's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathOrder {
+    /// zerobrew's bin comes first (or Homebrew's isn't on `PATH` at all)
+    ZerobrewFirst,
+    /// Homebrew's bin comes first, so its binaries shadow migrated ones
+    HomebrewFirst,
+    /// zerobrew's bin isn't on `PATH`
+    ZerobrewMissing,
+}
 
-            if let Some(pkg) = pkg_map.get(name) {
-                for dep in &pkg.dependencies {
-                    visit(dep, pkg_map, visited, result);
-                }
-                result.push((*pkg).clone());
-            }
+/// Compare the positions of the Homebrew and zerobrew bin directories in a `PATH` value
+pub fn check_path_order(path: &OsStr, homebrew_bin: &Path, zerobrew_bin: &Path) -> PathOrder {
+    let same_dir = |entry: &Path, dir: &Path| {
+        entry == dir
+            || matches!(
+                (fs::canonicalize(entry), fs::canonicalize(dir)),
+                (Ok(a), Ok(b)) if a == b
+            )
+    };
+    for entry in std::env::split_paths(path) {
+        if same_dir(&entry, zerobrew_bin) {
+            return PathOrder::ZerobrewFirst;
         }
-
-        for pkg in packages {
-            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
+        if same_dir(&entry, homebrew_bin) {
+            return if std::env::split_paths(path).any(|e| same_dir(&e, zerobrew_bin)) {
+                PathOrder::HomebrewFirst
+            } else {
+                PathOrder::ZerobrewMissing
+            };
         }
-
-        Ok(result)
     }
+    PathOrder::ZerobrewMissing
+}
 
-    /// Cleanup: Optionally remove Homebrew packages after migration
-    pub fn cleanup_homebrew(&self, packages: &[String], force: bool) -> Result<()> {
-        if !force {
-            println!("WARNING: This will uninstall packages from Homebrew.");
-            println!("Make sure zerobrew has successfully installed them first.");
-            println!("Run with --force to proceed.");
-            return Ok(());
+/// The startup file and line that put `zerobrew_bin` at the front of `PATH` for `shell`
+/// (the value of `$SHELL`). Unknown shells get a POSIX `~/.profile` edit.
+pub fn path_fix_suggestion(shell: Option<&str>, zerobrew_bin: &Path) -> (&'static str, String) {
+    let name = shell
+        .and_then(|s| Path::new(s).file_name())
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    let bin = zerobrew_bin.display();
+    match name {
+        "fish" => (
+            "~/.config/fish/config.fish",
+            format!("fish_add_path --move {bin}"),
+        ),
+        "zsh" => ("~/.zshrc", format!("export PATH=\"{bin}:$PATH\"")),
+        "bash" if cfg!(target_os = "macos") => {
+            ("~/.bash_profile", format!("export PATH=\"{bin}:$PATH\""))
         }
+        "bash" => ("~/.bashrc", format!("export PATH=\"{bin}:$PATH\"")),
+        _ => ("~/.profile", format!("export PATH=\"{bin}:$PATH\"")),
+    }
+}
 
-        for name in packages {
-            println!("Removing from Homebrew: {}", name);
-            let _ = Command::new("brew")
-                .args(["uninstall", "--ignore-dependencies", name])
-                .status();
-        }
+/// File in the zerobrew config directory holding default command-line options
+pub const CONFIG_FILE_NAME: &str = "zb-migrate.toml";
 
-        Ok(())
+/// A value in the config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigValue {
+    Bool(bool),
+    Integer(i64),
+    String(String),
+    Array(Vec<String>),
+}
+
+impl TryFrom<toml::Value> for ConfigValue {
+    type Error = String;
+
+    fn try_from(value: toml::Value) -> Result<Self, String> {
+        let expected = "expected true/false, a number, a \"string\" or an array of those";
+        match value {
+            toml::Value::Boolean(b) => Ok(ConfigValue::Bool(b)),
+            toml::Value::Integer(n) => Ok(ConfigValue::Integer(n)),
+            toml::Value::String(s) => Ok(ConfigValue::String(s)),
+            toml::Value::Array(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    toml::Value::String(s) => Ok(s),
+                    toml::Value::Integer(n) => Ok(n.to_string()),
+                    _ => Err("array items must be strings or integers".to_string()),
+                })
+                .collect::<Result<_, _>>()
+                .map(ConfigValue::Array),
+            _ => Err(expected.to_string()),
+        }
     }
+}
 
-    /// Save migration state
-    pub fn save_state(&self, state: &MigrationState) -> Result<()> {
-        let json = serde_json::to_string_pretty(state)?;
-        if let Some(parent) = self.state_file.parent() {
-            fs::create_dir_all(parent)?;
+/// Default options from `zb-migrate.toml`. Top-level keys apply to global options and
+/// `[command]` tables to that subcommand; keys are long flag names such as `fail-fast`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "toml::Table")]
+pub struct CliConfig {
+    pub global: BTreeMap<String, ConfigValue>,
+    pub commands: BTreeMap<String, BTreeMap<String, ConfigValue>>,
+}
+
+impl CliConfig {
+    /// Options for a subcommand, or the global options with `None`
+    pub fn options(&self, command: Option<&str>) -> impl Iterator<Item = (&String, &ConfigValue)> {
+        match command {
+            None => Some(&self.global),
+            Some(name) => self.commands.get(name),
         }
-        fs::write(&self.state_file, json)?;
-        Ok(())
+        .into_iter()
+        .flatten()
     }
+}
 
-    /// Load migration state
-    pub fn load_state(&self) -> Result<MigrationState> {
-        if self.state_file.exists() {
-            let content = fs::read_to_string(&self.state_file)?;
-            Ok(serde_json::from_str(&content)?)
-        } else {
-            Ok(MigrationState::default())
+impl TryFrom<toml::Table> for CliConfig {
+    type Error = String;
+
+    fn try_from(table: toml::Table) -> Result<Self, String> {
+        let mut config = CliConfig::default();
+        for (key, value) in table {
+            match value {
+                toml::Value::Table(options) => {
+                    let options = config_options(options, &format!(" in [{}]", key))?;
+                    config.commands.insert(key, options);
+                }
+                value => insert_config_option(&mut config.global, key, value, "")?,
+            }
         }
+        Ok(config)
     }
+}
 
-    /// Analyze all installed packages and categorize them by migration risk.
-    ///
-    /// This method:
-    /// 1. Lists all installed Homebrew packages
-    /// 2. Checks which ones are in the known problematic list
-    /// 3. Analyzes dependency chains to find packages that depend on problematic ones
-    /// 4. Returns a categorized report with recommendations
-    pub fn analyze_packages(&self) -> Result<AnalysisReport> {
-        println!("Analyzing installed packages...");
+fn config_options(
+    table: toml::Table,
+    section: &str,
+) -> Result<BTreeMap<String, ConfigValue>, String> {
+    let mut options = BTreeMap::new();
+    for (key, value) in table {
+        insert_config_option(&mut options, key, value, section)?;
+    }
+    Ok(options)
+}
 
-        // Get all installed packages with their dependencies
-        let packages = self.list_installed_formulae_detailed()?;
-        let total = packages.len();
+/// Add one option, accepting `_` in keys in place of `-`
+fn insert_config_option(
+    options: &mut BTreeMap<String, ConfigValue>,
+    key: String,
+    value: toml::Value,
+    section: &str,
+) -> Result<(), String> {
+    let value = ConfigValue::try_from(value)
+        .map_err(|e| format!("invalid value for `{}`{}: {}", key, section, e))?;
+    let key = key.replace('_', "-");
+    if options.insert(key.clone(), value).is_some() {
+        return Err(format!("`{}`{} is set more than once", key, section));
+    }
+    Ok(())
+}
 
-        // Build a set of problematic package names for quick lookup
-        let problematic_set: HashSet<&str> = KNOWN_PROBLEMATIC_PACKAGES.iter().copied().collect();
+/// Parse `zb-migrate.toml`
+pub fn parse_config(content: &str) -> Result<CliConfig> {
+    Ok(toml::from_str(content)?)
+}
 
-        // Build a map of package name -> package for dependency lookup
-        let pkg_map: HashMap<&str, &BrewPackage> =
-            packages.iter().map(|p| (p.name.as_str(), p)).collect();
+/// File in the zerobrew config directory that maps Homebrew names to zerobrew names
+pub const ALIASES_FILE_NAME: &str = "aliases.toml";
+
+/// Formulae installed under a name zerobrew doesn't use: old names Homebrew has
+/// since renamed, which stay on disk for packages installed before the rename
+pub const BUILTIN_ALIASES: &[(&str, &str)] =
+    &[("gnupg2", "gnupg"), ("mysql-connector-c", "mysql-client")];
+
+/// Parse `aliases.toml`: one `"brew-name" = "zerobrew-name"` pair per line.
+/// Keys are quoted when they contain characters like `@`.
+pub fn parse_aliases(content: &str) -> Result<BTreeMap<String, String>> {
+    let aliases: BTreeMap<String, String> = toml::from_str(content)?;
+    if let Some(key) = aliases
+        .keys()
+        .find(|key| key.is_empty() || key.contains(char::is_whitespace))
+    {
+        bail!("invalid formula name `{}`", key);
+    }
+    Ok(aliases)
+}
 
-        let mut report = AnalysisReport::new();
-        report.total_packages = total;
+fn builtin_aliases() -> BTreeMap<String, String> {
+    BUILTIN_ALIASES
+        .iter()
+        .map(|(brew, zb)| (brew.to_string(), zb.to_string()))
+        .collect()
+}
 
-        println!("Categorizing {} packages...", total);
+/// The built-in aliases overridden by the user's `aliases.toml`, if there is one
+pub fn load_aliases(path: &Path) -> Result<BTreeMap<String, String>> {
+    let mut aliases = builtin_aliases();
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(aliases),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    let overrides = parse_aliases(&content).with_context(|| {
+        format!(
+            "Invalid package aliases in {}.\n\n\
+             Suggestions:\n\
+             - Write one mapping per line, e.g. \"openssl@3\" = \"openssl\"\n\
+             - Quote names that contain characters like @",
+            path.display()
+        )
+    })?;
+    aliases.extend(overrides);
+    Ok(aliases)
+}
 
-        for pkg in &packages {
-            // Check if this package is itself problematic
-            if problematic_set.contains(pkg.name.as_str()) {
-                let reason = Self::get_problematic_reason(&pkg.name);
-                report.should_keep_in_homebrew.push(PackageAnalysis {
-                    name: pkg.name.clone(),
-                    version: pkg.version.clone(),
-                    risk: MigrationRisk::KeepInHomebrew,
-                    reason,
-                    problematic_dependencies: Vec::new(),
-                });
-                continue;
-            }
+/// Where the Homebrew installers put `brew`, tried when it isn't on PATH: Apple
+/// Silicon, Intel macOS and Linux
+pub const STANDARD_BREW_LOCATIONS: &[&str] = &[
+    "/opt/homebrew/bin/brew",
+    "/usr/local/bin/brew",
+    "/home/linuxbrew/.linuxbrew/bin/brew",
+];
 
-            // Check if this package depends on any problematic packages
-            let problematic_deps: Vec<String> = pkg
-                .dependencies
-                .iter()
-                .filter(|dep| problematic_set.contains(dep.as_str()))
-                .cloned()
-                .collect();
+/// Runs the `brew` and `zb` commands whose output the migrator parses.
+///
+/// The default [`SystemRunner`] spawns real processes; tests substitute a mock so no
+/// Homebrew or zerobrew installation is needed. Runners are shared between threads
+/// when formula details are loaded in parallel.
+pub trait CommandRunner: Send + Sync {
+    /// Run `program` with `args`, capturing its exit status, stdout and stderr
+    fn output(&self, program: &Path, args: &[&str]) -> std::io::Result<Output>;
+
+    /// Run `program` with `args` attached to the terminal, e.g. `brew uninstall`
+    fn status(&self, program: &Path, args: &[&str]) -> std::io::Result<ExitStatus> {
+        Command::new(program).args(args).status()
+    }
+}
 
-            if !problematic_deps.is_empty() {
-                report.risky.push(PackageAnalysis {
-                    name: pkg.name.clone(),
-                    version: pkg.version.clone(),
-                    risk: MigrationRisk::Risky,
-                    reason: format!(
-                        "Depends on {} problematic package(s)",
-                        problematic_deps.len()
-                    ),
-                    problematic_dependencies: problematic_deps,
-                });
-            } else {
-                // Check transitive dependencies (dependencies of dependencies)
-                let transitive_problematic =
-                    Self::find_transitive_problematic_deps(pkg, &pkg_map, &problematic_set);
+/// [`CommandRunner`] that spawns the program as a child process
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemRunner;
 
-                if !transitive_problematic.is_empty() {
-                    report.risky.push(PackageAnalysis {
-                        name: pkg.name.clone(),
-                        version: pkg.version.clone(),
-                        risk: MigrationRisk::Risky,
-                        reason: format!(
-                            "Has transitive dependency on {} problematic package(s)",
-                            transitive_problematic.len()
-                        ),
-                        problematic_dependencies: transitive_problematic,
-                    });
-                } else {
-                    // Safe to migrate
-                    report.safe_to_migrate.push(PackageAnalysis {
-                        name: pkg.name.clone(),
-                        version: pkg.version.clone(),
-                        risk: MigrationRisk::Safe,
-                        reason: "No known problematic dependencies".to_string(),
-                        problematic_dependencies: Vec::new(),
-                    });
-                }
-            }
-        }
+impl CommandRunner for SystemRunner {
+    fn output(&self, program: &Path, args: &[&str]) -> std::io::Result<Output> {
+        Command::new(program).args(args).output()
+    }
+}
 
-        // Sort each category alphabetically
-        report.safe_to_migrate.sort_by(|a, b| a.name.cmp(&b.name));
-        report.risky.sort_by(|a, b| a.name.cmp(&b.name));
-        report
-            .should_keep_in_homebrew
-            .sort_by(|a, b| a.name.cmp(&b.name));
+/// How long one `brew` or `zb` command took
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandTiming {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub elapsed: Duration,
+}
 
-        Ok(report)
+impl CommandTiming {
+    /// The command line, e.g. `brew deps --installed jq`
+    pub fn command_line(&self) -> String {
+        let program = self
+            .program
+            .file_name()
+            .unwrap_or(self.program.as_os_str())
+            .to_string_lossy();
+        std::iter::once(program.into_owned())
+            .chain(self.args.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Where a run spent its time, reported at the end of verbose runs
+#[derive(Debug, Clone, Default)]
+pub struct TimingSummary {
+    /// Every command run, in order
+    pub commands: Vec<CommandTiming>,
+    /// Time spent categorizing packages, outside any command
+    pub analysis: Duration,
+}
+
+impl TimingSummary {
+    /// Total time spent running `program`
+    pub fn total_for(&self, program: &Path) -> Duration {
+        self.commands
+            .iter()
+            .filter(|c| c.program == program)
+            .map(|c| c.elapsed)
+            .sum()
     }
 
-    /// Find transitive problematic dependencies (dependencies of dependencies)
-    fn find_transitive_problematic_deps(
-        pkg: &BrewPackage,
-        pkg_map: &HashMap<&str, &BrewPackage>,
-        problematic_set: &HashSet<&str>,
-    ) -> Vec<String> {
-        let mut visited: HashSet<String> = HashSet::new();
-        let mut problematic_found: Vec<String> = Vec::new();
+    /// The `limit` slowest commands, slowest first
+    pub fn slowest(&self, limit: usize) -> Vec<&CommandTiming> {
+        let mut commands: Vec<&CommandTiming> = self.commands.iter().collect();
+        commands.sort_by_key(|c| std::cmp::Reverse(c.elapsed));
+        commands.truncate(limit);
+        commands
+    }
+}
 
-        Self::find_transitive_deps_recursive(
-            pkg,
-            pkg_map,
-            problematic_set,
-            &mut visited,
-            &mut problematic_found,
-        );
+/// How long one `brew` command took over the runs of `benchmark_brew`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandBenchmark {
+    /// The command line, e.g. `brew deps --installed jq`
+    pub command: String,
+    /// Duration of every run, in order
+    pub runs: Vec<Duration>,
+}
 
-        problematic_found
+impl CommandBenchmark {
+    pub fn min(&self) -> Duration {
+        self.runs.iter().copied().min().unwrap_or_default()
     }
 
-    /// Recursive helper for finding transitive dependencies
-    fn find_transitive_deps_recursive(
-        pkg: &BrewPackage,
-        pkg_map: &HashMap<&str, &BrewPackage>,
-        problematic_set: &HashSet<&str>,
-        visited: &mut HashSet<String>,
-        found: &mut Vec<String>,
-    ) {
-        for dep_name in &pkg.dependencies {
-            if visited.contains(dep_name) {
-                continue;
-            }
-            visited.insert(dep_name.clone());
+    pub fn max(&self) -> Duration {
+        self.runs.iter().copied().max().unwrap_or_default()
+    }
 
-            // Check if this dependency is problematic
-            if problematic_set.contains(dep_name.as_str()) && !found.contains(dep_name) {
-                found.push(dep_name.clone());
-            }
+    /// The median run, the lower of the two middle runs for an even count
+    pub fn median(&self) -> Duration {
+        let mut runs = self.runs.clone();
+        runs.sort();
+        runs.get(runs.len().saturating_sub(1) / 2)
+            .copied()
+            .unwrap_or_default()
+    }
+}
 
-            // Recurse into this dependency's dependencies
-            if let Some(dep_pkg) = pkg_map.get(dep_name.as_str()) {
-                Self::find_transitive_deps_recursive(
-                    dep_pkg,
-                    pkg_map,
-                    problematic_set,
-                    visited,
-                    found,
-                );
-            }
+/// Timings of the `brew` commands `analyze` and `migrate` depend on
+#[derive(Debug, Clone)]
+pub struct BrewBenchmark {
+    /// Formula used for the per-package commands
+    pub sample: String,
+    /// Number of installed formulae
+    pub formula_count: usize,
+    /// `brew list`, `brew list --pinned`, `brew deps` and `brew info`, in that order
+    pub commands: Vec<CommandBenchmark>,
+}
+
+impl BrewBenchmark {
+    /// Rough time `analyze` spends in `brew`: the listing commands once, plus
+    /// `brew deps` and `brew info` for every installed formula
+    pub fn estimated_analysis(&self) -> Duration {
+        let per_package: Duration = self
+            .commands
+            .iter()
+            .filter(|c| c.command.starts_with("brew deps") || c.command.starts_with("brew info"))
+            .map(|c| c.median())
+            .sum();
+        let listing: Duration = self
+            .commands
+            .iter()
+            .filter(|c| c.command.starts_with("brew list"))
+            .map(|c| c.median())
+            .sum();
+        listing + per_package * self.formula_count as u32
+    }
+}
+
+/// [`CommandRunner`] that times every command another runner executes
+pub struct TimingRunner {
+    inner: Box<dyn CommandRunner>,
+    timings: Arc<Mutex<TimingSummary>>,
+}
+
+impl TimingRunner {
+    fn record(&self, program: &Path, args: &[&str], elapsed: Duration) {
+        if let Ok(mut timings) = self.timings.lock() {
+            timings.commands.push(CommandTiming {
+                program: program.to_path_buf(),
+                args: args.iter().map(|a| a.to_string()).collect(),
+                elapsed,
+            });
         }
     }
+}
 
-    /// Get a human-readable reason why a package is problematic
-    fn get_problematic_reason(name: &str) -> String {
-        match name {
-            // SSL/TLS
-            n if n.starts_with("openssl") => {
-                "Core SSL/TLS library - many packages link against it".to_string()
-            }
-            "gnutls" => "GNU TLS library - system-level security dependency".to_string(),
-            "libressl" => "LibreSSL - alternative SSL library with wide usage".to_string(),
-            n if n.starts_with("libssh") => {
-                "SSH library - security-critical dependency".to_string()
-            }
+impl CommandRunner for TimingRunner {
+    fn output(&self, program: &Path, args: &[&str]) -> std::io::Result<Output> {
+        let start = Instant::now();
+        let output = self.inner.output(program, args);
+        self.record(program, args, start.elapsed());
+        output
+    }
 
-            // Python
-            n if n.starts_with("python@") || n == "python" => {
-                "Python runtime - complex virtual environment and pip dependencies".to_string()
-            }
+    fn status(&self, program: &Path, args: &[&str]) -> std::io::Result<ExitStatus> {
+        let start = Instant::now();
+        let status = self.inner.status(program, args);
+        self.record(program, args, start.elapsed());
+        status
+    }
+}
 
-            // Event libraries
-            "libevent" => "Event notification library - used by many network tools".to_string(),
-            "libuv" => "Async I/O library - core dependency for Node.js ecosystem".to_string(),
-            "libev" => "Event loop library - embedded in many applications".to_string(),
+/// Build an [`ExitStatus`] for a process that exited with `code`
+pub fn exit_status(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw(code << 8)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(code as u32)
+    }
+}
 
-            // HTTP/networking
-            "nghttp2" => "HTTP/2 library - used by curl and many HTTP clients".to_string(),
-            "curl" => "URL transfer library - fundamental networking tool".to_string(),
-            "wget" => "Network downloader - may have complex SSL dependencies".to_string(),
+/// Installed Homebrew packages described in a `--mock-brew` fixture file
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BrewFixture {
+    /// What `brew --prefix` reports
+    #[serde(default = "default_fixture_prefix")]
+    pub prefix: PathBuf,
+    #[serde(default)]
+    pub formulae: Vec<FixturePackage>,
+    #[serde(default)]
+    pub casks: Vec<FixturePackage>,
+}
 
-            // GLib ecosystem
-            "gobject-introspection" => {
-                "GObject introspection - required for many GTK/GNOME tools".to_string()
-            }
-            "glib" => "GLib core library - foundation for GTK ecosystem".to_string(),
-            n if n.starts_with("gtk") || n == "cairo" || n == "pango" => {
-                "GTK/graphics library - complex native rendering dependencies".to_string()
-            }
-            "gdk-pixbuf" => "Image loading library - part of GTK stack".to_string(),
+fn default_fixture_prefix() -> PathBuf {
+    PathBuf::from("/opt/homebrew")
+}
 
-            // Node.js
-            n if n.starts_with("node") => {
-                "Node.js runtime - native modules require specific linking".to_string()
-            }
+/// One package in a [`BrewFixture`]; everything but the name and version is optional
+#[derive(Debug, Clone, Deserialize)]
+pub struct FixturePackage {
+    pub name: String,
+    pub version: String,
+    /// Tap the package comes from; `homebrew/core` when omitted
+    #[serde(default)]
+    pub tap: Option<String>,
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    #[serde(default)]
+    pub build_dependencies: Vec<String>,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default)]
+    pub keg_only: bool,
+    /// Report no bottle for any platform, as for a formula built from source
+    #[serde(default)]
+    pub no_bottle: bool,
+    /// Listed by `brew list --installed-on-request`; set to false for dependencies
+    #[serde(default = "default_true")]
+    pub installed_on_request: bool,
+}
 
-            // Databases
-            n if n.starts_with("postgresql") || n == "libpq" => {
-                "PostgreSQL client - complex library dependencies".to_string()
-            }
-            "mysql-client" => "MySQL client - database connectivity library".to_string(),
+/// [`CommandRunner`] that answers `brew` commands from a [`BrewFixture`] and runs
+/// everything else (i.e. `zb`) for real.
+///
+/// Backs the hidden `--mock-brew` option, so the analyze and migrate pipeline can run
+/// deterministically on machines without Homebrew. Commands that change Homebrew
+/// (`brew uninstall`, `brew services stop`) succeed without doing anything.
+#[derive(Debug)]
+pub struct MockBrewRunner {
+    fixture: BrewFixture,
+    brew_bin: PathBuf,
+}
 
-            // Compression
-            "zlib" => "Core compression library - nearly universal dependency".to_string(),
-            "xz" | "lz4" | "zstd" | "brotli" => {
-                "Compression library - widely linked by other packages".to_string()
-            }
+impl MockBrewRunner {
+    /// Answer commands run as `brew_bin` from `fixture`
+    pub fn new(fixture: BrewFixture, brew_bin: PathBuf) -> Self {
+        Self { fixture, brew_bin }
+    }
 
-            // Image libraries
-            "libpng" | "libjpeg" | "libtiff" | "webp" => {
-                "Image format library - many graphics tools depend on it".to_string()
-            }
+    /// Read a JSON fixture file
+    pub fn load(path: &Path, brew_bin: PathBuf) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read brew fixture {}", path.display()))?;
+        let fixture = serde_json::from_str(&content).with_context(|| {
+            format!(
+                "Failed to parse brew fixture {}.\n\n\
+                 Suggestions:\n\
+                 - Write a JSON object with `formulae` and `casks` arrays\n\
+                 - Give each entry at least {{\"name\", \"version\"}}",
+                path.display()
+            )
+        })?;
+        Ok(Self::new(fixture, brew_bin))
+    }
 
-            // ICU
-            "icu4c" => {
-                "Unicode library - heavily depended upon for internationalization".to_string()
-            }
+    fn find(&self, name: &str) -> Option<(&FixturePackage, bool)> {
+        let formula = self.fixture.formulae.iter().find(|p| p.name == name);
+        let cask = || self.fixture.casks.iter().find(|p| p.name == name);
+        formula
+            .map(|p| (p, false))
+            .or_else(|| cask().map(|p| (p, true)))
+    }
 
-            // Build tools
-            "pkg-config" => "Build configuration tool - used during compilation".to_string(),
-            "cmake" => "Build system - required for building many packages".to_string(),
-            "autoconf" | "automake" | "libtool" => {
-                "GNU build tools - required for package compilation".to_string()
+    /// The `(exit code, stdout, stderr)` real `brew` would produce for `args`
+    fn respond(&self, args: &[&str]) -> (i32, String, String) {
+        let lines = |names: Vec<String>| names.iter().map(|n| format!("{}\n", n)).collect();
+        let versions = |packages: &[FixturePackage]| {
+            lines(
+                packages
+                    .iter()
+                    .map(|p| format!("{} {}", p.name, p.version))
+                    .collect(),
+            )
+        };
+        match args {
+            ["--prefix"] => (
+                0,
+                format!("{}\n", self.fixture.prefix.display()),
+                String::new(),
+            ),
+            ["list", "--formula", "--versions"] => {
+                (0, versions(&self.fixture.formulae), String::new())
             }
-
-            // Ruby
-            n if n.starts_with("ruby@") || n == "ruby" => {
-                "Ruby runtime - gem native extensions require specific linking".to_string()
+            ["list", "--cask", "--versions"] => (0, versions(&self.fixture.casks), String::new()),
+            ["list", "--installed-on-request"] => {
+                let requested = self
+                    .fixture
+                    .formulae
+                    .iter()
+                    .filter(|p| p.installed_on_request);
+                (
+                    0,
+                    lines(requested.map(|p| p.name.clone()).collect()),
+                    String::new(),
+                )
             }
-
-            // Other
-            "gettext" => "Internationalization library - widely used for translations".to_string(),
-            "readline" => "Command-line editing library - used by many CLI tools".to_string(),
-            "ncurses" => "Terminal UI library - fundamental for terminal apps".to_string(),
-            "pcre" | "pcre2" => {
-                "Regular expression library - used by many text processing tools".to_string()
+            ["list", "--pinned"] => {
+                let pinned = self.fixture.formulae.iter().filter(|p| p.pinned);
+                (
+                    0,
+                    lines(pinned.map(|p| p.name.clone()).collect()),
+                    String::new(),
+                )
             }
-
-            _ => "Known to cause migration issues".to_string(),
+            ["deps", "--installed", flags @ .., name] => {
+                let Some((pkg, _)) = self.find(name) else {
+                    return (1, String::new(), format!("Error: No such keg: {}", name));
+                };
+                let mut deps = pkg.dependencies.clone();
+                if flags.contains(&"--include-build") {
+                    deps.extend(pkg.build_dependencies.iter().cloned());
+                }
+                (0, lines(deps), String::new())
+            }
+            ["uses", "--installed", name] => {
+                let users = self
+                    .fixture
+                    .formulae
+                    .iter()
+                    .filter(|p| p.dependencies.iter().any(|d| d == name));
+                (
+                    0,
+                    lines(users.map(|p| p.name.clone()).collect()),
+                    String::new(),
+                )
+            }
+            ["info", "--json=v2", name] => match self.find(name) {
+                Some((pkg, is_cask)) => {
+                    let bottle = if pkg.no_bottle {
+                        serde_json::json!({})
+                    } else {
+                        serde_json::json!({ "stable": { "files": { "all": {} } } })
+                    };
+                    let entry = serde_json::json!({
+                        "name": pkg.name,
+                        "installed": [{ "version": pkg.version }],
+                        "linked_keg": pkg.version,
+                        "keg_only": pkg.keg_only,
+                        "bottle": bottle,
+                        "tap": pkg.tap.as_deref().unwrap_or(if is_cask {
+                            "homebrew/cask"
+                        } else {
+                            "homebrew/core"
+                        }),
+                    });
+                    let key = if is_cask { "casks" } else { "formulae" };
+                    (
+                        0,
+                        serde_json::json!({ key: [entry] }).to_string(),
+                        String::new(),
+                    )
+                }
+                None => (
+                    1,
+                    String::new(),
+                    format!("Error: No available formula with the name \"{}\".", name),
+                ),
+            },
+            ["services", "list", "--json"] => (0, "[]".to_string(), String::new()),
+            _ => (
+                1,
+                String::new(),
+                format!(
+                    "Error: `brew {}` is not supported by --mock-brew",
+                    args.join(" ")
+                ),
+            ),
         }
     }
 }
 
-#[derive(Debug)]
-pub enum MigrateResult {
-    Success { name: String, version: String },
-    Failed { name: String, reason: String },
+impl CommandRunner for MockBrewRunner {
+    fn output(&self, program: &Path, args: &[&str]) -> std::io::Result<Output> {
+        if program != self.brew_bin {
+            return SystemRunner.output(program, args);
+        }
+        let (code, stdout, stderr) = self.respond(args);
+        Ok(Output {
+            status: exit_status(code),
+            stdout: stdout.into_bytes(),
+            stderr: stderr.into_bytes(),
+        })
+    }
+
+    fn status(&self, program: &Path, args: &[&str]) -> std::io::Result<ExitStatus> {
+        if program != self.brew_bin {
+            return SystemRunner.status(program, args);
+        }
+        Ok(exit_status(0))
+    }
 }
 
-#[derive(Debug, Default)]
-pub struct MigrationReport {
-    pub total_formulae: usize,
-    pub total_casks: usize,
-    pub successful: Vec<String>,
-    pub failed: Vec<(String, String)>,
-    pub skipped: Vec<(String, String)>,
+/// Outcome of adding a tap with `zb tap`: the failure reason for the report and its kind
+type TapResult = std::result::Result<(), (String, MigrateError)>;
+
+/// Main migrator struct
+pub struct HomebrewMigrator {
+    pub homebrew_prefix: PathBuf,
+    /// zerobrew install prefix from `zb --prefix`, when zerobrew is available
+    pub zerobrew_prefix: Option<PathBuf>,
+    /// Also load build-only dependencies when listing package details
+    pub include_build_deps: bool,
+    /// Have `analyze_packages` treat any dependency on a problematic package as blocking
+    pub strict: bool,
+    /// How many levels of dependencies `analyze_packages` follows (`None` for no limit)
+    pub max_depth: Option<usize>,
+    /// Copy the existing state file aside before `save_state` overwrites it
+    pub backup_state: bool,
+    /// How many state backups to keep; older ones are deleted
+    pub keep_backups: usize,
+    /// How many formulae to load details for in parallel when listing them in detail.
+    /// Loading only reads from Homebrew, so it is safe to run concurrently
+    pub analyze_jobs: usize,
+    /// Homebrew executable (`brew` on PATH unless overridden)
+    pub brew_bin: PathBuf,
+    /// zerobrew executable (`zb` on PATH unless overridden)
+    pub zb_bin: PathBuf,
+    /// Runs `brew` and `zb` commands whose output is captured
+    pub runner: Box<dyn CommandRunner>,
+    /// `aliases.toml` in the zerobrew config directory
+    aliases_file: PathBuf,
+    /// Homebrew name → zerobrew name, for formulae zerobrew knows by another name.
+    /// Loaded on first use so a broken `aliases.toml` only affects migrations
+    aliases: OnceLock<BTreeMap<String, String>>,
+    /// Outcome of `zb tap` for each tap added this run, so a tap is added only once
+    added_taps: Mutex<HashMap<String, TapResult>>,
+    state_file: PathBuf,
+    verbose: bool,
+    /// Command and analysis timings, collected once `enable_timings` is called
+    timings: Option<Arc<Mutex<TimingSummary>>>,
 }
 
-impl MigrationReport {
-    pub fn print_summary(&self) {
-        println!("\n=== Migration Summary ===");
-        println!("Total formulae: {}", self.total_formulae);
-        println!("Total casks: {}", self.total_casks);
-        println!("Successful: {}", self.successful.len());
-        println!("Failed: {}", self.failed.len());
-        println!("Skipped: {}", self.skipped.len());
+impl HomebrewMigrator {
+    /// Create a new migrator instance using `brew` and `zb` from PATH
+    pub fn new(verbose: bool) -> Result<Self> {
+        Self::with_binaries(verbose, PathBuf::from("brew"), PathBuf::from("zb"))
+    }
 
-        if !self.failed.is_empty() {
-            println!("\nFailed packages:");
-            for (name, reason) in &self.failed {
-                println!("  {} - {}", name, reason);
-            }
+    /// Create a migrator that runs the given Homebrew and zerobrew executables
+    pub fn with_binaries(verbose: bool, brew_bin: PathBuf, zb_bin: PathBuf) -> Result<Self> {
+        Self::with_runner(verbose, brew_bin, zb_bin, Box::new(SystemRunner))
+    }
+
+    /// Create a migrator whose `brew` and `zb` commands go through `runner`
+    pub fn with_runner(
+        verbose: bool,
+        brew_bin: PathBuf,
+        zb_bin: PathBuf,
+        runner: Box<dyn CommandRunner>,
+    ) -> Result<Self> {
+        let fallbacks: Vec<PathBuf> = STANDARD_BREW_LOCATIONS.iter().map(PathBuf::from).collect();
+        let (brew_bin, homebrew_prefix) =
+            Self::detect_homebrew_prefix(runner.as_ref(), &brew_bin, &fallbacks, verbose)?;
+        let zerobrew_prefix = Self::detect_zerobrew_prefix(runner.as_ref(), &zb_bin, verbose);
+        let zerobrew_home = resolve_zerobrew_home(
+            std::env::var(ZEROBREW_HOME_ENV).ok(),
+            std::env::var("HOME").ok(),
+        )?;
+        if verbose {
+            eprintln!(
+                "[verbose] zerobrew config directory: {}",
+                zerobrew_home.display()
+            );
         }
 
-        if !self.skipped.is_empty() {
-            println!("\nSkipped packages:");
-            for (name, reason) in &self.skipped {
-                println!("  {} - {}", name, reason);
-            }
+        Ok(Self {
+            homebrew_prefix,
+            zerobrew_prefix,
+            include_build_deps: false,
+            strict: false,
+            max_depth: None,
+            backup_state: false,
+            keep_backups: DEFAULT_KEEP_BACKUPS,
+            analyze_jobs: DEFAULT_ANALYZE_JOBS,
+            brew_bin,
+            zb_bin,
+            runner,
+            aliases_file: zerobrew_home.join(ALIASES_FILE_NAME),
+            aliases: OnceLock::new(),
+            added_taps: Mutex::new(HashMap::new()),
+            state_file: zerobrew_home.join("migration_state.json"),
+            verbose,
+            timings: None,
+        })
+    }
+
+    /// Start timing every command from now on, for `timing_summary`
+    pub fn enable_timings(&mut self) {
+        if self.timings.is_some() {
+            return;
         }
+        let timings = Arc::new(Mutex::new(TimingSummary::default()));
+        let inner = std::mem::replace(&mut self.runner, Box::new(SystemRunner));
+        self.runner = Box::new(TimingRunner {
+            inner,
+            timings: Arc::clone(&timings),
+        });
+        self.timings = Some(timings);
+    }
+
+    /// Run `analyze`, counting its duration as analysis time when timings are enabled
+    pub fn time_analysis<T>(&self, analyze: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = analyze();
+        if let Some(Ok(mut timings)) = self.timings.as_ref().map(|t| t.lock()) {
+            timings.analysis += start.elapsed();
+        }
+        result
+    }
+
+    /// Timings collected since `enable_timings`, or `None` if it wasn't called
+    pub fn timing_summary(&self) -> Option<TimingSummary> {
+        self.timings
+            .as_ref()
+            .and_then(|t| t.lock().ok().map(|t| t.clone()))
+    }
+
+    /// Time the `brew` commands behind `analyze`, running each `iterations` times.
+    ///
+    /// The per-package commands run against `sample`, or the first installed
+    /// formula when none is given.
+    pub fn benchmark_brew(&self, sample: Option<&str>, iterations: usize) -> Result<BrewBenchmark> {
+        let iterations = iterations.max(1);
+        let time = |args: &[&str]| -> Result<CommandBenchmark> {
+            let command = std::iter::once("brew")
+                .chain(args.iter().copied())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let mut runs = Vec::with_capacity(iterations);
+            for _ in 0..iterations {
+                let start = Instant::now();
+                let output = self
+                    .runner
+                    .output(&self.brew_bin, args)
+                    .with_context(|| format!("Failed to run '{}'", command))?;
+                runs.push(start.elapsed());
+                if !output.status.success() {
+                    bail!(
+                        "'{}' failed: {}\n\n\
+                         Suggestions:\n\
+                         - Run '{}' directly to see the full error\n\
+                         - Run 'brew doctor' to check your Homebrew installation",
+                        command,
+                        String::from_utf8_lossy(&output.stderr).trim(),
+                        command
+                    );
+                }
+            }
+            Ok(CommandBenchmark { command, runs })
+        };
+
+        let list = time(&["list", "--formula", "--versions"])?;
+        let formulae = self.list_installed_formulae()?;
+        let sample = match sample {
+            Some(name) => {
+                if !formulae.iter().any(|p| p.name == name) {
+                    bail!(
+                        "'{}' is not an installed Homebrew formula.\n\n\
+                         Suggestions:\n\
+                         - Run 'zb-migrate list' to see installed formulae\n\
+                         - Omit --package to use the first installed formula",
+                        name
+                    );
+                }
+                name.to_string()
+            }
+            None => match formulae.first() {
+                Some(pkg) => pkg.name.clone(),
+                None => bail!(
+                    "No Homebrew formulae are installed, so there is nothing to benchmark.\n\n\
+                     Suggestions:\n\
+                     - Install a formula with 'brew install <name>' and try again"
+                ),
+            },
+        };
+
+        let commands = vec![
+            list,
+            time(&["list", "--pinned"])?,
+            time(&["deps", "--installed", &sample])?,
+            time(&["info", "--json=v2", &sample])?,
+        ];
+        Ok(BrewBenchmark {
+            sample,
+            formula_count: formulae.len(),
+            commands,
+        })
+    }
+
+    /// Detect the Homebrew installation prefix, returning the `brew` executable that
+    /// answered along with it.
+    ///
+    /// When a bare `brew` isn't on PATH (e.g. a fresh terminal that hasn't run
+    /// `brew shellenv`), the executables in `fallbacks` that exist are tried in order.
+    fn detect_homebrew_prefix(
+        runner: &dyn CommandRunner,
+        brew_bin: &Path,
+        fallbacks: &[PathBuf],
+        verbose: bool,
+    ) -> Result<(PathBuf, PathBuf)> {
+        let start = Instant::now();
+        if verbose {
+            eprintln!("[verbose] Running: {} --prefix", brew_bin.display());
+        }
+
+        // Try to get prefix from brew command
+        let mut brew_bin = brew_bin.to_path_buf();
+        let mut result = runner.output(&brew_bin, &["--prefix"]);
+        let on_path_only = brew_bin.components().count() == 1;
+        if on_path_only && matches!(&result, Err(e) if e.kind() == std::io::ErrorKind::NotFound) {
+            for candidate in fallbacks.iter().filter(|path| path.is_file()) {
+                if verbose {
+                    eprintln!(
+                        "[verbose] {} is not on PATH; trying {}",
+                        brew_bin.display(),
+                        candidate.display()
+                    );
+                }
+                if let Ok(output) = runner.output(candidate, &["--prefix"]) {
+                    brew_bin = candidate.clone();
+                    result = Ok(output);
+                    break;
+                }
+            }
+        }
+        let output = result.context(
+                "Failed to run 'brew --prefix': Homebrew does not appear to be installed.\n\n\
+                 To install Homebrew, run:\n\
+                   /bin/bash -c \"$(curl -fsSL https://raw.githubusercontent.com/Homebrew/install/HEAD/install.sh)\"\n\n\
+                 For more information, visit: https://brew.sh",
+            )?;
+
+        let elapsed = start.elapsed();
+        if verbose {
+            eprintln!("[verbose] Command completed in {:.2?}", elapsed);
+            eprintln!("[verbose] Exit code: {}", output.status);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = filter_brew_stderr(&String::from_utf8_lossy(&output.stderr));
+            if !stdout.trim().is_empty() {
+                eprintln!("[verbose] stdout: {}", stdout.trim());
+            }
+            if !stderr.is_empty() {
+                eprintln!("[verbose] stderr: {}", stderr);
+            }
+        }
+
+        if !output.status.success() {
+            let stderr = filter_brew_stderr(&String::from_utf8_lossy(&output.stderr));
+            bail!(
+                "Failed to detect Homebrew prefix: 'brew --prefix' returned an error.{}\n\n\
+                 Suggestions:\n\
+                 - Ensure Homebrew is properly installed and configured\n\
+                 - Try running 'brew doctor' to diagnose issues\n\
+                 - Check that 'brew' is in your PATH",
+                error_output_section(&stderr)
+            );
+        }
+
+        let prefix = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+        Ok((brew_bin, PathBuf::from(prefix)))
+    }
+
+    /// Detect the zerobrew prefix via `zb --prefix`; `None` if zerobrew is unavailable
+    fn detect_zerobrew_prefix(
+        runner: &dyn CommandRunner,
+        zb_bin: &Path,
+        verbose: bool,
+    ) -> Option<PathBuf> {
+        if verbose {
+            eprintln!("[verbose] Running: {} --prefix", zb_bin.display());
+        }
+        let output = runner.output(zb_bin, &["--prefix"]).ok()?;
+        let prefix = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() || prefix.is_empty() {
+            if verbose {
+                eprintln!("[verbose] Could not determine the zerobrew prefix");
+            }
+            return None;
+        }
+        if verbose {
+            eprintln!("[verbose] zerobrew prefix: {}", prefix);
+        }
+        Some(PathBuf::from(prefix))
+    }
+
+    /// List all installed Homebrew formulae (fast mode - minimal brew calls)
+    ///
+    /// `brew list --versions` prints `<name> <version>...` per line. Formula and
+    /// cask names never contain whitespace, so the first field is taken as the name;
+    /// anything written back out (e.g. a Brewfile) is still quoted with [`brewfile_quote`].
+    pub fn list_installed_formulae(&self) -> Result<Vec<BrewPackage>> {
+        let start = Instant::now();
+        if self.verbose {
+            eprintln!("[verbose] Running: brew list --formula --versions");
+        }
+
+        let output = self
+            .runner
+            .output(&self.brew_bin, &["list", "--formula", "--versions"])
+            .context(
+                "Failed to list Homebrew formulae: Could not execute 'brew list'.\n\n\
+                 Suggestions:\n\
+                 - Verify Homebrew is installed: run 'brew --version'\n\
+                 - Check your internet connection if this is a network-related issue\n\
+                 - Try running 'brew update' to refresh Homebrew",
+            )?;
+
+        let elapsed = start.elapsed();
+        if self.verbose {
+            eprintln!("[verbose] Command completed in {:.2?}", elapsed);
+            eprintln!("[verbose] Exit code: {}", output.status);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            eprintln!("[verbose] Found {} lines of output", stdout.lines().count());
+        }
+
+        if !output.status.success() {
+            let stderr = filter_brew_stderr(&String::from_utf8_lossy(&output.stderr));
+            bail!(
+                "Failed to list Homebrew formulae: 'brew list --formula' returned an error.{}\n\n\
+                 Suggestions:\n\
+                 - Run 'brew doctor' to check for issues\n\
+                 - Try 'brew update' to refresh package information",
+                error_output_section(&stderr)
+            );
+        }
+
+        // Get pinned and explicitly installed packages once
+        let pinned_set = self.get_pinned_packages();
+        let requested_set = self.get_requested_packages();
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut packages = Vec::new();
+
+        for line in stdout.lines() {
+            // Names are whitespace-free, so field 0 is the name and field 1 the version
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                let name = parts[0].to_string();
+                let version = parts[1].to_string();
+                let pinned = pinned_set.contains(&name);
+                let installed_on_request = requested_set
+                    .as_ref()
+                    .is_none_or(|requested| requested.contains(&name));
+                let head = is_head_version(&version);
+
+                packages.push(BrewPackage {
+                    name,
+                    version,
+                    tap: None, // Skip tap lookup for speed
+                    is_cask: false,
+                    dependencies: Vec::new(), // Lazy load when needed
+                    build_dependencies: Vec::new(),
+                    pinned,
+                    keg_only: false,
+                    no_bottle: false,
+                    installed_on_request,
+                    head,
+                });
+            }
+        }
+
+        Ok(packages)
+    }
+
+    /// Get all pinned packages at once.
+    ///
+    /// Pinned state is only metadata, so if `brew list --pinned` fails every package is
+    /// treated as unpinned rather than failing the whole listing.
+    fn get_pinned_packages(&self) -> HashSet<String> {
+        match self.runner.output(&self.brew_bin, &["list", "--pinned"]) {
+            Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+                .map(|l| l.to_string())
+                .collect(),
+            Ok(out) => {
+                if self.verbose {
+                    let stderr = filter_brew_stderr(&String::from_utf8_lossy(&out.stderr));
+                    eprintln!(
+                        "[verbose] Warning: 'brew list --pinned' exited with {}: {}; treating all packages as unpinned",
+                        out.status, stderr
+                    );
+                }
+                HashSet::new()
+            }
+            Err(e) => {
+                if self.verbose {
+                    eprintln!(
+                        "[verbose] Warning: failed to run 'brew list --pinned': {}; treating all packages as unpinned",
+                        e
+                    );
+                }
+                HashSet::new()
+            }
+        }
+    }
+
+    /// Get the formulae installed explicitly rather than as dependencies, or `None` when
+    /// `brew list --installed-on-request` fails (e.g. an older Homebrew), in which case
+    /// every formula counts as requested
+    fn get_requested_packages(&self) -> Option<HashSet<String>> {
+        match self
+            .runner
+            .output(&self.brew_bin, &["list", "--installed-on-request"])
+        {
+            Ok(out) if out.status.success() => Some(
+                String::from_utf8_lossy(&out.stdout)
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_string())
+                    .collect(),
+            ),
+            Ok(out) => {
+                if self.verbose {
+                    let stderr = filter_brew_stderr(&String::from_utf8_lossy(&out.stderr));
+                    eprintln!(
+                        "[verbose] Warning: 'brew list --installed-on-request' exited with {}: {}; treating all packages as requested",
+                        out.status, stderr
+                    );
+                }
+                None
+            }
+            Err(e) => {
+                if self.verbose {
+                    eprintln!(
+                        "[verbose] Warning: failed to run 'brew list --installed-on-request': {}; treating all packages as requested",
+                        e
+                    );
+                }
+                None
+            }
+        }
+    }
+
+    /// List installed formulae with full details (slower, used for migration).
+    ///
+    /// Details are loaded for up to `analyze_jobs` formulae at once.
+    pub fn list_installed_formulae_detailed(&self) -> Result<Vec<BrewPackage>> {
+        let mut packages = self.list_installed_formulae()?;
+        self.load_package_details(&mut packages)?;
+        Ok(packages)
+    }
+
+    /// Fill in dependencies and `brew info` details for already-listed formulae,
+    /// loading up to `analyze_jobs` of them at once
+    fn load_package_details(&self, packages: &mut [BrewPackage]) -> Result<()> {
+        let total = packages.len();
+
+        let bottle_tag = self.current_bottle_tag();
+        let pb = create_progress_bar(total as u64, "Loading package details...");
+
+        let jobs = self.analyze_jobs.clamp(1, total.max(1));
+        let next = AtomicUsize::new(0);
+        let loaded = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    scope.spawn(|| -> Result<Vec<(usize, FormulaDetails)>> {
+                        let mut loaded = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(pkg) = packages.get(i) else {
+                                return Ok(loaded);
+                            };
+                            pb.set_message(format!("Loading: {}", pkg.name));
+                            match self.load_details(&pkg.name, bottle_tag.as_deref()) {
+                                Ok(details) => loaded.push((i, details)),
+                                Err(e) => {
+                                    // Stop the other workers from starting new formulae
+                                    next.store(total, Ordering::Relaxed);
+                                    return Err(e);
+                                }
+                            }
+                            pb.inc(1);
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("package detail worker panicked"))
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        for (i, details) in loaded.into_iter().flatten() {
+            let pkg = &mut packages[i];
+            pkg.dependencies = details.dependencies;
+            pkg.build_dependencies = details.build_dependencies;
+            pkg.tap = details.info.tap.into_tap();
+            pkg.keg_only = details.info.keg_only;
+            pkg.no_bottle = details.info.no_bottle;
+            if let Some(version) = details.info.head_version {
+                pkg.head = true;
+                pkg.version = version;
+            }
+        }
+
+        pb.finish_with_message(format!("Loaded {} packages", total));
+        Ok(())
+    }
+
+    /// Load the dependencies and `brew info` details of one formula
+    fn load_details(&self, name: &str, bottle_tag: Option<&str>) -> Result<FormulaDetails> {
+        let dependencies = self.get_dependencies(name)?;
+        let build_dependencies = if self.include_build_deps {
+            self.get_build_dependencies(name, &dependencies)?
+        } else {
+            Vec::new()
+        };
+        Ok(FormulaDetails {
+            dependencies,
+            build_dependencies,
+            info: self.load_formula_info(name, bottle_tag)?,
+        })
+    }
+
+    /// List the distinct third-party taps installed formulae come from
+    pub fn list_taps(&self) -> Result<Vec<String>> {
+        let mut packages = self.list_installed_formulae()?;
+        let total = packages.len();
+
+        let pb = create_progress_bar(total as u64, "Looking up taps...");
+        for (i, pkg) in packages.iter_mut().enumerate() {
+            pb.set_message(format!("Checking: {}", pkg.name));
+            pkg.tap = self.load_formula_info(&pkg.name, None)?.tap.into_tap();
+            pb.set_position((i + 1) as u64);
+        }
+        pb.finish_and_clear();
+
+        Ok(distinct_taps(&packages))
+    }
+
+    /// List all installed Homebrew casks
+    pub fn list_installed_casks(&self) -> Result<Vec<BrewPackage>> {
+        let output = self
+            .runner
+            .output(&self.brew_bin, &["list", "--cask", "--versions"])
+            .context(
+                "Failed to list Homebrew casks: Could not execute 'brew list --cask'.\n\n\
+                 Suggestions:\n\
+                 - Verify Homebrew is installed: run 'brew --version'\n\
+                 - Check your internet connection if this is a network-related issue",
+            )?;
+
+        if !output.status.success() {
+            // Casks might not be installed, return empty
+            return Ok(Vec::new());
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut packages = Vec::new();
+
+        for line in stdout.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                packages.push(BrewPackage {
+                    name: parts[0].to_string(),
+                    version: parts[1].to_string(),
+                    tap: None,
+                    is_cask: true,
+                    dependencies: Vec::new(),
+                    build_dependencies: Vec::new(),
+                    pinned: false,
+                    keg_only: false,
+                    no_bottle: false,
+                    installed_on_request: true,
+                    head: false,
+                });
+            }
+        }
+
+        Ok(packages)
+    }
+
+    /// Get runtime dependencies for a package. `brew deps` leaves out
+    /// build-only dependencies unless `--include-build` is passed.
+    fn get_dependencies(&self, name: &str) -> Result<Vec<String>> {
+        self.brew_deps(name, &[])
+    }
+
+    /// Get dependencies only needed to build a package from source
+    fn get_build_dependencies(&self, name: &str, runtime: &[String]) -> Result<Vec<String>> {
+        Ok(self
+            .brew_deps(name, &["--include-build"])?
+            .into_iter()
+            .filter(|dep| !runtime.contains(dep))
+            .collect())
+    }
+
+    /// Run `brew deps --installed` for a package with extra flags
+    fn brew_deps(&self, name: &str, flags: &[&str]) -> Result<Vec<String>> {
+        let mut args = vec!["deps", "--installed"];
+        args.extend_from_slice(flags);
+        args.push(name);
+        let output = self.runner.output(&self.brew_bin, &args);
+
+        match output {
+            Ok(out) if out.status.success() => {
+                let stdout = String::from_utf8_lossy(&out.stdout);
+                Ok(stdout.lines().map(|s| s.to_string()).collect())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Get the installed packages that directly depend on a package
+    fn get_dependents(&self, name: &str) -> Result<Vec<String>> {
+        if self.verbose {
+            eprintln!("[verbose] Running: brew uses --installed {}", name);
+        }
+        let output = self
+            .runner
+            .output(&self.brew_bin, &["uses", "--installed", name])
+            .with_context(|| format!("Failed to run 'brew uses --installed {}'", name))?;
+        if !output.status.success() {
+            bail!(
+                "'brew uses --installed {}' failed: {}",
+                name,
+                filter_brew_stderr(&String::from_utf8_lossy(&output.stderr))
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(|l| l.to_string())
+            .collect())
+    }
+
+    /// Find installed reverse dependents of the named packages, up to `max_depth`
+    /// levels away, via `brew uses --installed`
+    pub fn find_dependents(&self, names: &[String], max_depth: usize) -> Result<Vec<String>> {
+        collect_dependents(names, max_depth, |name| self.get_dependents(name))
+    }
+
+    /// Dependencies that installing `name` will pull in and installed formulae that depend
+    /// on it, via `brew deps --installed` and `brew uses --installed`
+    pub fn package_impact(&self, name: &str) -> Result<PackageImpact> {
+        let mut dependencies = self.brew_deps(name, &[])?;
+        dependencies.sort();
+        let in_zerobrew = self.zerobrew_installed_best_effort();
+        let dependencies_to_install = dependencies
+            .iter()
+            .filter(|dep| !in_zerobrew.contains(*dep))
+            .cloned()
+            .collect();
+        Ok(PackageImpact {
+            name: name.to_string(),
+            dependencies,
+            dependencies_to_install,
+            dependents: self.find_dependents(&[name.to_string()], usize::MAX)?,
+        })
+    }
+
+    /// Homebrew's bottle tag for this machine, asking `sw_vers` for the macOS version
+    fn current_bottle_tag(&self) -> Option<String> {
+        let macos_version = if std::env::consts::OS == "macos" {
+            let output = self
+                .runner
+                .output(Path::new("sw_vers"), &["-productVersion"])
+                .ok()
+                .filter(|out| out.status.success());
+            output.map(|out| String::from_utf8_lossy(&out.stdout).into_owned())
+        } else {
+            None
+        };
+        let tag = bottle_tag(
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            macos_version.as_deref(),
+        );
+        if tag.is_none() && self.verbose {
+            eprintln!("[verbose] Unknown platform; not checking bottle availability");
+        }
+        tag
+    }
+
+    /// Load what `brew info` says about a formula: its tap, whether it is keg-only or
+    /// installed at HEAD and, given this platform's bottle tag, whether it lacks a bottle
+    fn load_formula_info(&self, name: &str, bottle_tag: Option<&str>) -> Result<FormulaInfo> {
+        let output = self
+            .runner
+            .output(&self.brew_bin, &["info", "--json=v2", name]);
+
+        let mut keg_only = false;
+        let mut no_bottle = false;
+        let mut head_version = None;
+        let lookup = match output {
+            Ok(out) if out.status.success() => {
+                let json = String::from_utf8_lossy(&out.stdout);
+                keg_only = parse_keg_only_from_info_json(&json);
+                head_version = parse_head_version_from_info_json(&json);
+                no_bottle =
+                    bottle_tag.is_some_and(|tag| parse_no_bottle_from_info_json(&json, tag));
+                parse_tap_from_info_json(&json)
+            }
+            Ok(out) => {
+                if self.verbose {
+                    let stderr = filter_brew_stderr(&String::from_utf8_lossy(&out.stderr));
+                    eprintln!(
+                        "[verbose] 'brew info {}' exited with {}: {}",
+                        name, out.status, stderr
+                    );
+                }
+                TapLookup::Unknown
+            }
+            Err(e) => {
+                if self.verbose {
+                    eprintln!("[verbose] Failed to run 'brew info {}': {}", name, e);
+                }
+                TapLookup::Unknown
+            }
+        };
+
+        if self.verbose && lookup == TapLookup::Unknown {
+            eprintln!(
+                "[verbose] Warning: could not determine tap for {} (formula may have been removed from its tap)",
+                name
+            );
+        }
+
+        Ok(FormulaInfo {
+            tap: lookup,
+            keg_only,
+            no_bottle,
+            head_version,
+        })
+    }
+
+    /// Get the install time of a formula from its Cellar directory mtime
+    fn get_install_time(&self, pkg: &BrewPackage) -> Option<SystemTime> {
+        let keg = self.homebrew_prefix.join("Cellar").join(&pkg.name);
+        fs::metadata(keg.join(&pkg.version))
+            .or_else(|_| fs::metadata(&keg))
+            .and_then(|meta| meta.modified())
+            .ok()
+    }
+
+    /// Split packages into those installed within `since` and those older than the cutoff.
+    /// Packages whose install time cannot be determined are kept.
+    fn filter_by_install_time(
+        &self,
+        packages: Vec<BrewPackage>,
+        since: Duration,
+    ) -> (Vec<BrewPackage>, Vec<BrewPackage>) {
+        let cutoff = SystemTime::now()
+            .checked_sub(since)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        packages.into_iter().partition(|pkg| {
+            let installed = self.get_install_time(pkg);
+            if self.verbose && installed.is_none() {
+                eprintln!(
+                    "[verbose] Could not determine install time for {}, keeping it",
+                    pkg.name
+                );
+            }
+            is_installed_after(installed, cutoff)
+        })
+    }
+
+    /// Export Homebrew packages to a Brewfile-compatible format for zerobrew.
+    ///
+    /// With `append`, an existing Brewfile is merged instead of overwritten: only
+    /// entries not already listed are added and existing lines are preserved.
+    /// Returns the number of entries written (or added, when appending).
+    pub fn export_to_brewfile(&self, path: &PathBuf, append: bool) -> Result<usize> {
+        let formulae = self.list_installed_formulae()?;
+        let casks = self.list_installed_casks()?;
+
+        let (content, written) = if append && path.exists() {
+            let existing = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read existing Brewfile {}", path.display()))?;
+            merge_brewfile(&existing, &formulae, &casks)
+        } else {
+            (
+                render_brewfile(&formulae, &casks),
+                formulae.len() + casks.len(),
+            )
+        };
+
+        write_atomic(path, content.as_bytes())?;
+        Ok(written)
+    }
+
+    /// Render the installed formulae and casks as a fresh Brewfile
+    pub fn render_installed_brewfile(&self) -> Result<String> {
+        let formulae = self.list_installed_formulae()?;
+        let casks = self.list_installed_casks()?;
+        Ok(render_brewfile(&formulae, &casks))
+    }
+
+    /// Migrate a single package from Homebrew to Zerobrew.
+    ///
+    /// Formulae from a third-party tap have the tap added with `zb tap` first and are
+    /// installed by their fully qualified `tap/name`.
+    pub fn migrate_package(
+        &self,
+        package: &BrewPackage,
+        options: &MigrateOptions,
+    ) -> Result<MigrateResult> {
+        if let Some(tap) = &package.tap {
+            if let Err((reason, error)) = self.add_tap(tap) {
+                return Ok(MigrateResult::Failed {
+                    name: package.name.clone(),
+                    error,
+                    reason,
+                    log: None,
+                });
+            }
+        }
+        let zb_name = self.zerobrew_name(&package.name)?;
+        if self.verbose && zb_name != package.name {
+            eprintln!(
+                "[verbose] {} is called {} in zerobrew",
+                package.name, zb_name
+            );
+        }
+        let install_name = match &package.tap {
+            Some(tap) => format!("{}/{}", tap, zb_name),
+            None => zb_name.to_string(),
+        };
+
+        let install_mode = options.install_mode(&package.name);
+        let install = |name: &str| {
+            let mut args = vec!["install"];
+            args.extend(install_mode.zb_flag());
+            args.push(name);
+            if self.verbose {
+                eprintln!("[verbose] Running: zb {}", args.join(" "));
+            }
+            self.runner.output(&self.zb_bin, &args)
+        };
+
+        let start = Instant::now();
+
+        // Step 1: Install via zerobrew (it will use cache if available)
+        let pinned = options
+            .pin_versions
+            .then(|| pinned_install_name(&install_name, &package.version))
+            .flatten();
+        let mut pin_fallback = false;
+        let zb_result = match pinned {
+            Some(pinned) => match install(&pinned) {
+                // Only an unavailable version falls back; other failures (network,
+                // dependencies) would fail the unpinned install too and are reported
+                Ok(output)
+                    if !output.status.success()
+                        && matches!(
+                            classify_install_error(
+                                output.status.code(),
+                                &String::from_utf8_lossy(&output.stderr)
+                            ),
+                            MigrateError::NotFoundInZerobrew
+                        ) =>
+                {
+                    if self.verbose {
+                        eprintln!(
+                            "[verbose] {} is not available, installing the latest {}",
+                            pinned, install_name
+                        );
+                    }
+                    pin_fallback = true;
+                    install(&install_name)
+                }
+                result => result,
+            },
+            None => install(&install_name),
+        };
+
+        let elapsed = start.elapsed();
+
+        match zb_result {
+            Ok(output) if output.status.success() => {
+                if self.verbose {
+                    eprintln!("[verbose] Command completed in {:.2?}", elapsed);
+                    eprintln!("[verbose] Exit code: {}", output.status);
+                    let stdout = String::from_utf8_lossy(&output.stdout);
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    if !stdout.trim().is_empty() {
+                        eprintln!("[verbose] stdout: {}", stdout.trim());
+                    }
+                    if !stderr.trim().is_empty() {
+                        eprintln!("[verbose] stderr: {}", stderr.trim());
+                    }
+                }
+                if options.keep_logs {
+                    self.write_install_log(&package.name, &output);
+                }
+                // Step 2: Optionally uninstall from Homebrew to free space
+                // (We don't do this automatically - user should confirm)
+                Ok(MigrateResult::Success {
+                    name: package.name.clone(),
+                    version: package.version.clone(),
+                    installed_version: self.query_installed_version(zb_name),
+                    pin_fallback,
+                    zerobrew_name: (zb_name != package.name).then(|| zb_name.to_string()),
+                    install_mode,
+                })
+            }
+            Ok(output) => {
+                if self.verbose {
+                    eprintln!("[verbose] Command completed in {:.2?}", elapsed);
+                    eprintln!("[verbose] Exit code: {}", output.status);
+                    let stdout = String::from_utf8_lossy(&output.stdout);
+                    if !stdout.trim().is_empty() {
+                        eprintln!("[verbose] stdout: {}", stdout.trim());
+                    }
+                }
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                if self.verbose {
+                    eprintln!("[verbose] stderr: {}", stderr.trim());
+                }
+                Ok(MigrateResult::Failed {
+                    name: package.name.clone(),
+                    reason: summarize_install_error(&stderr),
+                    error: classify_install_error(output.status.code(), &stderr),
+                    log: self.write_install_log(&package.name, &output),
+                })
+            }
+            Err(e) => {
+                if self.verbose {
+                    eprintln!("[verbose] Command failed after {:.2?}: {}", elapsed, e);
+                }
+                Ok(MigrateResult::Failed {
+                    name: package.name.clone(),
+                    reason: format!("Failed to run zb: {}", e),
+                    error: MigrateError::Other(e.to_string()),
+                    log: None,
+                })
+            }
+        }
+    }
+
+    /// Check that `zb install --help` lists the flag for every install mode the run
+    /// asks for. When the help text can't be read the flags are passed through anyway
+    /// and zerobrew reports any it doesn't know.
+    pub fn check_install_mode_support(&self, options: &MigrateOptions) -> Result<()> {
+        let modes = options.requested_install_modes();
+        if modes.is_empty() {
+            return Ok(());
+        }
+        let help = match self.runner.output(&self.zb_bin, &["install", "--help"]) {
+            Ok(output) if output.status.success() => {
+                let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+                text.push_str(&String::from_utf8_lossy(&output.stderr));
+                text
+            }
+            Ok(_) | Err(_) => {
+                if self.verbose {
+                    eprintln!(
+                        "[verbose] Could not read `zb install --help`; passing install flags through unchecked"
+                    );
+                }
+                return Ok(());
+            }
+        };
+        for flag in modes.into_iter().filter_map(InstallMode::zb_flag) {
+            if !help.contains(flag) {
+                bail!(
+                    "This zerobrew version doesn't support `zb install {}`\n\n\
+                     Suggestions:\n\
+                     - Upgrade zerobrew and try again\n\
+                     - Drop {} to use zerobrew's default install method",
+                    flag,
+                    flag
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Make a third-party tap available to zerobrew via `zb tap`; the error is a
+    /// failure reason for the report. Each tap is only added once per run, and every
+    /// formula from it shares the outcome.
+    fn add_tap(&self, tap: &str) -> TapResult {
+        let mut added = self
+            .added_taps
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        added
+            .entry(tap.to_string())
+            .or_insert_with(|| self.run_zb_tap(tap))
+            .clone()
+    }
+
+    /// Run `zb tap` for one tap
+    fn run_zb_tap(&self, tap: &str) -> TapResult {
+        if self.verbose {
+            eprintln!("[verbose] Running: zb tap {}", tap);
+        }
+        match self.runner.output(&self.zb_bin, &["tap", tap]) {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let reason = format!(
+                    "Failed to add tap {}: {}",
+                    tap,
+                    summarize_install_error(&stderr)
+                );
+                let error = match classify_install_error(output.status.code(), &stderr) {
+                    MigrateError::Other(_) => MigrateError::Other(reason.clone()),
+                    error => error,
+                };
+                Err((reason, error))
+            }
+            Err(e) => {
+                let reason = format!("Failed to add tap {}: {}", tap, e);
+                Err((reason.clone(), MigrateError::Other(reason)))
+            }
+        }
+    }
+
+    /// Ask zerobrew which version of a package it has installed via `zb info`
+    fn query_installed_version(&self, name: &str) -> Option<String> {
+        if self.verbose {
+            eprintln!("[verbose] Running: zb info {}", name);
+        }
+        let output = self.runner.output(&self.zb_bin, &["info", name]).ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let version = parse_zb_info_version(&String::from_utf8_lossy(&output.stdout), name);
+        if self.verbose {
+            eprintln!(
+                "[verbose] zerobrew has {} {}",
+                name,
+                version.as_deref().unwrap_or("unknown version")
+            );
+        }
+        version
+    }
+
+    /// Directory holding per-package install logs (`~/.zerobrew/logs`)
+    pub fn log_dir(&self) -> PathBuf {
+        self.state_file.with_file_name("logs")
+    }
+
+    /// Write the full output of `zb install` to `<log_dir>/<name>.log`.
+    /// Logging is best-effort: failures are reported in verbose mode only.
+    fn write_install_log(&self, name: &str, output: &std::process::Output) -> Option<PathBuf> {
+        let path = self
+            .log_dir()
+            .join(format!("{}.log", name.replace('/', "_")));
+        let content = format!(
+            "$ zb install {}\nexit status: {}\n\n--- stdout ---\n{}\n--- stderr ---\n{}",
+            name,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let written = fs::create_dir_all(self.log_dir()).and_then(|_| fs::write(&path, content));
+        match written {
+            Ok(()) => Some(path),
+            Err(e) => {
+                if self.verbose {
+                    eprintln!(
+                        "[verbose] Failed to write install log {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+                None
+            }
+        }
+    }
+
+    /// Select the formulae and casks for a bulk migration, in dependency order.
+    ///
+    /// Dry runs use the fast listing (no dependency lookups) and an approximate
+    /// order, unless `exact_order` is set or `--after`, `--before` or
+    /// `--exclude-taps` need the details.
+    pub fn plan_migration(&self, options: &MigrateOptions) -> Result<MigrationPlan> {
+        let casks = self.list_installed_casks()?;
+        let load_details = !options.dry_run
+            || options.exact_order
+            || !options.order_constraints.is_empty()
+            || !options.exclude_taps.is_empty();
+        let formulae = if load_details {
+            self.list_installed_formulae_detailed()?
+        } else {
+            self.list_installed_formulae()?
+        };
+
+        validate_order_constraints(&options.order_constraints, &formulae)?;
+
+        let total_formulae = formulae.len();
+        let mut skipped = Vec::new();
+        let formulae = self.apply_since_filter(formulae, options, &mut skipped);
+        let formulae = exclude_taps(formulae, &options.exclude_taps, &mut skipped);
+        let formulae = if options.requested_only {
+            exclude_dependencies_only(formulae, &mut skipped)
+        } else {
+            formulae
+        };
+        let (already_migrated, already_in_zerobrew, formulae) = if options.force {
+            (Vec::new(), Vec::new(), formulae)
+        } else {
+            let (migrated, formulae) = split_already_migrated(formulae, &self.load_state()?);
+            let (in_zerobrew, formulae) =
+                split_already_in_zerobrew(formulae, &self.zerobrew_installed_best_effort());
+            (migrated, in_zerobrew, formulae)
+        };
+
+        Ok(MigrationPlan {
+            formulae: Self::topological_sort_with(&formulae, &options.order_constraints)?,
+            casks,
+            skipped,
+            total_formulae,
+            dependencies_loaded: load_details,
+            already_in_zerobrew,
+            already_migrated: already_migrated.into_iter().map(|p| p.name).collect(),
+        })
+    }
+
+    /// Compute a plan for `migrate --save-plan`, annotated with each formula's risk.
+    /// Nothing is filtered by migration state so the plan can be replayed elsewhere.
+    /// Risks are computed from the formulae already loaded for the plan.
+    pub fn save_plan(&self, options: &MigrateOptions) -> Result<SavedPlan> {
+        let plan = self.plan_migration(&MigrateOptions {
+            force: true,
+            ..options.clone()
+        })?;
+        let mut analysis = Self::categorize_packages_with_depth(&plan.formulae, self.max_depth);
+        if self.strict {
+            analysis.apply_strict();
+        }
+        let risks = plan
+            .formulae
+            .iter()
+            .filter_map(|pkg| {
+                let risk = analysis.find(&pkg.name)?.risk.clone();
+                Some((pkg.name.clone(), risk))
+            })
+            .collect();
+        Ok(SavedPlan {
+            format_version: PLAN_FORMAT_VERSION,
+            created_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            homebrew_prefix: self.homebrew_prefix.clone(),
+            risks,
+            plan,
+        })
+    }
+
+    /// Turn a saved plan into one for this machine, keeping its order. Every planned
+    /// formula must be installed in Homebrew here; ones already migrated or already in
+    /// zerobrew are skipped unless `--force` is passed, as with a fresh plan.
+    ///
+    /// Casks are taken from this machine rather than the saved plan: zerobrew can't
+    /// install them, so they are only reported as skipped, and the report should
+    /// list the casks actually installed here.
+    pub fn replay_plan(
+        &self,
+        saved: &SavedPlan,
+        options: &MigrateOptions,
+    ) -> Result<MigrationPlan> {
+        let installed: HashSet<String> = self
+            .list_installed_formulae()?
+            .into_iter()
+            .map(|pkg| pkg.name)
+            .collect();
+        let missing: Vec<&str> = saved
+            .plan
+            .formulae
+            .iter()
+            .map(|pkg| pkg.name.as_str())
+            .filter(|name| !installed.contains(*name))
+            .collect();
+        if !missing.is_empty() {
+            bail!(
+                "The plan names {} formulae that aren't installed in Homebrew here: {}\n\n\
+                 Suggestions:\n\
+                 - Install them with 'brew install' before replaying the plan\n\
+                 - Save a new plan on this machine with 'zb-migrate migrate --save-plan'",
+                missing.len(),
+                missing.join(", ")
+            );
+        }
+
+        let formulae = saved.plan.formulae.clone();
+        let (already_migrated, already_in_zerobrew, formulae) = if options.force {
+            (Vec::new(), Vec::new(), formulae)
+        } else {
+            let (migrated, formulae) = split_already_migrated(formulae, &self.load_state()?);
+            let (in_zerobrew, formulae) =
+                split_already_in_zerobrew(formulae, &self.zerobrew_installed_best_effort());
+            (migrated, in_zerobrew, formulae)
+        };
+        Ok(MigrationPlan {
+            formulae,
+            casks: self.list_installed_casks()?,
+            skipped: saved
+                .plan
+                .skipped
+                .iter()
+                .filter(|(name, _)| installed.contains(name))
+                .cloned()
+                .collect(),
+            total_formulae: installed.len(),
+            dependencies_loaded: saved.plan.dependencies_loaded,
+            already_in_zerobrew,
+            already_migrated: already_migrated.into_iter().map(|p| p.name).collect(),
+        })
+    }
+
+    /// Migrate all packages from Homebrew to Zerobrew.
+    ///
+    /// `on_event` is called before and after each package install so callers can
+    /// render progress. With `options.dry_run` nothing is installed and the report
+    /// only lists the planned and skipped packages.
+    pub fn migrate_all(
+        &self,
+        options: &MigrateOptions,
+        on_event: &mut dyn FnMut(MigrationEvent),
+    ) -> Result<MigrationReport> {
+        let plan = self.plan_migration(options)?;
+        if options.dry_run {
+            return Ok(plan.dry_run_report());
+        }
+        self.execute_plan(&plan, options, on_event)
+    }
+
+    /// Install every formula in the plan via zerobrew and persist the results
+    pub fn execute_plan(
+        &self,
+        plan: &MigrationPlan,
+        options: &MigrateOptions,
+        on_event: &mut dyn FnMut(MigrationEvent),
+    ) -> Result<MigrationReport> {
+        self.execute_plan_with(plan, options, on_event, &mut |_, _| {
+            Ok(PackageChoice::Migrate)
+        })
+    }
+
+    /// Like [`execute_plan`](Self::execute_plan), asking `choose` before each package
+    /// whether to install it.
+    ///
+    /// Every run stops here the same way: before the next package once
+    /// `options.cancel` is set, and after a failure for `fail_fast` / `max_failures`.
+    /// With `options.dry_run` chosen packages are only recorded as planned.
+    pub fn execute_plan_with(
+        &self,
+        plan: &MigrationPlan,
+        options: &MigrateOptions,
+        on_event: &mut dyn FnMut(MigrationEvent),
+        choose: &mut dyn FnMut(usize, &BrewPackage) -> Result<PackageChoice>,
+    ) -> Result<MigrationReport> {
+        let mut report = MigrationReport {
+            dry_run: options.dry_run,
+            ..plan.new_report()
+        };
+        let total = plan.formulae.len();
+
+        for (index, package) in plan.formulae.iter().enumerate() {
+            if options.cancelled() {
+                report.skip_interrupted(&plan.formulae[index..]);
+                break;
+            }
+            match choose(index, package)? {
+                PackageChoice::Migrate => {}
+                PackageChoice::Skip(reason) => {
+                    report.skipped.push((package.name.clone(), reason));
+                    continue;
+                }
+                PackageChoice::Stop => break,
+            }
+            if options.dry_run {
+                report.planned.push(package.name.clone());
+                continue;
+            }
+            on_event(MigrationEvent::Started {
+                index,
+                total,
+                package,
+            });
+            let start = Instant::now();
+            let result = self.migrate_package(package, options)?;
+            on_event(MigrationEvent::Finished {
+                index,
+                total,
+                result: &result,
+                elapsed: start.elapsed(),
+            });
+
+            let failed = matches!(result, MigrateResult::Failed { .. });
+            if failed && options.cancelled() {
+                // The install was most likely killed by the same signal
+                report.skip_interrupted(&plan.formulae[index..]);
+                break;
+            }
+            report.push_result(result);
+            if failed && options.fail_fast {
+                report.skip_remaining(&plan.formulae[index + 1..]);
+                break;
+            }
+            if failed && options.failure_limit_reached(report.failed.len()) {
+                report.skip_over_failure_limit(&plan.formulae[index + 1..]);
+                break;
+            }
+        }
+
+        if !options.dry_run {
+            let action = options.history_action.unwrap_or("migrate");
+            self.finish_report(&mut report, &plan.formulae, action)?;
+        }
+
+        Ok(report)
+    }
+
+    /// Plan a retry of the packages recorded in `failed_packages`, in dependency order.
+    /// Details are only loaded for the formulae being retried.
+    pub fn plan_retry(&self) -> Result<MigrationPlan> {
+        let state = self.load_state()?;
+        let installed = self.list_installed_formulae()?;
+        let mut plan = Self::plan_retry_from(&state, &installed);
+        if !plan.formulae.is_empty() {
+            self.load_package_details(&mut plan.formulae)?;
+            plan.formulae = Self::topological_sort(&plan.formulae)?;
+        }
+        plan.dependencies_loaded = true;
+        Ok(plan)
+    }
+
+    /// Select the failed packages that are still installed in Homebrew;
+    /// the rest are reported as skipped
+    pub fn plan_retry_from(state: &MigrationState, installed: &[BrewPackage]) -> MigrationPlan {
+        let mut plan = MigrationPlan::default();
+        let mut seen = HashSet::new();
+        for (name, _) in &state.failed_packages {
+            if !seen.insert(name.as_str()) {
+                continue;
+            }
+            plan.total_formulae += 1;
+            match installed.iter().find(|p| &p.name == name) {
+                Some(pkg) => plan.formulae.push(pkg.clone()),
+                None => plan
+                    .skipped
+                    .push((name.clone(), "No longer installed in Homebrew".to_string())),
+            }
+        }
+        plan
+    }
+
+    /// Drop formulae installed before the `--since` cutoff, recording them as skipped
+    fn apply_since_filter(
+        &self,
+        formulae: Vec<BrewPackage>,
+        options: &MigrateOptions,
+        skipped: &mut Vec<(String, String)>,
+    ) -> Vec<BrewPackage> {
+        let Some(since) = options.since else {
+            return formulae;
+        };
+
+        let (recent, old) = self.filter_by_install_time(formulae, since);
+        for pkg in old {
+            skipped.push((pkg.name, "older than cutoff".to_string()));
+        }
+        recent
+    }
+
+    /// Topological sort for dependency order
+    pub fn topological_sort(packages: &[BrewPackage]) -> Result<Vec<BrewPackage>> {
+        Self::topological_sort_with(packages, &[])
+    }
+
+    /// Topological sort that also honors `--after` / `--before` constraints between
+    /// packages in `packages`; constraints naming other packages are ignored. Fails if
+    /// a constraint contradicts the dependencies or an earlier constraint.
+    pub fn topological_sort_with(
+        packages: &[BrewPackage],
+        constraints: &[OrderConstraint],
+    ) -> Result<Vec<BrewPackage>> {
+        let mut result = Vec::new();
+        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+        let pkg_map: HashMap<String, &BrewPackage> =
+            packages.iter().map(|p| (p.name.clone(), p)).collect();
+
+        // Extra "comes after" edges, added one at a time so the first contradiction is named
+        let mut extra: HashMap<String, Vec<String>> = HashMap::new();
+        for constraint in constraints {
+            if !pkg_map.contains_key(&constraint.first) || !pkg_map.contains_key(&constraint.then) {
+                continue;
+            }
+            if let Some(path) =
+                Self::order_path(&pkg_map, &extra, &constraint.first, &constraint.then)
+            {
+                bail!(
+                    "Cannot migrate {}: {} already has to come after {} ({}).\n\n\
+                     Suggestions:\n\
+                     - Drop or reverse this --after / --before constraint\n\
+                     - Check the dependency chain with 'zb-migrate analyze --tree'",
+                    constraint,
+                    constraint.first,
+                    constraint.then,
+                    path.join(" → ")
+                );
+            }
+            extra
+                .entry(constraint.then.clone())
+                .or_default()
+                .push(constraint.first.clone());
+        }
+
+        fn visit(
+            name: &str,
+            pkg_map: &HashMap<String, &BrewPackage>,
+            extra: &HashMap<String, Vec<String>>,
+            visited: &mut std::collections::HashSet<String>,
+            result: &mut Vec<BrewPackage>,
+        ) {
+            if visited.contains(name) {
+                return;
+            }
+            visited.insert(name.to_string());
+
+            if let Some(pkg) = pkg_map.get(name) {
+                // Build-only dependencies only affect ordering, so sort them in too
+                let mut deps: Vec<&String> = pkg
+                    .dependencies
+                    .iter()
+                    .chain(&pkg.build_dependencies)
+                    .chain(extra.get(name).into_iter().flatten())
+                    .collect();
+                deps.sort();
+                for dep in deps {
+                    visit(dep, pkg_map, extra, visited, result);
+                }
+                result.push((*pkg).clone());
+            }
+        }
+
+        // Visit in name order so independent packages come out the same on every run
+        let mut roots: Vec<&BrewPackage> = packages.iter().collect();
+        roots.sort_by(|a, b| a.name.cmp(&b.name));
+        for pkg in roots {
+            visit(&pkg.name, &pkg_map, &extra, &mut visited, &mut result);
+        }
+
+        Ok(result)
+    }
+
+    /// The chain `from → … → to` through which `from` has to come after `to`, following
+    /// dependencies and `extra` ordering edges between packages in `pkg_map`
+    fn order_path(
+        pkg_map: &HashMap<String, &BrewPackage>,
+        extra: &HashMap<String, Vec<String>>,
+        from: &str,
+        to: &str,
+    ) -> Option<Vec<String>> {
+        let mut parents: HashMap<&str, &str> = HashMap::new();
+        let mut queue = std::collections::VecDeque::from([from]);
+        while let Some(name) = queue.pop_front() {
+            if name == to {
+                let mut path = vec![to.to_string()];
+                let mut current = to;
+                while let Some(parent) = parents.get(current) {
+                    path.push(parent.to_string());
+                    current = parent;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let Some(pkg) = pkg_map.get(name) else {
+                continue;
+            };
+            let next = pkg
+                .dependencies
+                .iter()
+                .chain(&pkg.build_dependencies)
+                .chain(extra.get(name).into_iter().flatten());
+            for dep in next {
+                if dep != from && pkg_map.contains_key(dep) && !parents.contains_key(dep.as_str()) {
+                    parents.insert(dep, name);
+                    queue.push_back(dep);
+                }
+            }
+        }
+        None
+    }
+
+    /// Expand the named packages with their installed dependency closure.
+    ///
+    /// Returns packages in dependency order (dependencies first). Dependencies that
+    /// should stay in Homebrew are excluded from the plan and reported separately;
+    /// explicitly named packages are always kept.
+    pub fn plan_with_deps(names: &[String], formulae: &[BrewPackage]) -> Result<DependencyPlan> {
+        let pkg_map: HashMap<&str, &BrewPackage> =
+            formulae.iter().map(|p| (p.name.as_str(), p)).collect();
+        let requested: HashSet<&str> = names.iter().map(|n| n.as_str()).collect();
+        let problematic_set: HashSet<&str> = KNOWN_PROBLEMATIC_PACKAGES.iter().copied().collect();
+
+        let mut closure: HashSet<&str> = HashSet::new();
+        let mut stack: Vec<&str> = names
+            .iter()
+            .map(|n| n.as_str())
+            .filter(|n| pkg_map.contains_key(n))
+            .collect();
+        while let Some(name) = stack.pop() {
+            if !closure.insert(name) {
+                continue;
+            }
+            if let Some(pkg) = pkg_map.get(name) {
+                stack.extend(
+                    pkg.dependencies
+                        .iter()
+                        .map(|d| d.as_str())
+                        .filter(|d| pkg_map.contains_key(d)),
+                );
+            }
+        }
+
+        let mut plan = DependencyPlan::default();
+        let mut selected = Vec::new();
+        for pkg in formulae {
+            let name = pkg.name.as_str();
+            if !closure.contains(name) {
+                continue;
+            }
+            if !requested.contains(name) && problematic_set.contains(name) {
+                plan.kept_in_homebrew
+                    .push((pkg.name.clone(), Self::get_problematic_reason(name)));
+            } else {
+                selected.push(pkg.clone());
+            }
+        }
+
+        plan.to_migrate = Self::topological_sort(&selected)?;
+        plan.kept_in_homebrew.sort();
+        Ok(plan)
+    }
+
+    /// Migrated packages in the order cleanup removes them from Homebrew: dependents
+    /// before their dependencies, using the dependencies recorded at migration time.
+    /// Packages unrelated to each other come out in reverse name order.
+    pub fn cleanup_order(state: &MigrationState) -> Result<Vec<String>> {
+        let packages: Vec<BrewPackage> = state.migrated_packages.values().cloned().collect();
+        Ok(Self::topological_sort(&packages)?
+            .into_iter()
+            .rev()
+            .map(|p| p.name)
+            .collect())
+    }
+
+    /// List migrated packages that cleanup would remove from Homebrew, in `cleanup_order`,
+    /// noting whether each is still installed there
+    pub fn cleanup_candidates(&self, state: &MigrationState) -> Result<Vec<CleanupCandidate>> {
+        let installed: HashSet<String> = self
+            .list_installed_formulae()?
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+
+        Ok(Self::cleanup_order(state)?
+            .into_iter()
+            .filter_map(|name| state.migrated_packages.get(&name))
+            .map(|pkg| CleanupCandidate {
+                name: pkg.name.clone(),
+                version: pkg.version.clone(),
+                installed_in_homebrew: installed.contains(&pkg.name),
+            })
+            .collect())
+    }
+
+    /// List package names installed in zerobrew via `zb list`
+    pub fn list_zerobrew_packages(&self) -> Result<HashSet<String>> {
+        if self.verbose {
+            eprintln!("[verbose] Running: zb list");
+        }
+        let output = self
+            .runner
+            .output(&self.zb_bin, &["list"])
+            .context("Failed to run zb list. Is zerobrew installed?")?;
+        if !output.status.success() {
+            bail!(
+                "zb list failed: {}",
+                summarize_install_error(&String::from_utf8_lossy(&output.stderr))
+            );
+        }
+        let mut installed = parse_zb_list(&String::from_utf8_lossy(&output.stdout));
+        // Report aliased formulae under their Homebrew name too, so state lookups match.
+        // A broken aliases.toml is reported by migrations; here the built-ins still apply
+        let aliases = match self.aliases() {
+            Ok(aliases) => aliases.clone(),
+            Err(_) => builtin_aliases(),
+        };
+        for (brew_name, zb_name) in &aliases {
+            if installed.contains(zb_name) {
+                installed.insert(brew_name.clone());
+            }
+        }
+        Ok(installed)
+    }
+
+    /// The built-in aliases overridden by `aliases.toml`, read on first use
+    pub fn aliases(&self) -> Result<&BTreeMap<String, String>> {
+        if let Some(aliases) = self.aliases.get() {
+            return Ok(aliases);
+        }
+        let loaded = load_aliases(&self.aliases_file)?;
+        Ok(self.aliases.get_or_init(|| loaded))
+    }
+
+    /// The name zerobrew knows a Homebrew formula by
+    pub fn zerobrew_name<'a>(&'a self, brew_name: &'a str) -> Result<&'a str> {
+        Ok(self
+            .aliases()?
+            .get(brew_name)
+            .map(String::as_str)
+            .unwrap_or(brew_name))
+    }
+
+    /// Like [`Self::list_zerobrew_packages`], but an empty set when `zb list` fails
+    fn zerobrew_installed_best_effort(&self) -> HashSet<String> {
+        self.list_zerobrew_packages().unwrap_or_else(|e| {
+            if self.verbose {
+                eprintln!("[verbose] Could not list zerobrew packages: {}", e);
+            }
+            HashSet::new()
+        })
+    }
+
+    /// Check that every migrated package is still installed in zerobrew.
+    /// With `check_info`, present packages must also pass `zb info`.
+    pub fn verify_migrated(
+        &self,
+        state: &MigrationState,
+        check_info: bool,
+    ) -> Result<Vec<VerifyResult>> {
+        let installed = self.list_zerobrew_packages()?;
+        let mut results = check_migrated_presence(state, &installed);
+
+        if check_info {
+            for result in results.iter_mut().filter(|r| r.status == VerifyStatus::Ok) {
+                if self.verbose {
+                    eprintln!("[verbose] Running: zb info {}", result.name);
+                }
+                match self.runner.output(&self.zb_bin, &["info", &result.name]) {
+                    Ok(output) if output.status.success() => {}
+                    Ok(output) => {
+                        result.status = VerifyStatus::Broken(summarize_install_error(
+                            &String::from_utf8_lossy(&output.stderr),
+                        ));
+                    }
+                    Err(e) => {
+                        result.status = VerifyStatus::Broken(format!("Failed to run zb: {}", e));
+                    }
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Uninstall a migrated package from Homebrew. Returns whether brew succeeded.
+    ///
+    /// With `ignore_dependencies`, brew removes the package even if other formulae still
+    /// depend on it; without it, brew refuses and the package is left in place.
+    pub fn uninstall_from_homebrew(&self, name: &str, ignore_dependencies: bool) -> Result<bool> {
+        let args = uninstall_args(name, ignore_dependencies);
+        if self.verbose {
+            eprintln!("[verbose] Running: brew {}", args.join(" "));
+        }
+        let status = self
+            .runner
+            .status(&self.brew_bin, &args)
+            .context("Failed to run 'brew uninstall'")?;
+        Ok(status.success())
+    }
+
+    /// Names of formulae with a running `brew services` daemon.
+    ///
+    /// Best-effort: if `brew services` is unavailable or fails, no services are reported.
+    pub fn list_running_services(&self) -> BTreeSet<String> {
+        if self.verbose {
+            eprintln!("[verbose] Running: brew services list --json");
+        }
+        let output = match self
+            .runner
+            .output(&self.brew_bin, &["services", "list", "--json"])
+        {
+            Ok(output) if output.status.success() => output,
+            _ => {
+                if self.verbose {
+                    eprintln!("[verbose] Could not list Homebrew services");
+                }
+                return BTreeSet::new();
+            }
+        };
+        parse_running_services(&String::from_utf8_lossy(&output.stdout)).unwrap_or_else(|e| {
+            if self.verbose {
+                eprintln!("[verbose] Could not parse brew services output: {}", e);
+            }
+            BTreeSet::new()
+        })
+    }
+
+    /// Stop a Homebrew-managed service; returns whether `brew services stop` succeeded
+    pub fn stop_service(&self, name: &str) -> Result<bool> {
+        if self.verbose {
+            eprintln!("[verbose] Running: brew services stop {}", name);
+        }
+        let status = self
+            .runner
+            .status(&self.brew_bin, &["services", "stop", name])
+            .context("Failed to run 'brew services stop'")?;
+        Ok(status.success())
+    }
+
+    /// Detect the installed zerobrew version via `zb --version`
+    fn detect_zerobrew_version(&self) -> Option<String> {
+        let output = self.runner.output(&self.zb_bin, &["--version"]).ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let version = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        if self.verbose {
+            eprintln!("[verbose] zerobrew version: {}", version);
+        }
+        (!version.is_empty()).then_some(version)
+    }
+
+    /// Persist a finished run and fill in what can only be known afterwards:
+    /// the zerobrew version change and Homebrew services still running for
+    /// the packages that moved
+    pub fn finish_report(
+        &self,
+        report: &mut MigrationReport,
+        formulae: &[BrewPackage],
+        action: &str,
+    ) -> Result<()> {
+        report.zerobrew_version_change = self.record_results(report, formulae, action)?;
+        if !report.successful.is_empty() {
+            let running = self.list_running_services();
+            report.running_services = report
+                .successful
+                .iter()
+                .filter(|name| running.contains(*name))
+                .cloned()
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Persist the results of a migration run, tagging each migrated package
+    /// with the zerobrew version that installed it, and log the run in the
+    /// history as `action`.
+    ///
+    /// Returns the previous and current zerobrew versions if they differ.
+    pub fn record_results(
+        &self,
+        report: &MigrationReport,
+        formulae: &[BrewPackage],
+        action: &str,
+    ) -> Result<Option<(String, String)>> {
+        let mut state = self.load_state()?;
+        state.homebrew_prefix = self.homebrew_prefix.clone();
+
+        let zerobrew_version = self.detect_zerobrew_version();
+        let version_change = match (&state.zerobrew_version, &zerobrew_version) {
+            (Some(previous), Some(current))
+                if previous != current && !report.successful.is_empty() =>
+            {
+                Some((previous.clone(), current.clone()))
+            }
+            _ => None,
+        };
+
+        let migrated_at = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        state.apply_report(report, formulae, &zerobrew_version, migrated_at);
+        self.append_history(&HistoryEntry::from_report(
+            action,
+            report,
+            &zerobrew_version,
+            migrated_at,
+        ))?;
+        if zerobrew_version.is_some() {
+            state.zerobrew_version = zerobrew_version;
+        }
+
+        self.save_state(&state)?;
+        Ok(version_change)
+    }
+
+    /// Path of the append-only run history, next to the state file
+    pub fn history_file(&self) -> PathBuf {
+        self.state_file.with_file_name("migration_history.jsonl")
+    }
+
+    /// Append one run to the history log
+    pub fn append_history(&self, entry: &HistoryEntry) -> Result<()> {
+        use std::io::Write;
+
+        let path = self.history_file();
+        let line = serde_json::to_string(entry)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let written = fs::create_dir_all(dir)
+            .and_then(|_| fs::OpenOptions::new().create(true).append(true).open(&path))
+            .and_then(|mut file| writeln!(file, "{}", line));
+        written
+            .with_context(|| unwritable_home_error("Failed to append to migration history", &path))
+    }
+
+    /// Load every recorded run, oldest first
+    pub fn load_history(&self) -> Result<Vec<HistoryEntry>> {
+        let path = self.history_file();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read migration history from {}", path.display()))?;
+        let (entries, malformed) = parse_history(&content);
+        if malformed > 0 && self.verbose {
+            eprintln!(
+                "[verbose] Ignored {} malformed line(s) in {}",
+                malformed,
+                path.display()
+            );
+        }
+        Ok(entries)
+    }
+
+    /// Save migration state
+    pub fn save_state(&self, state: &MigrationState) -> Result<()> {
+        if self.backup_state && self.state_file.exists() {
+            let backup = self.backup_state_file()?;
+            if self.verbose {
+                eprintln!(
+                    "[verbose] Backed up migration state to {}",
+                    backup.display()
+                );
+            }
+        }
+        let json = serde_json::to_string_pretty(state)?;
+        if let Some(parent) = self.state_file.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                unwritable_home_error("Failed to create the zerobrew config directory", parent)
+            })?;
+        }
+        // Written atomically so an interrupted run can't leave a truncated state file
+        write_atomic(&self.state_file, json.as_bytes()).with_context(|| {
+            unwritable_home_error("Failed to write migration state to", &self.state_file)
+        })?;
+        Ok(())
+    }
+
+    /// Fail if Homebrew and zerobrew share an install prefix. Only commands that
+    /// install or uninstall need this; read-only commands work either way.
+    pub fn check_separate_prefixes(&self) -> Result<()> {
+        if let Some(zb_prefix) = &self.zerobrew_prefix {
+            if prefixes_overlap(&self.homebrew_prefix, zb_prefix) {
+                bail!(
+                    "Homebrew and zerobrew share an install prefix:\n\
+                       Homebrew: {}\n\
+                       zerobrew: {}\n\n\
+                     Migrating would make both managers write the same symlinks.\n\n\
+                     Suggestions:\n\
+                     - Reinstall zerobrew into its own prefix (the default is /opt/zerobrew)\n\
+                     - Check HOMEBREW_PREFIX and zerobrew's configuration for overrides",
+                    self.homebrew_prefix.display(),
+                    zb_prefix.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Path of the lock file guarding state-mutating runs, next to the state file
+    pub fn lock_file(&self) -> PathBuf {
+        self.state_file.with_file_name("migrate.lock")
+    }
+
+    /// Take the exclusive migration lock, failing if another process holds it.
+    ///
+    /// This is an OS-level advisory lock: it is released when the returned guard is
+    /// dropped or the process exits, so a crashed run never leaves a stale lock behind.
+    pub fn acquire_lock(&self) -> Result<MigrationLock> {
+        use std::io::Write;
+
+        let path = self.lock_file();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                unwritable_home_error("Failed to create the zerobrew config directory", parent)
+            })?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+
+        if !try_lock_exclusive(&file)
+            .with_context(|| format!("Failed to lock {}", path.display()))?
+        {
+            let holder = fs::read_to_string(&path).unwrap_or_default();
+            let holder = holder.trim();
+            bail!(
+                "Another migration is in progress{}.\n\n\
+                 Running two migrations at once would corrupt {}.\n\n\
+                 Suggestions:\n\
+                 - Wait for the other zb-migrate process to finish\n\
+                 - Check for it with: ps aux | grep zb-migrate",
+                if holder.is_empty() {
+                    String::new()
+                } else {
+                    format!(" (pid {})", holder)
+                },
+                self.state_file.display()
+            );
+        }
+
+        // Record our pid for the error message above; purely informational
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+        if self.verbose {
+            eprintln!("[verbose] Acquired migration lock {}", path.display());
+        }
+        Ok(MigrationLock { _file: file })
+    }
+
+    /// Copy the current state file to `migration_state.<timestamp>.json.bak`
+    /// (Unix time in milliseconds), then delete all but the newest `keep_backups`
+    /// backups. An existing backup is never overwritten: if the name is taken,
+    /// the next free timestamp is used.
+    pub fn backup_state_file(&self) -> Result<PathBuf> {
+        let mut timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let mut source = fs::File::open(&self.state_file).with_context(|| {
+            format!(
+                "Failed to read migration state {}",
+                self.state_file.display()
+            )
+        })?;
+        let (backup, mut file) = loop {
+            let backup = self
+                .state_file
+                .with_file_name(format!("migration_state.{}.json.bak", timestamp));
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&backup)
+            {
+                Ok(file) => break (backup, file),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => timestamp += 1,
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Failed to back up migration state to {}", backup.display())
+                    })
+                }
+            }
+        };
+        std::io::copy(&mut source, &mut file).with_context(|| {
+            format!("Failed to back up migration state to {}", backup.display())
+        })?;
+
+        let backups = self.list_state_backups()?;
+        let excess = backups.len().saturating_sub(self.keep_backups.max(1));
+        for old in &backups[..excess] {
+            if let Err(e) = fs::remove_file(old) {
+                if self.verbose {
+                    eprintln!(
+                        "[verbose] Failed to remove old backup {}: {}",
+                        old.display(),
+                        e
+                    );
+                }
+            }
+        }
+        Ok(backup)
+    }
+
+    /// State backups next to the state file, oldest first
+    pub fn list_state_backups(&self) -> Result<Vec<PathBuf>> {
+        let Some(dir) = self.state_file.parent() else {
+            return Ok(Vec::new());
+        };
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut backups: Vec<(u64, PathBuf)> = fs::read_dir(dir)
+            .with_context(|| format!("Failed to list {}", dir.display()))?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let timestamp = parse_backup_timestamp(name.to_str()?)?;
+                Some((timestamp, entry.path()))
+            })
+            .collect();
+        backups.sort();
+        Ok(backups.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Replace the migration state with a backup (or any saved state file).
+    /// The file is validated before anything is overwritten, and the current
+    /// state is always backed up first so a restore can itself be undone.
+    pub fn restore_state(&self, backup: &Path) -> Result<MigrationState> {
+        let content = fs::read_to_string(backup)
+            .with_context(|| format!("Failed to read {}", backup.display()))?;
+        let state = state_from_json(&content, backup, || {
+            format!(
+                "{} is not a valid migration state file.\n\n\
+                 Suggestion: list available backups with 'zb-migrate restore-state --list'",
+                backup.display()
+            )
+        })?;
+        if !self.backup_state && self.state_file.exists() {
+            self.backup_state_file()?;
+        }
+        self.save_state(&state)?;
+        Ok(state)
+    }
+
+    /// Remove migrated entries for packages no longer installed in zerobrew or
+    /// Homebrew. With `dry_run` the state file is left untouched.
+    pub fn prune_state(&self, dry_run: bool) -> Result<Vec<String>> {
+        let mut state = self.load_state()?;
+        let zb_installed = self.list_zerobrew_packages()?;
+        let brew_installed: HashSet<String> = self
+            .list_installed_formulae()?
+            .into_iter()
+            .map(|pkg| pkg.name)
+            .collect();
+        let pruned = state.prune_stale(&zb_installed, &brew_installed);
+        if !dry_run && !pruned.is_empty() {
+            self.save_state(&state)?;
+        }
+        Ok(pruned)
+    }
+
+    /// Load migration state
+    pub fn load_state(&self) -> Result<MigrationState> {
+        if self.state_file.exists() {
+            let content = fs::read_to_string(&self.state_file).with_context(|| {
+                format!(
+                    "Failed to read migration state from {}",
+                    self.state_file.display()
+                )
+            })?;
+            if content.trim().is_empty() && self.verbose {
+                eprintln!(
+                    "[verbose] {} is empty; starting with no migration state",
+                    self.state_file.display()
+                );
+            }
+            parse_state(&content, &self.state_file)
+        } else {
+            Ok(MigrationState::default())
+        }
+    }
+
+    /// Analyze all installed packages and categorize them by migration risk.
+    ///
+    /// This method:
+    /// 1. Lists all installed Homebrew packages
+    /// 2. Checks which ones are in the known problematic list
+    /// 3. Analyzes dependency chains to find packages that depend on problematic ones
+    /// 4. Returns a categorized report with recommendations
+    pub fn analyze_packages(&self, include_casks: bool) -> Result<AnalysisReport> {
+        // Get all installed packages with their dependencies
+        let mut packages = self.list_installed_formulae_detailed()?;
+        if include_casks {
+            packages.extend(self.list_installed_casks()?);
+        }
+
+        self.time_analysis(|| {
+            let mut report = Self::categorize_packages_with_depth(&packages, self.max_depth);
+            if self.strict {
+                report.apply_strict();
+            }
+            report.longest_chain = longest_dependency_chain(&packages)?;
+            Ok(report)
+        })
+    }
+
+    /// Categorize already-loaded packages by migration risk
+    pub fn categorize_packages(packages: &[BrewPackage]) -> AnalysisReport {
+        Self::categorize_packages_with_depth(packages, None)
+    }
+
+    /// Categorize packages, following transitive dependencies at most `max_depth` levels
+    /// down (`None` for no limit)
+    pub fn categorize_packages_with_depth(
+        packages: &[BrewPackage],
+        max_depth: Option<usize>,
+    ) -> AnalysisReport {
+        let total = packages.len();
+
+        // Build a set of problematic package names for quick lookup
+        let problematic_set: HashSet<&str> = KNOWN_PROBLEMATIC_PACKAGES.iter().copied().collect();
+
+        // Build a map of package name -> package for dependency lookup
+        let pkg_map: HashMap<&str, &BrewPackage> =
+            packages.iter().map(|p| (p.name.as_str(), p)).collect();
+
+        let mut report = AnalysisReport::new();
+        report.total_packages = total;
+        let mut transitive_memo = HashMap::new();
+
+        for pkg in packages {
+            // Check if this package is itself problematic
+            if problematic_set.contains(pkg.name.as_str()) {
+                let reason = Self::get_problematic_reason(&pkg.name);
+                report.should_keep_in_homebrew.push(PackageAnalysis {
+                    name: pkg.name.clone(),
+                    version: pkg.version.clone(),
+                    risk: MigrationRisk::KeepInHomebrew,
+                    reason,
+                    problematic_dependencies: Vec::new(),
+                    is_cask: pkg.is_cask,
+                    risk_score: KEEP_IN_HOMEBREW_SCORE,
+                });
+                continue;
+            }
+
+            // Check if this package depends on any problematic packages
+            let problematic_deps: Vec<String> = pkg
+                .dependencies
+                .iter()
+                .filter(|dep| problematic_set.contains(dep.as_str()))
+                .cloned()
+                .collect();
+
+            if !problematic_deps.is_empty() {
+                // Problematic packages only reachable through other dependencies add to
+                // the score too
+                let transitive_only: Vec<String> = Self::reachable_problematic(
+                    &pkg.name,
+                    &pkg_map,
+                    &problematic_set,
+                    max_depth,
+                    &mut transitive_memo,
+                )
+                .into_iter()
+                .filter(|name| !problematic_deps.iter().any(|dep| dep == name))
+                .map(|name| name.to_string())
+                .collect();
+                report.risky.push(PackageAnalysis {
+                    name: pkg.name.clone(),
+                    version: pkg.version.clone(),
+                    risk: MigrationRisk::Risky,
+                    risk_score: Self::risk_score(&problematic_deps, DIRECT_DEP_WEIGHT)
+                        + Self::risk_score(&transitive_only, TRANSITIVE_DEP_WEIGHT),
+                    reason: format!(
+                        "Depends on {} problematic package(s)",
+                        problematic_deps.len()
+                    ),
+                    problematic_dependencies: problematic_deps,
+                    is_cask: pkg.is_cask,
+                });
+            } else {
+                // Check transitive dependencies (dependencies of dependencies)
+                let transitive_problematic: Vec<String> = Self::reachable_problematic(
+                    &pkg.name,
+                    &pkg_map,
+                    &problematic_set,
+                    max_depth,
+                    &mut transitive_memo,
+                )
+                .into_iter()
+                .map(|name| name.to_string())
+                .collect();
+
+                // Build-only dependencies are loaded on request and matter least
+                let build_problematic: Vec<String> = pkg
+                    .build_dependencies
+                    .iter()
+                    .filter(|dep| problematic_set.contains(dep.as_str()))
+                    .cloned()
+                    .collect();
+
+                if !transitive_problematic.is_empty() {
+                    report.risky.push(PackageAnalysis {
+                        name: pkg.name.clone(),
+                        version: pkg.version.clone(),
+                        risk: MigrationRisk::Risky,
+                        risk_score: Self::risk_score(
+                            &transitive_problematic,
+                            TRANSITIVE_DEP_WEIGHT,
+                        ),
+                        reason: format!(
+                            "Has transitive dependency on {} problematic package(s)",
+                            transitive_problematic.len()
+                        ),
+                        problematic_dependencies: transitive_problematic,
+                        is_cask: pkg.is_cask,
+                    });
+                } else if !build_problematic.is_empty() {
+                    report.risky.push(PackageAnalysis {
+                        name: pkg.name.clone(),
+                        version: pkg.version.clone(),
+                        risk: MigrationRisk::Risky,
+                        risk_score: Self::risk_score(&build_problematic, BUILD_DEP_WEIGHT),
+                        reason: format!(
+                            "Build-time only dependency on {} problematic package(s)",
+                            build_problematic.len()
+                        ),
+                        problematic_dependencies: build_problematic,
+                        is_cask: pkg.is_cask,
+                    });
+                } else if pkg.keg_only {
+                    report.risky.push(PackageAnalysis {
+                        name: pkg.name.clone(),
+                        version: pkg.version.clone(),
+                        risk: MigrationRisk::Risky,
+                        risk_score: KEG_ONLY_SCORE,
+                        reason: KEG_ONLY_REASON.to_string(),
+                        problematic_dependencies: Vec::new(),
+                        is_cask: pkg.is_cask,
+                    });
+                } else if pkg.no_bottle {
+                    report.risky.push(PackageAnalysis {
+                        name: pkg.name.clone(),
+                        version: pkg.version.clone(),
+                        risk: MigrationRisk::Risky,
+                        risk_score: NO_BOTTLE_SCORE,
+                        reason: NO_BOTTLE_REASON.to_string(),
+                        problematic_dependencies: Vec::new(),
+                        is_cask: pkg.is_cask,
+                    });
+                } else {
+                    // Safe to migrate
+                    report.safe_to_migrate.push(PackageAnalysis {
+                        name: pkg.name.clone(),
+                        version: pkg.version.clone(),
+                        risk: MigrationRisk::Safe,
+                        reason: if pkg.is_cask {
+                            "Cask: no known issues, but cask migration is not yet supported"
+                                .to_string()
+                        } else {
+                            "No known problematic dependencies".to_string()
+                        },
+                        problematic_dependencies: Vec::new(),
+                        is_cask: pkg.is_cask,
+                        risk_score: 0,
+                    });
+                }
+            }
+        }
+
+        // Keg-only and bottle-less packages that are risky for other reasons rank higher
+        for entry in &mut report.risky {
+            let Some(pkg) = pkg_map.get(entry.name.as_str()) else {
+                continue;
+            };
+            if entry.reason != KEG_ONLY_REASON && pkg.keg_only {
+                entry.risk_score += KEG_ONLY_SCORE;
+                entry.reason.push_str(" (keg-only)");
+            }
+            if entry.reason != NO_BOTTLE_REASON && pkg.no_bottle {
+                entry.risk_score += NO_BOTTLE_SCORE;
+                entry.reason.push_str(" (no bottle)");
+            }
+        }
+
+        // Sort each category alphabetically
+        report.safe_to_migrate.sort_by(|a, b| a.name.cmp(&b.name));
+        // Worst risky packages first so users tackle them before the rest
+        report.risky.sort_by(|a, b| {
+            b.risk_score
+                .cmp(&a.risk_score)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        report
+            .should_keep_in_homebrew
+            .sort_by(|a, b| a.name.cmp(&b.name));
+
+        report
+    }
+
+    /// Explain the risk classification of one package among `packages`
+    pub fn explain_package(
+        packages: &[BrewPackage],
+        name: &str,
+        strict: bool,
+    ) -> Result<PackageExplanation> {
+        let Some(pkg) = packages.iter().find(|p| p.name == name) else {
+            let suggestions = suggest_similar(name, packages.iter().map(|p| p.name.as_str()), 3);
+            let hint = if suggestions.is_empty() {
+                String::new()
+            } else {
+                format!("\n- Did you mean: {}?", suggestions.join(", "))
+            };
+            bail!(
+                "Package '{}' is not installed in Homebrew.\n\n\
+                 Suggestions:\n\
+                 - Check the name with 'zb-migrate list'{}",
+                name,
+                hint
+            );
+        };
+
+        let mut report = Self::categorize_packages(packages);
+        if strict {
+            report.apply_strict();
+        }
+        let analysis = report
+            .find(name)
+            .cloned()
+            .context("Analysis is missing the package")?;
+
+        let problematic_set: HashSet<&str> = KNOWN_PROBLEMATIC_PACKAGES.iter().copied().collect();
+        let pkg_map: HashMap<&str, &BrewPackage> =
+            packages.iter().map(|p| (p.name.as_str(), p)).collect();
+        let direct_problematic = pkg
+            .dependencies
+            .iter()
+            .filter(|dep| problematic_set.contains(dep.as_str()))
+            .cloned()
+            .collect();
+
+        // Breadth-first, so the first path found to each package is a shortest one
+        let mut parent: HashMap<&str, &str> = HashMap::new();
+        let mut queue = std::collections::VecDeque::from([pkg.name.as_str()]);
+        let mut chains = Vec::new();
+        while let Some(current) = queue.pop_front() {
+            let Some(current_pkg) = pkg_map.get(current) else {
+                continue;
+            };
+            for dep in &current_pkg.dependencies {
+                let dep = dep.as_str();
+                if dep == pkg.name || parent.contains_key(dep) {
+                    continue;
+                }
+                parent.insert(dep, current);
+                queue.push_back(dep);
+                if problematic_set.contains(dep) {
+                    let mut chain = vec![dep.to_string()];
+                    let mut node = dep;
+                    while let Some(&up) = parent.get(node) {
+                        chain.push(up.to_string());
+                        node = up;
+                    }
+                    chain.reverse();
+                    chains.push(chain);
+                }
+            }
+        }
+
+        let mut reasons = BTreeMap::new();
+        let involved = chains
+            .iter()
+            .filter_map(|chain| chain.last())
+            .chain(&analysis.problematic_dependencies)
+            .map(String::as_str)
+            .chain(problematic_set.contains(name).then_some(name));
+        for dep in involved {
+            reasons.insert(dep.to_string(), Self::get_problematic_reason(dep));
+        }
+
+        Ok(PackageExplanation {
+            analysis,
+            direct_problematic,
+            chains,
+            reasons,
+            no_bottle: pkg.no_bottle,
+        })
+    }
+
+    /// Collect a read-only overview of the local Homebrew installation
+    pub fn collect_stats(&self) -> Result<HomebrewStats> {
+        let formulae = self.list_installed_formulae_detailed()?;
+        let casks = self.list_installed_casks()?;
+        let analysis = Self::categorize_packages(&formulae);
+
+        Ok(HomebrewStats {
+            total_formulae: formulae.len(),
+            total_casks: casks.len(),
+            pinned: formulae.iter().filter(|p| p.pinned).count(),
+            from_third_party_taps: formulae.iter().filter(|p| p.tap.is_some()).count(),
+            safe_to_migrate: analysis.safe_to_migrate.len(),
+            risky: analysis.risky.len(),
+            keep_in_homebrew: analysis.should_keep_in_homebrew.len(),
+            most_depended_upon: most_depended_upon(&formulae, 10),
+            longest_chain: longest_dependency_chain(&formulae)?,
+        })
+    }
+
+    /// Problematic packages reachable through `name`'s runtime dependencies, looking at
+    /// most `max_depth` levels down (`None` for no limit; 1 means direct dependencies only).
+    ///
+    /// Results are memoized per package and remaining depth, so analyzing a whole
+    /// installation walks each package's subtree once instead of once per dependent.
+    /// Without a limit, packages in a dependency cycle all reach the same set, so the
+    /// cycle is found first (see [`SccWalk`]) and memoized as a whole. With a limit the
+    /// remaining depth shrinks at every step, so cycles end on their own.
+    fn reachable_problematic<'a>(
+        name: &'a str,
+        pkg_map: &HashMap<&'a str, &'a BrewPackage>,
+        problematic_set: &HashSet<&str>,
+        max_depth: Option<usize>,
+        memo: &mut HashMap<(&'a str, Option<usize>), BTreeSet<&'a str>>,
+    ) -> BTreeSet<&'a str> {
+        if max_depth == Some(0) {
+            return BTreeSet::new();
+        }
+        if let Some(found) = memo.get(&(name, max_depth)) {
+            return found.clone();
+        }
+        if max_depth.is_none() {
+            SccWalk::default().visit(name, pkg_map, problematic_set, memo);
+            return memo[&(name, None)].clone();
+        }
+
+        let mut found = BTreeSet::new();
+        if let Some(pkg) = pkg_map.get(name) {
+            for dep in &pkg.dependencies {
+                if problematic_set.contains(dep.as_str()) {
+                    found.insert(dep.as_str());
+                }
+                found.extend(Self::reachable_problematic(
+                    dep,
+                    pkg_map,
+                    problematic_set,
+                    max_depth.map(|depth| depth - 1),
+                    memo,
+                ));
+            }
+        }
+        memo.insert((name, max_depth), found.clone());
+        found
+    }
+
+    /// Score a set of problematic dependencies, weighting each by its severity
+    fn risk_score(problematic_deps: &[String], weight: u32) -> u32 {
+        problematic_deps
+            .iter()
+            .map(|dep| Self::problematic_severity(dep) * weight)
+            .sum()
+    }
+
+    /// How disruptive a problematic package is: libraries that cause link
+    /// conflicts (SSL, Python) rank above the rest
+    fn problematic_severity(name: &str) -> u32 {
+        match name {
+            n if n.starts_with("openssl") || n == "libressl" => 3,
+            n if n.starts_with("python@") || n == "python" => 3,
+            _ => 2,
+        }
+    }
+
+    /// Get a human-readable reason why a package is problematic
+    fn get_problematic_reason(name: &str) -> String {
+        match name {
+            // SSL/TLS
+            n if n.starts_with("openssl") => {
+                "Core SSL/TLS library - many packages link against it".to_string()
+            }
+            "gnutls" => "GNU TLS library - system-level security dependency".to_string(),
+            "libressl" => "LibreSSL - alternative SSL library with wide usage".to_string(),
+            n if n.starts_with("libssh") => {
+                "SSH library - security-critical dependency".to_string()
+            }
+
+            // Python
+            n if n.starts_with("python@") || n == "python" => {
+                "Python runtime - complex virtual environment and pip dependencies".to_string()
+            }
+
+            // Event libraries
+            "libevent" => "Event notification library - used by many network tools".to_string(),
+            "libuv" => "Async I/O library - core dependency for Node.js ecosystem".to_string(),
+            "libev" => "Event loop library - embedded in many applications".to_string(),
+
+            // HTTP/networking
+            "nghttp2" => "HTTP/2 library - used by curl and many HTTP clients".to_string(),
+            "curl" => "URL transfer library - fundamental networking tool".to_string(),
+            "wget" => "Network downloader - may have complex SSL dependencies".to_string(),
+
+            // GLib ecosystem
+            "gobject-introspection" => {
+                "GObject introspection - required for many GTK/GNOME tools".to_string()
+            }
+            "glib" => "GLib core library - foundation for GTK ecosystem".to_string(),
+            n if n.starts_with("gtk") || n == "cairo" || n == "pango" => {
+                "GTK/graphics library - complex native rendering dependencies".to_string()
+            }
+            "gdk-pixbuf" => "Image loading library - part of GTK stack".to_string(),
+
+            // Node.js
+            n if n.starts_with("node") => {
+                "Node.js runtime - native modules require specific linking".to_string()
+            }
+
+            // Databases
+            n if n.starts_with("postgresql") || n == "libpq" => {
+                "PostgreSQL client - complex library dependencies".to_string()
+            }
+            "mysql-client" => "MySQL client - database connectivity library".to_string(),
+
+            // Compression
+            "zlib" => "Core compression library - nearly universal dependency".to_string(),
+            "xz" | "lz4" | "zstd" | "brotli" => {
+                "Compression library - widely linked by other packages".to_string()
+            }
+
+            // Image libraries
+            "libpng" | "libjpeg" | "libtiff" | "webp" => {
+                "Image format library - many graphics tools depend on it".to_string()
+            }
+
+            // ICU
+            "icu4c" => {
+                "Unicode library - heavily depended upon for internationalization".to_string()
+            }
+
+            // Build tools
+            "pkg-config" => "Build configuration tool - used during compilation".to_string(),
+            "cmake" => "Build system - required for building many packages".to_string(),
+            "autoconf" | "automake" | "libtool" => {
+                "GNU build tools - required for package compilation".to_string()
+            }
+
+            // Ruby
+            n if n.starts_with("ruby@") || n == "ruby" => {
+                "Ruby runtime - gem native extensions require specific linking".to_string()
+            }
+
+            // Other
+            "gettext" => "Internationalization library - widely used for translations".to_string(),
+            "readline" => "Command-line editing library - used by many CLI tools".to_string(),
+            "ncurses" => "Terminal UI library - fundamental for terminal apps".to_string(),
+            "pcre" | "pcre2" => {
+                "Regular expression library - used by many text processing tools".to_string()
+            }
+
+            _ => "Known to cause migration issues".to_string(),
+        }
+    }
+}
+
+/// Whether an install time falls on or after the cutoff.
+/// Unknown install times are treated as recent so they are not silently dropped.
+fn is_installed_after(installed: Option<SystemTime>, cutoff: SystemTime) -> bool {
+    installed.map(|t| t >= cutoff).unwrap_or(true)
+}
+
+/// Extract the installed version from `zb info <name>` output. Accepts either a
+/// `version: X` line or a header line of the form `<name> X`.
+pub fn parse_zb_info_version(output: &str, name: &str) -> Option<String> {
+    let lines: Vec<&str> = output.lines().map(str::trim).collect();
+
+    let labeled = lines.iter().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        let key = key.trim().to_lowercase();
+        (key == "version" || key == "installed").then(|| value.trim())
+    });
+    let headed = || {
+        lines.iter().find_map(|line| {
+            let mut parts = line.trim_start_matches("==> ").split_whitespace();
+            (parts.next()? == name).then(|| parts.next()).flatten()
+        })
+    };
+
+    labeled
+        .or_else(headed)
+        .map(|v| v.trim_end_matches(',').to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Compare a Homebrew version with a zerobrew one, ignoring Homebrew's
+/// `_N` revision suffix (e.g. `1.7.1_1` matches `1.7.1`)
+pub fn versions_match(homebrew: &str, zerobrew: &str) -> bool {
+    strip_revision(homebrew) == strip_revision(zerobrew)
+}
+
+/// Drop a Homebrew bottle revision suffix: `1.7.1_1` becomes `1.7.1`
+fn strip_revision(version: &str) -> &str {
+    match version.rsplit_once('_') {
+        Some((base, rev)) if !rev.is_empty() && rev.chars().all(|c| c.is_ascii_digit()) => base,
+        _ => version,
+    }
+}
+
+/// The `name@version` zerobrew is asked for with `--pin-versions`, or `None` when
+/// the Homebrew version isn't known, is a `--HEAD` build, or the formula is already
+/// versioned (`openssl@3`), since `openssl@3@3.2.0` isn't a name zerobrew accepts
+pub fn pinned_install_name(install_name: &str, version: &str) -> Option<String> {
+    (version != UNKNOWN_VERSION && !is_head_version(version) && !install_name.contains('@'))
+        .then(|| format!("{}@{}", install_name, strip_revision(version)))
+}
+
+/// Reduce zerobrew's stderr to a short one-line summary for reports.
+/// Prefers the first line starting with "error", falling back to the last line.
+pub fn summarize_install_error(stderr: &str) -> String {
+    const MAX_LEN: usize = 160;
+
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect();
+    let line = lines
+        .iter()
+        .find(|l| l.to_lowercase().starts_with("error"))
+        .or(lines.last())
+        .copied()
+        .unwrap_or("zb install failed without error output");
+
+    if line.chars().count() > MAX_LEN {
+        let truncated: String = line.chars().take(MAX_LEN).collect();
+        format!("{}…", truncated)
+    } else {
+        line.to_string()
+    }
+}
+
+/// Why a package failed to migrate, for callers that need to decide what to do about it
+/// (e.g. only retrying network errors) rather than read the error text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum MigrateError {
+    /// Zerobrew has no formula by that name
+    NotFoundInZerobrew,
+    /// Downloading the package or its metadata failed
+    NetworkError,
+    /// A dependency could not be found or installed
+    DependencyMissing,
+    /// The install was killed for taking too long
+    Timeout,
+    /// Anything else, with the error summary
+    Other(String),
+}
+
+impl MigrateError {
+    /// Whether retrying the same install later could plausibly succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::NetworkError | Self::Timeout)
+    }
+}
+
+impl std::fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFoundInZerobrew => write!(f, "not found in zerobrew"),
+            Self::NetworkError => write!(f, "network error"),
+            Self::DependencyMissing => write!(f, "dependency missing"),
+            Self::Timeout => write!(f, "timed out"),
+            Self::Other(detail) => write!(f, "{}", detail),
+        }
+    }
+}
+
+/// Exit status `timeout(1)` uses when it kills the command
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Classify a failed `zb` command from its exit code and stderr.
+///
+/// Only `Error:` lines are considered, and phrases must stand on their own, so
+/// package names and paths (`gnutls`, `NetworkManager`) or progress output never
+/// decide the kind. Dependency errors are checked before "not found" since they
+/// usually mention a dependency that was not found.
+pub fn classify_install_error(code: Option<i32>, stderr: &str) -> MigrateError {
+    let errors: Vec<String> = stderr
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim().to_lowercase();
+            line.strip_prefix("error:")
+                .map(|rest| rest.trim().to_string())
+        })
+        .collect();
+    let mentions = |phrases: &[&str]| {
+        errors
+            .iter()
+            .any(|line| phrases.iter().any(|phrase| contains_phrase(line, phrase)))
+    };
+
+    if code == Some(TIMEOUT_EXIT_CODE) || mentions(&["timed out"]) {
+        MigrateError::Timeout
+    } else if mentions(&[
+        "could not resolve host",
+        "connection refused",
+        "connection reset",
+        "network is unreachable",
+        "network error",
+        "failed to download",
+        "download failed",
+        "tls handshake",
+        "http error",
+    ]) {
+        MigrateError::NetworkError
+    } else if mentions(&["dependency", "dependencies"]) {
+        MigrateError::DependencyMissing
+    } else if mentions(&[
+        "not found",
+        "no formula",
+        "no available formula",
+        "unknown formula",
+    ]) {
+        MigrateError::NotFoundInZerobrew
+    } else {
+        MigrateError::Other(summarize_install_error(stderr))
+    }
+}
+
+/// Whether `phrase` occurs in `text` as whole words, not inside a name like
+/// `gnutls` or `dependency-check`
+fn contains_phrase(text: &str, phrase: &str) -> bool {
+    let is_name_char = |c: char| c.is_alphanumeric() || matches!(c, '-' | '_' | '@');
+    text.match_indices(phrase).any(|(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + phrase.len()..].chars().next();
+        !before.is_some_and(is_name_char) && !after.is_some_and(is_name_char)
+    })
+}
+
+#[derive(Debug)]
+pub enum MigrateResult {
+    Success {
+        name: String,
+        /// Version that was installed in Homebrew
+        version: String,
+        /// Version zerobrew reports after the install, if it could be determined
+        installed_version: Option<String>,
+        /// `--pin-versions` asked for the Homebrew version but zerobrew didn't have it,
+        /// so the latest version was installed instead
+        pin_fallback: bool,
+        /// The name it was installed under when an alias mapped it to a different one
+        zerobrew_name: Option<String>,
+        /// How zerobrew was asked to install it
+        install_mode: InstallMode,
+    },
+    Failed {
+        name: String,
+        /// Short error summary
+        reason: String,
+        /// What kind of failure this was
+        error: MigrateError,
+        /// Full install log, if one was written
+        log: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub total_formulae: usize,
+    pub total_casks: usize,
+    pub successful: Vec<String>,
+    pub failed: Vec<(String, String)>,
+    pub skipped: Vec<(String, String)>,
+    /// Previous and current zerobrew versions, when they changed since the last run
+    pub zerobrew_version_change: Option<(String, String)>,
+    /// Install log paths for failed packages, keyed by package name
+    pub logs: HashMap<String, PathBuf>,
+    /// Classified errors for failed packages, keyed by package name
+    pub errors: HashMap<String, MigrateError>,
+    /// Versions zerobrew reports for successfully migrated packages
+    pub installed_versions: HashMap<String, String>,
+    /// Packages `--pin-versions` couldn't install at their Homebrew version
+    pub pin_fallbacks: Vec<PinFallback>,
+    /// Packages installed under a different zerobrew name, as `(homebrew, zerobrew)`
+    pub renamed: Vec<(String, String)>,
+    /// Packages installed with `--build-from-source` or `--force-bottle`
+    pub install_modes: Vec<(String, InstallMode)>,
+    /// Packages a dry run would migrate (in interactive mode, the ones chosen)
+    pub planned: Vec<String>,
+    /// Nothing was installed; the report only shows what a real run would do
+    pub dry_run: bool,
+    /// Packages zerobrew installed at a different version than Homebrew had
+    pub version_drift: Vec<VersionDrift>,
+    /// `--HEAD` formulae, installed at the stable version instead
+    pub head_installs: Vec<HeadInstall>,
+    /// Migrated packages whose Homebrew service is still running
+    pub running_services: Vec<String>,
+    /// The run was stopped by a signal before every package was attempted
+    pub interrupted: bool,
+    /// The run was aborted after reaching `--max-failures`
+    pub failure_limit_reached: bool,
+    /// Formulae found already installed in zerobrew, to be recorded as migrated
+    pub already_in_zerobrew: Vec<BrewPackage>,
+}
+
+/// A package whose zerobrew version differs from the Homebrew one it replaced
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionDrift {
+    pub name: String,
+    pub homebrew_version: String,
+    pub zerobrew_version: String,
+}
+
+/// A `--HEAD` formula, which zerobrew replaced with the stable version
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeadInstall {
+    pub name: String,
+    /// The Homebrew HEAD version, e.g. `HEAD-abc1234`
+    pub homebrew_version: String,
+    /// The stable version zerobrew installed, if it could be determined
+    pub zerobrew_version: Option<String>,
+}
+
+/// A package `--pin-versions` installed at the latest version because zerobrew
+/// didn't have the Homebrew one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinFallback {
+    pub name: String,
+    /// The Homebrew version that was requested
+    pub requested_version: String,
+    /// The version zerobrew installed instead, if it could be determined
+    pub installed_version: Option<String>,
+}
+
+/// Packages selected for a bulk migration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrationPlan {
+    /// Formulae to migrate, dependencies first
+    pub formulae: Vec<BrewPackage>,
+    /// Installed casks (not yet supported by zerobrew)
+    pub casks: Vec<BrewPackage>,
+    /// Formulae excluded from the run, with the reason
+    pub skipped: Vec<(String, String)>,
+    /// Number of installed formulae before filtering
+    pub total_formulae: usize,
+    /// Whether `formulae` carry their dependencies, i.e. the order is exact
+    #[serde(default)]
+    pub dependencies_loaded: bool,
+    /// Formulae zerobrew already has (e.g. installed with `zb` directly); recorded as
+    /// migrated without reinstalling
+    #[serde(default)]
+    pub already_in_zerobrew: Vec<BrewPackage>,
+    /// Formulae an earlier run already migrated; skipped unless `--force` is passed
+    #[serde(default)]
+    pub already_migrated: Vec<String>,
+}
+
+impl MigrationPlan {
+    /// Start a report for this plan, with casks and filtered formulae already skipped
+    pub fn new_report(&self) -> MigrationReport {
+        let mut report = MigrationReport {
+            total_formulae: self.total_formulae,
+            total_casks: self.casks.len(),
+            skipped: self.skipped.clone(),
+            already_in_zerobrew: self.already_in_zerobrew.clone(),
+            ..Default::default()
+        };
+        for name in &self.already_migrated {
+            report
+                .skipped
+                .push((name.clone(), ALREADY_MIGRATED_REASON.to_string()));
+        }
+        for pkg in &self.already_in_zerobrew {
+            report
+                .skipped
+                .push((pkg.name.clone(), ALREADY_IN_ZEROBREW_REASON.to_string()));
+        }
+        // Note: Casks are currently not supported by zerobrew
+        for pkg in &self.casks {
+            report
+                .skipped
+                .push((pkg.name.clone(), "Casks not yet supported".to_string()));
+        }
+        report
+    }
+
+    /// Start a dry-run report for this plan, listing every formula as planned
+    pub fn dry_run_report(&self) -> MigrationReport {
+        MigrationReport {
+            planned: self.formulae.iter().map(|p| p.name.clone()).collect(),
+            dry_run: true,
+            ..self.new_report()
+        }
+    }
+
+    /// Formulae installed with `--HEAD`, which zerobrew will install at the stable version
+    pub fn head_formulae(&self) -> Vec<&BrewPackage> {
+        self.formulae.iter().filter(|p| p.head).collect()
+    }
+}
+
+/// Format version written to `migrate --save-plan` files
+pub const PLAN_FORMAT_VERSION: u32 = 1;
+
+/// A migration plan written by `migrate --save-plan` and replayed with `migrate --plan`.
+///
+/// The plan holds every formula left after `--since` and `--exclude-taps`, in
+/// migration order; what is already migrated is decided on the machine replaying it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedPlan {
+    pub format_version: u32,
+    /// Unix time the plan was computed
+    pub created_at: u64,
+    /// Homebrew prefix of the machine the plan was computed on
+    pub homebrew_prefix: PathBuf,
+    /// Migration risk of each planned formula
+    pub risks: BTreeMap<String, MigrationRisk>,
+    pub plan: MigrationPlan,
+}
+
+/// Read a plan saved with `migrate --save-plan`
+pub fn load_saved_plan(path: &Path) -> Result<SavedPlan> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let saved: SavedPlan = serde_json::from_str(&content).with_context(|| {
+        format!(
+            "{} is not a valid migration plan.\n\n\
+             Suggestion: write one with 'zb-migrate migrate --save-plan {}'",
+            path.display(),
+            path.display()
+        )
+    })?;
+    if saved.format_version > PLAN_FORMAT_VERSION {
+        bail!(
+            "Migration plan {} uses format version {}, but this zb-migrate only \
+             understands up to version {}.\n\n\
+             Suggestion: upgrade zb-migrate to the version that saved the plan",
+            path.display(),
+            saved.format_version,
+            PLAN_FORMAT_VERSION
+        );
+    }
+    Ok(saved)
+}
+
+/// Progress notifications emitted while migrating packages
+#[derive(Debug)]
+pub enum MigrationEvent<'a> {
+    /// A package is about to be installed via zerobrew
+    Started {
+        index: usize,
+        total: usize,
+        package: &'a BrewPackage,
+    },
+    /// A package install finished
+    Finished {
+        index: usize,
+        total: usize,
+        result: &'a MigrateResult,
+        /// Time spent migrating this package
+        elapsed: Duration,
+    },
+}
+
+/// Tarjan's strongly connected components walk behind
+/// [`HomebrewMigrator::reachable_problematic`] without a depth limit.
+///
+/// A package's result is only memoized once its whole component (the dependency cycle
+/// it is part of, or just itself) is finished, so no package caches a partial set.
+#[derive(Default)]
+struct SccWalk<'a> {
+    next_index: usize,
+    index: HashMap<&'a str, usize>,
+    lowlink: HashMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    /// Problematic packages found so far from each package still on the stack
+    partial: HashMap<&'a str, BTreeSet<&'a str>>,
+}
+
+impl<'a> SccWalk<'a> {
+    fn visit(
+        &mut self,
+        name: &'a str,
+        pkg_map: &HashMap<&'a str, &'a BrewPackage>,
+        problematic_set: &HashSet<&str>,
+        memo: &mut HashMap<(&'a str, Option<usize>), BTreeSet<&'a str>>,
+    ) {
+        self.index.insert(name, self.next_index);
+        self.lowlink.insert(name, self.next_index);
+        self.next_index += 1;
+        self.stack.push(name);
+        self.partial.insert(name, BTreeSet::new());
+
+        let mut found = BTreeSet::new();
+        if let Some(pkg) = pkg_map.get(name) {
+            for dep in &pkg.dependencies {
+                let dep = dep.as_str();
+                if problematic_set.contains(dep) {
+                    found.insert(dep);
+                }
+                if !self.index.contains_key(dep) && !memo.contains_key(&(dep, None)) {
+                    self.visit(dep, pkg_map, problematic_set, memo);
+                    let low = self.lowlink[name].min(self.lowlink[dep]);
+                    self.lowlink.insert(name, low);
+                } else if self.partial.contains_key(dep) {
+                    // On the stack: same component, merged when it finishes
+                    let low = self.lowlink[name].min(self.index[dep]);
+                    self.lowlink.insert(name, low);
+                }
+                if let Some(done) = memo.get(&(dep, None)) {
+                    found.extend(done.iter().copied());
+                }
+            }
+        }
+        self.partial.insert(name, found);
+
+        if self.lowlink[name] == self.index[name] {
+            let at = self.stack.iter().rposition(|n| *n == name).unwrap_or(0);
+            let component: Vec<&'a str> = self.stack.drain(at..).collect();
+            let mut reachable = BTreeSet::new();
+            for member in &component {
+                reachable.extend(self.partial.remove(member).unwrap_or_default());
+            }
+            for member in component {
+                memo.insert((member, None), reachable.clone());
+            }
+        }
+    }
+}
+
+/// Whether [`HomebrewMigrator::execute_plan_with`] should install a package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PackageChoice {
+    Migrate,
+    /// Leave the package in Homebrew, recording it as skipped with this reason
+    Skip(String),
+    /// Stop the run without attempting the remaining packages
+    Stop,
+}
+
+/// A migrated package that cleanup would uninstall from Homebrew
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CleanupCandidate {
+    pub name: String,
+    pub version: String,
+    /// Whether the package is still installed in Homebrew
+    pub installed_in_homebrew: bool,
+}
+
+/// Outcome of verifying a single migrated package against zerobrew
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VerifyStatus {
+    /// Installed in zerobrew and passed the requested checks
+    Ok,
+    /// No longer listed by `zb list`; needs to be migrated again
+    Missing,
+    /// Listed by zerobrew but `zb info` failed
+    Broken(String),
+}
+
+/// Verification result for a package recorded in the migration state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifyResult {
+    pub name: String,
+    pub version: String,
+    pub status: VerifyStatus,
+}
+
+/// Parse `zb list` output into the set of installed package names.
+/// Each line starts with the package name, optionally followed by a version.
+pub fn parse_zb_list(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with("==>"))
+        .filter_map(|l| l.split_whitespace().next())
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// What migrating a single package touches, shown by `migrate --show-impact`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageImpact {
+    pub name: String,
+    /// Installed Homebrew dependencies, direct and indirect
+    pub dependencies: Vec<String>,
+    /// The subset of `dependencies` zerobrew doesn't have yet, which `zb install` will pull in
+    pub dependencies_to_install: Vec<String>,
+    /// Installed formulae that depend on the package, direct and indirect
+    pub dependents: Vec<String>,
+}
+
+/// Breadth-first walk of reverse dependencies starting from `names`.
+/// Returns the dependents found within `max_depth` levels, sorted by name and
+/// excluding the starting packages.
+pub fn collect_dependents(
+    names: &[String],
+    max_depth: usize,
+    mut dependents_of: impl FnMut(&str) -> Result<Vec<String>>,
+) -> Result<Vec<String>> {
+    let mut seen: HashSet<String> = names.iter().cloned().collect();
+    let mut found = BTreeSet::new();
+    let mut frontier: Vec<String> = names.to_vec();
+
+    for _ in 0..max_depth {
+        let mut next = Vec::new();
+        for name in &frontier {
+            for dependent in dependents_of(name)? {
+                if seen.insert(dependent.clone()) {
+                    found.insert(dependent.clone());
+                    next.push(dependent);
+                }
+            }
+        }
+        if next.is_empty() {
+            break;
+        }
+        frontier = next;
+    }
+
+    Ok(found.into_iter().collect())
+}
+
+/// Compare migrated packages against what zerobrew reports as installed, sorted by name
+pub fn check_migrated_presence(
+    state: &MigrationState,
+    zb_installed: &HashSet<String>,
+) -> Vec<VerifyResult> {
+    let mut results: Vec<VerifyResult> = state
+        .migrated_packages
+        .values()
+        .map(|pkg| VerifyResult {
+            name: pkg.name.clone(),
+            version: pkg.version.clone(),
+            status: if zb_installed.contains(&pkg.name) {
+                VerifyStatus::Ok
+            } else {
+                VerifyStatus::Missing
+            },
+        })
+        .collect();
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+    results
+}
+
+/// Skip reason recorded for packages left untouched after a `--fail-fast` stop
+pub const FAIL_FAST_SKIP_REASON: &str = "Not attempted (stopped by --fail-fast)";
+
+/// Skip reason for formulae zerobrew already has, which are recorded as migrated
+pub const ALREADY_IN_ZEROBREW_REASON: &str = "already in zerobrew";
+
+/// Skip reason for formulae recorded as migrated by an earlier run
+pub const ALREADY_MIGRATED_REASON: &str = "already migrated";
+
+/// Split `formulae` into those the state records as migrated and those still to migrate
+pub fn split_already_migrated(
+    formulae: Vec<BrewPackage>,
+    state: &MigrationState,
+) -> (Vec<BrewPackage>, Vec<BrewPackage>) {
+    formulae
+        .into_iter()
+        .partition(|pkg| state.migrated_packages.contains_key(&pkg.name))
+}
+
+/// Split `formulae` into those already listed by `zb list` and those still to migrate
+pub fn split_already_in_zerobrew(
+    formulae: Vec<BrewPackage>,
+    installed: &HashSet<String>,
+) -> (Vec<BrewPackage>, Vec<BrewPackage>) {
+    formulae
+        .into_iter()
+        .partition(|pkg| installed.contains(&pkg.name))
+}
+
+/// Drop formulae from any of `taps`, recording them as skipped. Tap names are
+/// matched case-insensitively, like Homebrew does.
+pub fn exclude_taps(
+    formulae: Vec<BrewPackage>,
+    taps: &[String],
+    skipped: &mut Vec<(String, String)>,
+) -> Vec<BrewPackage> {
+    let (excluded, kept): (Vec<BrewPackage>, Vec<BrewPackage>) =
+        formulae.into_iter().partition(|pkg| {
+            pkg.tap
+                .as_ref()
+                .is_some_and(|tap| taps.iter().any(|t| t.eq_ignore_ascii_case(tap)))
+        });
+    for pkg in excluded {
+        let tap = pkg.tap.unwrap_or_default();
+        skipped.push((pkg.name, format!("from excluded tap {}", tap)));
+    }
+    kept
+}
+
+/// Skip reason for formulae left out by `--requested-only`
+pub const DEPENDENCY_ONLY_SKIP_REASON: &str =
+    "Installed as a dependency (zerobrew installs it with the formulae that need it)";
+
+/// Drop formulae Homebrew installed only as dependencies, recording them as skipped
+pub fn exclude_dependencies_only(
+    formulae: Vec<BrewPackage>,
+    skipped: &mut Vec<(String, String)>,
+) -> Vec<BrewPackage> {
+    let (kept, dependencies): (Vec<BrewPackage>, Vec<BrewPackage>) = formulae
+        .into_iter()
+        .partition(|pkg| pkg.installed_on_request);
+    for pkg in dependencies {
+        skipped.push((pkg.name, DEPENDENCY_ONLY_SKIP_REASON.to_string()));
+    }
+    kept
+}
+
+/// Skip reason for packages left untouched after hitting `--max-failures`
+pub const MAX_FAILURES_SKIP_REASON: &str = "Not attempted (stopped by --max-failures)";
+
+/// Skip reason for packages left unattempted after Ctrl-C or SIGTERM
+pub const INTERRUPTED_SKIP_REASON: &str = "Not attempted (interrupted)";
+
+impl MigrationReport {
+    /// Record the outcome of a single package migration
+    pub fn push_result(&mut self, result: MigrateResult) {
+        match result {
+            MigrateResult::Success {
+                name,
+                version,
+                installed_version,
+                pin_fallback,
+                zerobrew_name,
+                install_mode,
+            } => {
+                if let Some(zb_name) = zerobrew_name {
+                    self.renamed.push((name.clone(), zb_name));
+                }
+                if install_mode != InstallMode::Default {
+                    self.install_modes.push((name.clone(), install_mode));
+                }
+                if pin_fallback {
+                    self.pin_fallbacks.push(PinFallback {
+                        name: name.clone(),
+                        requested_version: version.clone(),
+                        installed_version: installed_version.clone(),
+                    });
+                }
+                if is_head_version(&version) {
+                    self.head_installs.push(HeadInstall {
+                        name: name.clone(),
+                        homebrew_version: version.clone(),
+                        zerobrew_version: installed_version.clone(),
+                    });
+                }
+                if let Some(installed) = installed_version {
+                    if version != UNKNOWN_VERSION
+                        && !is_head_version(&version)
+                        && !versions_match(&version, &installed)
+                    {
+                        self.version_drift.push(VersionDrift {
+                            name: name.clone(),
+                            homebrew_version: version,
+                            zerobrew_version: installed.clone(),
+                        });
+                    }
+                    self.installed_versions.insert(name.clone(), installed);
+                }
+                self.successful.push(name);
+            }
+            MigrateResult::Failed {
+                name,
+                reason,
+                error,
+                log,
+            } => {
+                if let Some(log) = log {
+                    self.logs.insert(name.clone(), log);
+                }
+                self.errors.insert(name.clone(), error);
+                self.failed.push((name, reason));
+            }
+        }
+    }
+
+    /// Record packages that were never attempted because the run stopped early
+    pub fn skip_remaining(&mut self, remaining: &[BrewPackage]) {
+        for pkg in remaining {
+            self.skipped
+                .push((pkg.name.clone(), FAIL_FAST_SKIP_REASON.to_string()));
+        }
+    }
+
+    /// Record packages left unattempted because too many packages failed
+    pub fn skip_over_failure_limit(&mut self, remaining: &[BrewPackage]) {
+        self.failure_limit_reached = true;
+        for pkg in remaining {
+            self.skipped
+                .push((pkg.name.clone(), MAX_FAILURES_SKIP_REASON.to_string()));
+        }
+    }
+
+    /// Record packages left unattempted because the run was interrupted
+    pub fn skip_interrupted(&mut self, remaining: &[BrewPackage]) {
+        self.interrupted = true;
+        for pkg in remaining {
+            self.skipped
+                .push((pkg.name.clone(), INTERRUPTED_SKIP_REASON.to_string()));
+        }
+    }
+
+    /// Render the report as a markdown document
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        md.push_str("# Migration Report\n\n");
+
+        md.push_str("## Summary\n\n");
+        md.push_str("| | Count |\n|---|---|\n");
+        md.push_str(&format!("| Total formulae | {} |\n", self.total_formulae));
+        md.push_str(&format!("| Total casks | {} |\n", self.total_casks));
+        md.push_str(&format!("| Successful | {} |\n", self.successful.len()));
+        md.push_str(&format!("| Failed | {} |\n", self.failed.len()));
+        md.push_str(&format!("| Skipped | {} |\n\n", self.skipped.len()));
+
+        if !self.planned.is_empty() {
+            md.push_str(&format!("## Would Migrate ({})\n\n", self.planned.len()));
+            md.push_str("| Package |\n|---|\n");
+            for name in &self.planned {
+                md.push_str(&format!("| {} |\n", md_cell(name)));
+            }
+            md.push('\n');
+        }
+
+        if !self.successful.is_empty() {
+            md.push_str(&format!("## Successful ({})\n\n", self.successful.len()));
+            md.push_str("| Package |\n|---|\n");
+            for name in &self.successful {
+                md.push_str(&format!("| {} |\n", md_cell(name)));
+            }
+            md.push('\n');
+        }
+
+        if !self.failed.is_empty() {
+            md.push_str(&format!("## Failed ({})\n\n", self.failed.len()));
+            md.push_str("| Package | Reason | Log |\n|---|---|---|\n");
+            for (name, reason) in &self.failed {
+                let log = self
+                    .logs
+                    .get(name)
+                    .map(|p| format!("`{}`", p.display()))
+                    .unwrap_or_default();
+                md.push_str(&format!(
+                    "| {} | {} | {} |\n",
+                    md_cell(name),
+                    md_cell(reason),
+                    md_cell(&log)
+                ));
+            }
+            md.push('\n');
+        }
+
+        if !self.version_drift.is_empty() {
+            md.push_str(&format!(
+                "## Version Drift ({})\n\n",
+                self.version_drift.len()
+            ));
+            md.push_str("| Package | Homebrew | Zerobrew |\n|---|---|---|\n");
+            for drift in &self.version_drift {
+                md.push_str(&format!(
+                    "| {} | {} | {} |\n",
+                    md_cell(&drift.name),
+                    md_cell(&drift.homebrew_version),
+                    md_cell(&drift.zerobrew_version)
+                ));
+            }
+            md.push('\n');
+        }
+
+        if !self.head_installs.is_empty() {
+            md.push_str(&format!(
+                "## HEAD Installs Replaced by Stable ({})\n\n",
+                self.head_installs.len()
+            ));
+            md.push_str("| Package | Homebrew | Zerobrew |\n|---|---|---|\n");
+            for head in &self.head_installs {
+                md.push_str(&format!(
+                    "| {} | {} | {} |\n",
+                    md_cell(&head.name),
+                    md_cell(&head.homebrew_version),
+                    md_cell(head.zerobrew_version.as_deref().unwrap_or("stable"))
+                ));
+            }
+            md.push('\n');
+        }
+
+        if !self.renamed.is_empty() {
+            md.push_str(&format!("## Renamed ({})\n\n", self.renamed.len()));
+            md.push_str("| Homebrew | Zerobrew |\n|---|---|\n");
+            for (brew_name, zb_name) in &self.renamed {
+                md.push_str(&format!(
+                    "| {} | {} |\n",
+                    md_cell(brew_name),
+                    md_cell(zb_name)
+                ));
+            }
+            md.push('\n');
+        }
+
+        if !self.install_modes.is_empty() {
+            md.push_str(&format!(
+                "## Install Method ({})\n\n",
+                self.install_modes.len()
+            ));
+            md.push_str("| Package | Method |\n|---|---|\n");
+            for (name, mode) in &self.install_modes {
+                md.push_str(&format!("| {} | {} |\n", md_cell(name), mode));
+            }
+            md.push('\n');
+        }
+
+        if !self.pin_fallbacks.is_empty() {
+            md.push_str(&format!(
+                "## Unpinned Versions ({})\n\n",
+                self.pin_fallbacks.len()
+            ));
+            md.push_str("| Package | Requested | Installed |\n|---|---|---|\n");
+            for fallback in &self.pin_fallbacks {
+                md.push_str(&format!(
+                    "| {} | {} | {} |\n",
+                    md_cell(&fallback.name),
+                    md_cell(&fallback.requested_version),
+                    md_cell(fallback.installed_version.as_deref().unwrap_or("unknown"))
+                ));
+            }
+            md.push('\n');
+        }
+
+        if !self.running_services.is_empty() {
+            md.push_str(&format!(
+                "## Running Homebrew Services ({})\n\n",
+                self.running_services.len()
+            ));
+            md.push_str(
+                "These services still run from Homebrew. They are stopped by `zb-migrate cleanup --force`; \
+                 set up an equivalent launchd service for the zerobrew install.\n\n",
+            );
+            for name in &self.running_services {
+                md.push_str(&format!("- `{}`\n", name));
+            }
+            md.push('\n');
+        }
+
+        if !self.skipped.is_empty() {
+            md.push_str(&format!("## Skipped ({})\n\n", self.skipped.len()));
+            md.push_str("| Package | Reason |\n|---|---|\n");
+            for (name, reason) in &self.skipped {
+                md.push_str(&format!("| {} | {} |\n", md_cell(name), md_cell(reason)));
+            }
+            md.push('\n');
+        }
+
+        md.push_str("## Recommendations\n\n");
+        if self.dry_run {
+            md.push_str("- This was a dry run and nothing was installed. Run the same command without `--dry-run` to migrate.\n");
+        } else if self.failed.is_empty() {
+            md.push_str("- All attempted packages migrated. Verify them with `zb list` before running `zb-migrate cleanup`.\n");
+        } else {
+            md.push_str("- Retry failed packages individually: `zb-migrate migrate --packages <package-name>`\n");
+            md.push_str("- Packages failing with link conflicts should stay in Homebrew (see `zb-migrate analyze`).\n");
+        }
+
+        md
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    // ============================================
+    // BrewPackage Parsing Tests
+    // ============================================
+
+    #[test]
+    fn test_brew_package_creation() {
+        let pkg = BrewPackage {
+            name: "git".to_string(),
+            version: "2.42.0".to_string(),
+            tap: None,
+            is_cask: false,
+            dependencies: vec!["pcre2".to_string(), "gettext".to_string()],
+            pinned: false,
+            ..Default::default()
+        };
+
+        assert_eq!(pkg.name, "git");
+        assert_eq!(pkg.version, "2.42.0");
+        assert!(pkg.tap.is_none());
+        assert!(!pkg.is_cask);
+        assert_eq!(pkg.dependencies.len(), 2);
+        assert!(!pkg.pinned);
+    }
+
+    #[test]
+    fn test_brew_package_with_tap() {
+        let pkg = BrewPackage {
+            name: "neovim".to_string(),
+            version: "0.9.4".to_string(),
+            tap: Some("homebrew/core".to_string()),
+            is_cask: false,
+            dependencies: vec![],
+            pinned: true,
+            ..Default::default()
+        };
+
+        assert_eq!(pkg.tap, Some("homebrew/core".to_string()));
+        assert!(pkg.pinned);
+    }
+
+    #[test]
+    fn test_brew_package_cask() {
+        let pkg = BrewPackage {
+            name: "visual-studio-code".to_string(),
+            version: "1.84.0".to_string(),
+            tap: Some("homebrew/cask".to_string()),
+            is_cask: true,
+            dependencies: vec![],
+            pinned: false,
+            ..Default::default()
+        };
+
+        assert!(pkg.is_cask);
+        assert_eq!(pkg.name, "visual-studio-code");
+    }
+
+    #[test]
+    fn test_parse_brew_list_output() {
+        // Simulate parsing brew list --formula --versions output
+        let brew_output = "git 2.42.0\nnode 20.9.0\nrust 1.73.0\npython@3.11 3.11.6";
+
+        let mut packages = Vec::new();
+        for line in brew_output.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                packages.push(BrewPackage {
+                    name: parts[0].to_string(),
+                    version: parts[1].to_string(),
+                    tap: None,
+                    is_cask: false,
+                    dependencies: Vec::new(),
+                    pinned: false,
+                    ..Default::default()
+                });
+            }
+        }
+
+        assert_eq!(packages.len(), 4);
+        assert_eq!(packages[0].name, "git");
+        assert_eq!(packages[0].version, "2.42.0");
+        assert_eq!(packages[1].name, "node");
+        assert_eq!(packages[2].name, "rust");
+        assert_eq!(packages[3].name, "python@3.11");
+        assert_eq!(packages[3].version, "3.11.6");
+    }
+
+    #[test]
+    fn test_parse_brew_list_empty_output() {
+        let brew_output = "";
+
+        let mut packages = Vec::new();
+        for line in brew_output.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                packages.push(BrewPackage {
+                    name: parts[0].to_string(),
+                    version: parts[1].to_string(),
+                    tap: None,
+                    is_cask: false,
+                    dependencies: Vec::new(),
+                    pinned: false,
+                    ..Default::default()
+                });
+            }
+        }
+
+        assert!(packages.is_empty());
+    }
+
+    #[test]
+    fn test_parse_brew_list_with_multiple_versions() {
+        // Some packages show multiple versions installed
+        let brew_output = "openssl@3 3.1.4 3.1.3";
+
+        let mut packages = Vec::new();
+        for line in brew_output.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                // Take the first (latest) version
+                packages.push(BrewPackage {
+                    name: parts[0].to_string(),
+                    version: parts[1].to_string(),
+                    tap: None,
+                    is_cask: false,
+                    dependencies: Vec::new(),
+                    pinned: false,
+                    ..Default::default()
+                });
+            }
+        }
+
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].name, "openssl@3");
+        assert_eq!(packages[0].version, "3.1.4");
+    }
+
+    #[test]
+    fn test_parse_malformed_brew_output() {
+        // Lines with less than 2 parts should be skipped
+        let brew_output = "git 2.42.0\ninvalid_line\nnode 20.9.0\n   \nrust";
+
+        let mut packages = Vec::new();
+        for line in brew_output.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                packages.push(BrewPackage {
+                    name: parts[0].to_string(),
+                    version: parts[1].to_string(),
+                    tap: None,
+                    is_cask: false,
+                    dependencies: Vec::new(),
+                    pinned: false,
+                    ..Default::default()
+                });
+            }
+        }
+
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "git");
+        assert_eq!(packages[1].name, "node");
+    }
+
+    // ============================================
+    // MigrationState Serialization Tests
+    // ============================================
+
+    #[test]
+    fn test_migration_state_default() {
+        let state = MigrationState::default();
+
+        assert!(state.migrated_packages.is_empty());
+        assert!(state.failed_packages.is_empty());
+        assert_eq!(state.homebrew_prefix, PathBuf::new());
+    }
+
+    #[test]
+    fn test_migration_state_serialization() {
+        let mut state = MigrationState::default();
+        state.homebrew_prefix = PathBuf::from("/opt/homebrew");
+        state
+            .failed_packages
+            .push(("broken-pkg".to_string(), "link conflict".to_string()));
+
+        let pkg = BrewPackage {
+            name: "git".to_string(),
+            version: "2.42.0".to_string(),
+            tap: None,
+            is_cask: false,
+            dependencies: vec!["pcre2".to_string()],
+            pinned: false,
+            ..Default::default()
+        };
+        state.migrated_packages.insert("git".to_string(), pkg);
+
+        let json = serde_json::to_string(&state).expect("Serialization failed");
+
+        assert!(json.contains("\"homebrew_prefix\""));
+        assert!(json.contains("/opt/homebrew"));
+        assert!(json.contains("\"git\""));
+        assert!(json.contains("broken-pkg"));
+    }
+
+    #[test]
+    fn test_migration_state_deserialization() {
+        let json = r#"{
+            "migrated_packages": {
+                "node": {
+                    "name": "node",
+                    "version": "20.9.0",
+                    "tap": null,
+                    "is_cask": false,
+                    "dependencies": ["icu4c"],
+                    "pinned": false
+                }
+            },
+            "failed_packages": ["broken-pkg"],
+            "homebrew_prefix": "/opt/homebrew"
+        }"#;
+
+        let state: MigrationState = serde_json::from_str(json).expect("Deserialization failed");
+
+        assert_eq!(state.homebrew_prefix, PathBuf::from("/opt/homebrew"));
+        assert_eq!(state.failed_packages.len(), 1);
+        // Files from before format version 2 list failures without a reason
+        assert_eq!(state.failed_packages, failed_entries(&["broken-pkg"]));
+        assert!(state.migrated_packages.contains_key("node"));
+
+        let node_pkg = state.migrated_packages.get("node").unwrap();
+        assert_eq!(node_pkg.version, "20.9.0");
+        assert_eq!(node_pkg.dependencies, vec!["icu4c"]);
+
+        let json = r#"{
+            "format_version": 2,
+            "migrated_packages": {},
+            "failed_packages": [["broken-pkg", "link conflict"]],
+            "homebrew_prefix": "/opt/homebrew"
+        }"#;
+        let state: MigrationState = serde_json::from_str(json).expect("Deserialization failed");
+        assert_eq!(
+            state.failed_packages,
+            vec![("broken-pkg".to_string(), "link conflict".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_migration_state_roundtrip() {
+        let mut original = MigrationState::default();
+        original.homebrew_prefix = PathBuf::from("/usr/local");
+        original.failed_packages = failed_entries(&["pkg1", "pkg2"]);
+
+        let pkg = BrewPackage {
+            name: "rust".to_string(),
+            version: "1.73.0".to_string(),
+            tap: Some("homebrew/core".to_string()),
+            is_cask: false,
+            dependencies: vec!["libssh2".to_string(), "openssl@3".to_string()],
+            pinned: true,
+            ..Default::default()
+        };
+        original.migrated_packages.insert("rust".to_string(), pkg);
+
+        // Serialize
+        let json = serde_json::to_string_pretty(&original).expect("Serialization failed");
+
+        // Deserialize
+        let restored: MigrationState = serde_json::from_str(&json).expect("Deserialization failed");
+
+        assert_eq!(restored.homebrew_prefix, original.homebrew_prefix);
+        assert_eq!(restored.failed_packages, original.failed_packages);
+        assert_eq!(
+            restored.migrated_packages.len(),
+            original.migrated_packages.len()
+        );
+
+        let rust_pkg = restored.migrated_packages.get("rust").unwrap();
+        assert_eq!(rust_pkg.version, "1.73.0");
+        assert!(rust_pkg.pinned);
+        assert_eq!(rust_pkg.tap, Some("homebrew/core".to_string()));
+    }
+
+    #[test]
+    fn test_migration_state_legacy_json_without_records() {
+        let json = r#"{
+            "migrated_packages": {},
+            "failed_packages": [],
+            "homebrew_prefix": "/opt/homebrew"
+        }"#;
+
+        let state: MigrationState = serde_json::from_str(json).expect("Deserialization failed");
+
+        assert!(state.zerobrew_version.is_none());
+        assert!(state.migration_records.is_empty());
+    }
+
+    #[test]
+    fn test_load_state_corrupt_empty_and_newer_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let migrator = test_migrator(dir.path());
+
+        // A zero-length file counts as no state
+        std::fs::write(&migrator.state_file, "").unwrap();
+        let state = migrator.load_state().unwrap();
+        assert!(state.migrated_packages.is_empty());
+        assert_eq!(state.format_version, STATE_FORMAT_VERSION);
+
+        // A truncated file explains itself instead of surfacing the serde error alone
+        std::fs::write(&migrator.state_file, r#"{"migrated_packages": {"#).unwrap();
+        let err = format!("{:#}", migrator.load_state().unwrap_err());
+        assert!(err.contains("is corrupt or was only partially written"));
+        assert!(err.contains("restore-state --list"));
+
+        // Files from before the version field are upgraded when saved again
+        std::fs::write(
+            &migrator.state_file,
+            r#"{"migrated_packages": {}, "failed_packages": [], "homebrew_prefix": ""}"#,
+        )
+        .unwrap();
+        let state = migrator.load_state().unwrap();
+        migrator.save_state(&state).unwrap();
+        let saved = std::fs::read_to_string(&migrator.state_file).unwrap();
+        assert!(saved.contains(&format!("\"format_version\": {}", STATE_FORMAT_VERSION)));
+
+        let newer = format!(
+            r#"{{"format_version": {}, "migrated_packages": {{}}, "failed_packages": [], "homebrew_prefix": ""}}"#,
+            STATE_FORMAT_VERSION + 1
+        );
+        std::fs::write(&migrator.state_file, newer).unwrap();
+        let err = migrator.load_state().unwrap_err().to_string();
+        assert!(err.contains("only understands up to version"));
+
+        // ...even when the newer layout doesn't parse as this version's state
+        let newer = format!(
+            r#"{{"format_version": {}, "failed_packages": {{"jq": {{}}}}}}"#,
+            STATE_FORMAT_VERSION + 1
+        );
+        std::fs::write(&migrator.state_file, newer).unwrap();
+        let err = migrator.load_state().unwrap_err().to_string();
+        assert!(err.contains("only understands up to version"));
+    }
+
+    #[test]
+    fn test_record_results_keeps_unreadable_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let migrator = test_migrator(dir.path());
+        let newer = format!(
+            r#"{{"format_version": {}, "migrated_packages": {{"important": {{}}}}}}"#,
+            STATE_FORMAT_VERSION + 1
+        );
+        std::fs::write(&migrator.state_file, &newer).unwrap();
+
+        let report = MigrationReport {
+            successful: vec!["jq".to_string()],
+            ..Default::default()
+        };
+        let err = migrator
+            .record_results(&report, &[BrewPackage::not_installed("jq")], "migrate")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("only understands up to version"));
+        assert_eq!(
+            std::fs::read_to_string(&migrator.state_file).unwrap(),
+            newer
+        );
+    }
+
+    #[test]
+    fn test_migration_record_roundtrip() {
+        let mut state = MigrationState {
+            zerobrew_version: Some("zb 0.1.2".to_string()),
+            ..Default::default()
+        };
+        state.migration_records.insert(
+            "git".to_string(),
+            MigrationRecord {
+                zerobrew_version: Some("zb 0.1.2".to_string()),
+                migrated_at: 1_700_000_000,
+                installed_version: Some("2.43.0".to_string()),
+            },
+        );
+
+        let json = serde_json::to_string(&state).expect("Serialization failed");
+        let restored: MigrationState = serde_json::from_str(&json).expect("Deserialization failed");
+
+        assert_eq!(restored.zerobrew_version.as_deref(), Some("zb 0.1.2"));
+        let record = &restored.migration_records["git"];
+        assert_eq!(record.zerobrew_version.as_deref(), Some("zb 0.1.2"));
+        assert_eq!(record.migrated_at, 1_700_000_000);
+    }
+
+    #[test]
+    fn test_migration_state_empty_json() {
+        let json = r#"{
+            "migrated_packages": {},
+            "failed_packages": [],
+            "homebrew_prefix": ""
+        }"#;
+
+        let state: MigrationState = serde_json::from_str(json).expect("Deserialization failed");
+
+        assert!(state.migrated_packages.is_empty());
+        assert!(state.failed_packages.is_empty());
+    }
+
+    // ============================================
+    // Topological Sort Tests
+    // ============================================
+
+    /// A migrator keeping its state in `dir`, without running brew or zb
+    fn test_migrator(dir: &Path) -> HomebrewMigrator {
+        HomebrewMigrator {
+            homebrew_prefix: dir.join("homebrew"),
+            zerobrew_prefix: None,
+            include_build_deps: false,
+            strict: false,
+            max_depth: None,
+            backup_state: false,
+            keep_backups: DEFAULT_KEEP_BACKUPS,
+            analyze_jobs: DEFAULT_ANALYZE_JOBS,
+            brew_bin: PathBuf::from("brew"),
+            zb_bin: PathBuf::from("zb"),
+            runner: Box::new(SystemRunner),
+            aliases_file: dir.join(ALIASES_FILE_NAME),
+            aliases: OnceLock::from(BTreeMap::new()),
+            added_taps: Mutex::new(HashMap::new()),
+            state_file: dir.join("migration_state.json"),
+            verbose: false,
+            timings: None,
+        }
+    }
+
+    #[test]
+    fn test_detect_homebrew_prefix_falls_back_to_standard_locations() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing/bin/brew");
+        let installed = dir.path().join("homebrew/bin/brew");
+        std::fs::create_dir_all(installed.parent().unwrap()).unwrap();
+        std::fs::write(&installed, "").unwrap();
+        let fallbacks = vec![missing, installed.clone()];
+        let runner = MockRunner::new(&[(
+            &format!("{} --prefix", installed.display()),
+            (0, "/opt/homebrew\n", ""),
+        )]);
+
+        // `brew` isn't on PATH, so the first existing standard location is used
+        let (brew_bin, prefix) =
+            HomebrewMigrator::detect_homebrew_prefix(&runner, Path::new("brew"), &fallbacks, false)
+                .unwrap();
+        assert_eq!(brew_bin, installed);
+        assert_eq!(prefix, PathBuf::from("/opt/homebrew"));
+
+        // An explicit path is never replaced
+        let err = HomebrewMigrator::detect_homebrew_prefix(
+            &runner,
+            Path::new("/custom/brew"),
+            &fallbacks,
+            false,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("does not appear to be installed"));
+    }
+
+    /// Canned `(exit code, stdout, stderr)` per command line, e.g. `"zb install jq"`.
+    /// Commands without a response fail to spawn, as if the program were missing.
+    struct MockRunner {
+        responses: HashMap<String, (i32, &'static str, &'static str)>,
+    }
+
+    impl MockRunner {
+        fn new(responses: &[(&str, (i32, &'static str, &'static str))]) -> Self {
+            MockRunner {
+                responses: responses
+                    .iter()
+                    .map(|(cmd, response)| (cmd.to_string(), *response))
+                    .collect(),
+            }
+        }
+    }
+
+    impl CommandRunner for MockRunner {
+        fn output(&self, program: &Path, args: &[&str]) -> std::io::Result<Output> {
+            let command = format!("{} {}", program.display(), args.join(" "));
+            let (code, stdout, stderr) = self.responses.get(&command).ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, "no such command")
+            })?;
+            Ok(Output {
+                status: exit_status(*code),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: stderr.as_bytes().to_vec(),
+            })
+        }
+    }
+
+    fn create_test_package(name: &str, deps: Vec<&str>) -> BrewPackage {
+        BrewPackage {
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            tap: None,
+            is_cask: false,
+            dependencies: deps.iter().map(|s| s.to_string()).collect(),
+            pinned: false,
+            ..Default::default()
+        }
+    }
+
+    /// `failed_packages` entries without a recorded reason
+    fn failed_entries(names: &[&str]) -> Vec<(String, String)> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), String::new()))
+            .collect()
+    }
+
+    #[test]
+    fn test_cleanup_order_removes_dependents_first() {
+        let mut state = MigrationState::default();
+        for pkg in [
+            create_test_package("pcre2", vec![]),
+            create_test_package("git", vec!["pcre2", "gettext"]),
+            create_test_package("gettext", vec![]),
+            // oniguruma wasn't migrated, so it stays out of the cleanup set
+            create_test_package("jq", vec!["oniguruma"]),
+            create_test_package("ripgrep", vec!["pcre2"]),
+        ] {
+            state.migrated_packages.insert(pkg.name.clone(), pkg);
+        }
+
+        let order = HomebrewMigrator::cleanup_order(&state).unwrap();
+        let position = |name: &str| order.iter().position(|n| n == name).unwrap();
+        assert_eq!(order.len(), 5);
+        assert!(position("git") < position("pcre2"));
+        assert!(position("git") < position("gettext"));
+        assert!(position("ripgrep") < position("pcre2"));
+        assert!(!order.contains(&"oniguruma".to_string()));
+    }
+
+    #[test]
+    fn test_cleanup_candidates() {
+        let dir = tempfile::tempdir().unwrap();
+        let migrator = fixture_migrator(dir.path());
+        let mut state = MigrationState::default();
+        for pkg in [
+            // openssl@3 was kept in Homebrew, so it is never a candidate
+            BrewPackage {
+                version: "8.4.0".to_string(),
+                ..create_test_package("curl", vec!["openssl@3"])
+            },
+            create_test_package("git", vec!["curl"]),
+            // Already uninstalled from Homebrew by hand
+            create_test_package("ripgrep", vec![]),
+        ] {
+            state.migrated_packages.insert(pkg.name.clone(), pkg);
+        }
+
+        let candidates = migrator.cleanup_candidates(&state).unwrap();
+        let summary: Vec<(&str, &str, bool)> = candidates
+            .iter()
+            .map(|c| (c.name.as_str(), c.version.as_str(), c.installed_in_homebrew))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("ripgrep", "1.0.0", false),
+                ("git", "1.0.0", true),
+                // The version recorded at migration time, not Homebrew's current one
+                ("curl", "8.4.0", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_topological_sort_no_dependencies() {
+        let packages = vec![
+            create_test_package("a", vec![]),
+            create_test_package("b", vec![]),
+            create_test_package("c", vec![]),
+        ];
+
+        let pkg_map: HashMap<String, &BrewPackage> =
+            packages.iter().map(|p| (p.name.clone(), p)).collect();
+
+        let mut result = Vec::new();
+        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+        fn visit(
+            name: &str,
+            pkg_map: &HashMap<String, &BrewPackage>,
+            visited: &mut std::collections::HashSet<String>,
+            result: &mut Vec<BrewPackage>,
+        ) {
+            if visited.contains(name) {
+                return;
+            }
+            visited.insert(name.to_string());
+
+            if let Some(pkg) = pkg_map.get(name) {
+                for dep in &pkg.dependencies {
+                    visit(dep, pkg_map, visited, result);
+                }
+                result.push((*pkg).clone());
+            }
+        }
+
+        for pkg in &packages {
+            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
+        }
+
+        assert_eq!(result.len(), 3);
+    }
+
+    #[test]
+    fn test_topological_sort_linear_dependencies() {
+        // c depends on b, b depends on a
+        let packages = vec![
+            create_test_package("a", vec![]),
+            create_test_package("b", vec!["a"]),
+            create_test_package("c", vec!["b"]),
+        ];
+
+        let pkg_map: HashMap<String, &BrewPackage> =
+            packages.iter().map(|p| (p.name.clone(), p)).collect();
+
+        let mut result = Vec::new();
+        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+        fn visit(
+            name: &str,
+            pkg_map: &HashMap<String, &BrewPackage>,
+            visited: &mut std::collections::HashSet<String>,
+            result: &mut Vec<BrewPackage>,
+        ) {
+            if visited.contains(name) {
+                return;
+            }
+            visited.insert(name.to_string());
+
+            if let Some(pkg) = pkg_map.get(name) {
+                for dep in &pkg.dependencies {
+                    visit(dep, pkg_map, visited, result);
+                }
+                result.push((*pkg).clone());
+            }
+        }
+
+        for pkg in &packages {
+            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
+        }
+
+        // a should come before b, b should come before c
+        let pos_a = result.iter().position(|p| p.name == "a").unwrap();
+        let pos_b = result.iter().position(|p| p.name == "b").unwrap();
+        let pos_c = result.iter().position(|p| p.name == "c").unwrap();
+
+        assert!(pos_a < pos_b, "a should come before b");
+        assert!(pos_b < pos_c, "b should come before c");
+    }
+
+    #[test]
+    fn test_topological_sort_diamond_dependency() {
+        // d depends on b and c, both b and c depend on a
+        let packages = vec![
+            create_test_package("a", vec![]),
+            create_test_package("b", vec!["a"]),
+            create_test_package("c", vec!["a"]),
+            create_test_package("d", vec!["b", "c"]),
+        ];
+
+        let pkg_map: HashMap<String, &BrewPackage> =
+            packages.iter().map(|p| (p.name.clone(), p)).collect();
+
+        let mut result = Vec::new();
+        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+        fn visit(
+            name: &str,
+            pkg_map: &HashMap<String, &BrewPackage>,
+            visited: &mut std::collections::HashSet<String>,
+            result: &mut Vec<BrewPackage>,
+        ) {
+            if visited.contains(name) {
+                return;
+            }
+            visited.insert(name.to_string());
+
+            if let Some(pkg) = pkg_map.get(name) {
+                for dep in &pkg.dependencies {
+                    visit(dep, pkg_map, visited, result);
+                }
+                result.push((*pkg).clone());
+            }
+        }
+
+        for pkg in &packages {
+            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
+        }
+
+        assert_eq!(result.len(), 4);
+
+        let pos_a = result.iter().position(|p| p.name == "a").unwrap();
+        let pos_b = result.iter().position(|p| p.name == "b").unwrap();
+        let pos_c = result.iter().position(|p| p.name == "c").unwrap();
+        let pos_d = result.iter().position(|p| p.name == "d").unwrap();
+
+        assert!(pos_a < pos_b, "a should come before b");
+        assert!(pos_a < pos_c, "a should come before c");
+        assert!(pos_b < pos_d, "b should come before d");
+        assert!(pos_c < pos_d, "c should come before d");
+    }
+
+    #[test]
+    fn test_parse_order_constraints() {
+        let after = OrderConstraint::parse_after("git:curl").unwrap();
+        assert_eq!(
+            after,
+            OrderConstraint {
+                first: "curl".to_string(),
+                then: "git".to_string()
+            }
+        );
+        assert_eq!(after.to_string(), "git after curl");
+        assert_eq!(
+            OrderConstraint::parse_before(" curl : git ").unwrap(),
+            after
+        );
+
+        for bad in ["git", "git:", ":curl", "git:git"] {
+            let err = OrderConstraint::parse_after(bad).unwrap_err().to_string();
+            assert!(
+                err.contains("expected two different package names"),
+                "{}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_topological_sort_with_constraints() {
+        let packages = vec![
+            create_test_package("a", vec![]),
+            create_test_package("b", vec![]),
+            create_test_package("c", vec!["b"]),
+        ];
+        let names = |sorted: Vec<BrewPackage>| -> Vec<String> {
+            sorted.into_iter().map(|p| p.name).collect()
+        };
+        assert_eq!(
+            names(HomebrewMigrator::topological_sort(&packages).unwrap()),
+            vec!["a", "b", "c"]
+        );
+
+        // a after c pulls c (and its dependency b) in front of a
+        let constraints = vec![OrderConstraint::parse_after("a:c").unwrap()];
+        assert_eq!(
+            names(HomebrewMigrator::topological_sort_with(&packages, &constraints).unwrap()),
+            vec!["b", "c", "a"]
+        );
+
+        // Constraints naming packages outside the list are ignored
+        let constraints = vec![OrderConstraint::parse_after("a:zz").unwrap()];
+        assert_eq!(
+            names(HomebrewMigrator::topological_sort_with(&packages, &constraints).unwrap()),
+            vec!["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn test_topological_sort_with_contradicting_constraints() {
+        let packages = vec![
+            create_test_package("a", vec![]),
+            create_test_package("b", vec!["a"]),
+            create_test_package("c", vec!["b"]),
+        ];
+
+        // c already comes after a through b
+        let constraints = vec![OrderConstraint::parse_before("c:a").unwrap()];
+        let err = HomebrewMigrator::topological_sort_with(&packages, &constraints)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Cannot migrate a after c: c already has to come after a (c → b → a)"));
+
+        // Two constraints that contradict each other
+        let packages = vec![
+            create_test_package("x", vec![]),
+            create_test_package("y", vec![]),
+        ];
+        let constraints = vec![
+            OrderConstraint::parse_after("x:y").unwrap(),
+            OrderConstraint::parse_after("y:x").unwrap(),
+        ];
+        let err = HomebrewMigrator::topological_sort_with(&packages, &constraints)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Cannot migrate y after x"));
+    }
+
+    #[test]
+    fn test_validate_order_constraints() {
+        let installed = vec![create_test_package("git", vec![])];
+        let constraints = vec![OrderConstraint::parse_after("git:crul").unwrap()];
+        let err = validate_order_constraints(&constraints, &installed)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Unknown package 'crul' in ordering constraint 'git after crul'"));
+        assert!(validate_order_constraints(&[], &installed).is_ok());
+    }
+
+    #[test]
+    fn test_topological_sort_missing_dependency() {
+        // b depends on "missing" which is not in the package list
+        let packages = vec![
+            create_test_package("a", vec![]),
+            create_test_package("b", vec!["missing"]),
+        ];
+
+        let pkg_map: HashMap<String, &BrewPackage> =
+            packages.iter().map(|p| (p.name.clone(), p)).collect();
+
+        let mut result = Vec::new();
+        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+        fn visit(
+            name: &str,
+            pkg_map: &HashMap<String, &BrewPackage>,
+            visited: &mut std::collections::HashSet<String>,
+            result: &mut Vec<BrewPackage>,
+        ) {
+            if visited.contains(name) {
+                return;
+            }
+            visited.insert(name.to_string());
+
+            if let Some(pkg) = pkg_map.get(name) {
+                for dep in &pkg.dependencies {
+                    visit(dep, pkg_map, visited, result);
+                }
+                result.push((*pkg).clone());
+            }
+        }
+
+        for pkg in &packages {
+            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
+        }
+
+        // Should still work - missing deps are just skipped
+        assert_eq!(result.len(), 2);
+    }
+
+    #[test]
+    fn test_topological_sort_empty_list() {
+        let packages: Vec<BrewPackage> = vec![];
+
+        let pkg_map: HashMap<String, &BrewPackage> =
+            packages.iter().map(|p| (p.name.clone(), p)).collect();
+
+        let mut result = Vec::new();
+        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+        fn visit(
+            name: &str,
+            pkg_map: &HashMap<String, &BrewPackage>,
+            visited: &mut std::collections::HashSet<String>,
+            result: &mut Vec<BrewPackage>,
+        ) {
+            if visited.contains(name) {
+                return;
+            }
+            visited.insert(name.to_string());
+
+            if let Some(pkg) = pkg_map.get(name) {
+                for dep in &pkg.dependencies {
+                    visit(dep, pkg_map, visited, result);
+                }
+                result.push((*pkg).clone());
+            }
+        }
+
+        for pkg in &packages {
+            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
+        }
+
+        assert!(result.is_empty());
+    }
+
+    #[test]
+    fn test_topological_sort_complex_graph() {
+        // Complex dependency graph:
+        // f -> d, e
+        // e -> c
+        // d -> b, c
+        // c -> a
+        // b -> a
+        // a -> (none)
+        let packages = vec![
+            create_test_package("a", vec![]),
+            create_test_package("b", vec!["a"]),
+            create_test_package("c", vec!["a"]),
+            create_test_package("d", vec!["b", "c"]),
+            create_test_package("e", vec!["c"]),
+            create_test_package("f", vec!["d", "e"]),
+        ];
+
+        let pkg_map: HashMap<String, &BrewPackage> =
+            packages.iter().map(|p| (p.name.clone(), p)).collect();
+
+        let mut result = Vec::new();
+        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+        fn visit(
+            name: &str,
+            pkg_map: &HashMap<String, &BrewPackage>,
+            visited: &mut std::collections::HashSet<String>,
+            result: &mut Vec<BrewPackage>,
+        ) {
+            if visited.contains(name) {
+                return;
+            }
+            visited.insert(name.to_string());
+
+            if let Some(pkg) = pkg_map.get(name) {
+                for dep in &pkg.dependencies {
+                    visit(dep, pkg_map, visited, result);
+                }
+                result.push((*pkg).clone());
+            }
+        }
+
+        for pkg in &packages {
+            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
+        }
+
+        assert_eq!(result.len(), 6);
+
+        // Verify ordering constraints
+        let positions: HashMap<String, usize> = result
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.name.clone(), i))
+            .collect();
+
+        assert!(positions["a"] < positions["b"]);
+        assert!(positions["a"] < positions["c"]);
+        assert!(positions["b"] < positions["d"]);
+        assert!(positions["c"] < positions["d"]);
+        assert!(positions["c"] < positions["e"]);
+        assert!(positions["d"] < positions["f"]);
+        assert!(positions["e"] < positions["f"]);
+    }
+
+    #[test]
+    fn test_topological_sort_is_deterministic() {
+        // Independent packages and sibling dependencies come out in name order,
+        // regardless of input order
+        let packages = vec![
+            create_test_package("zsh", vec![]),
+            create_test_package("tool", vec!["libz", "liba"]),
+            create_test_package("libz", vec![]),
+            create_test_package("jq", vec![]),
+            create_test_package("liba", vec![]),
+        ];
+        let mut reversed = packages.clone();
+        reversed.reverse();
+
+        for input in [&packages, &reversed] {
+            let sorted = HomebrewMigrator::topological_sort(input).unwrap();
+            let names: Vec<&str> = sorted.iter().map(|p| p.name.as_str()).collect();
+            assert_eq!(names, vec!["jq", "liba", "libz", "tool", "zsh"]);
+        }
+    }
+
+    // ============================================
+    // --with-deps Planning Tests
+    // ============================================
+
+    #[test]
+    fn test_plan_with_deps_orders_dependencies_first() {
+        let formulae = vec![
+            create_test_package("tool", vec!["libfoo", "libbar"]),
+            create_test_package("libfoo", vec!["libbar"]),
+            create_test_package("libbar", vec![]),
+            create_test_package("unrelated", vec![]),
+        ];
+
+        let plan = HomebrewMigrator::plan_with_deps(&["tool".to_string()], &formulae).unwrap();
+        let names: Vec<&str> = plan.to_migrate.iter().map(|p| p.name.as_str()).collect();
+
+        assert_eq!(names, vec!["libbar", "libfoo", "tool"]);
+        assert!(plan.kept_in_homebrew.is_empty());
+    }
+
+    #[test]
+    fn test_plan_with_deps_keeps_problematic_dependencies() {
+        let formulae = vec![
+            create_test_package("tool", vec!["openssl@3", "libfoo"]),
+            create_test_package("libfoo", vec![]),
+            create_test_package("openssl@3", vec![]),
+        ];
+
+        let plan = HomebrewMigrator::plan_with_deps(&["tool".to_string()], &formulae).unwrap();
+        let names: Vec<&str> = plan.to_migrate.iter().map(|p| p.name.as_str()).collect();
+
+        assert_eq!(names, vec!["libfoo", "tool"]);
+        assert_eq!(plan.kept_in_homebrew.len(), 1);
+        assert_eq!(plan.kept_in_homebrew[0].0, "openssl@3");
+    }
+
+    #[test]
+    fn test_plan_with_deps_explicit_problematic_is_migrated() {
+        let formulae = vec![create_test_package("openssl@3", vec![])];
+
+        let plan = HomebrewMigrator::plan_with_deps(&["openssl@3".to_string()], &formulae).unwrap();
+
+        assert_eq!(plan.to_migrate.len(), 1);
+        assert!(plan.kept_in_homebrew.is_empty());
+    }
+
+    #[test]
+    fn test_analysis_report_find() {
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            create_test_package("aria2", vec!["openssl@3"]),
+            create_test_package("jq", vec![]),
+        ];
+
+        let report = HomebrewMigrator::categorize_packages(&packages);
+
+        assert_eq!(report.find("jq").unwrap().risk, MigrationRisk::Safe);
+        assert_eq!(report.find("aria2").unwrap().risk, MigrationRisk::Risky);
+        assert_eq!(
+            report.find("openssl@3").unwrap().risk,
+            MigrationRisk::KeepInHomebrew
+        );
+        assert!(report.find("missing").is_none());
+    }
+
+    // ============================================
+    // Stats Tests
+    // ============================================
+
+    #[test]
+    fn test_most_depended_upon_ranking() {
+        let packages = vec![
+            create_test_package("git", vec!["pcre2", "gettext"]),
+            create_test_package("wget", vec!["openssl@3", "gettext"]),
+            create_test_package("curl", vec!["openssl@3"]),
+            create_test_package("grep", vec!["pcre2"]),
+            create_test_package("tmux", vec!["libevent", "libevent"]),
+        ];
+
+        let top = most_depended_upon(&packages, 3);
+
+        assert_eq!(top.len(), 3);
+        // Ties are sorted alphabetically
+        assert_eq!(top[0].name, "gettext");
+        assert_eq!(top[0].dependents, 2);
+        assert_eq!(top[1].name, "openssl@3");
+        assert_eq!(top[2].name, "pcre2");
+    }
+
+    #[test]
+    fn test_most_depended_upon_counts_duplicates_once() {
+        let packages = vec![create_test_package("tmux", vec!["libevent", "libevent"])];
+
+        let top = most_depended_upon(&packages, 10);
+
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].dependents, 1);
+    }
+
+    #[test]
+    fn test_uninstall_args() {
+        assert_eq!(
+            uninstall_args("jq", true),
+            vec!["uninstall", "--ignore-dependencies", "jq"]
+        );
+        assert_eq!(uninstall_args("jq", false), vec!["uninstall", "jq"]);
+    }
+
+    #[test]
+    fn test_dependent_counts_include_leaves() {
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            create_test_package("curl", vec!["openssl@3", "openssl@3"]),
+            create_test_package("wget", vec!["openssl@3", "libidn2"]),
+        ];
+
+        let counts = dependent_counts(&packages);
+
+        assert_eq!(counts["openssl@3"], 2);
+        assert_eq!(counts["curl"], 0);
+        assert_eq!(counts["wget"], 0);
+        // Dependencies that aren't installed are still counted
+        assert_eq!(counts["libidn2"], 1);
+    }
+
+    #[test]
+    fn test_json_envelope() {
+        let json = json_envelope(&vec![create_test_package("jq", vec![])]).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(value["schema_version"], JSON_SCHEMA_VERSION);
+        assert_eq!(value["data"][0]["name"], "jq");
+    }
+
+    #[test]
+    fn test_longest_dependency_chain() {
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            create_test_package("libssh2", vec!["openssl@3"]),
+            create_test_package("curl", vec!["libssh2", "openssl@3"]),
+            create_test_package("git", vec!["curl", "pcre2"]),
+            create_test_package("pcre2", vec![]),
+            create_test_package("jq", vec!["oniguruma"]),
+        ];
+
+        let chain = longest_dependency_chain(&packages).unwrap();
+
+        assert_eq!(chain, vec!["openssl@3", "libssh2", "curl", "git"]);
+    }
+
+    #[test]
+    fn test_longest_dependency_chain_ties_and_empty() {
+        let packages = vec![
+            create_test_package("b", vec![]),
+            create_test_package("a", vec![]),
+        ];
+
+        assert_eq!(longest_dependency_chain(&packages).unwrap(), vec!["a"]);
+        assert!(longest_dependency_chain(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_categorize_packages() {
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            create_test_package("aria2", vec!["openssl@3"]),
+            create_test_package("tool", vec!["aria2"]),
+            create_test_package("jq", vec![]),
+        ];
+
+        let report = HomebrewMigrator::categorize_packages(&packages);
+
+        assert_eq!(report.total_packages, 4);
+        assert_eq!(report.should_keep_in_homebrew[0].name, "openssl@3");
+        let risky: Vec<&str> = report.risky.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(risky, vec!["aria2", "tool"]);
+        assert_eq!(report.safe_to_migrate[0].name, "jq");
+    }
+
+    #[test]
+    fn test_risky_packages_sorted_by_score() {
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            create_test_package("libevent", vec![]),
+            create_test_package("curl", vec![]),
+            // One direct dependency on a link-conflict library
+            create_test_package("aria2", vec!["openssl@3"]),
+            // Two direct dependencies on milder problematic packages
+            create_test_package("tmux", vec!["libevent", "curl"]),
+            // Only transitive exposure
+            create_test_package("tool", vec!["aria2"]),
+            // Direct and transitive exposure both count
+            create_test_package("fetcher", vec!["libevent", "aria2"]),
+        ];
+
+        let report = HomebrewMigrator::categorize_packages(&packages);
+
+        let scored: Vec<(&str, u32)> = report
+            .risky
+            .iter()
+            .map(|p| (p.name.as_str(), p.risk_score))
+            .collect();
+        assert_eq!(
+            scored,
+            vec![("tmux", 40), ("fetcher", 32), ("aria2", 30), ("tool", 12)]
+        );
+        assert!(report
+            .should_keep_in_homebrew
+            .iter()
+            .all(|p| p.risk_score == KEEP_IN_HOMEBREW_SCORE));
+
+        let json = report.to_json().unwrap();
+        assert!(json.contains("\"risk_score\": 40"));
+    }
+
+    #[test]
+    fn test_build_only_dependencies_are_low_risk() {
+        let packages = vec![
+            create_test_package("python@3.12", vec![]),
+            create_test_package("cmake", vec![]),
+            BrewPackage {
+                build_dependencies: vec!["python@3.12".to_string(), "cmake".to_string()],
+                ..create_test_package("ninja", vec![])
+            },
+            create_test_package("aria2", vec!["python@3.12"]),
+        ];
+
+        let report = HomebrewMigrator::categorize_packages(&packages);
+
+        let ninja = report.find("ninja").unwrap();
+        assert_eq!(ninja.risk, MigrationRisk::Risky);
+        assert_eq!(ninja.problematic_dependencies, vec!["python@3.12", "cmake"]);
+        assert!(ninja.reason.starts_with("Build-time only"));
+        // Runtime dependencies outrank build-only ones
+        let risky: Vec<&str> = report.risky.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(risky, vec!["aria2", "ninja"]);
+
+        // Without build dependencies loaded, cmake no longer taints ninja
+        let runtime_only: Vec<BrewPackage> = packages
+            .into_iter()
+            .map(|p| BrewPackage {
+                build_dependencies: Vec::new(),
+                ..p
+            })
+            .collect();
+        let report = HomebrewMigrator::categorize_packages(&runtime_only);
+        assert_eq!(report.find("ninja").unwrap().risk, MigrationRisk::Safe);
+    }
+
+    #[test]
+    fn test_topological_sort_orders_build_dependencies_first() {
+        let packages = vec![
+            BrewPackage {
+                build_dependencies: vec!["cmake".to_string()],
+                ..create_test_package("aaa-tool", vec![])
+            },
+            create_test_package("cmake", vec![]),
+        ];
+
+        let sorted = HomebrewMigrator::topological_sort(&packages).unwrap();
+        let names: Vec<&str> = sorted.iter().map(|p| p.name.as_str()).collect();
+
+        assert_eq!(names, vec!["cmake", "aaa-tool"]);
+    }
+
+    #[test]
+    fn test_categorize_keg_only_packages() {
+        let keg_only = |name: &str, deps: Vec<&str>| BrewPackage {
+            keg_only: true,
+            ..create_test_package(name, deps)
+        };
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            keg_only("sqlite", vec![]),
+            keg_only("libxml2", vec!["openssl@3"]),
+            create_test_package("aria2", vec!["openssl@3"]),
+        ];
+
+        let report = HomebrewMigrator::categorize_packages(&packages);
+
+        let sqlite = report.find("sqlite").unwrap();
+        assert_eq!(sqlite.risk, MigrationRisk::Risky);
+        assert_eq!(sqlite.reason, KEG_ONLY_REASON);
+        let libxml2 = report.find("libxml2").unwrap();
+        assert!(libxml2.reason.ends_with("(keg-only)"));
+        assert_eq!(
+            libxml2.risk_score,
+            report.find("aria2").unwrap().risk_score + KEG_ONLY_SCORE
+        );
+    }
+
+    #[test]
+    fn test_categorize_no_bottle_packages() {
+        let no_bottle = |name: &str, deps: Vec<&str>| BrewPackage {
+            no_bottle: true,
+            ..create_test_package(name, deps)
+        };
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            no_bottle("tmux", vec![]),
+            no_bottle("lftp", vec!["openssl@3"]),
+            create_test_package("aria2", vec!["openssl@3"]),
+        ];
+
+        let report = HomebrewMigrator::categorize_packages(&packages);
+
+        let tmux = report.find("tmux").unwrap();
+        assert_eq!(tmux.risk, MigrationRisk::Risky);
+        assert_eq!(tmux.reason, NO_BOTTLE_REASON);
+        let lftp = report.find("lftp").unwrap();
+        assert!(lftp.reason.ends_with("(no bottle)"));
+        assert_eq!(
+            lftp.risk_score,
+            report.find("aria2").unwrap().risk_score + NO_BOTTLE_SCORE
+        );
+    }
+
+    #[test]
+    fn test_parse_no_bottle_from_info_json() {
+        let info = |bottle: &str| {
+            format!(
+                r#"{{"formulae": [{{"name": "x", "bottle": {}}}], "casks": []}}"#,
+                bottle
+            )
+        };
+        let tagged = info(r#"{"stable": {"files": {"arm64_sonoma": {}, "x86_64_linux": {}}}}"#);
+        assert!(!parse_no_bottle_from_info_json(&tagged, "arm64_sonoma"));
+        assert!(parse_no_bottle_from_info_json(&tagged, "arm64_sequoia"));
+        let all = info(r#"{"stable": {"files": {"all": {}}}}"#);
+        assert!(!parse_no_bottle_from_info_json(&all, "arm64_sequoia"));
+        assert!(parse_no_bottle_from_info_json(&info("{}"), "arm64_sonoma"));
+        // No bottle information at all is not treated as a missing bottle
+        assert!(!parse_no_bottle_from_info_json(
+            r#"{"formulae": [{"name": "x"}], "casks": []}"#,
+            "arm64_sonoma"
+        ));
+        assert!(!parse_no_bottle_from_info_json("not json", "arm64_sonoma"));
+
+        assert_eq!(
+            bottle_tag("macos", "aarch64", Some("14.4.1\n")).as_deref(),
+            Some("arm64_sonoma")
+        );
+        assert_eq!(
+            bottle_tag("macos", "x86_64", Some("13.6")).as_deref(),
+            Some("ventura")
+        );
+        assert_eq!(
+            bottle_tag("linux", "x86_64", None).as_deref(),
+            Some("x86_64_linux")
+        );
+        assert_eq!(bottle_tag("macos", "aarch64", Some("10.15")), None);
+        assert_eq!(bottle_tag("macos", "aarch64", None), None);
+    }
+
+    #[test]
+    fn test_dependency_tree() {
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            create_test_package("libfoo", vec!["openssl@3"]),
+            create_test_package("app", vec!["libfoo", "jq"]),
+            create_test_package("tool", vec!["libfoo"]),
+            create_test_package("jq", vec![]),
+        ];
+        let report = HomebrewMigrator::categorize_packages(&packages);
+        let rendered: Vec<String> = report
+            .dependency_tree(&packages)
+            .iter()
+            .map(|line| {
+                let risk = line.risk.as_ref().map_or("?", |r| r.label());
+                let repeated = if line.repeated { " (shown above)" } else { "" };
+                format!("{}{} [{}]{}", line.prefix, line.name, risk, repeated)
+            })
+            .collect();
+        assert_eq!(
+            rendered,
+            vec![
+                "app [Risky]",
+                "├── libfoo [Risky]",
+                "│   └── openssl@3 [Keep in Homebrew]",
+                "└── jq [Safe]",
+                "tool [Risky]",
+                "└── libfoo [Risky] (shown above)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dependency_tree_with_cycle() {
+        let packages = vec![
+            create_test_package("a", vec!["b"]),
+            create_test_package("b", vec!["a"]),
+        ];
+        let report = HomebrewMigrator::categorize_packages(&packages);
+        let lines = report.dependency_tree(&packages);
+        let names: Vec<&str> = lines.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "b", "a"]);
+        assert!(lines[2].repeated);
+    }
+
+    #[test]
+    fn test_explain_package() {
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            create_test_package("zlib", vec![]),
+            create_test_package("curl", vec!["openssl@3"]),
+            create_test_package("libfoo", vec!["openssl@3", "zlib"]),
+            create_test_package("app", vec!["libfoo", "curl"]),
+        ];
+
+        let explanation = HomebrewMigrator::explain_package(&packages, "app", false).unwrap();
+        assert_eq!(explanation.analysis.risk, MigrationRisk::Risky);
+        assert!(!explanation.no_bottle);
+        assert_eq!(explanation.direct_problematic, vec!["curl"]);
+        assert_eq!(
+            explanation.chains,
+            vec![
+                vec!["app", "curl"],
+                vec!["app", "libfoo", "openssl@3"],
+                vec!["app", "libfoo", "zlib"],
+            ]
+        );
+        assert_eq!(
+            explanation.reasons.keys().collect::<Vec<_>>(),
+            vec!["curl", "openssl@3", "zlib"]
+        );
+
+        let strict = HomebrewMigrator::explain_package(&packages, "app", true).unwrap();
+        assert_eq!(strict.analysis.risk, MigrationRisk::KeepInHomebrew);
+
+        let own = HomebrewMigrator::explain_package(&packages, "zlib", false).unwrap();
+        assert!(own.chains.is_empty());
+        assert!(own.reasons.contains_key("zlib"));
+
+        let err = HomebrewMigrator::explain_package(&packages, "libfo", false).unwrap_err();
+        assert!(err.to_string().contains("Did you mean: libfoo?"));
+
+        let mut packages = packages;
+        packages.push(BrewPackage {
+            no_bottle: true,
+            ..create_test_package("tool", vec![])
+        });
+        let tool = HomebrewMigrator::explain_package(&packages, "tool", false).unwrap();
+        assert!(tool.no_bottle);
+        assert_eq!(tool.analysis.risk, MigrationRisk::Risky);
+    }
+
+    #[test]
+    fn test_parse_keg_only_from_info_json() {
+        assert!(parse_keg_only_from_info_json(
+            r#"{"formulae": [{"name": "sqlite", "keg_only": true}], "casks": []}"#
+        ));
+        assert!(!parse_keg_only_from_info_json(
+            r#"{"formulae": [{"name": "jq", "keg_only": false}], "casks": []}"#
+        ));
+        assert!(!parse_keg_only_from_info_json("not json"));
+    }
+
+    #[test]
+    fn test_parse_head_version_from_info_json() {
+        assert!(is_head_version("HEAD-1a2b3c4"));
+        assert!(is_head_version("HEAD"));
+        assert!(!is_head_version("0.10.0"));
+
+        let linked = r#"{"formulae": [{"name": "neovim", "linked_keg": "HEAD-1a2b3c4",
+            "installed": [{"version": "0.9.5"}, {"version": "HEAD-1a2b3c4"}]}]}"#;
+        assert_eq!(
+            parse_head_version_from_info_json(linked).as_deref(),
+            Some("HEAD-1a2b3c4")
+        );
+        let stable_linked = r#"{"formulae": [{"name": "neovim", "linked_keg": "0.9.5",
+            "installed": [{"version": "0.9.5"}, {"version": "HEAD-1a2b3c4"}]}]}"#;
+        assert_eq!(parse_head_version_from_info_json(stable_linked), None);
+        let unlinked = r#"{"formulae": [{"name": "neovim", "linked_keg": null,
+            "installed": [{"version": "HEAD-1a2b3c4"}]}]}"#;
+        assert_eq!(
+            parse_head_version_from_info_json(unlinked).as_deref(),
+            Some("HEAD-1a2b3c4")
+        );
+        assert_eq!(parse_head_version_from_info_json("not json"), None);
+    }
+
+    #[test]
+    fn test_categorize_packages_with_casks() {
+        let cask = |name: &str| BrewPackage {
+            is_cask: true,
+            ..create_test_package(name, vec![])
+        };
+        let packages = vec![
+            create_test_package("jq", vec![]),
+            cask("iterm2"),
+            cask("python@3.12"),
+        ];
+
+        let report = HomebrewMigrator::categorize_packages(&packages);
+
+        let iterm = report.find("iterm2").unwrap();
+        assert!(iterm.is_cask);
+        assert_eq!(iterm.risk, MigrationRisk::Safe);
+        assert!(iterm.reason.contains("not yet supported"));
+        assert!(report.find("python@3.12").unwrap().is_cask);
+        assert_eq!(
+            report.find("python@3.12").unwrap().risk,
+            MigrationRisk::KeepInHomebrew
+        );
+
+        // Casks never show up in migration suggestions
+        assert_eq!(report.safe_formulae(), vec!["jq"]);
+        let md = report.to_markdown();
+        assert!(md.contains("| iterm2 (cask) |"));
+        assert!(md.contains("`zb-migrate migrate --packages jq`"));
+    }
+
+    // ============================================
+    // Brewfile Export Format Tests
+    // ============================================
+
+    #[test]
+    fn test_brewfile_format_basic() {
+        let formulae = vec![
+            create_test_package("git", vec![]),
+            create_test_package("node", vec![]),
+        ];
+
+        let casks = vec![BrewPackage {
+            name: "visual-studio-code".to_string(),
+            version: "1.84.0".to_string(),
+            tap: None,
+            is_cask: true,
+            dependencies: vec![],
+            pinned: false,
+            ..Default::default()
+        }];
+
+        let mut content = String::new();
+        content.push_str("# Zerobrew Migration Brewfile\n");
+        content.push_str("# Generated from Homebrew installation\n\n");
+
+        // Collect taps
+        let mut taps: std::collections::HashSet<String> = std::collections::HashSet::new();
+        for pkg in &formulae {
+            if let Some(ref tap) = pkg.tap {
+                taps.insert(tap.clone());
+            }
+        }
+
+        for tap in &taps {
+            content.push_str(&format!("tap \"{}\"\n", tap));
+        }
+        content.push('\n');
+
+        // Formulae
+        for pkg in &formulae {
+            content.push_str(&format!("brew \"{}\"\n", pkg.name));
+        }
+        content.push('\n');
+
+        // Casks
+        for pkg in &casks {
+            content.push_str(&format!("cask \"{}\"\n", pkg.name));
+        }
+
+        assert!(content.contains("# Zerobrew Migration Brewfile"));
+        assert!(content.contains("brew \"git\""));
+        assert!(content.contains("brew \"node\""));
+        assert!(content.contains("cask \"visual-studio-code\""));
+    }
+
+    #[test]
+    fn test_brewfile_format_with_taps() {
+        let formulae = vec![
+            BrewPackage {
+                name: "neovim".to_string(),
+                version: "0.9.4".to_string(),
+                tap: Some("homebrew/core".to_string()),
+                is_cask: false,
+                dependencies: vec![],
+                pinned: false,
+                ..Default::default()
+            },
+            BrewPackage {
+                name: "custom-tool".to_string(),
+                version: "1.0.0".to_string(),
+                tap: Some("user/custom-tap".to_string()),
+                is_cask: false,
+                dependencies: vec![],
+                pinned: false,
+                ..Default::default()
+            },
+        ];
+
+        let mut content = String::new();
+        content.push_str("# Zerobrew Migration Brewfile\n");
+        content.push_str("# Generated from Homebrew installation\n\n");
+
+        // Collect taps
+        let mut taps: std::collections::HashSet<String> = std::collections::HashSet::new();
+        for pkg in &formulae {
+            if let Some(ref tap) = pkg.tap {
+                taps.insert(tap.clone());
+            }
+        }
+
+        for tap in &taps {
+            content.push_str(&format!("tap \"{}\"\n", tap));
+        }
+        content.push('\n');
+
+        for pkg in &formulae {
+            content.push_str(&format!("brew \"{}\"\n", pkg.name));
+        }
+
+        assert!(
+            content.contains("tap \"homebrew/core\"")
+                || content.contains("tap \"user/custom-tap\"")
+        );
+        assert!(content.contains("brew \"neovim\""));
+        assert!(content.contains("brew \"custom-tool\""));
+    }
+
+    #[test]
+    fn test_brewfile_format_empty() {
+        let formulae: Vec<BrewPackage> = vec![];
+        let casks: Vec<BrewPackage> = vec![];
+
+        let mut content = String::new();
+        content.push_str("# Zerobrew Migration Brewfile\n");
+        content.push_str("# Generated from Homebrew installation\n\n");
+
+        let taps: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+        for tap in &taps {
+            content.push_str(&format!("tap \"{}\"\n", tap));
+        }
+        content.push('\n');
+
+        for pkg in &formulae {
+            content.push_str(&format!("brew \"{}\"\n", pkg.name));
+        }
+        content.push('\n');
+
+        for pkg in &casks {
+            content.push_str(&format!("cask \"{}\"\n", pkg.name));
+        }
+
+        assert!(content.contains("# Zerobrew Migration Brewfile"));
+        assert!(!content.contains("brew \""));
+        assert!(!content.contains("cask \""));
+    }
+
+    #[test]
+    fn test_render_brewfile() {
+        let mut formulae = vec![
+            create_test_package("git", vec![]),
+            create_test_package("k9s", vec![]),
+        ];
+        formulae[1].tap = Some("derailed/k9s".to_string());
+        let casks = vec![BrewPackage {
+            is_cask: true,
+            ..create_test_package("iterm2", vec![])
+        }];
+
+        let content = render_brewfile(&formulae, &casks);
+
+        assert!(content.starts_with("# Zerobrew Migration Brewfile\n"));
+        assert!(content.contains("tap \"derailed/k9s\"\n"));
+        assert!(content.contains("brew \"git\"\nbrew \"k9s\"\n"));
+        assert!(content.contains("cask \"iterm2\"\n"));
+    }
+
+    #[test]
+    fn test_merge_brewfile_preserves_existing_lines() {
+        let existing = "# Team Brewfile\n\
+                        tap \"derailed/k9s\"\n\
+                        \n\
+                        brew \"git\" # pinned to 2.42 on CI\n\
+                        brew \"jq\"\n\
+                        \n\
+                        cask \"iterm2\"\n";
+        let mut formulae = vec![
+            create_test_package("git", vec![]),
+            create_test_package("ripgrep", vec![]),
+            create_test_package("k9s", vec![]),
+        ];
+        formulae[2].tap = Some("derailed/k9s".to_string());
+        let casks = vec![create_test_package("iterm2", vec![])];
+
+        let (merged, added) = merge_brewfile(existing, &formulae, &casks);
+
+        assert_eq!(added, 2);
+        assert_eq!(
+            merged,
+            "# Team Brewfile\n\
+             tap \"derailed/k9s\"\n\
+             \n\
+             brew \"git\" # pinned to 2.42 on CI\n\
+             brew \"jq\"\n\
+             brew \"ripgrep\"\n\
+             brew \"k9s\"\n\
+             \n\
+             cask \"iterm2\"\n"
+        );
+    }
+
+    #[test]
+    fn test_merge_brewfile_appends_missing_kinds() {
+        let existing = "brew \"git\"\n";
+        let formulae = vec![create_test_package("git", vec![])];
+        let casks = vec![create_test_package("iterm2", vec![])];
+
+        let (merged, added) = merge_brewfile(existing, &formulae, &casks);
+
+        assert_eq!(added, 1);
+        assert_eq!(merged, "brew \"git\"\ncask \"iterm2\"\n");
+    }
+
+    #[test]
+    fn test_merge_brewfile_matches_qualified_names() {
+        let existing = "tap \"derailed/k9s\"\nbrew \"derailed/k9s/k9s\"\nbrew \"jq\"\n";
+        let mut formulae = vec![
+            create_test_package("k9s", vec![]),
+            create_test_package("homebrew/core/jq", vec![]),
+        ];
+        formulae[0].tap = Some("derailed/k9s".to_string());
+
+        let (merged, added) = merge_brewfile(existing, &formulae, &[]);
+
+        assert_eq!(added, 0);
+        assert_eq!(merged, existing);
+    }
+
+    #[test]
+    fn test_render_brewfile_escapes_pathological_names() {
+        let mut formulae = vec![create_test_package(
+            "evil\"\nsystem(\"rm -rf ~\")#{x}\\",
+            vec![],
+        )];
+        formulae[0].tap = Some("user/ta\"p".to_string());
+
+        let content = render_brewfile(&formulae, &[]);
+
+        assert!(content.contains("tap \"user/ta\\\"p\"\n"));
+        assert!(content.contains("brew \"evil\\\"\\nsystem(\\\"rm -rf ~\\\")\\#{x}\\\\\"\n"));
+        // Every entry stays on a single line and round-trips through the parser
+        let parsed = parse_brewfile(&content);
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].name, formulae[0].name);
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Brewfile");
+        fs::write(&path, "brew \"old\"\n").unwrap();
+
+        write_atomic(&path, b"brew \"new\"\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "brew \"new\"\n");
+        // Only the Brewfile remains; the temporary file was renamed over it
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_atomic_failure_leaves_target_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        // Renaming a file over a non-empty directory fails
+        let path = dir.path().join("Brewfile");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("keep"), "x").unwrap();
+
+        assert!(write_atomic(&path, b"brew \"jq\"\n").is_err());
+        assert_eq!(fs::read_to_string(path.join("keep")).unwrap(), "x");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_export_append_rewrites_existing_brewfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let migrator = fixture_migrator(dir.path());
+        let path = dir.path().join("Brewfile");
+        fs::write(&path, "# curated\nbrew \"jq\"\n").unwrap();
+
+        let added = migrator.export_to_brewfile(&path, true).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# curated\nbrew \"jq\"\n"));
+        assert!(content.contains("brew \"git\""));
+        assert_eq!(added, 5);
+    }
+
+    #[test]
+    fn test_brewfile_quote_roundtrip() {
+        for name in [
+            "git",
+            "a\"b",
+            "back\\slash",
+            "#{interp}",
+            "tab\there",
+            "bell\u{7}",
+        ] {
+            let quoted = brewfile_quote(name);
+            assert!(!quoted.contains('\n'));
+            assert_eq!(brewfile_unquote(&quoted).as_deref(), Some(name));
+        }
+        assert_eq!(brewfile_unquote("\"unterminated"), None);
+    }
+
+    #[test]
+    fn test_brewfile_format_special_characters() {
+        let formulae = vec![
+            create_test_package("python@3.11", vec![]),
+            create_test_package("openssl@3", vec![]),
+        ];
+
+        let mut content = String::new();
+        for pkg in &formulae {
+            content.push_str(&format!("brew \"{}\"\n", pkg.name));
+        }
+
+        assert!(content.contains("brew \"python@3.11\""));
+        assert!(content.contains("brew \"openssl@3\""));
+    }
+
+    // ============================================
+    // MigrationReport Tests
+    // ============================================
+
+    #[test]
+    fn test_migration_report_default() {
+        let report = MigrationReport::default();
+
+        assert_eq!(report.total_formulae, 0);
+        assert_eq!(report.total_casks, 0);
+        assert!(report.successful.is_empty());
+        assert!(report.failed.is_empty());
+        assert!(report.skipped.is_empty());
+    }
+
+    #[test]
+    fn test_migration_report_with_data() {
+        let mut report = MigrationReport::default();
+        report.total_formulae = 10;
+        report.total_casks = 5;
+        report.successful.push("git".to_string());
+        report.successful.push("node".to_string());
+        report
+            .failed
+            .push(("broken-pkg".to_string(), "Install failed".to_string()));
+        report
+            .skipped
+            .push(("cask-app".to_string(), "Casks not supported".to_string()));
+
+        assert_eq!(report.total_formulae, 10);
+        assert_eq!(report.total_casks, 5);
+        assert_eq!(report.successful.len(), 2);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.skipped.len(), 1);
+    }
+
+    #[test]
+    fn test_migration_report_markdown() {
+        let mut report = MigrationReport {
+            total_formulae: 3,
+            ..Default::default()
+        };
+        report.successful.push("git".to_string());
+        report.failed.push((
+            "openssl@3".to_string(),
+            "link conflict | bin/openssl\nsecond line".to_string(),
+        ));
+        report
+            .skipped
+            .push(("iterm2".to_string(), "Casks not yet supported".to_string()));
+
+        let md = report.to_markdown();
+
+        assert!(md.starts_with("# Migration Report"));
+        assert!(md.contains("| Total formulae | 3 |"));
+        assert!(md.contains("## Successful (1)"));
+        assert!(md.contains("| git |"));
+        assert!(md.contains("| openssl@3 | link conflict \\| bin/openssl<br>second line | - |"));
+        assert!(md.contains("| iterm2 | Casks not yet supported |"));
+        assert!(md.contains("Retry failed packages"));
+        assert!(!md.contains("## Would Migrate"));
+
+        // A dry run lists what it would install instead of installs
+        let planned = MigrationReport {
+            planned: vec!["jq".to_string()],
+            dry_run: true,
+            ..Default::default()
+        };
+        let md = planned.to_markdown();
+        assert!(md.contains("## Would Migrate (1)\n\n| Package |\n|---|\n| jq |"));
+        assert!(md.contains("This was a dry run"));
+        assert!(!md.contains("All attempted packages migrated"));
+
+        // ...even when nothing would be migrated, e.g. every package skipped interactively
+        let md = MigrationReport {
+            dry_run: true,
+            ..Default::default()
+        }
+        .to_markdown();
+        assert!(md.contains("This was a dry run"));
+        assert!(!md.contains("All attempted packages migrated"));
+    }
+
+    #[test]
+    fn test_analysis_report_json_files() {
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            create_test_package("curl", vec!["openssl@3"]),
+            create_test_package("jq", vec![]),
+        ];
+        let report = HomebrewMigrator::categorize_packages(&packages);
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("analysis");
+
+        let written = report.write_json_files(&out).unwrap();
+
+        assert_eq!(written.len(), 4);
+        let read = |name: &str| -> serde_json::Value {
+            serde_json::from_str(&std::fs::read_to_string(out.join(name)).unwrap()).unwrap()
+        };
+        assert_eq!(read("safe.json")["data"][0]["name"], "jq");
+        let keep: Vec<PackageAnalysis> =
+            serde_json::from_value(read("keep.json")["data"].clone()).unwrap();
+        assert_eq!(keep.len(), 2);
+        assert_eq!(read("risky.json")["data"], serde_json::json!([]));
+        let summary: AnalysisSummary =
+            serde_json::from_value(read("summary.json")["data"].clone()).unwrap();
+        assert_eq!(summary, report.summary());
+        assert_eq!(summary.should_keep_in_homebrew, 2);
+    }
+
+    #[test]
+    fn test_analysis_report_table_aligns_columns() {
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            create_test_package("a-very-long-package-name", vec!["openssl@3"]),
+            create_test_package("jq", vec![]),
+        ];
+        let report = HomebrewMigrator::categorize_packages(&packages);
+
+        let table = report.to_table();
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("PACKAGE "));
+        // The VERSION column starts at the same offset on every line
+        let offset = lines[0].find("VERSION").unwrap();
+        assert_eq!(offset, "a-very-long-package-name".len() + 2);
+        for line in &lines[2..] {
+            assert_eq!(&line[offset..offset + 5], "1.0.0");
+        }
+        assert!(table.contains("Keep in Homebrew  -"));
+        assert!(lines.iter().all(|l| !l.ends_with(' ')));
+    }
+
+    #[test]
+    fn test_analysis_report_markdown() {
+        let mut report = AnalysisReport::new();
+        report.total_packages = 2;
+        report.safe_to_migrate.push(PackageAnalysis {
+            name: "jq".to_string(),
+            version: "1.7".to_string(),
+            risk: MigrationRisk::Safe,
+            reason: "No known problematic dependencies".to_string(),
+            problematic_dependencies: Vec::new(),
+            is_cask: false,
+            risk_score: 0,
+        });
+        report.risky.push(PackageAnalysis {
+            name: "wget2".to_string(),
+            version: "2.1".to_string(),
+            risk: MigrationRisk::Risky,
+            reason: "Depends on 1 problematic package(s)".to_string(),
+            problematic_dependencies: vec!["openssl@3".to_string()],
+            is_cask: false,
+            risk_score: 30,
+        });
+
+        let md = report.to_markdown();
+
+        assert!(md.contains("| Safe to migrate | 1 |"));
+        assert!(md.contains("| jq | 1.7 | No known problematic dependencies | - |"));
+        assert!(md.contains("| wget2 | 2.1 | Depends on 1 problematic package(s) | openssl@3 |"));
+        assert!(!md.contains("## Keep in Homebrew"));
+        assert!(md.contains("zb-migrate migrate --packages jq"));
+    }
+
+    #[test]
+    fn test_analysis_report_migration_script() {
+        let report = HomebrewMigrator::categorize_packages(&[
+            create_test_package("openssl@3", vec![]),
+            create_test_package("jq", vec![]),
+            create_test_package("tree", vec![]),
+            create_test_package("aria2", vec!["openssl@3"]),
+        ]);
+
+        let script = report.to_migration_script(false);
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("zb-migrate migrate \\\n    --packages 'jq,tree'\n"));
+        assert!(!script.contains("aria2"));
+        assert!(!script.contains("openssl@3"));
+
+        let script = report.to_migration_script(true);
+        assert!(script.contains("zb-migrate migrate --packages 'aria2'\n"));
+        assert!(script.contains("read -r _"));
+    }
+
+    #[test]
+    fn test_migration_script_quotes_names() {
+        let name = "jq'; rm -rf ~ #";
+        let report = HomebrewMigrator::categorize_packages(&[
+            create_test_package(name, vec![]),
+            create_test_package("openssl@3", vec![]),
+            create_test_package("aria2'$(id)", vec!["openssl@3"]),
+        ]);
+
+        let script = report.to_migration_script(true);
+        assert!(script.contains("--packages 'jq'\\''; rm -rf ~ #'\n"));
+        assert!(script.contains("zb-migrate migrate --packages 'aria2'\\''$(id)'\n"));
+        assert!(script.contains("printf '%s' 'Test aria2'\\''$(id), then press Enter"));
+        assert!(!script.contains("jq'; rm"));
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("git"), "'git'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_migration_report_skip_remaining() {
+        let mut report = MigrationReport::default();
+        let remaining = vec![
+            create_test_package("b", vec![]),
+            create_test_package("c", vec![]),
+        ];
+
+        report.skip_remaining(&remaining);
+        report.skip_remaining(&[]);
+
+        assert_eq!(
+            report.skipped,
+            vec![
+                ("b".to_string(), FAIL_FAST_SKIP_REASON.to_string()),
+                ("c".to_string(), FAIL_FAST_SKIP_REASON.to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_failures_threshold() {
+        let options = MigrateOptions {
+            max_failures: Some(3),
+            ..Default::default()
+        };
+        assert!(!options.failure_limit_reached(2));
+        assert!(options.failure_limit_reached(3));
+        assert!(!MigrateOptions::default().failure_limit_reached(100));
+
+        let mut report = MigrationReport::default();
+        report.skip_over_failure_limit(&[create_test_package("c", vec![])]);
+        assert!(report.failure_limit_reached);
+        assert_eq!(
+            report.skipped,
+            vec![("c".to_string(), MAX_FAILURES_SKIP_REASON.to_string())]
+        );
+    }
+
+    #[test]
+    fn test_push_result_unknown_version_is_not_drift() {
+        let mut report = MigrationReport::default();
+        let package = BrewPackage::not_installed("jq");
+        report.push_result(MigrateResult::Success {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            installed_version: Some("1.7.1".to_string()),
+            pin_fallback: false,
+            zerobrew_name: None,
+            install_mode: InstallMode::Default,
+        });
+
+        assert_eq!(report.successful, vec!["jq"]);
+        assert_eq!(report.installed_versions["jq"], "1.7.1");
+        assert!(report.version_drift.is_empty());
+    }
+
+    #[test]
+    fn test_push_result_head_install_is_not_drift() {
+        let mut report = MigrationReport::default();
+        report.push_result(MigrateResult::Success {
+            name: "neovim".to_string(),
+            version: "HEAD-1a2b3c4".to_string(),
+            installed_version: Some("0.9.5".to_string()),
+            pin_fallback: false,
+            zerobrew_name: None,
+            install_mode: InstallMode::Default,
+        });
+
+        assert!(report.version_drift.is_empty());
+        assert_eq!(
+            report.head_installs,
+            vec![HeadInstall {
+                name: "neovim".to_string(),
+                homebrew_version: "HEAD-1a2b3c4".to_string(),
+                zerobrew_version: Some("0.9.5".to_string()),
+            }]
+        );
+        assert!(report
+            .to_markdown()
+            .contains("## HEAD Installs Replaced by Stable (1)"));
+    }
+
+    #[test]
+    fn test_head_formulae_detected_when_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture: BrewFixture = serde_json::from_str(
+            r#"{"formulae": [{"name": "neovim", "version": "HEAD-1a2b3c4"},
+                             {"name": "jq", "version": "1.7.1"}]}"#,
+        )
+        .unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockBrewRunner::new(fixture, migrator.brew_bin.clone()));
+
+        for packages in [
+            migrator.list_installed_formulae().unwrap(),
+            migrator.list_installed_formulae_detailed().unwrap(),
+        ] {
+            let head: Vec<&str> = packages
+                .iter()
+                .filter(|p| p.head)
+                .map(|p| p.name.as_str())
+                .collect();
+            assert_eq!(head, vec!["neovim"]);
+        }
+
+        let plan = migrator.plan_migration(&MigrateOptions::default()).unwrap();
+        let head: Vec<&str> = plan
+            .head_formulae()
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(head, vec!["neovim"]);
+    }
+
+    #[test]
+    fn test_skip_interrupted_marks_report() {
+        static CANCEL: AtomicBool = AtomicBool::new(false);
+        let options = MigrateOptions {
+            cancel: Some(&CANCEL),
+            ..Default::default()
+        };
+        assert!(!options.cancelled());
+        CANCEL.store(true, Ordering::SeqCst);
+        assert!(options.cancelled());
+        assert!(!MigrateOptions::default().cancelled());
+
+        let mut report = MigrationReport::default();
+        report.skip_interrupted(&[create_test_package("jq", vec![])]);
+        assert!(report.interrupted);
+        assert_eq!(
+            report.skipped,
+            vec![("jq".to_string(), INTERRUPTED_SKIP_REASON.to_string())]
+        );
+    }
+
+    #[test]
+    fn test_migration_plan_new_report() {
+        let plan = MigrationPlan {
+            formulae: vec![create_test_package("git", vec![])],
+            casks: vec![create_test_package("iterm2", vec![])],
+            skipped: vec![("old".to_string(), "older than cutoff".to_string())],
+            total_formulae: 2,
+            dependencies_loaded: true,
+            ..Default::default()
+        };
+
+        let report = plan.new_report();
+
+        assert!(!report.dry_run);
+        assert_eq!(report.total_formulae, 2);
+        assert_eq!(report.total_casks, 1);
+        assert!(report.successful.is_empty());
+        assert_eq!(
+            report.skipped,
+            vec![
+                ("old".to_string(), "older than cutoff".to_string()),
+                ("iterm2".to_string(), "Casks not yet supported".to_string()),
+            ]
+        );
+
+        let report = plan.dry_run_report();
+        assert!(report.dry_run);
+        assert_eq!(report.planned, vec!["git"]);
+        assert_eq!(report.skipped.len(), 2);
+    }
+
+    #[test]
+    fn test_migration_report_push_result() {
+        let mut report = MigrationReport::default();
+
+        report.push_result(MigrateResult::Success {
+            name: "git".to_string(),
+            version: "2.42.0".to_string(),
+            installed_version: Some("2.43.0".to_string()),
+            pin_fallback: false,
+            zerobrew_name: None,
+            install_mode: InstallMode::Default,
+        });
+        report.push_result(MigrateResult::Success {
+            name: "jq".to_string(),
+            version: "1.7.1_1".to_string(),
+            installed_version: Some("1.7.1".to_string()),
+            pin_fallback: false,
+            zerobrew_name: None,
+            install_mode: InstallMode::Default,
+        });
+        report.push_result(MigrateResult::Failed {
+            name: "openssl@3".to_string(),
+            reason: "link conflict".to_string(),
+            error: MigrateError::Other("link conflict".to_string()),
+            log: Some(PathBuf::from("/tmp/logs/openssl@3.log")),
+        });
+
+        assert_eq!(report.successful, vec!["git", "jq"]);
+        assert_eq!(report.installed_versions["git"], "2.43.0");
+        // Homebrew revision suffixes are not drift
+        assert_eq!(
+            report.version_drift,
+            vec![VersionDrift {
+                name: "git".to_string(),
+                homebrew_version: "2.42.0".to_string(),
+                zerobrew_version: "2.43.0".to_string(),
+            }]
+        );
+        assert!(report.to_markdown().contains("| git | 2.42.0 | 2.43.0 |"));
+        assert_eq!(
+            report.failed,
+            vec![("openssl@3".to_string(), "link conflict".to_string())]
+        );
+        assert_eq!(
+            report.logs["openssl@3"],
+            PathBuf::from("/tmp/logs/openssl@3.log")
+        );
+    }
+
+    #[test]
+    fn test_parse_zb_info_version() {
+        assert_eq!(
+            parse_zb_info_version("jq 1.7.1\nLightweight JSON processor\n", "jq"),
+            Some("1.7.1".to_string())
+        );
+        assert_eq!(
+            parse_zb_info_version("==> ripgrep\nVersion: 14.1.0\n", "ripgrep"),
+            Some("14.1.0".to_string())
+        );
+        assert_eq!(parse_zb_info_version("no version here\n", "jq"), None);
+    }
+
+    #[test]
+    fn test_versions_match_ignores_revision() {
+        assert!(versions_match("1.7.1_1", "1.7.1"));
+        assert!(versions_match("2.43.0", "2.43.0"));
+        assert!(!versions_match("2.42.0", "2.43.0"));
+        assert!(!versions_match("3.0_beta", "3.0"));
+    }
+
+    #[test]
+    fn test_summarize_install_error() {
+        let stderr = "==> Fetching openssl@3\n\
+                      Error: link conflict at '/opt/zerobrew/prefix/bin/openssl'\n\
+                      Please remove the conflicting file and retry.\n";
+        assert_eq!(
+            summarize_install_error(stderr),
+            "Error: link conflict at '/opt/zerobrew/prefix/bin/openssl'"
+        );
+
+        assert_eq!(
+            summarize_install_error("first\nsomething went wrong\n\n"),
+            "something went wrong"
+        );
+        assert_eq!(
+            summarize_install_error(""),
+            "zb install failed without error output"
+        );
+
+        let long = "x".repeat(500);
+        assert_eq!(summarize_install_error(&long).chars().count(), 161);
+    }
+
+    // ============================================
+    // MigrateResult Tests
+    // ============================================
+
+    #[test]
+    fn test_migrate_result_success() {
+        let result = MigrateResult::Success {
+            name: "git".to_string(),
+            version: "2.42.0".to_string(),
+            installed_version: None,
+            pin_fallback: false,
+            zerobrew_name: None,
+            install_mode: InstallMode::Default,
+        };
+
+        match result {
+            MigrateResult::Success { name, version, .. } => {
+                assert_eq!(name, "git");
+                assert_eq!(version, "2.42.0");
+            }
+            MigrateResult::Failed { .. } => panic!("Expected Success variant"),
+        }
+    }
+
+    #[test]
+    fn test_migrate_result_failed() {
+        let result = MigrateResult::Failed {
+            name: "broken-pkg".to_string(),
+            reason: "Package not found".to_string(),
+            error: MigrateError::NotFoundInZerobrew,
+            log: None,
+        };
+
+        match result {
+            MigrateResult::Success { .. } => panic!("Expected Failed variant"),
+            MigrateResult::Failed { name, reason, .. } => {
+                assert_eq!(name, "broken-pkg");
+                assert_eq!(reason, "Package not found");
+            }
+        }
+    }
+
+    // ============================================
+    // BrewPackage Serialization Tests
+    // ============================================
+
+    #[test]
+    fn test_brew_package_serialization() {
+        let pkg = BrewPackage {
+            name: "git".to_string(),
+            version: "2.42.0".to_string(),
+            tap: Some("homebrew/core".to_string()),
+            is_cask: false,
+            dependencies: vec!["pcre2".to_string(), "gettext".to_string()],
+            pinned: true,
+            ..Default::default()
+        };
+
+        let json = serde_json::to_string(&pkg).expect("Serialization failed");
+
+        assert!(json.contains("\"name\":\"git\""));
+        assert!(json.contains("\"version\":\"2.42.0\""));
+        assert!(json.contains("\"tap\":\"homebrew/core\""));
+        assert!(json.contains("\"is_cask\":false"));
+        assert!(json.contains("\"pinned\":true"));
+        assert!(json.contains("pcre2"));
+        assert!(json.contains("gettext"));
+    }
+
+    #[test]
+    fn test_brew_package_deserialization() {
+        let json = r#"{
+            "name": "node",
+            "version": "20.9.0",
+            "tap": null,
+            "is_cask": false,
+            "dependencies": ["icu4c", "libnghttp2"],
+            "pinned": false
+        }"#;
+
+        let pkg: BrewPackage = serde_json::from_str(json).expect("Deserialization failed");
+
+        assert_eq!(pkg.name, "node");
+        assert_eq!(pkg.version, "20.9.0");
+        assert!(pkg.tap.is_none());
+        assert!(!pkg.is_cask);
+        assert_eq!(pkg.dependencies, vec!["icu4c", "libnghttp2"]);
+        assert!(!pkg.pinned);
+    }
+
+    #[test]
+    fn test_brew_package_roundtrip() {
+        let original = BrewPackage {
+            name: "rust".to_string(),
+            version: "1.73.0".to_string(),
+            tap: Some("homebrew/core".to_string()),
+            is_cask: false,
+            dependencies: vec!["libssh2".to_string(), "openssl@3".to_string()],
+            pinned: true,
+            ..Default::default()
+        };
+
+        let json = serde_json::to_string(&original).expect("Serialization failed");
+        let restored: BrewPackage = serde_json::from_str(&json).expect("Deserialization failed");
+
+        assert_eq!(restored.name, original.name);
+        assert_eq!(restored.version, original.version);
+        assert_eq!(restored.tap, original.tap);
+        assert_eq!(restored.is_cask, original.is_cask);
+        assert_eq!(restored.dependencies, original.dependencies);
+        assert_eq!(restored.pinned, original.pinned);
+    }
+
+    // ============================================
+    // Brewfile Parsing Tests
+    // ============================================
+
+    #[test]
+    fn test_parse_brewfile() {
+        let content = r#"# Zerobrew Migration Brewfile
+tap "derailed/k9s"
+
+brew "git"
+brew "openssl@3"
+brew "derailed/k9s/k9s"
+brew "postgresql@16", restart_service: true
+cask "iterm2"
+mas "Xcode", id: 497799835
+"#;
+
+        let packages = parse_brewfile(content);
+        let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+
+        assert_eq!(names, vec!["git", "openssl@3", "k9s", "postgresql@16"]);
+        assert_eq!(packages[2].tap.as_deref(), Some("derailed/k9s"));
+        assert!(packages
+            .iter()
+            .all(|p| !p.is_cask && p.version == "unknown"));
+    }
+
+    #[test]
+    fn test_parse_brewfile_bundle_dump() {
+        let content = r#"tap "homebrew/bundle"
+cask_args appdir: "~/Applications"
+brew "ffmpeg", args: ["with-fdk-aac"]
+brew "derailed/k9s/k9s", link: false
+cask "firefox", greedy: true
+# brew "commented-out"
+mas "Xcode", id: 497799835
+vscode "rust-lang.rust-analyzer"
+"#;
+
+        let brewfile = parse_brewfile_entries(content);
+
+        assert_eq!(brewfile.taps, vec!["homebrew/bundle"]);
+        let formulae: Vec<&str> = brewfile.formulae.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(formulae, vec!["ffmpeg", "k9s"]);
+        assert_eq!(brewfile.casks.len(), 1);
+        assert_eq!(brewfile.casks[0].name, "firefox");
+        assert!(brewfile.casks[0].is_cask);
+        assert_eq!(
+            brewfile.unsupported,
+            vec![
+                ("mas".to_string(), "Xcode".to_string()),
+                ("vscode".to_string(), "rust-lang.rust-analyzer".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_brewfile_single_quotes() {
+        let content = r#"tap 'derailed/k9s'
+brew 'jq'
+brew 'derailed/k9s/k9s', link: false
+cask 'it\'s-a-cask'
+brew 'back\slash'
+brew 'unterminated
+"#;
+
+        let brewfile = parse_brewfile_entries(content);
+
+        assert_eq!(brewfile.taps, vec!["derailed/k9s"]);
+        let formulae: Vec<&str> = brewfile.formulae.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(formulae, vec!["jq", "k9s", "back\\slash"]);
+        assert_eq!(brewfile.casks[0].name, "it's-a-cask");
+    }
+
+    #[test]
+    fn test_categorize_brewfile_packages() {
+        let packages = parse_brewfile("brew \"git\"\nbrew \"openssl@3\"\n");
+
+        let report = HomebrewMigrator::categorize_packages(&packages);
+
+        assert_eq!(report.safe_to_migrate.len(), 1);
+        assert_eq!(report.should_keep_in_homebrew[0].name, "openssl@3");
+    }
+
+    #[test]
+    fn test_load_brewfile_from_disk() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        temp_file
+            .write_all(b"brew \"jq\"\n")
+            .expect("Failed to write");
+
+        let packages = load_brewfile(temp_file.path())
+            .expect("Failed to load Brewfile")
+            .formulae;
+
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].name, "jq");
+    }
+
+    // ============================================
+    // File I/O Tests (using tempfile)
+    // ============================================
+
+    #[test]
+    fn test_brewfile_write_to_file() {
+        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
+
+        let content = "# Zerobrew Migration Brewfile\nbrew \"git\"\ncask \"vscode\"\n";
+        temp_file
+            .write_all(content.as_bytes())
+            .expect("Failed to write");
+
+        let read_content =
+            std::fs::read_to_string(temp_file.path()).expect("Failed to read temp file");
+
+        assert_eq!(read_content, content);
+    }
+
+    #[test]
+    fn test_migration_state_file_io() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+
+        let mut state = MigrationState::default();
+        state.homebrew_prefix = PathBuf::from("/opt/homebrew");
+        state
+            .migrated_packages
+            .insert("git".to_string(), create_test_package("git", vec![]));
+
+        // Write state
+        let json = serde_json::to_string_pretty(&state).expect("Serialization failed");
+        std::fs::write(temp_file.path(), &json).expect("Failed to write");
+
+        // Read state
+        let read_content =
+            std::fs::read_to_string(temp_file.path()).expect("Failed to read temp file");
+        let restored: MigrationState =
+            serde_json::from_str(&read_content).expect("Deserialization failed");
+
+        assert_eq!(restored.homebrew_prefix, state.homebrew_prefix);
+        assert!(restored.migrated_packages.contains_key("git"));
+    }
+
+    // ============================================
+    // Tap Lookup Tests
+    // ============================================
+
+    #[test]
+    fn test_parse_tap_core_formula() {
+        let json = r#"{"formulae": [{"name": "git", "tap": "homebrew/core"}], "casks": []}"#;
+        assert_eq!(parse_tap_from_info_json(json), TapLookup::Core);
+    }
+
+    #[test]
+    fn test_parse_tap_third_party_formula() {
+        let json = r#"{"formulae": [{"name": "k9s", "tap": "derailed/k9s"}], "casks": []}"#;
+        assert_eq!(
+            parse_tap_from_info_json(json),
+            TapLookup::Tap("derailed/k9s".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_tap_falls_back_to_casks() {
+        let json =
+            r#"{"formulae": [], "casks": [{"token": "font-fira", "tap": "homebrew/cask-fonts"}]}"#;
+        assert_eq!(
+            parse_tap_from_info_json(json),
+            TapLookup::Tap("homebrew/cask-fonts".to_string())
+        );
+
+        let json = r#"{"formulae": [], "casks": [{"token": "iterm2", "tap": "homebrew/cask"}]}"#;
+        assert_eq!(parse_tap_from_info_json(json), TapLookup::Core);
+    }
+
+    #[test]
+    fn test_parse_tap_unknown() {
+        // Deleted formula: tap is null
+        let json = r#"{"formulae": [{"name": "gone", "tap": null}], "casks": []}"#;
+        assert_eq!(parse_tap_from_info_json(json), TapLookup::Unknown);
+
+        assert_eq!(
+            parse_tap_from_info_json(r#"{"formulae": [], "casks": []}"#),
+            TapLookup::Unknown
+        );
+        assert_eq!(parse_tap_from_info_json("not json"), TapLookup::Unknown);
+    }
+
+    #[test]
+    fn test_tap_lookup_into_tap() {
+        assert_eq!(TapLookup::Core.into_tap(), None);
+        assert_eq!(TapLookup::Unknown.into_tap(), None);
+        assert_eq!(
+            TapLookup::Tap("user/tap".to_string()).into_tap(),
+            Some("user/tap".to_string())
+        );
+    }
+
+    // ============================================
+    // Brew stderr Filtering Tests
+    // ============================================
+
+    #[test]
+    fn test_filter_brew_stderr_removes_update_noise() {
+        let stderr = "==> Auto-updating Homebrew...\n\
+                      Adjust how often this is run with HOMEBREW_AUTO_UPDATE_SECS or disable with\n\
+                      HOMEBREW_NO_AUTO_UPDATE. Hide these hints with HOMEBREW_NO_ENV_HINTS (see `man brew`).\n\
+                      ==> Auto-updated Homebrew!\n\
+                      ==> Updated 2 taps (homebrew/core and homebrew/cask).\n\
+                      Already up-to-date.\n";
+
+        assert_eq!(filter_brew_stderr(stderr), "");
+    }
+
+    #[test]
+    fn test_filter_brew_stderr_keeps_errors() {
+        let stderr = "==> Auto-updated Homebrew!\n\
+                      Error: No available formula with the name \"nope\".\n\
+                      Warning: Treating nope as a cask.";
+
+        assert_eq!(
+            filter_brew_stderr(stderr),
+            "Error: No available formula with the name \"nope\".\nWarning: Treating nope as a cask."
+        );
+    }
+
+    #[test]
+    fn test_filter_brew_stderr_removes_only_the_outdated_notice() {
+        let stderr = "You have 3 outdated formulae installed.\n\
+                      You can upgrade them with brew upgrade\n\
+                      or list them with brew outdated.\n\
+                      Error: curl: outdated formula pinned, refusing to link\n";
+
+        assert_eq!(
+            filter_brew_stderr(stderr),
+            "Error: curl: outdated formula pinned, refusing to link"
+        );
+        assert_eq!(
+            filter_brew_stderr("You have 1 outdated formula installed.\n"),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_error_output_section_omitted_when_empty() {
+        assert_eq!(error_output_section(""), "");
+        assert_eq!(
+            error_output_section("Error: boom"),
+            "\n\nError output: Error: boom"
+        );
+    }
+
+    // ============================================
+    // Did-you-mean Suggestion Tests
+    // ============================================
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("git", "git"), 0);
+        assert_eq!(edit_distance("gti", "git"), 2);
+        assert_eq!(edit_distance("pyton@3.11", "python@3.11"), 1);
+        assert_eq!(edit_distance("node", ""), 4);
+    }
+
+    #[test]
+    fn test_suggest_similar() {
+        let installed = ["python@3.11", "python@3.12", "node", "git", "ripgrep"];
+
+        assert_eq!(
+            suggest_similar("pyton@3.11", installed, 3),
+            vec!["python@3.11", "python@3.12"]
+        );
+        assert_eq!(suggest_similar("nod", installed, 3), vec!["node"]);
+        assert!(suggest_similar("completely-different", installed, 3).is_empty());
+    }
+
+    // ============================================
+    // Duration / --since Tests
+    // ============================================
+
+    #[test]
+    fn test_parse_duration_units() {
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("15m").unwrap(), Duration::from_secs(15 * 60));
+        assert_eq!(
+            parse_duration("12h").unwrap(),
+            Duration::from_secs(12 * 3600)
+        );
+        assert_eq!(
+            parse_duration("7d").unwrap(),
+            Duration::from_secs(7 * 86400)
+        );
+        assert_eq!(
+            parse_duration("2w").unwrap(),
+            Duration::from_secs(14 * 86400)
+        );
+        assert_eq!(parse_duration("3").unwrap(), Duration::from_secs(3 * 86400));
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("7y").is_err());
+        assert!(parse_duration("-1d").is_err());
+        assert!(parse_duration("99999999999999999w").is_err());
+    }
+
+    #[test]
+    fn test_is_installed_after_cutoff() {
+        let now = SystemTime::now();
+        let cutoff = now - Duration::from_secs(7 * 86400);
+
+        assert!(is_installed_after(Some(now), cutoff));
+        assert!(!is_installed_after(
+            Some(cutoff - Duration::from_secs(1)),
+            cutoff
+        ));
+        assert!(is_installed_after(None, cutoff));
+    }
+
+    // Config File Tests
+    // ============================================
+
+    #[test]
+    fn test_parse_config() {
+        let config = parse_config(
+            r#"
+            # Defaults for every run
+            verbose = true
+            zb_bin = "/opt/zb/bin/zb"   # trailing comment
+
+            [migrate]
+            keep-logs = true
+            max-failures = 5
+            packages = ["git", 'node#22', 3,]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.options(None).collect::<Vec<_>>(),
+            vec![
+                (&"verbose".to_string(), &ConfigValue::Bool(true)),
+                (
+                    &"zb-bin".to_string(),
+                    &ConfigValue::String("/opt/zb/bin/zb".to_string())
+                ),
+            ]
+        );
+        let migrate = &config.commands["migrate"];
+        assert_eq!(migrate["max-failures"], ConfigValue::Integer(5));
+        assert_eq!(
+            migrate["packages"],
+            ConfigValue::Array(vec![
+                "git".to_string(),
+                "node#22".to_string(),
+                "3".to_string()
+            ])
+        );
+        assert_eq!(config.options(Some("analyze")).count(), 0);
+    }
+
+    #[test]
+    fn test_parse_config_errors() {
+        let error = |content: &str| format!("{:#}", parse_config(content).unwrap_err());
+
+        assert!(error("verbose").contains("line 1"));
+        assert!(error("\n[migrate\n").contains("line 2"));
+        assert!(error("a = 1\na = 2").contains("duplicate key"));
+        assert!(error("zb_bin = \"a\"\nzb-bin = \"b\"").contains("`zb-bin` is set more than once"));
+        assert!(error(r#"zb-bin = "/opt/zb"#).contains("line 1"));
+        assert!(error("[migrate]\njobs = 1.5").contains("invalid value for `jobs` in [migrate]"));
+        assert!(error("packages = [true]").contains("array items must be strings or integers"));
+        assert_eq!(parse_config("").unwrap(), CliConfig::default());
+    }
+
+    // ============================================
+    // Config Directory Tests
+    // ============================================
+
+    #[test]
+    fn test_resolve_zerobrew_home() {
+        let home = Some("/Users/me".to_string());
+
+        assert_eq!(
+            resolve_zerobrew_home(None, home.clone()).unwrap(),
+            PathBuf::from("/Users/me/.zerobrew")
+        );
+        assert_eq!(
+            resolve_zerobrew_home(Some("/tmp/zb".to_string()), home.clone()).unwrap(),
+            PathBuf::from("/tmp/zb")
+        );
+        // An empty override falls back to HOME
+        assert_eq!(
+            resolve_zerobrew_home(Some(String::new()), home).unwrap(),
+            PathBuf::from("/Users/me/.zerobrew")
+        );
+
+        let err = resolve_zerobrew_home(None, None).unwrap_err();
+        assert!(err.to_string().contains("ZEROBREW_HOME"));
+    }
+
+    // ============================================
+    // --packages Pattern Tests
+    // ============================================
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("node*", "node"));
+        assert!(glob_match("node*", "node@20"));
+        assert!(glob_match("python@3.1?", "python@3.12"));
+        assert!(glob_match("*ssl*", "openssl@3"));
+        assert!(glob_match("*", "anything"));
+        assert!(!glob_match("python@*", "python"));
+        assert!(!glob_match("lib?", "libuv"));
+        assert!(!glob_match("*@3", "openssl@3.1"));
+    }
+
+    #[test]
+    fn test_failure_list_roundtrip() {
+        let report = MigrationReport {
+            failed: vec![
+                ("ffmpeg".to_string(), "link conflict".to_string()),
+                ("python@3.12".to_string(), "timeout".to_string()),
+            ],
+            ..Default::default()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("failed.txt");
+        let mut content = render_failure_list(&report);
+        assert_eq!(content, "ffmpeg\npython@3.12\n");
+        content.push_str("\n# not this one\nimagemagick  # retry later\n");
+        fs::write(&path, content).unwrap();
+
+        let names =
+            expand_package_files(vec!["jq".to_string(), format!("@{}", path.display())]).unwrap();
+        assert_eq!(names, vec!["jq", "ffmpeg", "python@3.12", "imagemagick"]);
+
+        let err = expand_package_files(vec!["@/nonexistent/failed.txt".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("/nonexistent/failed.txt"));
+    }
+
+    #[test]
+    fn test_expand_package_patterns() {
+        let installed = ["node", "node@20", "python@3.12", "python@3.11", "jq"];
+        let entries = vec![
+            "jq".to_string(),
+            "python@*".to_string(),
+            "node*".to_string(),
+            "node".to_string(),
+            "ruby*".to_string(),
+        ];
+
+        let (names, unmatched) = expand_package_patterns(&entries, installed.iter().copied());
+
+        assert_eq!(
+            names,
+            vec!["jq", "python@3.11", "python@3.12", "node", "node@20"]
+        );
+        assert_eq!(unmatched, vec!["ruby*"]);
+    }
+
+    // ============================================
+    // Prefix Overlap Tests
+    // ============================================
+
+    #[test]
+    fn test_prefixes_overlap() {
+        let brew = Path::new("/opt/homebrew");
+
+        assert!(prefixes_overlap(brew, Path::new("/opt/homebrew")));
+        assert!(prefixes_overlap(brew, Path::new("/opt/homebrew/zerobrew")));
+        assert!(prefixes_overlap(Path::new("/opt/homebrew/zerobrew"), brew));
+        assert!(!prefixes_overlap(brew, Path::new("/opt/zerobrew")));
+        // Component-wise, not a string prefix
+        assert!(!prefixes_overlap(brew, Path::new("/opt/homebrew2")));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_prefixes_overlap_through_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().join("prefix");
+        fs::create_dir(&real).unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        assert!(prefixes_overlap(&real, &link));
+    }
+
+    #[test]
+    fn test_check_separate_prefixes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.homebrew_prefix = PathBuf::from("/opt/homebrew");
+        migrator.zerobrew_prefix = Some(PathBuf::from("/opt/zerobrew"));
+        assert!(migrator.check_separate_prefixes().is_ok());
+
+        // Constructing the migrator still works, so read-only commands can run
+        migrator.zerobrew_prefix = Some(PathBuf::from("/opt/homebrew/zerobrew"));
+        let err = migrator.check_separate_prefixes().unwrap_err();
+        assert!(err.to_string().contains("share an install prefix"));
+    }
+
+    #[test]
+    fn test_check_path_order() {
+        let brew = Path::new("/opt/homebrew/bin");
+        let zb = Path::new("/opt/zerobrew/bin");
+        let order = |path: &str| check_path_order(OsStr::new(path), brew, zb);
+
+        assert_eq!(
+            order("/opt/zerobrew/bin:/opt/homebrew/bin:/usr/bin"),
+            PathOrder::ZerobrewFirst
+        );
+        assert_eq!(
+            order("/usr/bin:/opt/homebrew/bin:/opt/zerobrew/bin"),
+            PathOrder::HomebrewFirst
+        );
+        assert_eq!(
+            order("/opt/zerobrew/bin:/usr/bin"),
+            PathOrder::ZerobrewFirst
+        );
+        assert_eq!(
+            order("/opt/homebrew/bin:/usr/bin"),
+            PathOrder::ZerobrewMissing
+        );
+        assert_eq!(order(""), PathOrder::ZerobrewMissing);
+    }
+
+    #[test]
+    fn test_path_fix_suggestion() {
+        let zb = Path::new("/opt/zerobrew/bin");
+        assert_eq!(
+            path_fix_suggestion(Some("/bin/zsh"), zb),
+            (
+                "~/.zshrc",
+                "export PATH=\"/opt/zerobrew/bin:$PATH\"".to_string()
+            )
+        );
+        assert_eq!(
+            path_fix_suggestion(Some("/usr/local/bin/fish"), zb),
+            (
+                "~/.config/fish/config.fish",
+                "fish_add_path --move /opt/zerobrew/bin".to_string()
+            )
+        );
+        assert_eq!(path_fix_suggestion(None, zb).0, "~/.profile");
+    }
+
+    // ============================================
+    // Taps Tests
+    // ============================================
+
+    #[test]
+    fn test_group_by_tap() {
+        let with_tap = |name: &str, tap: &str| BrewPackage {
+            tap: Some(tap.to_string()),
+            ..create_test_package(name, vec![])
+        };
+        let packages = vec![
+            with_tap("terraform", "hashicorp/tap"),
+            create_test_package("jq", vec![]),
+            with_tap("k9s", "derailed/k9s"),
+            with_tap("wget", "homebrew/core"),
+            with_tap("vault", "hashicorp/tap"),
+        ];
+        let groups: Vec<(&str, Vec<&str>)> = group_by_tap(&packages)
+            .into_iter()
+            .map(|(tap, pkgs)| (tap, pkgs.iter().map(|p| p.name.as_str()).collect()))
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                ("homebrew/core", vec!["jq", "wget"]),
+                ("derailed/k9s", vec!["k9s"]),
+                ("hashicorp/tap", vec!["terraform", "vault"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_packages_to_csv() {
+        let packages = vec![
+            BrewPackage {
+                tap: Some("user/tap".to_string()),
+                pinned: true,
+                ..create_test_package("wget", vec!["openssl@3", "libidn2"])
+            },
+            BrewPackage {
+                version: "1.0,2\"beta\"".to_string(),
+                is_cask: true,
+                ..create_test_package("odd", vec![])
+            },
+        ];
+        assert_eq!(
+            packages_to_csv(&packages),
+            "name,version,tap,is_cask,pinned,dependencies\r\n\
+             wget,1.0.0,user/tap,false,true,2\r\n\
+             odd,\"1.0,2\"\"beta\"\"\",,true,false,0\r\n"
+        );
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
-
-    // ============================================
-    // BrewPackage Parsing Tests
-    // ============================================
 
     #[test]
-    fn test_brew_package_creation() {
-        let pkg = BrewPackage {
-            name: "git".to_string(),
-            version: "2.42.0".to_string(),
-            tap: None,
-            is_cask: false,
-            dependencies: vec!["pcre2".to_string(), "gettext".to_string()],
-            pinned: false,
+    fn test_distinct_taps() {
+        let with_tap = |name: &str, tap: &str| BrewPackage {
+            tap: Some(tap.to_string()),
+            ..create_test_package(name, vec![])
         };
+        let packages = vec![
+            with_tap("k9s", "derailed/k9s"),
+            create_test_package("jq", vec![]),
+            with_tap("terraform", "hashicorp/tap"),
+            with_tap("vault", "hashicorp/tap"),
+        ];
 
-        assert_eq!(pkg.name, "git");
-        assert_eq!(pkg.version, "2.42.0");
-        assert!(pkg.tap.is_none());
-        assert!(!pkg.is_cask);
-        assert_eq!(pkg.dependencies.len(), 2);
-        assert!(!pkg.pinned);
+        let taps = distinct_taps(&packages);
+
+        assert_eq!(taps, vec!["derailed/k9s", "hashicorp/tap"]);
+        assert_eq!(
+            render_taps(&taps),
+            "tap \"derailed/k9s\"\ntap \"hashicorp/tap\"\n"
+        );
     }
 
+    // ============================================
+    // Retry Tests
+    // ============================================
+
     #[test]
-    fn test_brew_package_with_tap() {
-        let pkg = BrewPackage {
-            name: "neovim".to_string(),
-            version: "0.9.4".to_string(),
-            tap: Some("homebrew/core".to_string()),
-            is_cask: false,
-            dependencies: vec![],
-            pinned: true,
+    fn test_plan_retry_from_failed_packages() {
+        let state = MigrationState {
+            failed_packages: failed_entries(&["openssl@3", "gone", "openssl@3", "aria2"]),
+            ..Default::default()
         };
+        let installed = vec![
+            create_test_package("aria2", vec![]),
+            create_test_package("openssl@3", vec![]),
+            create_test_package("jq", vec![]),
+        ];
 
-        assert_eq!(pkg.tap, Some("homebrew/core".to_string()));
-        assert!(pkg.pinned);
+        let plan = HomebrewMigrator::plan_retry_from(&state, &installed);
+
+        let names: Vec<&str> = plan.formulae.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["openssl@3", "aria2"]);
+        assert_eq!(plan.total_formulae, 3);
+        assert_eq!(
+            plan.skipped,
+            vec![(
+                "gone".to_string(),
+                "No longer installed in Homebrew".to_string()
+            )]
+        );
     }
 
     #[test]
-    fn test_brew_package_cask() {
-        let pkg = BrewPackage {
-            name: "visual-studio-code".to_string(),
-            version: "1.84.0".to_string(),
-            tap: Some("homebrew/cask".to_string()),
-            is_cask: true,
-            dependencies: vec![],
-            pinned: false,
+    fn test_plan_retry_loads_details_for_retried_formulae() {
+        let dir = tempfile::tempdir().unwrap();
+        let migrator = fixture_migrator(dir.path());
+        let state = MigrationState {
+            failed_packages: failed_entries(&["curl", "openssl@3"]),
+            ..Default::default()
         };
-
-        assert!(pkg.is_cask);
-        assert_eq!(pkg.name, "visual-studio-code");
+        migrator.save_state(&state).unwrap();
+
+        let plan = migrator.plan_retry().unwrap();
+        let names: Vec<&str> = plan.formulae.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["openssl@3", "curl"]);
+        assert!(plan.dependencies_loaded);
+        assert_eq!(plan.formulae[1].dependencies, vec!["openssl@3"]);
+        assert!(plan.formulae[0].keg_only);
     }
 
     #[test]
-    fn test_parse_brew_list_output() {
-        // Simulate parsing brew list --formula --versions output
-        let brew_output = "git 2.42.0\nnode 20.9.0\nrust 1.73.0\npython@3.11 3.11.6";
+    fn test_failure_reason_persists_in_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[(
+            "zb install jq",
+            (1, "", "Error: jq conflicts with existing files\n"),
+        )]));
+        let plan = MigrationPlan {
+            formulae: vec![create_test_package("jq", vec![])],
+            total_formulae: 1,
+            ..Default::default()
+        };
 
-        let mut packages = Vec::new();
-        for line in brew_output.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                packages.push(BrewPackage {
-                    name: parts[0].to_string(),
-                    version: parts[1].to_string(),
-                    tap: None,
-                    is_cask: false,
-                    dependencies: Vec::new(),
-                    pinned: false,
-                });
-            }
-        }
+        let report = migrator
+            .execute_plan(&plan, &MigrateOptions::default(), &mut |_| {})
+            .unwrap();
 
-        assert_eq!(packages.len(), 4);
-        assert_eq!(packages[0].name, "git");
-        assert_eq!(packages[0].version, "2.42.0");
-        assert_eq!(packages[1].name, "node");
-        assert_eq!(packages[2].name, "rust");
-        assert_eq!(packages[3].name, "python@3.11");
-        assert_eq!(packages[3].version, "3.11.6");
+        let state = migrator.load_state().unwrap();
+        assert_eq!(state.failed_packages, report.failed);
+        assert!(state.failed_packages[0]
+            .1
+            .contains("conflicts with existing files"));
     }
 
     #[test]
-    fn test_parse_brew_list_empty_output() {
-        let brew_output = "";
+    fn test_execute_plan_with_choices() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[
+            ("zb install jq", (0, "", "")),
+            ("zb list", (0, "jq 1.7.1\n", "")),
+        ]));
+        let plan = MigrationPlan {
+            formulae: ["git", "jq", "fd", "rg"]
+                .into_iter()
+                .map(|name| create_test_package(name, vec![]))
+                .collect(),
+            total_formulae: 4,
+            ..Default::default()
+        };
+        let mut choose = |index: usize, _: &BrewPackage| {
+            Ok(match index {
+                0 => PackageChoice::Skip("User skipped".to_string()),
+                1 => PackageChoice::Migrate,
+                _ => PackageChoice::Stop,
+            })
+        };
 
-        let mut packages = Vec::new();
-        for line in brew_output.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                packages.push(BrewPackage {
-                    name: parts[0].to_string(),
-                    version: parts[1].to_string(),
-                    tap: None,
-                    is_cask: false,
-                    dependencies: Vec::new(),
-                    pinned: false,
-                });
-            }
-        }
+        let report = migrator
+            .execute_plan_with(&plan, &MigrateOptions::default(), &mut |_| {}, &mut choose)
+            .unwrap();
+        assert_eq!(report.successful, vec!["jq"]);
+        assert_eq!(
+            report.skipped,
+            vec![("git".to_string(), "User skipped".to_string())]
+        );
+        assert!(!report.interrupted);
 
-        assert!(packages.is_empty());
+        // A dry run records the chosen packages without installing them
+        let options = MigrateOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = migrator
+            .execute_plan_with(
+                &plan,
+                &options,
+                &mut |_| panic!("nothing is installed"),
+                &mut choose,
+            )
+            .unwrap();
+        assert_eq!(report.planned, vec!["jq"]);
+        assert!(report.successful.is_empty());
     }
 
     #[test]
-    fn test_parse_brew_list_with_multiple_versions() {
-        // Some packages show multiple versions installed
-        let brew_output = "openssl@3 3.1.4 3.1.3";
+    fn test_apply_report_moves_retried_packages() {
+        let mut state = MigrationState {
+            failed_packages: failed_entries(&["aria2", "openssl@3"]),
+            ..Default::default()
+        };
+        let formulae = vec![
+            create_test_package("aria2", vec![]),
+            create_test_package("openssl@3", vec![]),
+        ];
+        let report = MigrationReport {
+            successful: vec!["aria2".to_string()],
+            failed: vec![("openssl@3".to_string(), "link conflict".to_string())],
+            ..Default::default()
+        };
 
-        let mut packages = Vec::new();
-        for line in brew_output.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                // Take the first (latest) version
-                packages.push(BrewPackage {
-                    name: parts[0].to_string(),
-                    version: parts[1].to_string(),
-                    tap: None,
-                    is_cask: false,
-                    dependencies: Vec::new(),
-                    pinned: false,
-                });
-            }
-        }
+        state.apply_report(&report, &formulae, &None, 1_700_000_000);
 
-        assert_eq!(packages.len(), 1);
-        assert_eq!(packages[0].name, "openssl@3");
-        assert_eq!(packages[0].version, "3.1.4");
+        assert!(state.migrated_packages.contains_key("aria2"));
+        assert_eq!(state.migration_records["aria2"].migrated_at, 1_700_000_000);
+        // Failures are not duplicated, and keep the latest reason
+        assert_eq!(
+            state.failed_packages,
+            vec![("openssl@3".to_string(), "link conflict".to_string())]
+        );
     }
 
     #[test]
-    fn test_parse_malformed_brew_output() {
-        // Lines with less than 2 parts should be skipped
-        let brew_output = "git 2.42.0\ninvalid_line\nnode 20.9.0\n   \nrust";
-
-        let mut packages = Vec::new();
-        for line in brew_output.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                packages.push(BrewPackage {
-                    name: parts[0].to_string(),
-                    version: parts[1].to_string(),
-                    tap: None,
-                    is_cask: false,
-                    dependencies: Vec::new(),
-                    pinned: false,
-                });
-            }
+    fn test_prune_stale_keeps_packages_installed_anywhere() {
+        let mut state = MigrationState::default();
+        for name in ["jq", "wget", "gone"] {
+            state
+                .migrated_packages
+                .insert(name.to_string(), create_test_package(name, vec![]));
+            state
+                .migration_records
+                .insert(name.to_string(), MigrationRecord::default());
         }
-
-        assert_eq!(packages.len(), 2);
-        assert_eq!(packages[0].name, "git");
-        assert_eq!(packages[1].name, "node");
+        let zb: HashSet<String> = ["jq".to_string()].into();
+        let brew: HashSet<String> = ["wget".to_string()].into();
+
+        assert_eq!(state.prune_stale(&zb, &brew), vec!["gone"]);
+        assert!(!state.migrated_packages.contains_key("gone"));
+        assert!(!state.migration_records.contains_key("gone"));
+        assert_eq!(state.migrated_packages.len(), 2);
+        assert!(state.prune_stale(&zb, &brew).is_empty());
     }
 
-    // ============================================
-    // MigrationState Serialization Tests
-    // ============================================
-
     #[test]
-    fn test_migration_state_default() {
-        let state = MigrationState::default();
+    fn test_apply_report_persists_skipped_packages() {
+        let mut state = MigrationState {
+            skipped_packages: vec![
+                ("jq".to_string(), "Skipped by user".to_string()),
+                ("iterm2".to_string(), "Casks not yet supported".to_string()),
+            ],
+            ..Default::default()
+        };
+        let formulae = vec![create_test_package("jq", vec![])];
+        let report = MigrationReport {
+            successful: vec!["jq".to_string()],
+            skipped: vec![
+                ("iterm2".to_string(), "Casks not yet supported".to_string()),
+                ("wget".to_string(), "older than cutoff".to_string()),
+            ],
+            ..Default::default()
+        };
 
-        assert!(state.migrated_packages.is_empty());
-        assert!(state.failed_packages.is_empty());
-        assert_eq!(state.homebrew_prefix, PathBuf::new());
+        state.apply_report(&report, &formulae, &None, 0);
+
+        // Migrated packages leave the skipped list; reasons are kept once per package
+        assert_eq!(
+            state.skipped_packages,
+            vec![
+                ("iterm2".to_string(), "Casks not yet supported".to_string()),
+                ("wget".to_string(), "older than cutoff".to_string()),
+            ]
+        );
     }
 
     #[test]
-    fn test_migration_state_serialization() {
-        let mut state = MigrationState::default();
-        state.homebrew_prefix = PathBuf::from("/opt/homebrew");
-        state.failed_packages.push("broken-pkg".to_string());
-
-        let pkg = BrewPackage {
-            name: "git".to_string(),
-            version: "2.42.0".to_string(),
-            tap: None,
-            is_cask: false,
-            dependencies: vec!["pcre2".to_string()],
-            pinned: false,
+    fn test_exclude_taps() {
+        let tapped = |name: &str, tap: &str| BrewPackage {
+            tap: Some(tap.to_string()),
+            ..create_test_package(name, vec![])
         };
-        state.migrated_packages.insert("git".to_string(), pkg);
+        let formulae = vec![
+            create_test_package("jq", vec![]),
+            tapped("jj", "example/tools"),
+            tapped("terraform", "HashiCorp/tap"),
+            tapped("stripe", "stripe/stripe-cli"),
+        ];
+        let mut skipped = Vec::new();
 
-        let json = serde_json::to_string(&state).expect("Serialization failed");
+        let kept = exclude_taps(
+            formulae,
+            &["example/tools".to_string(), "hashicorp/tap".to_string()],
+            &mut skipped,
+        );
 
-        assert!(json.contains("\"homebrew_prefix\""));
-        assert!(json.contains("/opt/homebrew"));
-        assert!(json.contains("\"git\""));
-        assert!(json.contains("broken-pkg"));
+        let kept: Vec<&str> = kept.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(kept, vec!["jq", "stripe"]);
+        assert_eq!(
+            skipped,
+            vec![
+                (
+                    "jj".to_string(),
+                    "from excluded tap example/tools".to_string()
+                ),
+                (
+                    "terraform".to_string(),
+                    "from excluded tap HashiCorp/tap".to_string()
+                ),
+            ]
+        );
     }
 
     #[test]
-    fn test_migration_state_deserialization() {
-        let json = r#"{
-            "migrated_packages": {
-                "node": {
-                    "name": "node",
-                    "version": "20.9.0",
-                    "tap": null,
-                    "is_cask": false,
-                    "dependencies": ["icu4c"],
-                    "pinned": false
-                }
-            },
-            "failed_packages": ["broken-pkg"],
-            "homebrew_prefix": "/opt/homebrew"
-        }"#;
-
-        let state: MigrationState = serde_json::from_str(json).expect("Deserialization failed");
-
-        assert_eq!(state.homebrew_prefix, PathBuf::from("/opt/homebrew"));
-        assert_eq!(state.failed_packages.len(), 1);
-        assert_eq!(state.failed_packages[0], "broken-pkg");
-        assert!(state.migrated_packages.contains_key("node"));
+    fn test_already_in_zerobrew_recorded_as_migrated() {
+        let formulae = vec![
+            create_test_package("jq", vec![]),
+            create_test_package("wget", vec![]),
+        ];
+        let installed: HashSet<String> = ["jq".to_string(), "ripgrep".to_string()].into();
+        let (already, remaining) = split_already_in_zerobrew(formulae, &installed);
+        assert_eq!(already.len(), 1);
+        assert_eq!(remaining[0].name, "wget");
+
+        let plan = MigrationPlan {
+            formulae: remaining,
+            already_in_zerobrew: already,
+            ..Default::default()
+        };
+        let report = plan.new_report();
+        assert_eq!(
+            report.skipped,
+            vec![("jq".to_string(), ALREADY_IN_ZEROBREW_REASON.to_string())]
+        );
 
-        let node_pkg = state.migrated_packages.get("node").unwrap();
-        assert_eq!(node_pkg.version, "20.9.0");
-        assert_eq!(node_pkg.dependencies, vec!["icu4c"]);
+        let mut state = MigrationState {
+            failed_packages: failed_entries(&["jq"]),
+            ..Default::default()
+        };
+        state.apply_report(&report, &plan.formulae, &None, 42);
+        assert_eq!(state.migrated_packages["jq"].name, "jq");
+        assert_eq!(state.migration_records["jq"].migrated_at, 42);
+        assert!(state.failed_packages.is_empty());
+        assert!(state.skipped_packages.is_empty());
     }
 
     #[test]
-    fn test_migration_state_roundtrip() {
-        let mut original = MigrationState::default();
-        original.homebrew_prefix = PathBuf::from("/usr/local");
-        original.failed_packages = vec!["pkg1".to_string(), "pkg2".to_string()];
+    fn test_already_migrated_skipped_distinctly() {
+        let mut state = MigrationState::default();
+        state
+            .migrated_packages
+            .insert("jq".to_string(), create_test_package("jq", vec![]));
+        let formulae = vec![
+            create_test_package("jq", vec![]),
+            create_test_package("aria2", vec![]),
+        ];
+        let (already, remaining) = split_already_migrated(formulae, &state);
+        assert_eq!(already[0].name, "jq");
+        assert_eq!(remaining[0].name, "aria2");
 
-        let pkg = BrewPackage {
-            name: "rust".to_string(),
-            version: "1.73.0".to_string(),
-            tap: Some("homebrew/core".to_string()),
-            is_cask: false,
-            dependencies: vec!["libssh2".to_string(), "openssl@3".to_string()],
-            pinned: true,
+        let plan = MigrationPlan {
+            formulae: remaining,
+            already_migrated: vec!["jq".to_string()],
+            ..Default::default()
         };
-        original.migrated_packages.insert("rust".to_string(), pkg);
+        let report = plan.new_report();
+        assert_eq!(
+            report.skipped,
+            vec![("jq".to_string(), ALREADY_MIGRATED_REASON.to_string())]
+        );
 
-        // Serialize
-        let json = serde_json::to_string_pretty(&original).expect("Serialization failed");
+        state.apply_report(&report, &plan.formulae, &None, 42);
+        assert!(state.migrated_packages.contains_key("jq"));
+        assert!(state.skipped_packages.is_empty());
+    }
 
-        // Deserialize
-        let restored: MigrationState = serde_json::from_str(&json).expect("Deserialization failed");
+    #[test]
+    fn test_detect_ci() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        assert!(detect_ci(env(&[("CI", "true")])));
+        assert!(detect_ci(env(&[("GITHUB_ACTIONS", "true")])));
+        assert!(detect_ci(env(&[("GITLAB_CI", "1")])));
+        assert!(detect_ci(env(&[("JENKINS_URL", "https://ci.example.com")])));
+        assert!(!detect_ci(env(&[])));
+        assert!(!detect_ci(env(&[("CI", "false")])));
+        assert!(!detect_ci(env(&[("CI", "0"), ("TRAVIS", "")])));
+    }
 
-        assert_eq!(restored.homebrew_prefix, original.homebrew_prefix);
-        assert_eq!(restored.failed_packages, original.failed_packages);
+    #[test]
+    fn test_timing_summary_records_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[
+            ("brew list --formula --versions", (0, "jq 1.7.1\n", "")),
+            ("brew list --pinned", (0, "", "")),
+            ("brew list --installed-on-request", (0, "jq\n", "")),
+            ("zb install jq", (0, "", "")),
+        ]));
+        assert!(migrator.timing_summary().is_none());
+
+        migrator.enable_timings();
+        migrator.list_installed_formulae().unwrap();
+        migrator
+            .runner
+            .output(&migrator.zb_bin, &["install", "jq"])
+            .unwrap();
+        let answer = migrator.time_analysis(|| 42);
+        assert_eq!(answer, 42);
+
+        let timings = migrator.timing_summary().unwrap();
+        let commands: Vec<String> = timings.commands.iter().map(|c| c.command_line()).collect();
         assert_eq!(
-            restored.migrated_packages.len(),
-            original.migrated_packages.len()
+            commands,
+            vec![
+                "brew list --formula --versions",
+                "brew list --pinned",
+                "brew list --installed-on-request",
+                "zb install jq"
+            ]
+        );
+        assert_eq!(
+            timings.total_for(Path::new("brew")),
+            timings.commands[..3].iter().map(|c| c.elapsed).sum()
         );
+        assert_eq!(timings.slowest(2).len(), 2);
+        assert!(timings.slowest(2)[0].elapsed >= timings.slowest(2)[1].elapsed);
+    }
 
-        let rust_pkg = restored.migrated_packages.get("rust").unwrap();
-        assert_eq!(rust_pkg.version, "1.73.0");
-        assert!(rust_pkg.pinned);
-        assert_eq!(rust_pkg.tap, Some("homebrew/core".to_string()));
+    #[test]
+    fn test_benchmark_brew_times_each_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[
+            (
+                "brew list --formula --versions",
+                (0, "jq 1.7.1\nwget 1.24\n", ""),
+            ),
+            ("brew list --pinned", (0, "", "")),
+            ("brew list --installed-on-request", (0, "jq\n", "")),
+            ("brew deps --installed jq", (0, "oniguruma\n", "")),
+            ("brew info --json=v2 jq", (0, "{}", "")),
+        ]));
+
+        let benchmark = migrator.benchmark_brew(None, 2).unwrap();
+        assert_eq!(benchmark.sample, "jq");
+        assert_eq!(benchmark.formula_count, 2);
+        let commands: Vec<&str> = benchmark
+            .commands
+            .iter()
+            .map(|c| c.command.as_str())
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                "brew list --formula --versions",
+                "brew list --pinned",
+                "brew deps --installed jq",
+                "brew info --json=v2 jq"
+            ]
+        );
+        assert!(benchmark.commands.iter().all(|c| c.runs.len() == 2));
+        for command in &benchmark.commands {
+            assert!(command.min() <= command.median() && command.median() <= command.max());
+        }
+
+        let err = migrator.benchmark_brew(Some("curl"), 1).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("'curl' is not an installed Homebrew formula"));
     }
 
     #[test]
-    fn test_migration_state_empty_json() {
-        let json = r#"{
-            "migrated_packages": {},
-            "failed_packages": [],
-            "homebrew_prefix": ""
-        }"#;
+    fn test_command_benchmark_median_and_estimate() {
+        let ms = Duration::from_millis;
+        let bench = |command: &str, runs: &[u64]| CommandBenchmark {
+            command: command.to_string(),
+            runs: runs.iter().map(|&r| ms(r)).collect(),
+        };
+        assert_eq!(bench("brew list", &[30, 10, 20]).median(), ms(20));
+        assert_eq!(bench("brew list", &[40, 10, 30, 20]).median(), ms(20));
+        assert_eq!(bench("brew list", &[]).median(), Duration::ZERO);
+
+        let benchmark = BrewBenchmark {
+            sample: "jq".to_string(),
+            formula_count: 10,
+            commands: vec![
+                bench("brew list --formula --versions", &[100]),
+                bench("brew list --pinned", &[50]),
+                bench("brew deps --installed jq", &[20]),
+                bench("brew info --json=v2 jq", &[30]),
+            ],
+        };
+        assert_eq!(benchmark.estimated_analysis(), ms(150 + 10 * 50));
+    }
 
-        let state: MigrationState = serde_json::from_str(json).expect("Deserialization failed");
+    #[test]
+    fn test_list_formulae_survives_pinned_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[
+            (
+                "brew list --formula --versions",
+                (0, "jq 1.7.1\nwget 1.24.5\n", ""),
+            ),
+            ("brew list --pinned", (1, "", "Error: something broke\n")),
+        ]));
+        let formulae = migrator.list_installed_formulae().unwrap();
+        assert_eq!(formulae.len(), 2);
+        assert!(formulae.iter().all(|p| !p.pinned));
+
+        // `brew list --pinned` can't even be spawned
+        migrator.runner = Box::new(MockRunner::new(&[(
+            "brew list --formula --versions",
+            (0, "jq 1.7.1\n", ""),
+        )]));
+        assert_eq!(migrator.list_installed_formulae().unwrap().len(), 1);
+
+        migrator.runner = Box::new(MockRunner::new(&[
+            ("brew list --formula --versions", (0, "jq 1.7.1\n", "")),
+            ("brew list --pinned", (0, "jq\n", "")),
+        ]));
+        assert!(migrator.list_installed_formulae().unwrap()[0].pinned);
+    }
 
-        assert!(state.migrated_packages.is_empty());
-        assert!(state.failed_packages.is_empty());
+    #[test]
+    fn test_installed_on_request_and_requested_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[
+            (
+                "brew list --formula --versions",
+                (0, "jq 1.7.1\noniguruma 6.9.9\n", ""),
+            ),
+            ("brew list --installed-on-request", (0, "jq\n", "")),
+        ]));
+        let formulae = migrator.list_installed_formulae().unwrap();
+        let requested: Vec<(&str, bool)> = formulae
+            .iter()
+            .map(|p| (p.name.as_str(), p.installed_on_request))
+            .collect();
+        assert_eq!(requested, vec![("jq", true), ("oniguruma", false)]);
+
+        // Without the information every formula counts as requested
+        migrator.runner = Box::new(MockRunner::new(&[(
+            "brew list --formula --versions",
+            (0, "oniguruma 6.9.9\n", ""),
+        )]));
+        assert!(migrator.list_installed_formulae().unwrap()[0].installed_on_request);
+
+        let mut skipped = Vec::new();
+        let kept = exclude_dependencies_only(formulae, &mut skipped);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].name, "jq");
+        assert_eq!(
+            skipped,
+            vec![(
+                "oniguruma".to_string(),
+                DEPENDENCY_ONLY_SKIP_REASON.to_string()
+            )]
+        );
     }
 
     // ============================================
-    // Topological Sort Tests
+    // migrate_package Tests
     // ============================================
 
-    fn create_test_package(name: &str, deps: Vec<&str>) -> BrewPackage {
-        BrewPackage {
-            name: name.to_string(),
-            version: "1.0.0".to_string(),
-            tap: None,
-            is_cask: false,
-            dependencies: deps.iter().map(|s| s.to_string()).collect(),
-            pinned: false,
+    #[test]
+    fn test_migrate_package_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[
+            ("zb install jq", (0, "Installed jq\n", "")),
+            ("zb info jq", (0, "jq 1.7.1\n", "")),
+        ]));
+
+        let result = migrator
+            .migrate_package(
+                &create_test_package("jq", vec![]),
+                &MigrateOptions::default(),
+            )
+            .unwrap();
+
+        match result {
+            MigrateResult::Success {
+                name,
+                installed_version,
+                ..
+            } => {
+                assert_eq!(name, "jq");
+                assert_eq!(installed_version.as_deref(), Some("1.7.1"));
+            }
+            other => panic!("expected success, got {:?}", other),
         }
+        // Logs are only kept for failures unless --keep-logs is set
+        assert!(!migrator.log_dir().join("jq.log").exists());
     }
 
     #[test]
-    fn test_topological_sort_no_dependencies() {
-        let packages = vec![
-            create_test_package("a", vec![]),
-            create_test_package("b", vec![]),
-            create_test_package("c", vec![]),
-        ];
-
-        let pkg_map: HashMap<String, &BrewPackage> =
-            packages.iter().map(|p| (p.name.clone(), p)).collect();
-
-        let mut result = Vec::new();
-        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
-
-        fn visit(
-            name: &str,
-            pkg_map: &HashMap<String, &BrewPackage>,
-            visited: &mut std::collections::HashSet<String>,
-            result: &mut Vec<BrewPackage>,
-        ) {
-            if visited.contains(name) {
-                return;
-            }
-            visited.insert(name.to_string());
+    fn test_migrate_package_failure_reports_stderr() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[(
+            "zb install openssl@3",
+            (
+                1,
+                "",
+                "resolving...\nerror: link conflict at '/opt/zerobrew/prefix/bin/openssl'\n",
+            ),
+        )]));
+
+        let result = migrator
+            .migrate_package(
+                &create_test_package("openssl@3", vec![]),
+                &MigrateOptions::default(),
+            )
+            .unwrap();
 
-            if let Some(pkg) = pkg_map.get(name) {
-                for dep in &pkg.dependencies {
-                    visit(dep, pkg_map, visited, result);
-                }
-                result.push((*pkg).clone());
+        match result {
+            MigrateResult::Failed {
+                name,
+                reason,
+                error,
+                log,
+            } => {
+                assert_eq!(name, "openssl@3");
+                assert_eq!(
+                    error,
+                    MigrateError::Other(
+                        "error: link conflict at '/opt/zerobrew/prefix/bin/openssl'".to_string()
+                    )
+                );
+                assert_eq!(
+                    reason,
+                    "error: link conflict at '/opt/zerobrew/prefix/bin/openssl'"
+                );
+                let log = fs::read_to_string(log.expect("failures are logged")).unwrap();
+                assert!(log.contains("resolving..."));
             }
+            other => panic!("expected failure, got {:?}", other),
         }
+    }
 
-        for pkg in &packages {
-            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
-        }
-
-        assert_eq!(result.len(), 3);
+    #[test]
+    fn test_classify_install_error() {
+        assert_eq!(
+            classify_install_error(Some(1), "Error: formula 'nope' not found\n"),
+            MigrateError::NotFoundInZerobrew
+        );
+        assert_eq!(
+            classify_install_error(Some(1), "error: could not resolve host ghcr.io\n"),
+            MigrateError::NetworkError
+        );
+        assert_eq!(
+            classify_install_error(Some(1), "error: dependency 'libfoo' not found\n"),
+            MigrateError::DependencyMissing
+        );
+        assert_eq!(classify_install_error(Some(124), ""), MigrateError::Timeout);
+        assert_eq!(
+            classify_install_error(Some(1), "error: link conflict\n"),
+            MigrateError::Other("error: link conflict".to_string())
+        );
+        assert!(MigrateError::NetworkError.is_transient());
+        assert!(!MigrateError::NotFoundInZerobrew.is_transient());
     }
 
     #[test]
-    fn test_topological_sort_linear_dependencies() {
-        // c depends on b, b depends on a
-        let packages = vec![
-            create_test_package("a", vec![]),
-            create_test_package("b", vec!["a"]),
-            create_test_package("c", vec!["b"]),
-        ];
+    fn test_classify_install_error_ignores_names_and_progress() {
+        let other = |stderr: &str| {
+            matches!(
+                classify_install_error(Some(1), stderr),
+                MigrateError::Other(_)
+            )
+        };
 
-        let pkg_map: HashMap<String, &BrewPackage> =
-            packages.iter().map(|p| (p.name.clone(), p)).collect();
+        // A link conflict in gnutls isn't a TLS failure
+        assert!(other(
+            "Error: Could not symlink bin/gnutls-cli\n\
+             Target /opt/zerobrew/bin/gnutls-cli already exists\n"
+        ));
+        // Package names and non-error lines don't count
+        assert!(other(
+            "==> Restarting NetworkManager after install\nError: link conflict\n"
+        ));
+        assert!(other("Error: dependency-check has a link conflict\n"));
+        assert!(other(
+            "==> Setting timeout to 30s\nError: checksum mismatch\n"
+        ));
+        assert!(other("could not resolve host ghcr.io\n"));
+        // The exit code alone still identifies a timeout
+        assert_eq!(
+            classify_install_error(Some(124), "==> Downloading\n"),
+            MigrateError::Timeout
+        );
+        assert_eq!(
+            classify_install_error(Some(1), "Error: TLS handshake failed.\n"),
+            MigrateError::NetworkError
+        );
+    }
 
-        let mut result = Vec::new();
-        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+    #[test]
+    fn test_migrate_error_serialization() {
+        assert_eq!(
+            serde_json::to_value(MigrateError::NetworkError).unwrap(),
+            serde_json::json!({ "kind": "network_error" })
+        );
+        assert_eq!(
+            serde_json::to_value(MigrateError::Other("boom".to_string())).unwrap(),
+            serde_json::json!({ "kind": "other", "detail": "boom" })
+        );
+    }
 
-        fn visit(
-            name: &str,
-            pkg_map: &HashMap<String, &BrewPackage>,
-            visited: &mut std::collections::HashSet<String>,
-            result: &mut Vec<BrewPackage>,
-        ) {
-            if visited.contains(name) {
-                return;
-            }
-            visited.insert(name.to_string());
+    #[test]
+    fn test_migrate_package_adds_tap_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[
+            ("zb tap example/tools", (0, "", "")),
+            ("zb install example/tools/jj", (0, "", "")),
+        ]));
+        let package = BrewPackage {
+            tap: Some("example/tools".to_string()),
+            ..create_test_package("jj", vec![])
+        };
 
-            if let Some(pkg) = pkg_map.get(name) {
-                for dep in &pkg.dependencies {
-                    visit(dep, pkg_map, visited, result);
-                }
-                result.push((*pkg).clone());
+        let result = migrator
+            .migrate_package(&package, &MigrateOptions::default())
+            .unwrap();
+        assert!(matches!(result, MigrateResult::Success { .. }));
+
+        // The tap is only added once per run
+        migrator.runner = Box::new(MockRunner::new(&[(
+            "zb install example/tools/jj",
+            (0, "", ""),
+        )]));
+        let result = migrator
+            .migrate_package(&package, &MigrateOptions::default())
+            .unwrap();
+        assert!(matches!(result, MigrateResult::Success { .. }));
+
+        // Without a successful `zb tap`, nothing is installed, and every formula from
+        // the tap shares the failure
+        migrator.runner = Box::new(MockRunner::new(&[(
+            "zb tap other/tools",
+            (1, "", "Error: unknown command 'tap'\n"),
+        )]));
+        for name in ["jj", "kk"] {
+            let package = BrewPackage {
+                tap: Some("other/tools".to_string()),
+                ..create_test_package(name, vec![])
+            };
+            match migrator
+                .migrate_package(&package, &MigrateOptions::default())
+                .unwrap()
+            {
+                MigrateResult::Failed { reason, .. } => assert_eq!(
+                    reason,
+                    "Failed to add tap other/tools: Error: unknown command 'tap'"
+                ),
+                other => panic!("expected failure, got {:?}", other),
             }
+            migrator.runner = Box::new(MockRunner::new(&[]));
         }
-
-        for pkg in &packages {
-            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
-        }
-
-        // a should come before b, b should come before c
-        let pos_a = result.iter().position(|p| p.name == "a").unwrap();
-        let pos_b = result.iter().position(|p| p.name == "b").unwrap();
-        let pos_c = result.iter().position(|p| p.name == "c").unwrap();
-
-        assert!(pos_a < pos_b, "a should come before b");
-        assert!(pos_b < pos_c, "b should come before c");
     }
 
     #[test]
-    fn test_topological_sort_diamond_dependency() {
-        // d depends on b and c, both b and c depend on a
-        let packages = vec![
-            create_test_package("a", vec![]),
-            create_test_package("b", vec!["a"]),
-            create_test_package("c", vec!["a"]),
-            create_test_package("d", vec!["b", "c"]),
-        ];
-
-        let pkg_map: HashMap<String, &BrewPackage> =
-            packages.iter().map(|p| (p.name.clone(), p)).collect();
-
-        let mut result = Vec::new();
-        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
-
-        fn visit(
-            name: &str,
-            pkg_map: &HashMap<String, &BrewPackage>,
-            visited: &mut std::collections::HashSet<String>,
-            result: &mut Vec<BrewPackage>,
-        ) {
-            if visited.contains(name) {
-                return;
-            }
-            visited.insert(name.to_string());
+    fn test_migrate_package_pin_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        let package = BrewPackage {
+            version: "1.7.1_1".to_string(),
+            ..create_test_package("jq", vec![])
+        };
+        let options = MigrateOptions {
+            pin_versions: true,
+            ..Default::default()
+        };
 
-            if let Some(pkg) = pkg_map.get(name) {
-                for dep in &pkg.dependencies {
-                    visit(dep, pkg_map, visited, result);
-                }
-                result.push((*pkg).clone());
-            }
+        // The Homebrew version is requested without its bottle revision
+        migrator.runner = Box::new(MockRunner::new(&[("zb install jq@1.7.1", (0, "", ""))]));
+        match migrator.migrate_package(&package, &options).unwrap() {
+            MigrateResult::Success { pin_fallback, .. } => assert!(!pin_fallback),
+            other => panic!("expected success, got {:?}", other),
         }
 
-        for pkg in &packages {
-            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
+        // An unavailable version falls back to the latest one and is reported
+        migrator.runner = Box::new(MockRunner::new(&[
+            (
+                "zb install jq@1.7.1",
+                (1, "", "Error: jq@1.7.1 not found\n"),
+            ),
+            ("zb install jq", (0, "", "")),
+        ]));
+        let result = migrator.migrate_package(&package, &options).unwrap();
+        assert!(matches!(
+            result,
+            MigrateResult::Success {
+                pin_fallback: true,
+                ..
+            }
+        ));
+        let mut report = MigrationReport::default();
+        report.push_result(result);
+        assert_eq!(
+            report.pin_fallbacks,
+            vec![PinFallback {
+                name: "jq".to_string(),
+                requested_version: "1.7.1_1".to_string(),
+                installed_version: None,
+            }]
+        );
+        assert!(report.to_markdown().contains("## Unpinned Versions (1)"));
+
+        // Other failures are reported instead of silently installing the latest version
+        migrator.runner = Box::new(MockRunner::new(&[
+            (
+                "zb install jq@1.7.1",
+                (1, "", "Error: failed to download jq@1.7.1\n"),
+            ),
+            ("zb install jq", (0, "", "")),
+        ]));
+        match migrator.migrate_package(&package, &options).unwrap() {
+            MigrateResult::Failed { error, .. } => assert_eq!(error, MigrateError::NetworkError),
+            other => panic!("expected failure, got {:?}", other),
         }
 
-        assert_eq!(result.len(), 4);
+        assert_eq!(pinned_install_name("jq", UNKNOWN_VERSION), None);
+        assert_eq!(pinned_install_name("neovim", "HEAD-1a2b3c4"), None);
+        assert_eq!(pinned_install_name("openssl@3", "3.2.0"), None);
+    }
 
-        let pos_a = result.iter().position(|p| p.name == "a").unwrap();
-        let pos_b = result.iter().position(|p| p.name == "b").unwrap();
-        let pos_c = result.iter().position(|p| p.name == "c").unwrap();
-        let pos_d = result.iter().position(|p| p.name == "d").unwrap();
+    #[test]
+    fn test_migrate_package_install_modes() {
+        let options = MigrateOptions {
+            build_from_source: Some(vec!["llvm".to_string()]),
+            force_bottle: Some(vec![]),
+            ..Default::default()
+        };
+        options.validate_install_modes().unwrap();
+        // A named package wins over the flag that applies to everything
+        assert_eq!(options.install_mode("llvm"), InstallMode::BuildFromSource);
+        assert_eq!(options.install_mode("jq"), InstallMode::ForceBottle);
+        assert_eq!(
+            MigrateOptions::default().install_mode("jq"),
+            InstallMode::Default
+        );
 
-        assert!(pos_a < pos_b, "a should come before b");
-        assert!(pos_a < pos_c, "a should come before c");
-        assert!(pos_b < pos_d, "b should come before d");
-        assert!(pos_c < pos_d, "c should come before d");
+        let conflicting = MigrateOptions {
+            build_from_source: Some(vec!["jq".to_string()]),
+            force_bottle: Some(vec!["jq".to_string()]),
+            ..Default::default()
+        };
+        assert!(conflicting.validate_install_modes().is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[
+            ("zb install --help", (0, "--force-bottle\n", "")),
+            ("zb install --force-bottle jq", (0, "", "")),
+        ]));
+        assert!(migrator.check_install_mode_support(&options).is_err());
+
+        let bottle_only = MigrateOptions {
+            force_bottle: Some(vec![]),
+            ..Default::default()
+        };
+        migrator.check_install_mode_support(&bottle_only).unwrap();
+        let result = migrator
+            .migrate_package(&create_test_package("jq", vec![]), &bottle_only)
+            .unwrap();
+        let mut report = MigrationReport::default();
+        report.push_result(result);
+        assert_eq!(
+            report.install_modes,
+            vec![("jq".to_string(), InstallMode::ForceBottle)]
+        );
+        assert!(report.to_markdown().contains("| jq | bottle |"));
     }
 
     #[test]
-    fn test_topological_sort_missing_dependency() {
-        // b depends on "missing" which is not in the package list
-        let packages = vec![
-            create_test_package("a", vec![]),
-            create_test_package("b", vec!["missing"]),
-        ];
+    fn test_parse_aliases() {
+        let aliases = parse_aliases(
+            "# brew name = zerobrew name\n\
+             gnupg2 = \"gnupg\"\n\
+             \"openssl@3\" = \"openssl\"  # versioned\n",
+        )
+        .unwrap();
+        assert_eq!(aliases["gnupg2"], "gnupg");
+        assert_eq!(aliases["openssl@3"], "openssl");
+
+        assert!(parse_aliases("jq = 1").is_err());
+        assert!(parse_aliases("jq = \"a\"\njq = \"b\"").is_err());
+        assert!(parse_aliases("[aliases]").is_err());
+    }
 
-        let pkg_map: HashMap<String, &BrewPackage> =
-            packages.iter().map(|p| (p.name.clone(), p)).collect();
+    #[test]
+    fn test_load_aliases_overrides_builtin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(ALIASES_FILE_NAME);
+        assert_eq!(load_aliases(&path).unwrap()["gnupg2"], "gnupg");
+
+        fs::write(&path, "gnupg2 = \"gnupg@2\"\n").unwrap();
+        let aliases = load_aliases(&path).unwrap();
+        assert_eq!(aliases["gnupg2"], "gnupg@2");
+        assert_eq!(aliases["mysql-connector-c"], "mysql-client");
+    }
 
-        let mut result = Vec::new();
-        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+    #[test]
+    fn test_migrate_package_uses_alias() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.aliases = OnceLock::from(BTreeMap::from([(
+            "gnupg2".to_string(),
+            "gnupg".to_string(),
+        )]));
+        migrator.runner = Box::new(MockRunner::new(&[
+            ("zb install gnupg", (0, "", "")),
+            ("zb list", (0, "gnupg 2.4.5\n", "")),
+        ]));
+
+        let result = migrator
+            .migrate_package(
+                &create_test_package("gnupg2", vec![]),
+                &MigrateOptions::default(),
+            )
+            .unwrap();
+        let mut report = MigrationReport::default();
+        report.push_result(result);
 
-        fn visit(
-            name: &str,
-            pkg_map: &HashMap<String, &BrewPackage>,
-            visited: &mut std::collections::HashSet<String>,
-            result: &mut Vec<BrewPackage>,
-        ) {
-            if visited.contains(name) {
-                return;
-            }
-            visited.insert(name.to_string());
+        assert_eq!(report.successful, vec!["gnupg2"]);
+        assert_eq!(
+            report.renamed,
+            vec![("gnupg2".to_string(), "gnupg".to_string())]
+        );
+        // The installed zerobrew package counts as the Homebrew one
+        assert!(migrator
+            .list_zerobrew_packages()
+            .unwrap()
+            .contains("gnupg2"));
+    }
 
-            if let Some(pkg) = pkg_map.get(name) {
-                for dep in &pkg.dependencies {
-                    visit(dep, pkg_map, visited, result);
-                }
-                result.push((*pkg).clone());
+    #[test]
+    fn test_broken_aliases_file_only_fails_migrations() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.aliases = OnceLock::new();
+        fs::write(&migrator.aliases_file, "gnupg2 = [\n").unwrap();
+        migrator.runner = Box::new(MockRunner::new(&[
+            ("zb list", (0, "gnupg 2.4.5\n", "")),
+            ("zb install jq", (0, "", "")),
+        ]));
+
+        // Listing still works, with the built-in aliases
+        let installed = migrator.list_zerobrew_packages().unwrap();
+        assert!(installed.contains("gnupg2"));
+
+        let error = migrator
+            .migrate_package(
+                &create_test_package("jq", vec![]),
+                &MigrateOptions::default(),
+            )
+            .unwrap_err();
+        assert!(error.to_string().contains("Invalid package aliases"));
+    }
+
+    #[test]
+    fn test_migrate_package_spawn_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[]));
+
+        let result = migrator
+            .migrate_package(
+                &create_test_package("jq", vec![]),
+                &MigrateOptions::default(),
+            )
+            .unwrap();
+
+        match result {
+            MigrateResult::Failed { reason, log, .. } => {
+                assert!(reason.starts_with("Failed to run zb"));
+                assert!(log.is_none());
             }
+            other => panic!("expected failure, got {:?}", other),
         }
+    }
 
-        for pkg in &packages {
-            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
-        }
+    // ============================================
+    // --mock-brew Tests
+    // ============================================
 
-        // Should still work - missing deps are just skipped
-        assert_eq!(result.len(), 2);
+    const BREW_FIXTURE: &str = r#"{
+        "prefix": "/opt/mockbrew",
+        "formulae": [
+            {"name": "openssl@3", "version": "3.2.0", "keg_only": true},
+            {"name": "curl", "version": "8.5.0", "dependencies": ["openssl@3"]},
+            {"name": "git", "version": "2.43.0", "dependencies": ["curl"],
+             "build_dependencies": ["gettext"], "pinned": true},
+            {"name": "jj", "version": "0.12.0", "tap": "example/tools"}
+        ],
+        "casks": [{"name": "iterm2", "version": "3.4.23"}]
+    }"#;
+
+    fn fixture_migrator(dir: &Path) -> HomebrewMigrator {
+        let fixture: BrewFixture = serde_json::from_str(BREW_FIXTURE).unwrap();
+        let mut migrator = test_migrator(dir);
+        migrator.runner = Box::new(MockBrewRunner::new(fixture, migrator.brew_bin.clone()));
+        migrator
     }
 
     #[test]
-    fn test_topological_sort_empty_list() {
-        let packages: Vec<BrewPackage> = vec![];
-
-        let pkg_map: HashMap<String, &BrewPackage> =
-            packages.iter().map(|p| (p.name.clone(), p)).collect();
+    fn test_detailed_listing_same_with_parallel_jobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = fixture_migrator(dir.path());
+        migrator.include_build_deps = true;
+        let details = |migrator: &HomebrewMigrator| {
+            migrator
+                .list_installed_formulae_detailed()
+                .unwrap()
+                .into_iter()
+                .map(|p| {
+                    (
+                        p.name,
+                        p.dependencies,
+                        p.build_dependencies,
+                        p.tap,
+                        p.keg_only,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
 
-        let mut result = Vec::new();
-        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+        migrator.analyze_jobs = 1;
+        let serial = details(&migrator);
+        assert_eq!(serial.len(), 4);
+        for jobs in [0, 3, 64] {
+            migrator.analyze_jobs = jobs;
+            assert_eq!(details(&migrator), serial, "analyze_jobs = {}", jobs);
+        }
+    }
 
-        fn visit(
-            name: &str,
-            pkg_map: &HashMap<String, &BrewPackage>,
-            visited: &mut std::collections::HashSet<String>,
-            result: &mut Vec<BrewPackage>,
-        ) {
-            if visited.contains(name) {
-                return;
-            }
-            visited.insert(name.to_string());
+    #[test]
+    fn test_dry_run_plan_loads_details_only_when_needed() {
+        let dir = tempfile::tempdir().unwrap();
+        let migrator = fixture_migrator(dir.path());
+        let curl_deps = |plan: &MigrationPlan| {
+            plan.formulae
+                .iter()
+                .find(|p| p.name == "curl")
+                .unwrap()
+                .dependencies
+                .clone()
+        };
 
-            if let Some(pkg) = pkg_map.get(name) {
-                for dep in &pkg.dependencies {
-                    visit(dep, pkg_map, visited, result);
-                }
-                result.push((*pkg).clone());
-            }
+        let fast = migrator
+            .plan_migration(&MigrateOptions {
+                dry_run: true,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(!fast.dependencies_loaded);
+        assert!(curl_deps(&fast).is_empty());
+
+        for options in [
+            MigrateOptions {
+                dry_run: true,
+                exact_order: true,
+                ..Default::default()
+            },
+            // Excluding taps needs each formula's tap
+            MigrateOptions {
+                dry_run: true,
+                exclude_taps: vec!["example/tools".to_string()],
+                ..Default::default()
+            },
+            MigrateOptions::default(),
+        ] {
+            let plan = migrator.plan_migration(&options).unwrap();
+            assert!(plan.dependencies_loaded);
+            assert_eq!(curl_deps(&plan), vec!["openssl@3"]);
         }
+    }
 
-        for pkg in &packages {
-            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
-        }
+    #[test]
+    fn test_save_and_replay_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let migrator = fixture_migrator(dir.path());
+        let mut state = MigrationState::default();
+        state.migrated_packages.insert(
+            "openssl@3".to_string(),
+            create_test_package("openssl@3", vec![]),
+        );
+        migrator.save_state(&state).unwrap();
 
-        assert!(result.is_empty());
+        // The saved plan ignores local state and carries risk annotations
+        let saved = migrator.save_plan(&MigrateOptions::default()).unwrap();
+        let order: Vec<&str> = saved
+            .plan
+            .formulae
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert!(order.contains(&"openssl@3"));
+        let position = |name: &str| order.iter().position(|n| *n == name).unwrap();
+        assert!(position("openssl@3") < position("curl"));
+        assert!(position("curl") < position("git"));
+        assert_eq!(saved.risks["openssl@3"], MigrationRisk::KeepInHomebrew);
+
+        let path = dir.path().join("plan.json");
+        std::fs::write(&path, serde_json::to_string(&saved).unwrap()).unwrap();
+        let loaded = load_saved_plan(&path).unwrap();
+
+        // Replaying keeps the saved order but skips what this machine already migrated
+        let plan = migrator
+            .replay_plan(&loaded, &MigrateOptions::default())
+            .unwrap();
+        assert_eq!(plan.already_migrated, vec!["openssl@3"]);
+        let replayed: Vec<&str> = plan.formulae.iter().map(|p| p.name.as_str()).collect();
+        let expected: Vec<&str> = order.into_iter().filter(|n| *n != "openssl@3").collect();
+        assert_eq!(replayed, expected);
+
+        // Every planned formula has to be installed here
+        let mut foreign = loaded.clone();
+        foreign
+            .plan
+            .formulae
+            .push(create_test_package("not-installed", vec![]));
+        let err = migrator
+            .replay_plan(&foreign, &MigrateOptions::default())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("not-installed"));
     }
 
     #[test]
-    fn test_topological_sort_complex_graph() {
-        // Complex dependency graph:
-        // f -> d, e
-        // e -> c
-        // d -> b, c
-        // c -> a
-        // b -> a
-        // a -> (none)
-        let packages = vec![
-            create_test_package("a", vec![]),
-            create_test_package("b", vec!["a"]),
-            create_test_package("c", vec!["a"]),
-            create_test_package("d", vec!["b", "c"]),
-            create_test_package("e", vec!["c"]),
-            create_test_package("f", vec!["d", "e"]),
-        ];
+    fn test_mock_brew_lists_fixture_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = fixture_migrator(dir.path());
+        migrator.include_build_deps = true;
+
+        let formulae = migrator.list_installed_formulae_detailed().unwrap();
+        let git = formulae.iter().find(|p| p.name == "git").unwrap();
+        assert_eq!(git.version, "2.43.0");
+        assert_eq!(git.dependencies, vec!["curl"]);
+        assert_eq!(git.build_dependencies, vec!["gettext"]);
+        assert!(git.pinned);
+        assert_eq!(git.tap, None);
+        assert!(!git.keg_only);
+        let jj = formulae.iter().find(|p| p.name == "jj").unwrap();
+        assert_eq!(jj.tap.as_deref(), Some("example/tools"));
+        assert!(formulae.iter().any(|p| p.name == "openssl@3" && p.keg_only));
+
+        let casks = migrator.list_installed_casks().unwrap();
+        assert_eq!(casks.len(), 1);
+        assert!(casks[0].is_cask);
 
-        let pkg_map: HashMap<String, &BrewPackage> =
-            packages.iter().map(|p| (p.name.clone(), p)).collect();
+        assert_eq!(
+            migrator
+                .find_dependents(&["openssl@3".to_string()], 3)
+                .unwrap(),
+            vec!["curl", "git"]
+        );
+    }
 
-        let mut result = Vec::new();
-        let mut visited: std::collections::HashSet<String> = std::collections::HashSet::new();
+    #[test]
+    fn test_mock_brew_prefix_and_unsupported_commands() {
+        let fixture: BrewFixture = serde_json::from_str(BREW_FIXTURE).unwrap();
+        let runner = MockBrewRunner::new(fixture, PathBuf::from("brew"));
 
-        fn visit(
-            name: &str,
-            pkg_map: &HashMap<String, &BrewPackage>,
-            visited: &mut std::collections::HashSet<String>,
-            result: &mut Vec<BrewPackage>,
-        ) {
-            if visited.contains(name) {
-                return;
-            }
-            visited.insert(name.to_string());
+        let prefix = runner.output(Path::new("brew"), &["--prefix"]).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&prefix.stdout).trim(),
+            "/opt/mockbrew"
+        );
 
-            if let Some(pkg) = pkg_map.get(name) {
-                for dep in &pkg.dependencies {
-                    visit(dep, pkg_map, visited, result);
-                }
-                result.push((*pkg).clone());
-            }
-        }
+        let unknown = runner.output(Path::new("brew"), &["doctor"]).unwrap();
+        assert!(!unknown.status.success());
+        assert!(runner
+            .status(Path::new("brew"), &["uninstall", "git"])
+            .unwrap()
+            .success());
+    }
 
-        for pkg in &packages {
-            visit(&pkg.name, &pkg_map, &mut visited, &mut result);
-        }
+    // ============================================
+    // --with-dependents Tests
+    // ============================================
 
-        assert_eq!(result.len(), 6);
+    #[test]
+    fn test_package_impact() {
+        let dir = tempfile::tempdir().unwrap();
+        let migrator = fixture_migrator(dir.path());
+
+        let impact = migrator.package_impact("curl").unwrap();
+        assert_eq!(impact.dependencies, vec!["openssl@3"]);
+        assert_eq!(impact.dependencies_to_install, vec!["openssl@3"]);
+        assert_eq!(impact.dependents, vec!["git"]);
+
+        let impact = migrator.package_impact("openssl@3").unwrap();
+        assert!(impact.dependencies.is_empty());
+        assert_eq!(impact.dependents, vec!["curl", "git"]);
+    }
 
-        // Verify ordering constraints
-        let positions: HashMap<String, usize> = result
-            .iter()
-            .enumerate()
-            .map(|(i, p)| (p.name.clone(), i))
-            .collect();
+    #[test]
+    fn test_collect_dependents_respects_depth() {
+        // openssl@3 <- curl <- git <- git-lfs
+        let uses: HashMap<&str, Vec<&str>> = [
+            ("openssl@3", vec!["curl", "wget"]),
+            ("curl", vec!["git"]),
+            ("wget", vec![]),
+            ("git", vec!["git-lfs", "curl"]),
+            ("git-lfs", vec![]),
+        ]
+        .into_iter()
+        .collect();
+        let lookup = |name: &str| -> Result<Vec<String>> {
+            Ok(uses[name].iter().map(|s| s.to_string()).collect())
+        };
+        let start = vec!["openssl@3".to_string()];
 
-        assert!(positions["a"] < positions["b"]);
-        assert!(positions["a"] < positions["c"]);
-        assert!(positions["b"] < positions["d"]);
-        assert!(positions["c"] < positions["d"]);
-        assert!(positions["c"] < positions["e"]);
-        assert!(positions["d"] < positions["f"]);
-        assert!(positions["e"] < positions["f"]);
+        assert_eq!(
+            collect_dependents(&start, 1, lookup).unwrap(),
+            vec!["curl", "wget"]
+        );
+        assert_eq!(
+            collect_dependents(&start, 2, lookup).unwrap(),
+            vec!["curl", "git", "wget"]
+        );
+        assert_eq!(
+            collect_dependents(&start, 10, lookup).unwrap(),
+            vec!["curl", "git", "git-lfs", "wget"]
+        );
+        assert!(collect_dependents(&start, 0, lookup).unwrap().is_empty());
     }
 
     // ============================================
-    // Brewfile Export Format Tests
+    // Verify Tests
     // ============================================
 
     #[test]
-    fn test_brewfile_format_basic() {
-        let formulae = vec![
+    fn test_parse_zb_list() {
+        let output = "==> Installed packages\njq 1.7.1\nripgrep 14.1.0\n\nfd\n";
+        let names = parse_zb_list(output);
+
+        assert_eq!(names.len(), 3);
+        assert!(names.contains("jq"));
+        assert!(names.contains("ripgrep"));
+        assert!(names.contains("fd"));
+    }
+
+    #[test]
+    fn test_check_migrated_presence_flags_missing() {
+        let mut state = MigrationState::default();
+        for name in ["ripgrep", "jq", "fd"] {
+            state
+                .migrated_packages
+                .insert(name.to_string(), create_test_package(name, vec![]));
+        }
+        let installed: HashSet<String> = ["jq", "ripgrep"].iter().map(|s| s.to_string()).collect();
+
+        let results = check_migrated_presence(&state, &installed);
+        let statuses: Vec<(&str, &VerifyStatus)> = results
+            .iter()
+            .map(|r| (r.name.as_str(), &r.status))
+            .collect();
+
+        assert_eq!(
+            statuses,
+            vec![
+                ("fd", &VerifyStatus::Missing),
+                ("jq", &VerifyStatus::Ok),
+                ("ripgrep", &VerifyStatus::Ok),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analysis_report_at_or_above() {
+        let packages = vec![
             create_test_package("git", vec![]),
-            create_test_package("node", vec![]),
+            create_test_package("aria2", vec!["openssl@3"]),
+            create_test_package("openssl@3", vec![]),
         ];
+        let report = HomebrewMigrator::categorize_packages(&packages);
 
-        let casks = vec![BrewPackage {
-            name: "visual-studio-code".to_string(),
-            version: "1.84.0".to_string(),
-            tap: None,
-            is_cask: true,
-            dependencies: vec![],
-            pinned: false,
-        }];
+        let risky_up = report.at_or_above(&MigrationRisk::Risky);
+        assert!(risky_up.safe_to_migrate.is_empty());
+        assert_eq!(risky_up.risky.len(), 1);
+        assert_eq!(risky_up.should_keep_in_homebrew.len(), 1);
+        assert_eq!(risky_up.total_packages, 3);
 
-        let mut content = String::new();
-        content.push_str("# Zerobrew Migration Brewfile\n");
-        content.push_str("# Generated from Homebrew installation\n\n");
+        let keep_only = report.at_or_above(&MigrationRisk::KeepInHomebrew);
+        assert!(keep_only.risky.is_empty());
+        assert_eq!(keep_only.should_keep_in_homebrew[0].name, "openssl@3");
 
-        // Collect taps
-        let mut taps: std::collections::HashSet<String> = std::collections::HashSet::new();
-        for pkg in &formulae {
-            if let Some(ref tap) = pkg.tap {
-                taps.insert(tap.clone());
+        let all = report.at_or_above(&MigrationRisk::Safe);
+        assert_eq!(all.safe_to_migrate.len(), 1);
+    }
+
+    /// The original per-package walk, kept as a reference for the memoized one
+    fn naive_reachable_problematic(
+        pkg: &BrewPackage,
+        pkg_map: &HashMap<&str, &BrewPackage>,
+        problematic_set: &HashSet<&str>,
+        visited: &mut HashSet<String>,
+        found: &mut BTreeSet<String>,
+    ) {
+        for dep_name in &pkg.dependencies {
+            if !visited.insert(dep_name.clone()) {
+                continue;
+            }
+            if problematic_set.contains(dep_name.as_str()) {
+                found.insert(dep_name.clone());
+            }
+            if let Some(dep_pkg) = pkg_map.get(dep_name.as_str()) {
+                naive_reachable_problematic(dep_pkg, pkg_map, problematic_set, visited, found);
             }
         }
+    }
 
-        for tap in &taps {
-            content.push_str(&format!("tap \"{}\"\n", tap));
-        }
-        content.push('\n');
+    fn assert_memoized_matches_naive(packages: &[BrewPackage], problematic: &[&str]) {
+        let problematic_set: HashSet<&str> = problematic.iter().copied().collect();
+        let pkg_map: HashMap<&str, &BrewPackage> =
+            packages.iter().map(|p| (p.name.as_str(), p)).collect();
+        let mut memo = HashMap::new();
 
-        // Formulae
-        for pkg in &formulae {
-            content.push_str(&format!("brew \"{}\"\n", pkg.name));
+        for pkg in packages {
+            let mut expected = BTreeSet::new();
+            naive_reachable_problematic(
+                pkg,
+                &pkg_map,
+                &problematic_set,
+                &mut HashSet::new(),
+                &mut expected,
+            );
+            let actual: BTreeSet<String> = HomebrewMigrator::reachable_problematic(
+                &pkg.name,
+                &pkg_map,
+                &problematic_set,
+                None,
+                &mut memo,
+            )
+            .into_iter()
+            .map(String::from)
+            .collect();
+            assert_eq!(actual, expected, "mismatch for {}", pkg.name);
         }
-        content.push('\n');
+    }
 
-        // Casks
-        for pkg in &casks {
-            content.push_str(&format!("cask \"{}\"\n", pkg.name));
-        }
+    #[test]
+    fn test_reachable_problematic_matches_naive_walk() {
+        // Diamond: d -> b, c; b, c -> a
+        let diamond = vec![
+            create_test_package("a", vec![]),
+            create_test_package("b", vec!["a"]),
+            create_test_package("c", vec!["a"]),
+            create_test_package("d", vec!["b", "c"]),
+        ];
+        assert_memoized_matches_naive(&diamond, &["a"]);
+        assert_memoized_matches_naive(&diamond, &["b", "a"]);
 
-        assert!(content.contains("# Zerobrew Migration Brewfile"));
-        assert!(content.contains("brew \"git\""));
-        assert!(content.contains("brew \"node\""));
-        assert!(content.contains("cask \"visual-studio-code\""));
+        // Complex: f -> d, e; e -> c; d -> b, c; c -> a; b -> a
+        let complex = vec![
+            create_test_package("a", vec![]),
+            create_test_package("b", vec!["a"]),
+            create_test_package("c", vec!["a"]),
+            create_test_package("d", vec!["b", "c"]),
+            create_test_package("e", vec!["c"]),
+            create_test_package("f", vec!["d", "e"]),
+        ];
+        assert_memoized_matches_naive(&complex, &["a"]);
+        assert_memoized_matches_naive(&complex, &["c", "e"]);
+        // A dependency that isn't installed is still reported when problematic
+        let mut with_missing = complex.clone();
+        with_missing[0].dependencies.push("openssl@3".to_string());
+        assert_memoized_matches_naive(&with_missing, &["openssl@3", "d"]);
     }
 
     #[test]
-    fn test_brewfile_format_with_taps() {
-        let formulae = vec![
-            BrewPackage {
-                name: "neovim".to_string(),
-                version: "0.9.4".to_string(),
-                tap: Some("homebrew/core".to_string()),
-                is_cask: false,
-                dependencies: vec![],
-                pinned: false,
-            },
-            BrewPackage {
-                name: "custom-tool".to_string(),
-                version: "1.0.0".to_string(),
-                tap: Some("user/custom-tap".to_string()),
-                is_cask: false,
-                dependencies: vec![],
-                pinned: false,
-            },
+    fn test_categorize_packages_with_depth() {
+        // app -> libgit -> ssh-wrapper -> openssl@3
+        let packages = vec![
+            create_test_package("openssl@3", vec![]),
+            create_test_package("ssh-wrapper", vec!["openssl@3"]),
+            create_test_package("libgit", vec!["ssh-wrapper"]),
+            create_test_package("app", vec!["libgit"]),
         ];
+        let risk = |depth: Option<usize>| {
+            HomebrewMigrator::categorize_packages_with_depth(&packages, depth)
+                .find("app")
+                .unwrap()
+                .risk
+                .clone()
+        };
+        assert_eq!(risk(None), MigrationRisk::Risky);
+        assert_eq!(risk(Some(3)), MigrationRisk::Risky);
+        assert_eq!(risk(Some(2)), MigrationRisk::Safe);
+        assert_eq!(risk(Some(1)), MigrationRisk::Safe);
 
-        let mut content = String::new();
-        content.push_str("# Zerobrew Migration Brewfile\n");
-        content.push_str("# Generated from Homebrew installation\n\n");
+        // Direct dependencies count at any depth
+        let report = HomebrewMigrator::categorize_packages_with_depth(&packages, Some(1));
+        assert_eq!(
+            report.find("ssh-wrapper").unwrap().risk,
+            MigrationRisk::Risky
+        );
+    }
+
+    #[test]
+    fn test_reachable_problematic_terminates_on_cycles() {
+        // a -> b -> c -> a, with openssl@3 only hanging off c; x depends on the cycle
+        let packages = [
+            create_test_package("a", vec!["b"]),
+            create_test_package("b", vec!["c"]),
+            create_test_package("c", vec!["a", "openssl@3"]),
+            create_test_package("x", vec!["b"]),
+            create_test_package("y", vec!["a", "d"]),
+            create_test_package("d", vec!["y"]),
+        ];
+        let problematic_set: HashSet<&str> = ["openssl@3"].into_iter().collect();
+        let pkg_map: HashMap<&str, &BrewPackage> =
+            packages.iter().map(|p| (p.name.as_str(), p)).collect();
 
-        // Collect taps
-        let mut taps: std::collections::HashSet<String> = std::collections::HashSet::new();
-        for pkg in &formulae {
-            if let Some(ref tap) = pkg.tap {
-                taps.insert(tap.clone());
+        // Every start order with a shared memo gives every node the full set
+        let orders = [
+            ["a", "b", "c", "x", "y", "d"],
+            ["c", "b", "a", "d", "y", "x"],
+            ["x", "d", "b", "y", "c", "a"],
+            ["y", "a", "x", "c", "d", "b"],
+        ];
+        for order in orders {
+            let mut memo = HashMap::new();
+            for name in order {
+                let found = HomebrewMigrator::reachable_problematic(
+                    name,
+                    &pkg_map,
+                    &problematic_set,
+                    None,
+                    &mut memo,
+                );
+                assert_eq!(
+                    found.into_iter().collect::<Vec<_>>(),
+                    vec!["openssl@3"],
+                    "{} in order {:?}",
+                    name,
+                    order
+                );
             }
         }
+        assert_memoized_matches_naive(&packages, &["openssl@3"]);
+        assert_memoized_matches_naive(&packages, &["a", "d"]);
+    }
 
-        for tap in &taps {
-            content.push_str(&format!("tap \"{}\"\n", tap));
-        }
-        content.push('\n');
+    #[test]
+    fn test_analysis_report_apply_strict() {
+        let packages = vec![
+            create_test_package("git", vec![]),
+            create_test_package("aria2", vec!["libssh2"]),
+            create_test_package("libssh2", vec!["openssl@3"]),
+            create_test_package("wrk", vec!["luajit"]),
+            create_test_package("luajit", vec![]),
+        ];
+        let default_report = HomebrewMigrator::categorize_packages(&packages);
+        assert_eq!(default_report.risky.len(), 1);
 
-        for pkg in &formulae {
-            content.push_str(&format!("brew \"{}\"\n", pkg.name));
-        }
+        let mut report = default_report.clone();
+        report.apply_strict();
 
-        assert!(
-            content.contains("tap \"homebrew/core\"")
-                || content.contains("tap \"user/custom-tap\"")
+        assert!(report.risky.is_empty());
+        let keep: Vec<&str> = report
+            .should_keep_in_homebrew
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(keep, vec!["aria2", "libssh2"]);
+        assert_eq!(
+            report.should_keep_in_homebrew[0].risk,
+            MigrationRisk::KeepInHomebrew
+        );
+        assert_eq!(
+            report.should_keep_in_homebrew[0].reason,
+            "Strict mode: depends on libssh2"
+        );
+        // Safe packages are untouched
+        assert_eq!(
+            report.safe_to_migrate.len(),
+            default_report.safe_to_migrate.len()
         );
-        assert!(content.contains("brew \"neovim\""));
-        assert!(content.contains("brew \"custom-tool\""));
     }
 
     #[test]
-    fn test_brewfile_format_empty() {
-        let formulae: Vec<BrewPackage> = vec![];
-        let casks: Vec<BrewPackage> = vec![];
+    fn test_apply_strict_keeps_keg_only_and_no_bottle_risky() {
+        let packages = vec![
+            create_test_package("aria2", vec!["libssh2"]),
+            create_test_package("libssh2", vec![]),
+            BrewPackage {
+                keg_only: true,
+                ..create_test_package("sqlite", vec![])
+            },
+            BrewPackage {
+                no_bottle: true,
+                ..create_test_package("ffmpeg", vec![])
+            },
+        ];
+        let mut report = HomebrewMigrator::categorize_packages(&packages);
+        report.apply_strict();
 
-        let mut content = String::new();
-        content.push_str("# Zerobrew Migration Brewfile\n");
-        content.push_str("# Generated from Homebrew installation\n\n");
+        let risky: Vec<&str> = report.risky.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(risky, vec!["ffmpeg", "sqlite"]);
+        assert!(report
+            .risky
+            .iter()
+            .all(|p| p.risk == MigrationRisk::Risky && !p.reason.starts_with("Strict mode")));
+        let keep: Vec<&str> = report
+            .should_keep_in_homebrew
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(keep, vec!["aria2", "libssh2"]);
+    }
 
-        let taps: std::collections::HashSet<String> = std::collections::HashSet::new();
+    // ============================================
+    // Migration History Tests
+    // ============================================
 
-        for tap in &taps {
-            content.push_str(&format!("tap \"{}\"\n", tap));
-        }
-        content.push('\n');
+    #[test]
+    fn test_history_entry_from_report() {
+        let report = MigrationReport {
+            successful: vec!["jq".to_string()],
+            failed: vec![("openssl@3".to_string(), "link conflict".to_string())],
+            skipped: vec![("iterm2".to_string(), "Casks not yet supported".to_string())],
+            ..Default::default()
+        };
+        let entry =
+            HistoryEntry::from_report("migrate", &report, &Some("zb 0.1.0".to_string()), 42);
+
+        assert_eq!(entry.timestamp, 42);
+        assert_eq!(entry.action, "migrate");
+        assert_eq!(entry.successful, vec!["jq"]);
+        assert_eq!(entry.failed, vec!["openssl@3"]);
+        assert_eq!(entry.skipped, vec!["iterm2"]);
+    }
 
-        for pkg in &formulae {
-            content.push_str(&format!("brew \"{}\"\n", pkg.name));
-        }
-        content.push('\n');
+    #[test]
+    fn test_history_records_the_running_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.runner = Box::new(MockRunner::new(&[("zb install jq", (0, "", ""))]));
+        let plan = MigrationPlan {
+            formulae: vec![create_test_package("jq", vec![])],
+            total_formulae: 1,
+            ..Default::default()
+        };
 
-        for pkg in &casks {
-            content.push_str(&format!("cask \"{}\"\n", pkg.name));
+        for options in [
+            MigrateOptions::default(),
+            MigrateOptions {
+                history_action: Some("retry-failed"),
+                ..Default::default()
+            },
+        ] {
+            migrator.execute_plan(&plan, &options, &mut |_| {}).unwrap();
         }
 
-        assert!(content.contains("# Zerobrew Migration Brewfile"));
-        assert!(!content.contains("brew \""));
-        assert!(!content.contains("cask \""));
+        let actions: Vec<String> = migrator
+            .load_history()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(actions, vec!["migrate", "retry-failed"]);
     }
 
     #[test]
-    fn test_brewfile_format_special_characters() {
-        let formulae = vec![
-            create_test_package("python@3.11", vec![]),
-            create_test_package("openssl@3", vec![]),
-        ];
-
-        let mut content = String::new();
-        for pkg in &formulae {
-            content.push_str(&format!("brew \"{}\"\n", pkg.name));
-        }
+    fn test_parse_history_skips_malformed_lines() {
+        let first = HistoryEntry::from_report("migrate", &MigrationReport::default(), &None, 1);
+        let second = HistoryEntry {
+            timestamp: 2,
+            successful: vec!["git".to_string()],
+            ..first.clone()
+        };
+        let content = format!(
+            "{}\n\n{}\n{{\"timestamp\": 3, \"act",
+            serde_json::to_string(&first).unwrap(),
+            serde_json::to_string(&second).unwrap()
+        );
 
-        assert!(content.contains("brew \"python@3.11\""));
-        assert!(content.contains("brew \"openssl@3\""));
+        let (entries, malformed) = parse_history(&content);
+        assert_eq!(entries, vec![first, second]);
+        assert_eq!(malformed, 1);
     }
 
-    // ============================================
-    // MigrationReport Tests
-    // ============================================
-
     #[test]
-    fn test_migration_report_default() {
-        let report = MigrationReport::default();
-
-        assert_eq!(report.total_formulae, 0);
-        assert_eq!(report.total_casks, 0);
-        assert!(report.successful.is_empty());
-        assert!(report.failed.is_empty());
-        assert!(report.skipped.is_empty());
+    fn test_format_unix_time() {
+        assert_eq!(format_unix_time(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_unix_time(1_700_000_000), "2023-11-14 22:13 UTC");
+        assert_eq!(format_unix_time(1_709_210_096), "2024-02-29 12:34 UTC");
     }
 
     #[test]
-    fn test_migration_report_with_data() {
-        let mut report = MigrationReport::default();
-        report.total_formulae = 10;
-        report.total_casks = 5;
-        report.successful.push("git".to_string());
-        report.successful.push("node".to_string());
-        report
-            .failed
-            .push(("broken-pkg".to_string(), "Install failed".to_string()));
-        report
-            .skipped
-            .push(("cask-app".to_string(), "Casks not supported".to_string()));
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(999), "999 B");
+        assert_eq!(format_size(1_500), "1.5 kB");
+        assert_eq!(format_size(2_340_000_000), "2.3 GB");
+    }
 
-        assert_eq!(report.total_formulae, 10);
-        assert_eq!(report.total_casks, 5);
-        assert_eq!(report.successful.len(), 2);
-        assert_eq!(report.failed.len(), 1);
-        assert_eq!(report.skipped.len(), 1);
+    #[test]
+    fn test_prefix_disk_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(prefix_disk_usage(dir.path()), None);
+
+        let cellar = dir.path().join("Cellar/jq/1.7.1/bin");
+        fs::create_dir_all(&cellar).unwrap();
+        fs::write(cellar.join("jq"), vec![0u8; 64 * 1024]).unwrap();
+        let with_file = prefix_disk_usage(dir.path()).unwrap();
+        assert!(with_file >= 64 * 1024);
+
+        // Hard links to the same file are only counted once
+        #[cfg(unix)]
+        {
+            fs::hard_link(cellar.join("jq"), cellar.join("jq-link")).unwrap();
+            assert_eq!(prefix_disk_usage(dir.path()).unwrap(), with_file);
+        }
     }
 
     // ============================================
-    // MigrateResult Tests
+    // Homebrew Services Tests
     // ============================================
 
     #[test]
-    fn test_migrate_result_success() {
-        let result = MigrateResult::Success {
-            name: "git".to_string(),
-            version: "2.42.0".to_string(),
-        };
-
-        match result {
-            MigrateResult::Success { name, version } => {
-                assert_eq!(name, "git");
-                assert_eq!(version, "2.42.0");
-            }
-            MigrateResult::Failed { .. } => panic!("Expected Success variant"),
-        }
+    fn test_parse_running_services() {
+        let json = r#"[
+            {"name": "postgresql@16", "status": "started", "user": "me", "file": "/x.plist", "exit_code": 0},
+            {"name": "redis", "status": "none", "user": null, "file": null, "exit_code": null},
+            {"name": "nginx", "status": "error", "user": "root", "file": "/y.plist", "exit_code": 1}
+        ]"#;
+
+        let running = parse_running_services(json).unwrap();
+        assert_eq!(
+            running.into_iter().collect::<Vec<_>>(),
+            vec!["postgresql@16"]
+        );
+        assert!(parse_running_services("Error: unknown command").is_err());
     }
 
     #[test]
-    fn test_migrate_result_failed() {
-        let result = MigrateResult::Failed {
-            name: "broken-pkg".to_string(),
-            reason: "Package not found".to_string(),
+    fn test_report_markdown_lists_running_services() {
+        let report = MigrationReport {
+            successful: vec!["postgresql@16".to_string()],
+            running_services: vec!["postgresql@16".to_string()],
+            ..Default::default()
         };
 
-        match result {
-            MigrateResult::Success { .. } => panic!("Expected Failed variant"),
-            MigrateResult::Failed { name, reason } => {
-                assert_eq!(name, "broken-pkg");
-                assert_eq!(reason, "Package not found");
-            }
-        }
+        let md = report.to_markdown();
+        assert!(md.contains("## Running Homebrew Services (1)"));
+        assert!(md.contains("- `postgresql@16`"));
     }
 
     // ============================================
-    // BrewPackage Serialization Tests
+    // State Backup Tests
     // ============================================
 
     #[test]
-    fn test_brew_package_serialization() {
-        let pkg = BrewPackage {
-            name: "git".to_string(),
-            version: "2.42.0".to_string(),
-            tap: Some("homebrew/core".to_string()),
-            is_cask: false,
-            dependencies: vec!["pcre2".to_string(), "gettext".to_string()],
-            pinned: true,
-        };
-
-        let json = serde_json::to_string(&pkg).expect("Serialization failed");
-
-        assert!(json.contains("\"name\":\"git\""));
-        assert!(json.contains("\"version\":\"2.42.0\""));
-        assert!(json.contains("\"tap\":\"homebrew/core\""));
-        assert!(json.contains("\"is_cask\":false"));
-        assert!(json.contains("\"pinned\":true"));
-        assert!(json.contains("pcre2"));
-        assert!(json.contains("gettext"));
+    fn test_parse_backup_timestamp() {
+        assert_eq!(
+            parse_backup_timestamp("migration_state.1767225600.json.bak"),
+            Some(1_767_225_600)
+        );
+        assert_eq!(parse_backup_timestamp("migration_state.json"), None);
+        assert_eq!(parse_backup_timestamp("migration_state.x.json.bak"), None);
     }
 
     #[test]
-    fn test_brew_package_deserialization() {
-        let json = r#"{
-            "name": "node",
-            "version": "20.9.0",
-            "tap": null,
-            "is_cask": false,
-            "dependencies": ["icu4c", "libnghttp2"],
-            "pinned": false
-        }"#;
+    fn test_backup_and_restore_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.backup_state = true;
+        migrator.keep_backups = 2;
+        for (timestamp, name) in [(100, "old"), (200, "older-but-kept"), (300, "newest")] {
+            fs::write(
+                dir.path()
+                    .join(format!("migration_state.{}.json.bak", timestamp)),
+                format!("{{\"failed_packages\": [\"{}\"]}}", name),
+            )
+            .unwrap();
+        }
 
-        let pkg: BrewPackage = serde_json::from_str(json).expect("Deserialization failed");
+        let mut state = MigrationState {
+            failed_packages: failed_entries(&["first"]),
+            ..Default::default()
+        };
+        migrator.save_state(&state).unwrap();
+        // Nothing to back up yet
+        assert_eq!(migrator.list_state_backups().unwrap().len(), 3);
+
+        state.failed_packages = failed_entries(&["second"]);
+        migrator.save_state(&state).unwrap();
+        let backups = migrator.list_state_backups().unwrap();
+        assert_eq!(backups.len(), 2, "only the newest backups are kept");
+        let newest = backups.last().unwrap();
+        assert!(fs::read_to_string(newest).unwrap().contains("first"));
+
+        let restored = migrator.restore_state(newest).unwrap();
+        assert_eq!(restored.failed_packages, failed_entries(&["first"]));
+        assert_eq!(
+            migrator.load_state().unwrap().failed_packages,
+            failed_entries(&["first"])
+        );
 
-        assert_eq!(pkg.name, "node");
-        assert_eq!(pkg.version, "20.9.0");
-        assert!(pkg.tap.is_none());
-        assert!(!pkg.is_cask);
-        assert_eq!(pkg.dependencies, vec!["icu4c", "libnghttp2"]);
-        assert!(!pkg.pinned);
+        let bogus = dir.path().join("bogus.json");
+        fs::write(&bogus, "not json").unwrap();
+        assert!(migrator.restore_state(&bogus).is_err());
+        assert_eq!(
+            migrator.load_state().unwrap().failed_packages,
+            failed_entries(&["first"])
+        );
     }
 
     #[test]
-    fn test_brew_package_roundtrip() {
-        let original = BrewPackage {
-            name: "rust".to_string(),
-            version: "1.73.0".to_string(),
-            tap: Some("homebrew/core".to_string()),
-            is_cask: false,
-            dependencies: vec!["libssh2".to_string(), "openssl@3".to_string()],
-            pinned: true,
-        };
-
-        let json = serde_json::to_string(&original).expect("Serialization failed");
-        let restored: BrewPackage = serde_json::from_str(&json).expect("Deserialization failed");
-
-        assert_eq!(restored.name, original.name);
-        assert_eq!(restored.version, original.version);
-        assert_eq!(restored.tap, original.tap);
-        assert_eq!(restored.is_cask, original.is_cask);
-        assert_eq!(restored.dependencies, original.dependencies);
-        assert_eq!(restored.pinned, original.pinned);
+    fn test_backup_state_file_never_overwrites() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut migrator = test_migrator(dir.path());
+        migrator.keep_backups = 10;
+        migrator.save_state(&MigrationState::default()).unwrap();
+
+        // Back-to-back backups within the same millisecond still get their own files
+        let backups: Vec<PathBuf> = (0..3)
+            .map(|_| migrator.backup_state_file().unwrap())
+            .collect();
+        let unique: HashSet<&PathBuf> = backups.iter().collect();
+        assert_eq!(unique.len(), 3);
+        assert_eq!(migrator.list_state_backups().unwrap(), backups);
     }
 
     // ============================================
-    // File I/O Tests (using tempfile)
+    // Migration Lock Tests
     // ============================================
 
     #[test]
-    fn test_brewfile_write_to_file() {
-        let mut temp_file = NamedTempFile::new().expect("Failed to create temp file");
-
-        let content = "# Zerobrew Migration Brewfile\nbrew \"git\"\ncask \"vscode\"\n";
-        temp_file
-            .write_all(content.as_bytes())
-            .expect("Failed to write");
+    fn test_migration_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let migrator = test_migrator(dir.path());
 
-        let read_content =
-            std::fs::read_to_string(temp_file.path()).expect("Failed to read temp file");
+        let lock = migrator.acquire_lock().expect("first lock should succeed");
+        let err = migrator.acquire_lock().unwrap_err();
+        assert!(err.to_string().contains("Another migration is in progress"));
 
-        assert_eq!(read_content, content);
+        drop(lock);
+        assert!(
+            migrator.acquire_lock().is_ok(),
+            "lock should be released on drop"
+        );
     }
 
-    #[test]
-    fn test_migration_state_file_io() {
-        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
-
-        let mut state = MigrationState::default();
-        state.homebrew_prefix = PathBuf::from("/opt/homebrew");
-        state
-            .migrated_packages
-            .insert("git".to_string(), create_test_package("git", vec![]));
-
-        // Write state
-        let json = serde_json::to_string_pretty(&state).expect("Serialization failed");
-        std::fs::write(temp_file.path(), &json).expect("Failed to write");
+    // ============================================
+    // Custom Binary Path Tests
+    // ============================================
 
-        // Read state
-        let read_content =
-            std::fs::read_to_string(temp_file.path()).expect("Failed to read temp file");
-        let restored: MigrationState =
-            serde_json::from_str(&read_content).expect("Deserialization failed");
+    #[test]
+    fn test_with_binaries_missing_brew_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = HomebrewMigrator::with_binaries(
+            false,
+            dir.path().join("no-such-brew"),
+            PathBuf::from("zb"),
+        );
+        assert!(result.is_err());
+    }
 
-        assert_eq!(restored.homebrew_prefix, state.homebrew_prefix);
-        assert!(restored.migrated_packages.contains_key("git"));
+    #[test]
+    fn test_detect_zerobrew_prefix_missing_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = HomebrewMigrator::detect_zerobrew_prefix(
+            &SystemRunner,
+            &dir.path().join("no-such-zb"),
+            false,
+        );
+        assert_eq!(prefix, None);
     }
 
     // ============================================
@@ -0,0 +1,34 @@
+//! Runs the `zb-migrate` binary against a Homebrew fixture, with no zerobrew installed.
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+pub const FIXTURE: &str = r#"{
+    "formulae": [
+        {"name": "openssl@3", "version": "3.2.0"},
+        {"name": "curl", "version": "8.5.0", "dependencies": ["openssl@3"]},
+        {"name": "jq", "version": "1.7.1"}
+    ],
+    "casks": [{"name": "iterm2", "version": "3.4.23"}]
+}"#;
+
+/// `zb-migrate ARGS` answering brew commands from [`FIXTURE`], with state under `dir`
+pub fn zb_migrate_command(dir: &Path, args: &[&str]) -> Command {
+    let fixture = dir.join("brew.json");
+    std::fs::write(&fixture, FIXTURE).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_zb-migrate"));
+    command
+        .arg("--mock-brew")
+        .arg(&fixture)
+        .arg("--zb-bin")
+        .arg(dir.join("missing-zb"))
+        .args(args)
+        .env("ZEROBREW_HOME", dir.join("home"))
+        .env_remove("ZB_MIGRATE_CONFIG");
+    command
+}
+
+/// Run `zb-migrate ARGS` to completion; stdin is closed, as without a terminal
+pub fn zb_migrate(dir: &Path, args: &[&str]) -> Output {
+    zb_migrate_command(dir, args).output().unwrap()
+}
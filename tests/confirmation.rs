@@ -0,0 +1,27 @@
+//! Migrations that install everything need a confirmation, even in modes that
+//! would otherwise prompt per package.
+
+mod common;
+
+use common::zb_migrate;
+
+#[test]
+fn test_interactive_without_terminal_requires_yes() {
+    let dir = tempfile::tempdir().unwrap();
+    for args in [&["migrate"][..], &["migrate", "--interactive"]] {
+        let output = zb_migrate(dir.path(), args);
+        assert!(
+            !output.status.success(),
+            "{:?} migrated: {:?}",
+            args,
+            output
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("Refusing to migrate 3 formulae without confirmation"),
+            "{:?}: {}",
+            args,
+            stderr
+        );
+    }
+}
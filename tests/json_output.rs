@@ -0,0 +1,109 @@
+//! `--json` and `--json-lines` output must stay machine-readable: results go to
+//! stdout, progress and status notes to stderr, even when both are redirected.
+
+use std::process::Stdio;
+
+mod common;
+
+use common::{zb_migrate, zb_migrate_command};
+
+#[test]
+fn test_json_stdout_has_no_progress_output() {
+    let dir = tempfile::tempdir().unwrap();
+    for args in [
+        &["list", "--json"][..],
+        &["analyze", "--json"],
+        &["status", "--json"],
+        &["taps", "--json"],
+    ] {
+        let output = zb_migrate(dir.path(), args);
+        assert!(output.status.success(), "{:?} failed: {:?}", args, output);
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&stdout)
+            .unwrap_or_else(|e| panic!("{:?} wrote non-JSON to stdout ({}): {}", args, e, stdout));
+        assert!(
+            json["data"].is_object() || json["data"].is_array(),
+            "{:?}",
+            args
+        );
+    }
+
+    // Progress still reaches the user, just not on stdout
+    let output = zb_migrate(dir.path(), &["analyze", "--json"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Analyzing installed packages"));
+}
+
+#[test]
+fn test_closed_stdout_exits_cleanly() {
+    let dir = tempfile::tempdir().unwrap();
+    for args in [&["list", "--json"][..], &["migrate", "--dry-run"]] {
+        let mut child = zb_migrate_command(dir.path(), args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Close the reader before anything is written, like `| head -0`
+        drop(child.stdout.take());
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(
+            output.status.code(),
+            Some(0),
+            "{:?} failed: {:?}",
+            args,
+            output
+        );
+        assert!(
+            !String::from_utf8_lossy(&output.stderr).contains("panicked"),
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn test_json_lines_stdout_is_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = zb_migrate(dir.path(), &["migrate", "--json-lines", "--yes"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| {
+            serde_json::from_str(line).unwrap_or_else(|e| panic!("non-JSON line ({}): {}", e, line))
+        })
+        .collect();
+    assert_eq!(events.len(), 4, "{}", stdout);
+    assert_eq!(events[3]["event"], "summary");
+
+    // Artifacts written to `-` would land in the same stream
+    for args in [&["--report", "-"][..], &["--export-failures", "-"]] {
+        let args: Vec<&str> = ["migrate", "--json-lines", "--yes"]
+            .iter()
+            .chain(args)
+            .copied()
+            .collect();
+        let output = zb_migrate(dir.path(), &args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(output.stdout.is_empty(), "{:?}", args);
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("would mix"),
+            "{:?}",
+            args
+        );
+    }
+}
+
+#[test]
+fn test_analyze_rejects_artifacts_sharing_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    for args in [
+        &["analyze", "--json", "--report", "-"][..],
+        &["analyze", "--json", "--emit-script", "-"],
+        &["analyze", "--report", "-", "--emit-script", "-"],
+    ] {
+        let output = zb_migrate(dir.path(), args);
+        assert!(!output.status.success(), "{:?}", args);
+        assert!(output.stdout.is_empty(), "{:?}", args);
+    }
+    let output = zb_migrate(dir.path(), &["analyze", "--emit-script", "-"]);
+    assert!(output.status.success(), "{:?}", output);
+}